@@ -20,19 +20,25 @@
 
 use std::error::Error;
 use std::fmt;
-use std::io::{self, Read};
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::str::FromStr;
 
 use async_stream::stream;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
 use futures::executor::block_on_stream;
 use futures::StreamExt;
 use futures_core::stream::Stream;
 
-use crate::bgzf::BgzfReader;
+use crate::bam_index::BamIndex;
+use crate::bgzf::{BgzfReader, BgzfWriter};
 use crate::genome::Genome;
 use crate::netfile::NetFile;
 use crate::range::Range;
 use crate::read;
+use crate::read::ReadBlock;
+use crate::sam_header::SamHeader;
 use crate::utility_io::{read_until_null, skip_n_bytes};
 
 /* -------------------------------------------------------------------------- */
@@ -60,6 +66,62 @@ impl fmt::Display for BamSeq {
 
 /* -------------------------------------------------------------------------- */
 
+impl BamSeq {
+    /// Encodes a nucleotide sequence given as text, packing two bases per byte.
+    ///
+    /// Characters that are not part of the BAM alphabet `=ACMGRSVTWYHKDBN`
+    /// are encoded as `N`.
+    pub fn encode(seq: &str) -> BamSeq {
+        let encode_base = |b: u8| -> u8 {
+            match b.to_ascii_uppercase() {
+                b'=' => 0,
+                b'A' => 1,
+                b'C' => 2,
+                b'M' => 3,
+                b'G' => 4,
+                b'R' => 5,
+                b'S' => 6,
+                b'V' => 7,
+                b'T' => 8,
+                b'W' => 9,
+                b'Y' => 10,
+                b'H' => 11,
+                b'K' => 12,
+                b'D' => 13,
+                b'B' => 14,
+                _ => 15,
+            }
+        };
+        BamSeq(
+            seq.as_bytes()
+                .chunks(2)
+                .map(|c| {
+                    let b1 = encode_base(c[0]) << 4;
+                    let b2 = if c.len() == 2 { encode_base(c[1]) } else { 0 };
+                    b1 | b2
+                })
+                .collect(),
+        )
+    }
+
+    /// Decodes the first `l_seq` bases of the sequence.
+    ///
+    /// Unlike the `Display` implementation, this method uses the sequence
+    /// length to decode `=` bases at the end of odd-length sequences correctly.
+    pub fn decode(&self, l_seq: usize) -> String {
+        let t = b"=ACMGRSVTWYHKDBN";
+        (0..l_seq.min(2 * self.0.len()))
+            .map(|i| {
+                let byte = self.0[i / 2];
+                let b = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+                t[b as usize] as char
+            })
+            .collect()
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
 // Represents BAM quality scores
 #[derive(Clone, Debug, Default)]
 pub struct BamQual(pub Vec<u8>);
@@ -119,6 +181,24 @@ impl Default for BamAuxValue {
 
 /* -------------------------------------------------------------------------- */
 
+impl BamAuxValue {
+    /// Returns the value of integer fields, regardless of their width and
+    /// signedness.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            BamAuxValue::C(v) => Some(*v as i64),
+            BamAuxValue::CUnsigned(v) => Some(*v as i64),
+            BamAuxValue::S(v) => Some(*v as i64),
+            BamAuxValue::SUnsigned(v) => Some(*v as i64),
+            BamAuxValue::I(v) => Some(*v as i64),
+            BamAuxValue::IUnsigned(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
 impl fmt::Display for BamAuxiliary {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}{}:", self.tag[0] as char, self.tag[1] as char)?;
@@ -204,12 +284,7 @@ impl BamAuxiliary {
             b'H' => {
                 let buffer: Vec<u8> = read_until_null(reader)?;
                 n += (buffer.len() + 1) as u64;
-                BamAuxValue::H(
-                    buffer
-                        .iter()
-                        .map(|b| format!("{:X}", b))
-                        .collect::<String>(),
-                )
+                BamAuxValue::H(String::from_utf8_lossy(&buffer).to_string())
             }
             b'B' => {
                 let array_type = reader.read_u8()?;
@@ -291,16 +366,141 @@ impl BamAuxiliary {
 
         Ok((n, BamAuxiliary { tag, value }))
     }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.tag)?;
+
+        match &self.value {
+            BamAuxValue::A(v) => {
+                writer.write_u8(b'A')?;
+                writer.write_u8(*v)?;
+            }
+            BamAuxValue::C(v) => {
+                writer.write_u8(b'c')?;
+                writer.write_i8(*v)?;
+            }
+            BamAuxValue::CUnsigned(v) => {
+                writer.write_u8(b'C')?;
+                writer.write_u8(*v)?;
+            }
+            BamAuxValue::S(v) => {
+                writer.write_u8(b's')?;
+                writer.write_i16::<LittleEndian>(*v)?;
+            }
+            BamAuxValue::SUnsigned(v) => {
+                writer.write_u8(b'S')?;
+                writer.write_u16::<LittleEndian>(*v)?;
+            }
+            BamAuxValue::I(v) => {
+                writer.write_u8(b'i')?;
+                writer.write_i32::<LittleEndian>(*v)?;
+            }
+            BamAuxValue::IUnsigned(v) => {
+                writer.write_u8(b'I')?;
+                writer.write_u32::<LittleEndian>(*v)?;
+            }
+            BamAuxValue::F(v) => {
+                writer.write_u8(b'f')?;
+                writer.write_f32::<LittleEndian>(*v)?;
+            }
+            BamAuxValue::D(v) => {
+                writer.write_u8(b'd')?;
+                writer.write_f64::<LittleEndian>(*v)?;
+            }
+            BamAuxValue::Z(v) => {
+                writer.write_u8(b'Z')?;
+                writer.write_all(v.as_bytes())?;
+                writer.write_u8(0)?;
+            }
+            BamAuxValue::H(v) => {
+                writer.write_u8(b'H')?;
+                writer.write_all(v.as_bytes())?;
+                writer.write_u8(0)?;
+            }
+            BamAuxValue::BInt8(v) => {
+                writer.write_all(b"Bc")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                for &x in v {
+                    writer.write_i8(x)?;
+                }
+            }
+            BamAuxValue::BUint8(v) => {
+                writer.write_all(b"BC")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                writer.write_all(v)?;
+            }
+            BamAuxValue::BInt16(v) => {
+                writer.write_all(b"Bs")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                for &x in v {
+                    writer.write_i16::<LittleEndian>(x)?;
+                }
+            }
+            BamAuxValue::BUint16(v) => {
+                writer.write_all(b"BS")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                for &x in v {
+                    writer.write_u16::<LittleEndian>(x)?;
+                }
+            }
+            BamAuxValue::BInt32(v) => {
+                writer.write_all(b"Bi")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                for &x in v {
+                    writer.write_i32::<LittleEndian>(x)?;
+                }
+            }
+            BamAuxValue::BUint32(v) => {
+                writer.write_all(b"BI")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                for &x in v {
+                    writer.write_u32::<LittleEndian>(x)?;
+                }
+            }
+            BamAuxValue::BFloat32(v) => {
+                writer.write_all(b"Bf")?;
+                writer.write_i32::<LittleEndian>(v.len() as i32)?;
+                for &x in v {
+                    writer.write_f32::<LittleEndian>(x)?;
+                }
+            }
+            BamAuxValue::None() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Auxiliary field without value",
+                ))
+            }
+        }
+        Ok(())
+    }
 }
 
 /* -------------------------------------------------------------------------- */
 
 // Represents BAM flags
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct BamFlag(pub u16);
 
 /* -------------------------------------------------------------------------- */
 
+/// Names of the flag bits as used by `samtools flags`.
+pub const BAM_FLAG_NAMES: [&str; 12] = [
+    "PAIRED",
+    "PROPER_PAIR",
+    "UNMAP",
+    "MUNMAP",
+    "REVERSE",
+    "MREVERSE",
+    "READ1",
+    "READ2",
+    "SECONDARY",
+    "QCFAIL",
+    "DUP",
+    "SUPPLEMENTARY",
+];
+
+/* -------------------------------------------------------------------------- */
+
 impl BamFlag {
     pub fn bit(&self, i: u8) -> bool {
         (self.0 >> i) & 1 == 1
@@ -353,6 +553,48 @@ impl BamFlag {
     pub fn duplicate(&self) -> bool {
         self.bit(10)
     }
+
+    /// Returns `true` if all bits of `flags` are set.
+    pub fn contains(&self, flags: u16) -> bool {
+        self.0 & flags == flags
+    }
+
+    /// Returns `true` if any bit of `flags` is set.
+    pub fn intersects(&self, flags: u16) -> bool {
+        self.0 & flags != 0
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for BamFlag {
+    type Err = String;
+
+    /// Parses flags given as decimal or hexadecimal (`0x`) number, or as a
+    /// comma separated list of flag names (see `BAM_FLAG_NAMES`, case
+    /// insensitive).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            return u16::from_str_radix(hex, 16)
+                .map(BamFlag)
+                .map_err(|_| format!("invalid flags `{}`", s));
+        }
+        if let Ok(flag) = s.parse::<u16>() {
+            return Ok(BamFlag(flag));
+        }
+        let mut flag = 0;
+        for name in s.split(',') {
+            match BAM_FLAG_NAMES
+                .iter()
+                .position(|x| x.eq_ignore_ascii_case(name.trim()))
+            {
+                Some(i) => flag |= 1 << i,
+                None => return Err(format!("invalid flag `{}`", name)),
+            }
+        }
+        Ok(BamFlag(flag))
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -386,6 +628,35 @@ impl BamCigar {
         length
     }
 
+    /// Returns `true` if the alignment contains deletions (`D`) or skipped
+    /// regions (`N`).
+    pub fn is_gapped(&self) -> bool {
+        self.parse_cigar()
+            .any(|cigar_block| cigar_block.type_ == 'D' || cigar_block.type_ == 'N')
+    }
+
+    /// Returns the segments of an alignment starting at `position` that
+    /// consist of aligned bases (`M`, `=`, `X`) or deletions (`D`).
+    pub fn read_blocks(&self, position: usize) -> Vec<ReadBlock> {
+        let mut blocks = Vec::new();
+        let mut from = position;
+        for cigar_block in self.parse_cigar() {
+            match cigar_block.type_ {
+                'M' | '=' | 'X' | 'D' => {
+                    let to = from + cigar_block.n as usize;
+                    blocks.push(ReadBlock {
+                        range: Range::new(from, to),
+                        type_: cigar_block.type_,
+                    });
+                    from = to;
+                }
+                'N' => from += cigar_block.n as usize,
+                _ => {}
+            }
+        }
+        blocks
+    }
+
     pub fn parse_cigar(&self) -> impl Iterator<Item = CigarBlock> + '_ {
         let types = b"MIDNSHP=X";
         self.0.iter().map(move |&c| {
@@ -401,6 +672,50 @@ impl BamCigar {
 
 /* -------------------------------------------------------------------------- */
 
+impl FromStr for BamCigar {
+    type Err = String;
+
+    /// Parses a CIGAR string such as `10M2I5M`. The string `*` denotes an
+    /// empty CIGAR.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let types = b"MIDNSHP=X";
+        let mut cigar = BamCigar::default();
+        let mut n: u32 = 0;
+        let mut has_n = false;
+
+        if s == "*" {
+            return Ok(cigar);
+        }
+        for c in s.bytes() {
+            if c.is_ascii_digit() {
+                n = n
+                    .checked_mul(10)
+                    .and_then(|n| n.checked_add((c - b'0') as u32))
+                    .filter(|&n| n < 1 << 28)
+                    .ok_or_else(|| format!("invalid CIGAR string `{}`", s))?;
+                has_n = true;
+            } else {
+                let op = types
+                    .iter()
+                    .position(|&t| t == c)
+                    .ok_or_else(|| format!("invalid CIGAR operation `{}`", c as char))?;
+                if !has_n {
+                    return Err(format!("invalid CIGAR string `{}`", s));
+                }
+                cigar.0.push((n << 4) | op as u32);
+                n = 0;
+                has_n = false;
+            }
+        }
+        if has_n {
+            return Err(format!("invalid CIGAR string `{}`", s));
+        }
+        Ok(cigar)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
 #[derive(Debug, Default)]
 pub struct CigarBlock {
     pub n: i32,
@@ -419,6 +734,37 @@ pub struct BamHeader {
 
 /* -------------------------------------------------------------------------- */
 
+impl BamHeader {
+    /// Parses the header text into a structured `SamHeader`.
+    ///
+    /// # Errors
+    /// Returns an error message if a header line is malformed.
+    pub fn parse(&self) -> Result<SamHeader, String> {
+        self.text.parse()
+    }
+
+    /// Replaces the header text by the serialization of a `SamHeader`.
+    pub fn set_sam_header(&mut self, header: &SamHeader) {
+        self.text = header.to_string();
+        self.text_length = self.text.len() as i32;
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl From<&SamHeader> for BamHeader {
+    fn from(header: &SamHeader) -> Self {
+        let mut bam_header = BamHeader {
+            n_ref: header.sequences.len() as i32,
+            ..Default::default()
+        };
+        bam_header.set_sam_header(header);
+        bam_header
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
 // Represents a BAM block
 #[derive(Clone, Debug, Default)]
 pub struct BamBlock {
@@ -442,6 +788,105 @@ pub struct BamBlock {
 
 /* -------------------------------------------------------------------------- */
 
+impl BamBlock {
+    /// Returns the value of the auxiliary field with the given tag.
+    pub fn get_auxiliary(&self, tag: &[u8; 2]) -> Option<&BamAuxValue> {
+        self.auxiliary
+            .iter()
+            .find(|aux| &aux.tag == tag)
+            .map(|aux| &aux.value)
+    }
+
+    /// Returns the read group of the record given by the `RG` tag.
+    pub fn read_group(&self) -> Option<&str> {
+        match self.get_auxiliary(b"RG") {
+            Some(BamAuxValue::Z(rg)) => Some(rg),
+            _ => None,
+        }
+    }
+
+    /// Sets the value of the auxiliary field with the given tag, replacing
+    /// an existing field or appending a new one.
+    pub fn set_auxiliary(&mut self, tag: &[u8; 2], value: BamAuxValue) {
+        match self.auxiliary.iter_mut().find(|aux| &aux.tag == tag) {
+            Some(aux) => aux.value = value,
+            None => self.auxiliary.push(BamAuxiliary { tag: *tag, value }),
+        }
+    }
+
+    /// Serializes the record in BAM format, including the leading `block_size`.
+    ///
+    /// The lengths of the read name and the CIGAR are derived from `read_name`
+    /// and `cigar`, whereas `l_seq` must match the stored sequence. Missing
+    /// quality scores are written as `0xff`, as required by the specification.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the sequence or quality data is inconsistent
+    /// with `l_seq` or if writing fails.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let l_seq = self.l_seq.max(0) as usize;
+
+        if self.seq.0.len() != l_seq.div_ceil(2) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record `{}` has invalid sequence length", self.read_name),
+            ));
+        }
+        if !self.qual.0.is_empty() && self.qual.0.len() != l_seq {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record `{}` has invalid quality length", self.read_name),
+            ));
+        }
+        if self.read_name.len() > 254 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "record `{}` has read name longer than 254 characters",
+                    self.read_name
+                ),
+            ));
+        }
+
+        let mut buf = Vec::with_capacity(
+            32 + self.read_name.len() + 1 + 4 * self.cigar.0.len() + l_seq.div_ceil(2) + l_seq,
+        );
+
+        let bin_mq_nl = ((self.bin as u32) << 16)
+            | ((self.mapq as u32) << 8)
+            | (self.read_name.len() as u32 + 1);
+        let flag_nc = ((self.flag.0 as u32) << 16) | (self.cigar.0.len() as u32 & 0xffff);
+
+        buf.write_i32::<LittleEndian>(self.ref_id)?;
+        buf.write_i32::<LittleEndian>(self.position)?;
+        buf.write_u32::<LittleEndian>(bin_mq_nl)?;
+        buf.write_u32::<LittleEndian>(flag_nc)?;
+        buf.write_i32::<LittleEndian>(l_seq as i32)?;
+        buf.write_i32::<LittleEndian>(self.next_ref_id)?;
+        buf.write_i32::<LittleEndian>(self.next_position)?;
+        buf.write_i32::<LittleEndian>(self.tlen)?;
+        buf.write_all(self.read_name.as_bytes())?;
+        buf.write_u8(0)?;
+        for &c in &self.cigar.0 {
+            buf.write_u32::<LittleEndian>(c)?;
+        }
+        buf.write_all(&self.seq.0)?;
+        if self.qual.0.is_empty() {
+            buf.resize(buf.len() + l_seq, 0xff);
+        } else {
+            buf.write_all(&self.qual.0)?;
+        }
+        for aux in &self.auxiliary {
+            aux.write(&mut buf)?;
+        }
+
+        writer.write_i32::<LittleEndian>(buf.len() as i32)?;
+        writer.write_all(&buf)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
 #[derive(Clone, Debug, Default)]
 pub struct BamReaderType1 {
     pub block: BamBlock,
@@ -468,6 +913,8 @@ pub struct BamReaderType2 {
 /// - `read_sequence`: If `true`, reads the sequence of each read.
 /// - `read_auxiliary`: If `true`, reads auxiliary fields in the BAM file.
 /// - `read_qual`: If `true`, reads the quality scores of each read.
+/// - `threads`: Number of threads used for BGZF decompression. Values of
+///   zero or one decompress on the calling thread.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct BamReaderOptions {
     pub read_name: bool,
@@ -475,6 +922,24 @@ pub struct BamReaderOptions {
     pub read_sequence: bool,
     pub read_auxiliary: bool,
     pub read_qual: bool,
+    pub threads: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamReaderOptions {
+    /// Returns options that read all sections of a record (the defaults of
+    /// `BamReader::new`) and decompress using `threads` threads.
+    pub fn with_threads(threads: usize) -> Self {
+        BamReaderOptions {
+            read_name: true,
+            read_cigar: true,
+            read_sequence: true,
+            read_auxiliary: true,
+            read_qual: true,
+            threads,
+        }
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -492,12 +957,14 @@ pub struct BamReaderOptions {
 /// - `header`: The header information from the BAM file, stored as a `BamHeader`.
 /// - `genome`: The genomic reference information associated with the BAM file.
 /// - `reader`: The underlying `BgzfReader` for reading compressed BAM data.
+/// - `index`: An optional `BamIndex` used for region queries.
 #[derive(Debug)]
 pub struct BamReader<R: Read> {
     options: BamReaderOptions,
     header: BamHeader,
     genome: Genome,
     reader: BgzfReader<R>,
+    index: Option<BamIndex>,
 }
 
 /* -------------------------------------------------------------------------- */
@@ -516,11 +983,13 @@ impl<R: Read> BamReader<R> {
     /// Returns an `io::Error` if the file does not start with the "BAM\x01" magic
     /// bytes or if header data cannot be read.
     pub fn new(reader: R, options_arg: Option<BamReaderOptions>) -> io::Result<Self> {
+        let options = options_arg.unwrap_or_default();
         let mut bam_reader = BamReader {
-            options: options_arg.unwrap_or_default(),
+            options,
             genome: Genome::default(),
             header: BamHeader::default(),
-            reader: BgzfReader::new(reader)?,
+            reader: BgzfReader::with_threads(reader, options.threads)?,
+            index: None,
         };
 
         if options_arg.is_none() {
@@ -572,11 +1041,145 @@ impl<R: Read> BamReader<R> {
     pub fn get_genome(&self) -> &Genome {
         &self.genome
     }
+
+    /// Returns a reference to the BAM header.
+    ///
+    /// # Returns
+    /// A reference to the `BamHeader` read from the beginning of the file.
+    pub fn get_header(&self) -> &BamHeader {
+        &self.header
+    }
+
+    /// Returns the options of the reader.
+    ///
+    /// # Returns
+    /// The `BamReaderOptions` that determine which fields of a record are parsed.
+    pub fn get_options(&self) -> &BamReaderOptions {
+        &self.options
+    }
+
+    /// Returns the virtual file offset of the next record.
+    pub fn virtual_offset(&self) -> u64 {
+        self.reader.virtual_offset()
+    }
+
+    /// Sets the index used for region queries.
+    ///
+    /// # Arguments
+    /// * `index` - A `BamIndex` matching the BAM file.
+    pub fn set_index(&mut self, index: BamIndex) {
+        self.index = Some(index);
+    }
+
+    /// Returns a reference to the index, if available.
+    pub fn get_index(&self) -> Option<&BamIndex> {
+        self.index.as_ref()
+    }
 }
 
 /* -------------------------------------------------------------------------- */
 
 impl<R: Read> BamReader<R> {
+    /// Reads the next record from the BAM file.
+    ///
+    /// # Returns
+    /// `Ok(None)` if no more records are available, otherwise the parsed
+    /// `BamBlock`. Fields that are disabled in `BamReaderOptions` are skipped.
+    pub(crate) fn read_block(&mut self) -> io::Result<Option<BamBlock>> {
+        let options = self.options;
+        self.read_block_with_options(&options)
+    }
+
+    /// Reads the next record like `read_block`, but parses the fields
+    /// enabled in `options` instead of the options of the reader.
+    pub(crate) fn read_block_with_options(
+        &mut self,
+        options: &BamReaderOptions,
+    ) -> io::Result<Option<BamBlock>> {
+        let mut block = BamBlock::default();
+        let mut buf = Vec::new();
+
+        let block_size = match self.reader.read_i32::<LittleEndian>() {
+            Ok(v) => v,
+            // No more reads available, exiting
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        block.ref_id = self.reader.read_i32::<LittleEndian>()?;
+        block.position = self.reader.read_i32::<LittleEndian>()?;
+
+        let bin_mq_nl = self.reader.read_u32::<LittleEndian>()?;
+        block.bin = ((bin_mq_nl >> 16) & 0xffff) as u16;
+        block.mapq = ((bin_mq_nl >> 8) & 0xff) as u8;
+        block.rname_len = (bin_mq_nl & 0xff) as u8;
+
+        let flag_nc = self.reader.read_u32::<LittleEndian>()?;
+        block.flag = BamFlag((flag_nc >> 16) as u16);
+        block.n_cigar_op = (flag_nc & 0xffff) as u16;
+
+        block.l_seq = self.reader.read_i32::<LittleEndian>()?;
+        block.next_ref_id = self.reader.read_i32::<LittleEndian>()?;
+        block.next_position = self.reader.read_i32::<LittleEndian>()?;
+        block.tlen = self.reader.read_i32::<LittleEndian>()?;
+
+        // Parse the read name
+        loop {
+            match self.reader.read_u8()? {
+                0 => {
+                    block.read_name = String::from_utf8(buf).unwrap();
+                    break;
+                }
+                b => buf.push(b),
+            }
+        }
+
+        // Parse CIGAR block
+        if options.read_cigar {
+            block.cigar = BamCigar(Vec::with_capacity(block.n_cigar_op as usize));
+            for _ in 0..block.n_cigar_op {
+                block.cigar.0.push(self.reader.read_u32::<LittleEndian>()?);
+            }
+        } else {
+            skip_n_bytes(&mut self.reader, block.n_cigar_op as usize * 4)?;
+        }
+
+        // Parse sequence
+        let seq_len = (block.l_seq + 1) / 2;
+        if options.read_sequence {
+            block.seq = BamSeq(vec![0; seq_len as usize]);
+            self.reader.read_exact(&mut block.seq.0)?;
+        } else {
+            skip_n_bytes(&mut self.reader, seq_len as usize)?;
+        }
+
+        // Parse qual block
+        if options.read_qual {
+            block.qual = BamQual(vec![0; block.l_seq as usize]);
+            self.reader.read_exact(&mut block.qual.0)?;
+        } else {
+            skip_n_bytes(&mut self.reader, block.l_seq as usize)?;
+        }
+
+        // Read auxiliary data
+        let mut position = (8 * 4
+            + block.rname_len as usize
+            + 4 * block.n_cigar_op as usize
+            + (block.l_seq as usize).div_ceil(2)
+            + block.l_seq as usize) as i32;
+
+        if options.read_auxiliary {
+            while position < block_size {
+                let (bytes_read, aux) = BamAuxiliary::read(&mut self.reader)?;
+                block.auxiliary.push(aux);
+                position += bytes_read as i32;
+            }
+        } else {
+            skip_n_bytes(&mut self.reader, (block_size - position) as usize)?;
+        }
+
+        Ok(Some(block))
+    }
+
     /// Reads single-end reads from the BAM file as a stream.
     ///
     /// # Returns
@@ -588,144 +1191,12 @@ impl<R: Read> BamReader<R> {
         &'a mut self,
     ) -> impl Stream<Item = io::Result<BamReaderType1>> + 'a {
         stream! {
-
-            let mut block_size: i32;
-            let mut flag_nc   : u32;
-            let mut bin_mq_nl : u32;
-
             loop {
-                let mut block = BamBlock::default();
-                let mut buf   = Vec::new();
-
-                block_size = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => {
-                        // No more reads available, exiting
-                        if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                            return;
-                        }
-                        yield Err(e); return;
-                    }
-                };
-                block.ref_id = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-                block.position = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-
-                bin_mq_nl = match self.reader.read_u32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-                block.bin       = ((bin_mq_nl >>   16) & 0xffff) as u16;
-                block.mapq      = ((bin_mq_nl >>    8) & 0xff  ) as u8;
-                block.rname_len =  (bin_mq_nl  & 0xff) as u8;
-
-                flag_nc = match self.reader.read_u32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-                block.flag       = BamFlag((flag_nc >> 16) as u16);
-                block.n_cigar_op = (flag_nc & 0xffff) as u16;
-
-                block.l_seq = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-                block.next_ref_id = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-                block.next_position = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
-                    Err(e) => { yield Err(e); return; }
-                };
-                block.tlen = match self.reader.read_i32::<LittleEndian>() {
-                    Ok (v) => v,
+                match self.read_block() {
+                    Ok(Some(block)) => yield Ok(BamReaderType1 { block }),
+                    Ok(None) => return,
                     Err(e) => { yield Err(e); return; }
-                };
-
-                // Parse the read name
-                loop {
-                    match self.reader.read_u8() {
-                        Ok (b) if b == 0 => {
-                            block.read_name = String::from_utf8(buf.clone()).unwrap();
-                            break;
-                        }
-                        Ok (b) => buf.push(b),
-                        Err(e) => { yield Err(e); return; }
-                    }
-                }
-
-                // Parse CIGAR block
-                if self.options.read_cigar {
-                    block.cigar = BamCigar(Vec::with_capacity(block.n_cigar_op as usize));
-                    for _ in 0..block.n_cigar_op {
-                        if let Err(e) = self.reader.read_u32::<LittleEndian>().map(|v| block.cigar.0.push(v)) {
-                            yield Err(e); return;
-                        }
-                    }
-                } else {
-                    if let Err(e) = skip_n_bytes(&mut self.reader, (block.n_cigar_op * 4) as usize) {
-                        yield Err(e); return;
-                    }
-                }
-
-                // Parse sequence
-                if self.options.read_sequence {
-                    let seq_len = (block.l_seq + 1) / 2;
-                    block.seq = BamSeq(vec![0; seq_len as usize]);
-
-                    if let Err(e) = self.reader.read_exact(&mut block.seq.0) {
-                        yield Err(e); return;
-                    }
-                } else {
-                    let seq_len = (block.l_seq + 1) / 2;
-
-                    if let Err(e) = skip_n_bytes(&mut self.reader, seq_len as usize) {
-                        yield Err(e); return;
-                    }
-                }
-
-                // Parse qual block
-                if self.options.read_qual {
-                    block.qual = BamQual(vec![0; block.l_seq as usize]);
-                    if let Err(e) = self.reader.read_exact(&mut block.qual.0) {
-                        yield Err(e); return;
-                    }
-                } else {
-                    if let Err(e) = skip_n_bytes(&mut self.reader, block.l_seq as usize) {
-                        yield Err(e); return;
-                    }
-                }
-
-                // Read auxiliary data
-                let mut position = (8 * 4 + block.rname_len as usize + 4 * block.n_cigar_op as usize
-                    + (block.l_seq as usize + 1) / 2 + block.l_seq as usize) as i32;
-
-                if self.options.read_auxiliary {
-                    while position < block_size {
-                        match BamAuxiliary::read(&mut self.reader) {
-                            Ok ((bytes_read, aux)) => {
-                                block.auxiliary.push(aux);
-                                position += bytes_read as i32;
-                            }
-                            Err(e) => { yield Err(e); return; }
-                        }
-                    }
-                } else {
-                    if let Err(e) = skip_n_bytes(&mut self.reader, (block_size - position) as usize) {
-                        yield Err(e); return;
-                    }
                 }
-
-                yield Ok(BamReaderType1{
-                    block
-                });
-
             }
         }
     }
@@ -800,6 +1271,34 @@ impl<R: Read> BamReader<R> {
         &'a mut self,
         join_pairs: bool,
         paired_end_strand_specific: bool,
+    ) -> impl Stream<Item = io::Result<read::Read>> + 'a {
+        self.read_reads_stream(join_pairs, paired_end_strand_specific, false)
+    }
+
+    /// Reads simplified read data as a stream like `read_simple_stream`, but
+    /// additionally records the aligned segments of each read (see
+    /// `Read::blocks`) as required for spliced coverage.
+    ///
+    /// # Arguments
+    /// * `join_pairs` - If true, pairs reads from paired-end sequencing.
+    /// * `paired_end_strand_specific` - If true, applies strand-specific behavior
+    ///   for paired-end reads.
+    ///
+    /// # Returns
+    /// An asynchronous stream of `io::Result<read::Read>` for each read.
+    pub fn read_spliced_stream<'a>(
+        &'a mut self,
+        join_pairs: bool,
+        paired_end_strand_specific: bool,
+    ) -> impl Stream<Item = io::Result<read::Read>> + 'a {
+        self.read_reads_stream(join_pairs, paired_end_strand_specific, true)
+    }
+
+    fn read_reads_stream<'a>(
+        &'a mut self,
+        join_pairs: bool,
+        paired_end_strand_specific: bool,
+        spliced: bool,
     ) -> impl Stream<Item = io::Result<read::Read>> + 'a {
         let genome = self.genome.clone();
 
@@ -843,13 +1342,29 @@ impl<R: Read> BamReader<R> {
                                 }
                             }
 
+                            // Both mates as aligned segments, which excludes the
+                            // unsequenced part of the fragment
+                            let mut blocks = Vec::new();
+                            if spliced {
+                                blocks = r.block1.cigar.read_blocks(r.block1.position as usize);
+                                blocks.extend(r.block2.cigar.read_blocks(r.block2.position as usize));
+                            }
+
+                            // Flags of the first mate, since combining both mates
+                            // would set REVERSE and MREVERSE for every FR pair
+                            let flag = if r.block2.flag.first_in_pair() { r.block2.flag } else { r.block1.flag };
+
                             yield Ok(read::Read {
+                                name      : r.block1.read_name,
                                 seqname   : seqname,
                                 range     : Range::new(from as usize, to as usize),
                                 strand    : strand as char,
                                 mapq      : mapq   as i64,
                                 duplicate : duplicate,
                                 paired_end: true,
+                                blocks    : blocks,
+                                flag      : flag,
+                                auxiliary : r.block1.auxiliary,
                             });
 
                         } else if !r.block1.flag.unmapped() {
@@ -862,13 +1377,23 @@ impl<R: Read> BamReader<R> {
                             let duplicate = r.block1.flag.duplicate();
                             let paired    = r.block1.flag.read_paired();
 
+                            let blocks = if spliced && r.block1.cigar.is_gapped() {
+                                r.block1.cigar.read_blocks(from as usize)
+                            } else {
+                                Vec::new()
+                            };
+
                             yield Ok(read::Read {
+                                name      : r.block1.read_name,
                                 seqname   : seqname,
                                 range     : Range::new(from as usize, to as usize),
                                 strand    : strand as char,
                                 mapq      : mapq   as i64,
                                 duplicate : duplicate,
                                 paired_end: paired,
+                                blocks    : blocks,
+                                flag      : r.block1.flag,
+                                auxiliary : r.block1.auxiliary,
                             });
                         }
                     }
@@ -930,6 +1455,108 @@ impl<R: Read> BamReader<R> {
 
 /* -------------------------------------------------------------------------- */
 
+impl<R: Read + Seek> BamReader<R> {
+    /// Reads all records overlapping a genomic region as a stream.
+    ///
+    /// The reader must have an index (see `set_index`). Only the chunks of
+    /// the BAM file listed in the index for the given region are read. After
+    /// the query the reader is positioned at an arbitrary record.
+    ///
+    /// # Arguments
+    /// * `seqname` - Name of the reference sequence.
+    /// * `range` - The 0-based, half-open region on the reference sequence.
+    ///
+    /// # Returns
+    /// An asynchronous stream of `io::Result<BamReaderType1>` with all records
+    /// that overlap the region, in the order of the BAM file.
+    pub fn query_stream<'a>(
+        &'a mut self,
+        seqname: &'a str,
+        range: Range,
+    ) -> impl Stream<Item = io::Result<BamReaderType1>> + 'a {
+        // Alignment lengths are required to test for overlaps
+        let mut options = self.options;
+        options.read_cigar = true;
+
+        self.query_stream_with_options(seqname, range, options)
+    }
+
+    /// Reads all records overlapping a genomic region like `query_stream`,
+    /// but parses the fields enabled in `options` instead of the options of
+    /// the reader. `options.read_cigar` must be set.
+    pub(crate) fn query_stream_with_options<'a>(
+        &'a mut self,
+        seqname: &'a str,
+        range: Range,
+        options: BamReaderOptions,
+    ) -> impl Stream<Item = io::Result<BamReaderType1>> + 'a {
+        stream! {
+
+            let ref_id = match self.genome.get_idx(seqname) {
+                Some(idx) => idx,
+                None => {
+                    yield Err(io::Error::new(io::ErrorKind::InvalidInput, format!("sequence `{}` not found in BAM header", seqname)));
+                    return;
+                }
+            };
+            let chunks = match &self.index {
+                Some(index) => index.query_chunks(ref_id, range.from, range.to),
+                None => {
+                    yield Err(io::Error::new(io::ErrorKind::InvalidInput, "BAM file has no index"));
+                    return;
+                }
+            };
+
+            for chunk in chunks {
+
+                if let Err(e) = self.reader.seek_virtual(chunk.begin) {
+                    yield Err(e); return;
+                }
+
+                while self.reader.virtual_offset() < chunk.end {
+
+                    let block = match self.read_block_with_options(&options) {
+                        Ok(Some(block)) => block,
+                        Ok(None) => break,
+                        Err(e) => { yield Err(e); return; }
+                    };
+
+                    // Records are sorted, no further overlaps possible
+                    if block.ref_id != ref_id as i32 || block.position as usize >= range.to {
+                        return;
+                    }
+
+                    let to = block.position as usize + block.cigar.alignment_length().max(1);
+
+                    if to > range.from {
+                        yield Ok(BamReaderType1 { block });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads all records overlapping a genomic region synchronously.
+    ///
+    /// # Arguments
+    /// * `seqname` - Name of the reference sequence.
+    /// * `range` - The 0-based, half-open region on the reference sequence.
+    ///
+    /// # Returns
+    /// An iterator over `io::Result<BamReaderType1>`.
+    pub fn query<'a>(
+        &'a mut self,
+        seqname: &'a str,
+        range: Range,
+    ) -> impl Iterator<Item = io::Result<BamReaderType1>> + 'a {
+        let s = Box::pin(self.query_stream(seqname, range));
+
+        block_on_stream(s)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
 /// `BamFile` is a wrapper around `BamReader`, designed to read BAM files from
 /// either local files or HTTP sources.
 #[derive(Debug)]
@@ -954,6 +1581,158 @@ impl BamFile {
 
         Ok(BamFile { reader: reader })
     }
+
+    /// Opens a BAM file together with its index `<filename>.bai` or, if no
+    /// BAI index exists, `<filename>.csi`.
+    ///
+    /// # Arguments
+    /// * `filename` - The file path or URL of the BAM file.
+    /// * `options` - Optional `BamReaderOptions` to specify read preferences.
+    ///
+    /// # Errors
+    /// Returns an error if the BAM file or its index cannot be opened. A BAI
+    /// index that exists but cannot be read is an error, even if a CSI index
+    /// is present.
+    pub fn open_indexed(
+        filename: &str,
+        options: Option<BamReaderOptions>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut bam = Self::open(filename, options)?;
+        let index = match BamIndex::import(&format!("{}.bai", filename)) {
+            Ok(index) => index,
+            Err(err)
+                if err
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|err| err.kind() == io::ErrorKind::NotFound) =>
+            {
+                BamIndex::import(&format!("{}.csi", filename))?
+            }
+            Err(err) => return Err(err),
+        };
+
+        bam.reader.set_index(index);
+
+        Ok(bam)
+    }
+
+    /// Creates a new BAM file and writes the header.
+    ///
+    /// # Arguments
+    /// * `filename` - The file path of the BAM file to create.
+    /// * `header` - The `BamHeader` to write.
+    /// * `genome` - The reference sequences stored in the BAM file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created or the header cannot be written.
+    pub fn create(
+        filename: &str,
+        header: &BamHeader,
+        genome: &Genome,
+    ) -> Result<BamWriter<File>, Box<dyn Error>> {
+        let file = File::create(filename)?;
+        let writer = BamWriter::new(file, header, genome)?;
+
+        Ok(writer)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A writer for BAM files.
+///
+/// `BamWriter` serializes a `BamHeader`, the reference sequences of a `Genome`
+/// and `BamBlock` records into a BGZF compressed stream.
+///
+/// # Type Parameters
+/// - `W`: A `Write` type that receives the compressed BAM data.
+#[derive(Debug)]
+pub struct BamWriter<W: Write> {
+    writer: BgzfWriter<W>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write> BamWriter<W> {
+    /// Creates a new `BamWriter` and writes the BAM header.
+    ///
+    /// # Arguments
+    /// * `writer` - A writer that implements `Write`.
+    /// * `header` - The `BamHeader` containing the SAM header text.
+    /// * `genome` - The reference sequences, written in the given order.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the header cannot be written.
+    pub fn new(writer: W, header: &BamHeader, genome: &Genome) -> io::Result<Self> {
+        Self::with_compression(writer, header, genome, Compression::default())
+    }
+
+    /// Creates a new `BamWriter` with the given compression level and writes
+    /// the BAM header.
+    ///
+    /// # Arguments
+    /// * `writer` - A writer that implements `Write`.
+    /// * `header` - The `BamHeader` containing the SAM header text.
+    /// * `genome` - The reference sequences, written in the given order.
+    /// * `level` - The deflate compression level of all BGZF blocks.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the header cannot be written.
+    pub fn with_compression(
+        writer: W,
+        header: &BamHeader,
+        genome: &Genome,
+        level: Compression,
+    ) -> io::Result<Self> {
+        let mut bam_writer = BamWriter {
+            writer: BgzfWriter::with_compression(writer, level),
+        };
+        let text = header.text.as_bytes();
+
+        bam_writer.writer.write_all(b"BAM\x01")?;
+        bam_writer
+            .writer
+            .write_i32::<LittleEndian>(text.len() as i32)?;
+        bam_writer.writer.write_all(text)?;
+        bam_writer
+            .writer
+            .write_i32::<LittleEndian>(genome.len() as i32)?;
+
+        for (seqname, &length) in genome.iter() {
+            bam_writer
+                .writer
+                .write_i32::<LittleEndian>(seqname.len() as i32 + 1)?;
+            bam_writer.writer.write_all(seqname.as_bytes())?;
+            bam_writer.writer.write_u8(0)?;
+            bam_writer.writer.write_i32::<LittleEndian>(length as i32)?;
+        }
+        // Records always start in a new BGZF block
+        bam_writer.writer.flush_block()?;
+
+        Ok(bam_writer)
+    }
+
+    /// Returns the virtual file offset at which the next record is written.
+    pub fn virtual_offset(&self) -> u64 {
+        self.writer.virtual_offset()
+    }
+
+    /// Writes a single record.
+    ///
+    /// # Arguments
+    /// * `block` - The `BamBlock` to serialize.
+    pub fn write_block(&mut self, block: &BamBlock) -> io::Result<()> {
+        block.write(&mut self.writer)
+    }
+
+    /// Flushes all pending records and writes the BGZF EOF marker.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.writer.try_finish()
+    }
+
+    /// Closes the BAM stream and returns the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
 }
 
 /* -------------------------------------------------------------------------- */
@@ -998,7 +1777,20 @@ pub fn bam_import_genome(filename: &str) -> Result<Genome, Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
 
-    use crate::bam::BamFile;
+    use std::env;
+    use std::fs;
+    use std::io::{BufWriter, Cursor};
+    use std::process;
+
+    use futures::executor::block_on_stream;
+
+    use crate::bam::{
+        BamBlock, BamCigar, BamFile, BamFlag, BamHeader, BamReader, BamReaderOptions, BamWriter,
+    };
+    use crate::bam_index::{BamIndex, BamIndexBin, BamIndexChunk, BamIndexReference};
+    use crate::genome::Genome;
+    use crate::range::Range;
+    use crate::read_stream::ReadStream;
 
     #[test]
     fn test_bam_genome() {
@@ -1032,4 +1824,226 @@ mod tests {
 
         assert_eq!(cnt, 2335);
     }
+
+    #[test]
+    fn test_bam_read_threads() {
+        let mut bam1 = BamFile::open("tests/test_bam_2.bam", None).unwrap();
+        let mut bam2 = BamFile::open(
+            "tests/test_bam_2.bam",
+            Some(BamReaderOptions::with_threads(4)),
+        )
+        .unwrap();
+
+        let blocks1: Vec<_> = bam1
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block)
+            .collect();
+        let blocks2: Vec<_> = bam2
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block)
+            .collect();
+
+        assert_eq!(blocks1.len(), 4964);
+        assert_eq!(blocks1.len(), blocks2.len());
+        for (b1, b2) in blocks1.iter().zip(blocks2.iter()) {
+            assert_eq!(b1.read_name, b2.read_name);
+            assert_eq!(b1.position, b2.position);
+            assert_eq!(b1.seq.0, b2.seq.0);
+        }
+    }
+
+    #[test]
+    fn test_bam_write() {
+        let mut bam = BamFile::open("tests/test_bam_2.bam", None).unwrap();
+        let header = bam.reader.get_header().clone();
+        let genome = bam.reader.get_genome().clone();
+        let blocks: Vec<_> = bam
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block)
+            .collect();
+
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        for block in &blocks {
+            writer.write_block(block).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(bytes.as_slice(), None).unwrap();
+
+        assert_eq!(reader.get_genome(), &genome);
+        assert_eq!(reader.get_header().text, header.text);
+
+        let result: Vec<_> = reader.read_single_end().map(|r| r.unwrap().block).collect();
+
+        assert_eq!(result.len(), blocks.len());
+        for (a, b) in result.iter().zip(blocks.iter()) {
+            assert_eq!(format!("{:?}", a), format!("{:?}", b));
+        }
+    }
+
+    #[test]
+    fn test_bam_read_simple_pair_flags() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![1000]);
+        let mate = |flag: u16, position: i32, next_position: i32| BamBlock {
+            ref_id: 0,
+            position,
+            next_ref_id: 0,
+            next_position,
+            flag: BamFlag(flag),
+            read_name: "pair".to_string(),
+            cigar: "10M".parse::<BamCigar>().unwrap(),
+            ..Default::default()
+        };
+        // Forward first mate and reverse second mate
+        let mut writer = BamWriter::new(Vec::new(), &BamHeader::default(), &genome).unwrap();
+        writer.write_block(&mate(0x1 | 0x2 | 0x20 | 0x40, 100, 200)).unwrap();
+        writer.write_block(&mate(0x1 | 0x2 | 0x10 | 0x80, 200, 100)).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(Cursor::new(bytes), None).unwrap();
+        let reads = Box::pin(reader.read_simple_stream(true, false));
+        let reads = ReadStream::filter_flags(reads, None, 0, 0x10);
+        let reads: Vec<_> = block_on_stream(reads).map(|r| r.unwrap()).collect();
+
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].range, Range::new(100, 210));
+        assert_eq!(reads[0].flag, BamFlag(0x1 | 0x2 | 0x20 | 0x40));
+    }
+
+    #[test]
+    fn test_bam_read_spliced_pair_blocks() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![1000]);
+        let mate = |flag: u16, position: i32, next_position: i32| BamBlock {
+            ref_id: 0,
+            position,
+            next_ref_id: 0,
+            next_position,
+            flag: BamFlag(flag),
+            read_name: "pair".to_string(),
+            cigar: "10M".parse::<BamCigar>().unwrap(),
+            ..Default::default()
+        };
+        let mut writer = BamWriter::new(Vec::new(), &BamHeader::default(), &genome).unwrap();
+        writer.write_block(&mate(0x1 | 0x2 | 0x20 | 0x40, 100, 200)).unwrap();
+        writer.write_block(&mate(0x1 | 0x2 | 0x10 | 0x80, 200, 100)).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        // Segments are only recorded for spliced coverage
+        let mut reader = BamReader::new(Cursor::new(bytes.clone()), None).unwrap();
+        let reads: Vec<_> = block_on_stream(Box::pin(reader.read_simple_stream(true, false)))
+            .map(|r| r.unwrap())
+            .collect();
+        assert!(reads[0].blocks.is_empty());
+
+        let mut reader = BamReader::new(Cursor::new(bytes), None).unwrap();
+        let reads: Vec<_> = block_on_stream(Box::pin(reader.read_spliced_stream(true, false)))
+            .map(|r| r.unwrap())
+            .collect();
+        let ranges: Vec<_> = reads[0].blocks.iter().map(|b| b.range).collect();
+        assert_eq!(ranges, vec![Range::new(100, 110), Range::new(200, 210)]);
+    }
+
+    #[test]
+    fn test_bam_query() {
+        let mut bam = BamFile::open("tests/test_bam_2.bam", None).unwrap();
+        let header = bam.reader.get_header().clone();
+        let genome = bam.reader.get_genome().clone();
+        let mut blocks: Vec<_> = bam
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block)
+            .collect();
+
+        blocks.sort_by_key(|b| (b.ref_id as u32, b.position));
+
+        // Write sorted BAM and store all records of a reference in bin 0
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        let mut index = BamIndex {
+            references: vec![BamIndexReference::default(); genome.len()],
+            ..Default::default()
+        };
+
+        for block in &blocks {
+            let begin = writer.virtual_offset();
+            writer.write_block(block).unwrap();
+            let end = writer.virtual_offset();
+
+            if block.ref_id >= 0 {
+                let reference = &mut index.references[block.ref_id as usize];
+                if reference.bins.is_empty() {
+                    reference.bins.push(BamIndexBin::default());
+                }
+                reference.bins[0].chunks.push(BamIndexChunk { begin, end });
+            }
+        }
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(Cursor::new(bytes), None).unwrap();
+        reader.set_index(index);
+
+        let from = 50000000;
+        let to = 100000000;
+        let expected: Vec<_> = blocks
+            .iter()
+            .filter(|b| {
+                b.ref_id == genome.get_idx("chr2").unwrap() as i32
+                    && (b.position as usize) < to
+                    && b.position as usize + b.cigar.alignment_length().max(1) > from
+            })
+            .map(|b| b.read_name.clone())
+            .collect();
+
+        let result: Vec<_> = reader
+            .query("chr2", Range::new(from, to))
+            .map(|r| r.unwrap().block.read_name)
+            .collect();
+
+        assert!(!expected.is_empty());
+        assert_eq!(result, expected);
+
+        assert!(reader
+            .query("chrUnknown", Range::new(0, 10))
+            .next()
+            .unwrap()
+            .is_err());
+
+        // Queries do not change the options of the reader
+        reader.options.read_cigar = false;
+        assert_eq!(
+            reader.query("chr2", Range::new(from, to)).count(),
+            expected.len()
+        );
+        assert!(!reader.options.read_cigar);
+    }
+
+    #[test]
+    fn test_bam_open_indexed() {
+        let filename = env::temp_dir().join(format!("rustynetics-{}-indexed.bam", process::id()));
+        let filename = filename.to_str().unwrap();
+        fs::copy("tests/test_bam_1.bam", filename).unwrap();
+
+        let filename_bai = format!("{}.bai", filename);
+        let filename_csi = format!("{}.csi", filename);
+        let index = BamIndex {
+            references: vec![BamIndexReference::default(); 2],
+            ..Default::default()
+        };
+        index
+            .write_csi(BufWriter::new(fs::File::create(&filename_csi).unwrap()))
+            .unwrap();
+
+        // The CSI index is used if there is no BAI index
+        assert!(BamFile::open_indexed(filename, None).is_ok());
+
+        // A broken BAI index is not silently replaced by the CSI index
+        fs::write(&filename_bai, b"BAI\x01").unwrap();
+        assert!(BamFile::open_indexed(filename, None).is_err());
+
+        fs::remove_file(&filename_bai).unwrap();
+        fs::remove_file(&filename_csi).unwrap();
+        fs::remove_file(filename).unwrap();
+    }
 }
@@ -0,0 +1,626 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::bam::{BamFile, BamReader, BamReaderOptions};
+use crate::bam_index::BamIndex;
+use crate::bam_pileup::{BamPileupColumn, BamPileupConfig};
+use crate::granges::GRanges;
+use crate::meta::MetaData;
+use crate::netfile::NetFile;
+use crate::range::Range;
+use crate::sam_header::SamHeader;
+
+/* -------------------------------------------------------------------------- */
+
+/// Name of the read group of records without `RG` tag or with a read group
+/// that is not declared in the header.
+pub const UNKNOWN_READ_GROUP: &str = "*";
+
+/// Maximum distance between sites that are counted with a single query of an
+/// indexed BAM file.
+const ALLELE_COUNTS_MAX_GAP: usize = 1000;
+
+/* -------------------------------------------------------------------------- */
+
+/// Number of fragments supporting the reference allele, an alternative
+/// allele or any other base (including deletions) at a site.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AlleleCounts {
+    pub ref_count: u64,
+    pub alt_count: u64,
+    pub other_count: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl AlleleCounts {
+    pub fn total(&self) -> u64 {
+        self.ref_count + self.alt_count + self.other_count
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Accumulates allele counts at single nucleotide variant sites from pileup
+/// columns.
+///
+/// Counts are kept separately for every read group. Overlapping mates of a
+/// paired-end fragment are counted only once: if both mates report the same
+/// base it is counted once, otherwise the base with the higher quality is
+/// used and bases of equal quality are counted as other.
+pub struct AlleleCounter {
+    sites: HashMap<String, HashMap<usize, Vec<usize>>>,
+    alleles: Vec<(u8, Vec<u8>)>,
+    site_indices: Vec<usize>,
+    read_groups: Vec<String>,
+    read_group_index: HashMap<String, usize>,
+    counts: Vec<Vec<AlleleCounts>>,
+    granges: GRanges,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl AlleleCounter {
+    /// Creates a new counter for the sites in `sites`, which must have the
+    /// string metadata columns "ref" and "alt". Multiple alternative alleles
+    /// are separated by commas. Sites where the reference or an alternative
+    /// allele is not a single nucleotide are ignored.
+    ///
+    /// # Arguments
+    /// * `sites` - The variant sites.
+    /// * `header` - The header of the BAM file, which declares the read groups.
+    ///
+    /// # Errors
+    /// Returns an error if the metadata columns "ref" or "alt" are missing.
+    pub fn new(sites: &GRanges, header: &SamHeader) -> Result<Self, Box<dyn Error>> {
+        let ref_ = sites.meta.get_column_str("ref").ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "sites have no `ref` column")
+        })?;
+        let alt = sites.meta.get_column_str("alt").ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "sites have no `alt` column")
+        })?;
+
+        let mut counter = AlleleCounter {
+            sites: HashMap::new(),
+            alleles: Vec::new(),
+            site_indices: Vec::new(),
+            read_groups: Vec::new(),
+            read_group_index: HashMap::new(),
+            counts: Vec::new(),
+            granges: sites.clone(),
+        };
+
+        for i in 0..sites.num_rows() {
+            let ref_allele = ref_[i].as_bytes();
+            let alt_alleles: Vec<&[u8]> = alt[i].split(',').map(|a| a.as_bytes()).collect();
+
+            if ref_allele.len() != 1 || alt_alleles.iter().any(|a| a.len() != 1) {
+                continue;
+            }
+            counter
+                .sites
+                .entry(sites.seqnames[i].clone())
+                .or_default()
+                .entry(sites.ranges[i].from)
+                .or_default()
+                .push(counter.site_indices.len());
+            counter.alleles.push((
+                ref_allele[0].to_ascii_uppercase(),
+                alt_alleles
+                    .iter()
+                    .map(|a| a[0].to_ascii_uppercase())
+                    .collect(),
+            ));
+            counter.site_indices.push(i);
+            counter.counts.push(Vec::new());
+        }
+        for read_group in &header.read_groups {
+            counter.read_group(&read_group.id);
+        }
+        if counter.read_groups.is_empty() {
+            counter.read_group(UNKNOWN_READ_GROUP);
+        }
+        Ok(counter)
+    }
+
+    /// Returns the index of a read group, which is added if necessary.
+    fn read_group(&mut self, name: &str) -> usize {
+        if let Some(&idx) = self.read_group_index.get(name) {
+            return idx;
+        }
+        let idx = self.read_groups.len();
+        self.read_groups.push(name.to_string());
+        self.read_group_index.insert(name.to_string(), idx);
+        for counts in &mut self.counts {
+            counts.push(AlleleCounts::default());
+        }
+        idx
+    }
+
+    /// Returns the number of single nucleotide variant sites.
+    pub fn num_sites(&self) -> usize {
+        self.site_indices.len()
+    }
+
+    /// Returns the declared or encountered read groups.
+    pub fn read_groups(&self) -> &[String] {
+        &self.read_groups
+    }
+
+    /// Returns the distinct positions of all sites.
+    pub fn positions(&self) -> Vec<(String, usize)> {
+        let mut positions: Vec<(String, usize)> = self
+            .sites
+            .iter()
+            .flat_map(|(seqname, sites)| sites.keys().map(move |&p| (seqname.clone(), p)))
+            .collect();
+        positions.sort();
+        positions
+    }
+
+    /// Returns the counts of a site per read group, where `i` is the index of
+    /// the site in the `GRanges` object used to create the counter.
+    pub fn get(&self, i: usize) -> Option<&[AlleleCounts]> {
+        // Site indices are increasing
+        self.site_indices
+            .binary_search(&i)
+            .ok()
+            .map(|k| self.counts[k].as_slice())
+    }
+
+    /// Adds the bases of a pileup column if it matches any site.
+    pub fn add_column(&mut self, column: &BamPileupColumn) {
+        let sites = match self
+            .sites
+            .get(&column.seqname)
+            .and_then(|sites| sites.get(&column.position))
+        {
+            Some(sites) => sites.clone(),
+            None => return,
+        };
+
+        // Collect one base per fragment
+        let mut fragments: HashMap<&str, (u8, u8, Option<&str>)> = HashMap::new();
+        let mut single: Vec<(u8, Option<&str>)> = Vec::new();
+
+        for read in &column.reads {
+            if read.is_refskip {
+                continue;
+            }
+            let base = if read.is_deletion {
+                b'*'
+            } else {
+                read.base.to_ascii_uppercase()
+            };
+            let read_group = read.block.read_group();
+
+            if !read.block.flag.read_paired() {
+                single.push((base, read_group));
+                continue;
+            }
+            fragments
+                .entry(read.block.read_name.as_str())
+                .and_modify(|entry| {
+                    if entry.0 != base {
+                        if read.qual > entry.1 {
+                            *entry = (base, read.qual, read_group);
+                        } else if read.qual == entry.1 {
+                            entry.0 = b'N';
+                        }
+                    }
+                })
+                .or_insert((base, read.qual, read_group));
+        }
+
+        let bases: Vec<(u8, Option<&str>)> = single
+            .into_iter()
+            .chain(fragments.into_values().map(|(base, _, rg)| (base, rg)))
+            .collect();
+
+        for (base, read_group) in bases {
+            let rg = match read_group {
+                Some(rg) if self.read_group_index.contains_key(rg) => self.read_group_index[rg],
+                _ => self.read_group(UNKNOWN_READ_GROUP),
+            };
+            for &k in &sites {
+                let (ref_allele, alt_alleles) = &self.alleles[k];
+                let counts = &mut self.counts[k][rg];
+                if base == *ref_allele {
+                    counts.ref_count += 1;
+                } else if alt_alleles.contains(&base) {
+                    counts.alt_count += 1;
+                } else {
+                    counts.other_count += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the counts with one row per site and read group. The result
+    /// contains the 1bp ranges of the sites and the metadata columns "ref",
+    /// "alt", "read_group", "ref_count", "alt_count" and "other_count".
+    pub fn result(&self) -> Result<GRanges, Box<dyn Error>> {
+        let ref_ = self.granges.meta.get_column_str("ref").unwrap();
+        let alt = self.granges.meta.get_column_str("alt").unwrap();
+
+        let mut seqnames = Vec::new();
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        let mut col_ref = Vec::new();
+        let mut col_alt = Vec::new();
+        let mut col_rg = Vec::new();
+        let mut col_ref_count = Vec::new();
+        let mut col_alt_count = Vec::new();
+        let mut col_other_count = Vec::new();
+
+        for (k, &i) in self.site_indices.iter().enumerate() {
+            for (rg, counts) in self.counts[k].iter().enumerate() {
+                seqnames.push(self.granges.seqnames[i].clone());
+                from.push(self.granges.ranges[i].from);
+                to.push(self.granges.ranges[i].from + 1);
+                col_ref.push(ref_[i].clone());
+                col_alt.push(alt[i].clone());
+                col_rg.push(self.read_groups[rg].clone());
+                col_ref_count.push(counts.ref_count as i64);
+                col_alt_count.push(counts.alt_count as i64);
+                col_other_count.push(counts.other_count as i64);
+            }
+        }
+        let mut granges = GRanges::new(seqnames, from, to, vec![]);
+        granges.meta.add("ref", MetaData::StringArray(col_ref))?;
+        granges.meta.add("alt", MetaData::StringArray(col_alt))?;
+        granges
+            .meta
+            .add("read_group", MetaData::StringArray(col_rg))?;
+        granges
+            .meta
+            .add("ref_count", MetaData::IntArray(col_ref_count))?;
+        granges
+            .meta
+            .add("alt_count", MetaData::IntArray(col_alt_count))?;
+        granges
+            .meta
+            .add("other_count", MetaData::IntArray(col_other_count))?;
+        Ok(granges)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Counts alleles at variant sites in a coordinate-sorted BAM stream, see
+/// `AlleleCounter`.
+///
+/// # Arguments
+/// * `reader` - The BAM reader.
+/// * `sites` - The variant sites with metadata columns "ref" and "alt".
+/// * `config` - Base quality, mapping quality and flag filters.
+///
+/// # Errors
+/// Returns an error if the records are not sorted, reading fails or the
+/// sites lack allele columns.
+pub fn bam_allele_counts<R: Read>(
+    reader: &mut BamReader<R>,
+    sites: &GRanges,
+    config: BamPileupConfig,
+) -> Result<GRanges, Box<dyn Error>> {
+    let header = reader.get_header().parse()?;
+    let mut counter = AlleleCounter::new(sites, &header)?;
+
+    for column in reader.pileup(config) {
+        counter.add_column(&column?);
+    }
+    counter.result()
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Counts alleles at variant sites in a coordinate-sorted BAM file. If the
+/// file is indexed, only the records overlapping the sites are read, where
+/// nearby sites are counted with a single query. Without `.bai` or `.csi`
+/// index the whole file is read.
+///
+/// # Arguments
+/// * `filename` - The file path or URL of the BAM file.
+/// * `sites` - The variant sites with metadata columns "ref" and "alt".
+/// * `config` - Base quality, mapping quality and flag filters.
+/// * `threads` - Number of threads used for BGZF decompression.
+///
+/// # Errors
+/// Returns an error if the BAM file or an existing index cannot be read, or
+/// if the sites lack allele columns.
+pub fn bam_allele_counts_file(
+    filename: &str,
+    sites: &GRanges,
+    config: BamPileupConfig,
+    threads: usize,
+) -> Result<GRanges, Box<dyn Error>> {
+    let options = BamReaderOptions::with_threads(threads);
+    let mut bam = BamFile::open(filename, Some(options))?;
+
+    let index = ["bai", "csi"]
+        .iter()
+        .find_map(|ext| NetFile::open(&format!("{}.{}", filename, ext)).ok());
+    match index {
+        Some(file) => bam.reader.set_index(BamIndex::read(BufReader::new(file))?),
+        None => return bam_allele_counts(&mut bam.reader, sites, config),
+    }
+    let header = bam.reader.get_header().parse()?;
+    let mut counter = AlleleCounter::new(sites, &header)?;
+
+    // Merge nearby sites into regions
+    let mut regions: Vec<(String, Range)> = Vec::new();
+    for (seqname, position) in counter.positions() {
+        if bam.reader.get_genome().get_idx(&seqname).is_none() {
+            continue;
+        }
+        match regions.last_mut() {
+            Some((s, range)) if *s == seqname && position < range.to + ALLELE_COUNTS_MAX_GAP => {
+                range.to = position + 1;
+            }
+            _ => regions.push((seqname, Range::new(position, position + 1))),
+        }
+    }
+    for (seqname, range) in regions {
+        for column in bam.reader.pileup_region(&seqname, range, config.clone()) {
+            counter.add_column(&column?);
+        }
+    }
+    counter.result()
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Imports variant sites with reference and alternative alleles. VCF files
+/// (`.vcf`, `.vcf.gz`) are read with `import_vcf`. BED files (`.bed`,
+/// `.bed.gz`) must contain the reference and alternative allele in the
+/// fourth and fifth column. Other files are read as `GRanges` tables with
+/// columns "ref" and "alt".
+///
+/// # Errors
+/// Returns an error if the file cannot be read or has an invalid format.
+pub fn import_allele_sites(filename: &str) -> Result<GRanges, Box<dyn Error>> {
+    let compress = filename.ends_with(".gz");
+    let basename = filename.trim_end_matches(".gz");
+    let mut sites = GRanges::default();
+
+    if basename.ends_with(".vcf") {
+        sites.import_vcf(filename, compress)?;
+    } else if basename.ends_with(".bed") {
+        let file = File::open(filename)?;
+        let reader: Box<dyn BufRead> = if compress {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        let mut ref_ = Vec::new();
+        let mut alt = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty()
+                || fields[0] == "track"
+                || fields[0] == "browser"
+                || fields[0].starts_with('#')
+            {
+                continue;
+            }
+            if fields.len() < 5 {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "BED file must have at least 5 columns (chrom, start, end, ref, alt)",
+                )));
+            }
+            let from = fields[1].parse::<usize>()?;
+            let to = fields[2].parse::<usize>()?;
+            sites.seqnames.push(fields[0].to_string());
+            sites.ranges.push(Range::new(from, to));
+            sites.strand.push('*');
+            ref_.push(fields[3].to_string());
+            alt.push(fields[4].to_string());
+        }
+        sites.meta.add("ref", MetaData::StringArray(ref_))?;
+        sites.meta.add("alt", MetaData::StringArray(alt))?;
+    } else {
+        sites.import_table_all(filename, compress)?;
+    }
+    Ok(sites)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use crate::bam::{BamAuxValue, BamBlock, BamFile, BamHeader};
+    use crate::bam_allele_counts::{
+        bam_allele_counts_file, AlleleCounter, AlleleCounts, UNKNOWN_READ_GROUP,
+    };
+    use crate::bam_fixtures::{genome, read};
+    use crate::bam_index::index_bam;
+    use crate::bam_pileup::{BamPileup, BamPileupConfig};
+    use crate::granges::GRanges;
+    use crate::meta::MetaData;
+    use crate::sam_header::SamHeader;
+
+    fn with_read_group(mut block: BamBlock, rg: &str) -> BamBlock {
+        block.set_auxiliary(b"RG", BamAuxValue::Z(rg.to_string()));
+        block
+    }
+
+    fn sites() -> GRanges {
+        let mut sites = GRanges::new(
+            vec!["chr1".to_string(), "chr1".to_string(), "chr1".to_string()],
+            vec![11, 12, 13],
+            vec![12, 13, 14],
+            vec![],
+        );
+        let ref_ = vec!["C", "G", "TA"].into_iter().map(String::from).collect();
+        let alt = vec!["T", "A,T", "T"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        sites.meta.add("ref", MetaData::StringArray(ref_)).unwrap();
+        sites.meta.add("alt", MetaData::StringArray(alt)).unwrap();
+        sites
+    }
+
+    #[test]
+    fn test_allele_counts() {
+        let header: SamHeader = "@RG\tID:rg1\tSM:s1\n@RG\tID:rg2\tSM:s2\n".parse().unwrap();
+        let blocks = vec![
+            // Overlapping mates with the same base at position 11
+            with_read_group(read("p1", 0x1 | 0x40, 10, "4M", "ACGT", 30), "rg1"),
+            // Overlapping mates with conflicting bases, the second has higher quality
+            with_read_group(read("p2", 0x1 | 0x40, 10, "4M", "ACAT", 20), "rg1"),
+            with_read_group(read("s1", 0x0, 10, "4M", "ATTT", 30), "rg2"),
+            read("s2", 0x0, 10, "4M", "AGCT", 30),
+            // Low base quality
+            with_read_group(read("s3", 0x0, 10, "4M", "ATTT", 5), "rg2"),
+            with_read_group(read("p1", 0x1 | 0x80, 11, "4M", "CGTA", 30), "rg1"),
+            with_read_group(read("p2", 0x1 | 0x80, 11, "4M", "TTTA", 35), "rg1"),
+        ];
+        let genome = genome(100);
+
+        let mut counter = AlleleCounter::new(&sites(), &header).unwrap();
+        assert_eq!(counter.num_sites(), 2);
+
+        for column in BamPileup::new(
+            blocks.into_iter().map(Ok),
+            genome,
+            BamPileupConfig::default(),
+        ) {
+            counter.add_column(&column.unwrap());
+        }
+        assert_eq!(counter.read_groups(), &["rg1", "rg2", UNKNOWN_READ_GROUP]);
+
+        let site = counter.get(0).unwrap();
+        assert_eq!(
+            site[0],
+            AlleleCounts {
+                ref_count: 1,
+                alt_count: 1,
+                other_count: 0
+            }
+        );
+        assert_eq!(
+            site[1],
+            AlleleCounts {
+                ref_count: 0,
+                alt_count: 1,
+                other_count: 0
+            }
+        );
+        assert_eq!(
+            site[2],
+            AlleleCounts {
+                ref_count: 0,
+                alt_count: 0,
+                other_count: 1
+            }
+        );
+
+        let site = counter.get(1).unwrap();
+        assert_eq!(
+            site[0],
+            AlleleCounts {
+                ref_count: 1,
+                alt_count: 1,
+                other_count: 0
+            }
+        );
+        assert_eq!(
+            site[1],
+            AlleleCounts {
+                ref_count: 0,
+                alt_count: 1,
+                other_count: 0
+            }
+        );
+        assert_eq!(site[2].total(), 1);
+        assert!(counter.get(2).is_none());
+
+        let result = counter.result().unwrap();
+        assert_eq!(result.num_rows(), 6);
+        assert_eq!(
+            result.meta.get_column_int("alt_count").unwrap(),
+            &vec![1, 1, 0, 1, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_allele_counts_file() {
+        let dir = env::temp_dir();
+        let filename = dir.join(format!("rustynetics-{}-alleles.bam", process::id()));
+        let filename = filename.to_str().unwrap();
+
+        let header = BamHeader::from(&SamHeader::default());
+        let mut writer = BamFile::create(filename, &header, &genome(10000)).unwrap();
+        for position in [10, 500, 5000] {
+            writer
+                .write_block(&read("r", 0x0, position, "4M", "ACGT", 30))
+                .unwrap();
+        }
+        writer.close().unwrap();
+
+        let mut sites = GRanges::new(
+            vec!["chr1".to_string(); 4],
+            vec![11, 501, 5001, 5002],
+            vec![12, 502, 5002, 5003],
+            vec![],
+        );
+        let ref_ = vec!["C", "C", "C", "G"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let alt = vec!["T", "T", "T", "A"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        sites.meta.add("ref", MetaData::StringArray(ref_)).unwrap();
+        sites.meta.add("alt", MetaData::StringArray(alt)).unwrap();
+
+        let count = || {
+            bam_allele_counts_file(filename, &sites, BamPileupConfig::default(), 1)
+                .map(|result| result.meta.get_column_int("ref_count").unwrap().clone())
+        };
+        // Without index the whole file is read
+        assert_eq!(count().unwrap(), vec![1, 1, 1, 1]);
+
+        let index = index_bam(filename).unwrap();
+        assert_eq!(count().unwrap(), vec![1, 1, 1, 1]);
+
+        // Invalid indices are not ignored
+        fs::write(&index, b"invalid").unwrap();
+        assert!(count().is_err());
+
+        fs::remove_file(index).unwrap();
+        fs::remove_file(filename).unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use crate::bam::{BamAuxValue, BamBlock, BamCigar, BamReader};
+use crate::bam_pileup::BAM_PILEUP_EXCLUDE_FLAGS;
+use crate::genome::Genome;
+use crate::granges::GRanges;
+use crate::meta::MetaData;
+use crate::orderedstringset::OrderedStringSet;
+use crate::track::MutableTrack;
+use crate::track_simple::SimpleTrack;
+
+/* -------------------------------------------------------------------------- */
+
+/// Sequence context of a cytosine, where H denotes A, C or T.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MethylationContext {
+    CG,
+    CHG,
+    CHH,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl MethylationContext {
+    pub const ALL: [MethylationContext; 3] = [
+        MethylationContext::CG,
+        MethylationContext::CHG,
+        MethylationContext::CHH,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MethylationContext::CG => "CG",
+            MethylationContext::CHG => "CHG",
+            MethylationContext::CHH => "CHH",
+        }
+    }
+
+    /// Determines the context of the cytosine at position `p` of the
+    /// reference on the given strand. On the reverse strand, `p` is the
+    /// position of the G on the forward strand.
+    ///
+    /// # Returns
+    /// `None` if the position is not a cytosine on the given strand or the
+    /// context is unknown (`N` or the end of the sequence).
+    pub fn of(reference: &[u8], p: usize, reverse: bool) -> Option<Self> {
+        let base = |i: Option<usize>| i.and_then(|i| reference.get(i)).map(u8::to_ascii_uppercase);
+        let (c, g, next1, next2) = if reverse {
+            (b'G', b'C', base(p.checked_sub(1)), base(p.checked_sub(2)))
+        } else {
+            (b'C', b'G', base(Some(p + 1)), base(Some(p + 2)))
+        };
+        if base(Some(p)) != Some(c) {
+            return None;
+        }
+        let is_h = |b: Option<u8>| matches!(b, Some(b) if b != g && b != b'N');
+        match (next1, next2) {
+            (Some(b), _) if b == g => Some(MethylationContext::CG),
+            (n1, Some(b)) if is_h(n1) && b == g => Some(MethylationContext::CHG),
+            (n1, n2) if is_h(n1) && is_h(n2) => Some(MethylationContext::CHH),
+            _ => None,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for MethylationContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for MethylationContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "CG" | "CPG" => Ok(MethylationContext::CG),
+            "CHG" => Ok(MethylationContext::CHG),
+            "CHH" => Ok(MethylationContext::CHH),
+            _ => Err(format!("invalid methylation context `{}`", s)),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Options of the bisulfite methylation extraction.
+///
+/// # Fields
+/// - `min_base_quality`: Minimum quality of a base.
+/// - `min_mapq`: Minimum mapping quality of a record.
+/// - `exclude_flags`: Records with any of these flags set are skipped.
+/// - `ignore_overlap`: If `true`, positions of the second mate that overlap
+///   the first mate are skipped, so that every fragment is counted once. The
+///   position of the mate is taken from the `MC` tag.
+#[derive(Clone, Debug)]
+pub struct BisulfiteConfig {
+    pub min_base_quality: u8,
+    pub min_mapq: u8,
+    pub exclude_flags: u16,
+    pub ignore_overlap: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for BisulfiteConfig {
+    fn default() -> Self {
+        BisulfiteConfig {
+            min_base_quality: 0,
+            min_mapq: 0,
+            exclude_flags: BAM_PILEUP_EXCLUDE_FLAGS,
+            ignore_overlap: true,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Methylated and unmethylated calls at a cytosine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BisulfiteSite {
+    pub context: MethylationContext,
+    pub strand: char,
+    pub methylated: u64,
+    pub unmethylated: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Counts of methylated and unmethylated cytosines in bisulfite sequencing
+/// data.
+///
+/// Calls are taken from the Bismark `XM` tag if present. Otherwise they are
+/// derived from the bases of the read, where the converted strand is given
+/// by the `XG` (Bismark) or `YD` (bwa-meth) tag. Without these tags a
+/// directional library is assumed, i.e. first mates (and single-end reads)
+/// mapped to the forward strand are C-to-T converted.
+pub struct BisulfiteMethylation {
+    pub genome: Genome,
+    config: BisulfiteConfig,
+    sites: Vec<BTreeMap<usize, BisulfiteSite>>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BisulfiteMethylation {
+    pub fn new(genome: Genome, config: BisulfiteConfig) -> Self {
+        let sites = vec![BTreeMap::new(); genome.len()];
+        BisulfiteMethylation {
+            genome,
+            config,
+            sites,
+        }
+    }
+
+    /// Returns `true` if the read is C-to-T converted on the forward strand
+    /// and `false` if it is G-to-A converted.
+    fn forward_conversion(block: &BamBlock) -> bool {
+        match block.get_auxiliary(b"XG").or(block.get_auxiliary(b"YD")) {
+            Some(BamAuxValue::Z(s)) if s == "CT" || s == "f" => true,
+            Some(BamAuxValue::Z(s)) if s == "GA" || s == "r" => false,
+            _ => block.flag.reverse_strand() == block.flag.second_in_pair(),
+        }
+    }
+
+    /// Returns the reference range of the mate if it overlaps this record
+    /// and this record is the second mate.
+    fn mate_range(block: &BamBlock) -> Option<(usize, usize)> {
+        let flag = &block.flag;
+        if !flag.read_paired() || !flag.second_in_pair() || flag.mate_unmapped() {
+            return None;
+        }
+        if block.next_ref_id != block.ref_id {
+            return None;
+        }
+        let cigar = match block.get_auxiliary(b"MC") {
+            Some(BamAuxValue::Z(mc)) => mc.parse::<BamCigar>().ok()?,
+            _ => return None,
+        };
+        let from = block.next_position.max(0) as usize;
+        Some((from, from + cigar.alignment_length()))
+    }
+
+    /// Adds the methylation calls of a record. Bases beyond the end of a
+    /// truncated `XM` tag are skipped.
+    ///
+    /// # Arguments
+    /// * `block` - The record, which must contain CIGAR, sequence, qualities
+    ///   and auxiliary data.
+    /// * `reference` - The reference sequence the record is aligned to.
+    pub fn add(&mut self, block: &BamBlock, reference: &[u8]) -> io::Result<()> {
+        if block.ref_id < 0
+            || block.ref_id as usize >= self.sites.len()
+            || block.flag.unmapped()
+            || block.flag.0 & self.config.exclude_flags != 0
+            || block.mapq < self.config.min_mapq
+        {
+            return Ok(());
+        }
+        let l_seq = block.l_seq.max(0) as usize;
+        let seq = block.seq.decode(l_seq).into_bytes();
+        let xm = match block.get_auxiliary(b"XM") {
+            Some(BamAuxValue::Z(xm)) => Some(xm.as_bytes()),
+            _ => None,
+        };
+        let forward = Self::forward_conversion(block);
+        let mate = if self.config.ignore_overlap {
+            Self::mate_range(block)
+        } else {
+            None
+        };
+        let sites = &mut self.sites[block.ref_id as usize];
+
+        let mut ref_pos = block.position.max(0) as usize;
+        let mut query_pos = 0;
+
+        for op in block.cigar.parse_cigar() {
+            let n = op.n as usize;
+            match op.type_ {
+                'M' | '=' | 'X' => {
+                    for k in 0..n {
+                        let (p, q) = (ref_pos + k, query_pos + k);
+                        if mate.is_some_and(|(from, to)| p >= from && p < to) {
+                            continue;
+                        }
+                        if block
+                            .qual
+                            .0
+                            .get(q)
+                            .is_some_and(|&v| v != 0xff && v < self.config.min_base_quality)
+                        {
+                            continue;
+                        }
+                        let methylated = match xm {
+                            Some(xm) => match xm.get(q) {
+                                Some(b'Z' | b'X' | b'H') => true,
+                                Some(b'z' | b'x' | b'h') => false,
+                                _ => continue,
+                            },
+                            None => match (forward, seq.get(q).copied()) {
+                                (true, Some(b'C')) | (false, Some(b'G')) => true,
+                                (true, Some(b'T')) | (false, Some(b'A')) => false,
+                                _ => continue,
+                            },
+                        };
+                        // Strand of the cytosine
+                        let reverse = match reference.get(p).map(u8::to_ascii_uppercase) {
+                            Some(b'C') if xm.is_some() || forward => false,
+                            Some(b'G') if xm.is_some() || !forward => true,
+                            _ => continue,
+                        };
+                        let context = match MethylationContext::of(reference, p, reverse) {
+                            Some(context) => context,
+                            None => continue,
+                        };
+                        let site = sites.entry(p).or_insert(BisulfiteSite {
+                            context,
+                            strand: if reverse { '-' } else { '+' },
+                            methylated: 0,
+                            unmethylated: 0,
+                        });
+                        if methylated {
+                            site.methylated += 1;
+                        } else {
+                            site.unmethylated += 1;
+                        }
+                    }
+                    ref_pos += n;
+                    query_pos += n;
+                }
+                'I' | 'S' => query_pos += n,
+                'D' | 'N' => ref_pos += n,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all cytosines with at least one call. The result contains the
+    /// strand of each cytosine and the metadata columns "context",
+    /// "methylated", "unmethylated" and "level" (fraction of methylated
+    /// calls).
+    pub fn granges(&self) -> Result<GRanges, Box<dyn Error>> {
+        let mut seqnames = Vec::new();
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        let mut strand = Vec::new();
+        let mut context = Vec::new();
+        let mut methylated = Vec::new();
+        let mut unmethylated = Vec::new();
+        let mut level = Vec::new();
+
+        for (i, sites) in self.sites.iter().enumerate() {
+            for (&position, site) in sites {
+                seqnames.push(self.genome.seqnames[i].clone());
+                from.push(position);
+                to.push(position + 1);
+                strand.push(site.strand);
+                context.push(site.context.to_string());
+                methylated.push(site.methylated as i64);
+                unmethylated.push(site.unmethylated as i64);
+                level.push(site.methylated as f64 / (site.methylated + site.unmethylated) as f64);
+            }
+        }
+        let mut granges = GRanges::new(seqnames, from, to, strand);
+        granges
+            .meta
+            .add("context", MetaData::StringArray(context))?;
+        granges
+            .meta
+            .add("methylated", MetaData::IntArray(methylated))?;
+        granges
+            .meta
+            .add("unmethylated", MetaData::IntArray(unmethylated))?;
+        granges.meta.add("level", MetaData::FloatArray(level))?;
+        Ok(granges)
+    }
+
+    /// Returns a track with the coverage-weighted methylation level of all
+    /// cytosines of the given context within each bin, i.e. the number of
+    /// methylated calls divided by the number of all calls. Bins without
+    /// calls are set to NaN.
+    ///
+    /// # Errors
+    /// Returns an error if `bin_size` is zero.
+    pub fn track(
+        &self,
+        context: MethylationContext,
+        name: &str,
+        bin_size: usize,
+    ) -> Result<SimpleTrack, Box<dyn Error>> {
+        if bin_size == 0 {
+            return Err("bin size must be positive".into());
+        }
+        let mut track =
+            SimpleTrack::alloc(name.to_string(), self.genome.clone(), f64::NAN, bin_size);
+
+        for (i, sites) in self.sites.iter().enumerate() {
+            let mut seq = track.get_sequence_mut(&self.genome.seqnames[i])?;
+            let mut bins: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+            for (&position, site) in sites.iter().filter(|(_, s)| s.context == context) {
+                let entry = bins.entry(position / bin_size).or_insert((0, 0));
+                entry.0 += site.methylated;
+                entry.1 += site.methylated + site.unmethylated;
+            }
+            for (bin, (m, n)) in bins {
+                if bin < seq.n_bins() {
+                    seq.set_bin(bin, m as f64 / n as f64);
+                }
+            }
+        }
+        Ok(track)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Extracts methylation calls from all records of a bisulfite sequencing
+/// BAM file.
+///
+/// # Arguments
+/// * `reader` - The BAM reader, which must read CIGAR, sequence, qualities
+///   and auxiliary data.
+/// * `reference` - The reference sequences. Records on sequences missing in
+///   the reference are skipped.
+/// * `config` - Filters of the extraction.
+pub fn bam_bisulfite_methylation<R: Read>(
+    reader: &mut BamReader<R>,
+    reference: &OrderedStringSet,
+    config: BisulfiteConfig,
+) -> Result<BisulfiteMethylation, Box<dyn Error>> {
+    let genome = reader.get_genome().clone();
+    let sequences: Vec<Option<&Vec<u8>>> = genome
+        .seqnames
+        .iter()
+        .map(|name| reference.sequences.get(name))
+        .collect();
+
+    let mut methylation = BisulfiteMethylation::new(genome, config);
+
+    while let Some(block) = reader.read_block()? {
+        if block.ref_id < 0 {
+            continue;
+        }
+        if let Some(Some(sequence)) = sequences.get(block.ref_id as usize) {
+            methylation.add(&block, sequence)?;
+        }
+    }
+    Ok(methylation)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use crate::bam::BamAuxValue;
+    use crate::bam_bisulfite::{BisulfiteConfig, BisulfiteMethylation, MethylationContext};
+    use crate::bam_fixtures::{genome, read};
+    use crate::track::Track;
+
+    //                          0123456789012
+    const REFERENCE: &[u8] = b"ACGTCAGTCTTGA";
+
+    #[test]
+    fn test_methylation_context() {
+        assert_eq!(
+            MethylationContext::of(REFERENCE, 1, false),
+            Some(MethylationContext::CG)
+        );
+        assert_eq!(
+            MethylationContext::of(REFERENCE, 4, false),
+            Some(MethylationContext::CHG)
+        );
+        assert_eq!(
+            MethylationContext::of(REFERENCE, 8, false),
+            Some(MethylationContext::CHH)
+        );
+        assert_eq!(
+            MethylationContext::of(REFERENCE, 2, true),
+            Some(MethylationContext::CG)
+        );
+        assert_eq!(
+            MethylationContext::of(REFERENCE, 6, true),
+            Some(MethylationContext::CHG)
+        );
+        assert_eq!(
+            MethylationContext::of(REFERENCE, 11, true),
+            Some(MethylationContext::CHH)
+        );
+        assert_eq!(MethylationContext::of(REFERENCE, 12, true), None);
+        assert_eq!(MethylationContext::of(REFERENCE, 0, false), None);
+    }
+
+    #[test]
+    fn test_bisulfite_methylation() {
+        let mut methylation =
+            BisulfiteMethylation::new(genome(REFERENCE.len()), BisulfiteConfig::default());
+
+        // C-to-T converted read: CpG at 1 methylated, CHG at 4 and CHH at 8 unmethylated
+        methylation
+            .add(&read("r", 0x0, 0, "12M", "ACGTTAGTTTTG", 30), REFERENCE)
+            .unwrap();
+        // Same read with XM tag
+        let mut block = read("r", 0x0, 0, "12M", "ACGTTAGTTTTG", 30);
+        block.set_auxiliary(b"XM", BamAuxValue::Z(".Z..x...h...".to_string()));
+        methylation.add(&block, REFERENCE).unwrap();
+        // G-to-A converted read: CpG at 2 methylated, CHG at 6 unmethylated
+        methylation
+            .add(&read("r", 0x10, 0, "8M", "ACGTCAAT", 30), REFERENCE)
+            .unwrap();
+
+        let granges = methylation.granges().unwrap();
+        let sites: Vec<(usize, char, String, i64, i64)> = (0..granges.num_rows())
+            .map(|i| {
+                (
+                    granges.ranges[i].from,
+                    granges.strand[i],
+                    granges.meta.get_column_str("context").unwrap()[i].clone(),
+                    granges.meta.get_column_int("methylated").unwrap()[i],
+                    granges.meta.get_column_int("unmethylated").unwrap()[i],
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            vec![
+                (1, '+', "CG".to_string(), 2, 0),
+                (2, '-', "CG".to_string(), 1, 0),
+                (4, '+', "CHG".to_string(), 0, 2),
+                (6, '-', "CHG".to_string(), 0, 1),
+                (8, '+', "CHH".to_string(), 0, 2),
+            ]
+        );
+
+        let track = methylation
+            .track(MethylationContext::CHG, "CHG", 4)
+            .unwrap();
+        let seq = track.get_sequence("chr1").unwrap();
+        assert!(seq.at_bin(0).is_nan());
+        assert_eq!(seq.at_bin(1), 0.0);
+        assert!(methylation
+            .track(MethylationContext::CHG, "CHG", 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_bisulfite_short_xm() {
+        let mut methylation =
+            BisulfiteMethylation::new(genome(REFERENCE.len()), BisulfiteConfig::default());
+
+        let mut block = read("r", 0x0, 0, "12M", "ACGTTAGTTTTG", 30);
+        block.set_auxiliary(b"XM", BamAuxValue::Z(".Z..x".to_string()));
+        methylation.add(&block, REFERENCE).unwrap();
+
+        let granges = methylation.granges().unwrap();
+        assert_eq!(granges.num_rows(), 2);
+        assert_eq!(
+            granges.meta.get_column_str("context").unwrap(),
+            &vec!["CG".to_string(), "CHG".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_bisulfite_overlap() {
+        let mut mate1 = read("r", 0x1 | 0x2 | 0x20 | 0x40, 0, "6M", "ACGTTA", 30);
+        mate1.next_position = 1;
+        let mut mate2 = read("r", 0x1 | 0x2 | 0x10 | 0x80, 1, "5M", "CGTTA", 30);
+        mate2.set_auxiliary(b"MC", BamAuxValue::Z("6M".to_string()));
+
+        for (ignore_overlap, expected) in [(true, vec![1, 0]), (false, vec![2, 0])] {
+            let config = BisulfiteConfig {
+                ignore_overlap,
+                ..Default::default()
+            };
+            let mut methylation = BisulfiteMethylation::new(genome(REFERENCE.len()), config);
+            methylation.add(&mate1, REFERENCE).unwrap();
+            methylation.add(&mate2, REFERENCE).unwrap();
+
+            let granges = methylation.granges().unwrap();
+            assert_eq!(
+                granges.meta.get_column_int("methylated").unwrap(),
+                &expected
+            );
+        }
+    }
+}
@@ -74,7 +74,7 @@ pub fn estimate_fraglen(
 ) -> Result<FraglenEstimate, Box<dyn Error>> {
     log!(config.logger, "Reading tags from `{}`", filename);
 
-    let bam_result = BamFile::open(filename, None);
+    let bam_result = BamFile::open(filename, Some(config.bam_reader_options()));
     let mut bam = match bam_result {
         Ok(b) => b,
         Err(err) => return Err(err),
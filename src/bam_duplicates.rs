@@ -0,0 +1,719 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::bam::{BamBlock, BamFile, BamReader, BamReaderOptions, BamWriter, CigarBlock};
+use crate::sam_header::SamHeader;
+
+/* -------------------------------------------------------------------------- */
+
+/// Name of the library of reads without read group or library information.
+pub const UNKNOWN_LIBRARY: &str = "Unknown Library";
+
+/* -------------------------------------------------------------------------- */
+
+/// Duplication metrics of a single library.
+///
+/// The fields follow the definitions of Picard's `MarkDuplicates`. Pairs are
+/// counted once, and pairs with an unmapped mate count as unpaired reads.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DuplicationMetrics {
+    pub library: String,
+    pub unpaired_reads_examined: u64,
+    pub read_pairs_examined: u64,
+    pub secondary_or_supplementary_reads: u64,
+    pub unmapped_reads: u64,
+    pub unpaired_read_duplicates: u64,
+    pub read_pair_duplicates: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl DuplicationMetrics {
+    /// Returns the fraction of mapped reads that are marked as duplicates.
+    pub fn percent_duplication(&self) -> f64 {
+        let n = self.unpaired_reads_examined + 2 * self.read_pairs_examined;
+        if n == 0 {
+            return 0.0;
+        }
+        (self.unpaired_read_duplicates + 2 * self.read_pair_duplicates) as f64 / n as f64
+    }
+
+    /// Estimates the number of unique molecules in the library from the
+    /// number of read pairs and unique read pairs, assuming that fragments
+    /// are sampled uniformly (Lander-Waterman equation).
+    ///
+    /// # Returns
+    /// `None` if there are no duplicate pairs, in which case the library
+    /// size cannot be estimated.
+    pub fn estimated_library_size(&self) -> Option<u64> {
+        let n = self.read_pairs_examined as f64;
+        let c = (self.read_pairs_examined - self.read_pair_duplicates) as f64;
+
+        if self.read_pair_duplicates == 0 || c == 0.0 {
+            return None;
+        }
+        // f(x) = c/x - 1 + exp(-n/x), whose root x is the library size
+        let f = |x: f64| c / x - 1.0 + (-n / x).exp();
+
+        let mut lower = 1.0;
+        let mut upper = 100.0;
+        if f(lower * c) < 0.0 {
+            return None;
+        }
+        while f(upper * c) > 0.0 {
+            upper *= 10.0;
+        }
+        for _ in 0..40 {
+            let r = (lower + upper) / 2.0;
+            let u = f(r * c);
+            if u == 0.0 {
+                break;
+            } else if u > 0.0 {
+                lower = r;
+            } else {
+                upper = r;
+            }
+        }
+        Some((c * (lower + upper) / 2.0) as u64)
+    }
+
+    /// Returns the header line of the tab-separated metrics table.
+    pub fn table_header() -> &'static str {
+        "LIBRARY\tUNPAIRED_READS_EXAMINED\tREAD_PAIRS_EXAMINED\tSECONDARY_OR_SUPPLEMENTARY_RDS\tUNMAPPED_READS\tUNPAIRED_READ_DUPLICATES\tREAD_PAIR_DUPLICATES\tPERCENT_DUPLICATION\tESTIMATED_LIBRARY_SIZE"
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for DuplicationMetrics {
+    /// Formats the metrics as a row of the tab-separated metrics table.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t",
+            self.library,
+            self.unpaired_reads_examined,
+            self.read_pairs_examined,
+            self.secondary_or_supplementary_reads,
+            self.unmapped_reads,
+            self.unpaired_read_duplicates,
+            self.read_pair_duplicates,
+            self.percent_duplication(),
+        )?;
+        if let Some(size) = self.estimated_library_size() {
+            write!(f, "{}", size)?;
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Unclipped 5' position of a read end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct ReadEnd {
+    ref_id: i32,
+    position: i64,
+    reverse: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Best representative of a group of reads or pairs with identical ends.
+#[derive(Debug)]
+struct DuplicateGroup {
+    score: u64,
+    name: String,
+    // Segment flags (0x40, 0x80) of single reads, `None` for pairs
+    segment: Option<u16>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// End of a pair whose mate has not been seen yet.
+#[derive(Debug)]
+struct PendingMate {
+    end: ReadEnd,
+    score: u64,
+    library: usize,
+    segment: u16,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns the unclipped 5' position of a mapped read, i.e. the alignment
+/// start minus leading clips for forward reads and the alignment end plus
+/// trailing clips for reverse reads.
+fn unclipped_five_prime(block: &BamBlock) -> ReadEnd {
+    let reverse = block.flag.reverse_strand();
+    let ops: Vec<_> = block.cigar.parse_cigar().collect();
+    let clipped = |op: &&CigarBlock| op.type_ == 'S' || op.type_ == 'H';
+
+    let position = if reverse {
+        let trailing: i64 = ops
+            .iter()
+            .rev()
+            .take_while(clipped)
+            .map(|op| op.n as i64)
+            .sum();
+        block.position as i64 + block.cigar.alignment_length() as i64 - 1 + trailing
+    } else {
+        let leading: i64 = ops.iter().take_while(clipped).map(|op| op.n as i64).sum();
+        block.position as i64 - leading
+    };
+    ReadEnd {
+        ref_id: block.ref_id,
+        position,
+        reverse,
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns the flags that identify the segment of a paired read.
+fn segment_flags(block: &BamBlock) -> u16 {
+    if block.flag.read_paired() {
+        block.flag.0 & 0xc0
+    } else {
+        0
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Sum of base qualities of a read, ignoring missing qualities.
+fn base_quality_score(block: &BamBlock) -> u64 {
+    block
+        .qual
+        .0
+        .iter()
+        .filter(|&&q| q != 0xff)
+        .map(|&q| q as u64)
+        .sum()
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Identifies duplicate reads and read pairs.
+///
+/// Reads are grouped by library, unclipped 5' position and strand, and read
+/// pairs additionally by the unclipped 5' position and strand of the mate.
+/// Within each group the read or pair with the highest sum of base
+/// qualities is kept and all others are duplicates. Single reads that share
+/// their position with an end of a read pair are always duplicates.
+///
+/// The marker is used in two passes: all records are first passed to `add`,
+/// and after calling `finish` each record can be marked using `mark`.
+/// Secondary and supplementary alignments inherit the status of their
+/// primary alignment.
+#[derive(Debug, Default)]
+pub struct DuplicateMarker {
+    read_groups: HashMap<String, usize>,
+    metrics: Vec<DuplicationMetrics>,
+    pending: HashMap<String, PendingMate>,
+    pairs: HashMap<(usize, ReadEnd, ReadEnd), DuplicateGroup>,
+    fragments: HashMap<(usize, ReadEnd), DuplicateGroup>,
+    pair_ends: HashSet<(usize, ReadEnd)>,
+    duplicates: HashSet<(String, u16)>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl DuplicateMarker {
+    /// Creates a new duplicate marker. Libraries are determined from the
+    /// `LB` tag of the read groups in `header`.
+    pub fn new(header: &SamHeader) -> Self {
+        let mut marker = DuplicateMarker::default();
+        let mut libraries: Vec<String> = vec![UNKNOWN_LIBRARY.to_string()];
+
+        for rg in &header.read_groups {
+            let library = rg.library.clone().unwrap_or(UNKNOWN_LIBRARY.to_string());
+            let i = match libraries.iter().position(|l| *l == library) {
+                Some(i) => i,
+                None => {
+                    libraries.push(library);
+                    libraries.len() - 1
+                }
+            };
+            marker.read_groups.insert(rg.id.clone(), i);
+        }
+        marker.metrics = libraries
+            .into_iter()
+            .map(|library| DuplicationMetrics {
+                library,
+                ..Default::default()
+            })
+            .collect();
+        marker
+    }
+
+    fn library(&self, block: &BamBlock) -> usize {
+        block
+            .read_group()
+            .and_then(|rg| self.read_groups.get(rg))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn add_duplicate(&mut self, group: DuplicateGroup) {
+        match group.segment {
+            Some(segment) => {
+                self.duplicates.insert((group.name, segment));
+            }
+            None => {
+                self.duplicates.insert((group.name.clone(), 0x40));
+                self.duplicates.insert((group.name, 0x80));
+            }
+        }
+    }
+
+    fn add_fragment(&mut self, library: usize, end: ReadEnd, name: &str, segment: u16, score: u64) {
+        self.metrics[library].unpaired_reads_examined += 1;
+
+        let group = DuplicateGroup {
+            score,
+            name: name.to_string(),
+            segment: Some(segment),
+        };
+        match self.fragments.get_mut(&(library, end)) {
+            None => {
+                self.fragments.insert((library, end), group);
+            }
+            Some(best) => {
+                self.metrics[library].unpaired_read_duplicates += 1;
+                let loser = if score > best.score {
+                    std::mem::replace(best, group)
+                } else {
+                    group
+                };
+                self.add_duplicate(loser);
+            }
+        }
+    }
+
+    fn add_pair(&mut self, library: usize, end1: ReadEnd, end2: ReadEnd, name: &str, score: u64) {
+        self.metrics[library].read_pairs_examined += 1;
+
+        let (end1, end2) = if end1 <= end2 {
+            (end1, end2)
+        } else {
+            (end2, end1)
+        };
+        self.pair_ends.insert((library, end1));
+        self.pair_ends.insert((library, end2));
+
+        let group = DuplicateGroup {
+            score,
+            name: name.to_string(),
+            segment: None,
+        };
+        match self.pairs.get_mut(&(library, end1, end2)) {
+            None => {
+                self.pairs.insert((library, end1, end2), group);
+            }
+            Some(best) => {
+                self.metrics[library].read_pair_duplicates += 1;
+                let loser = if score > best.score {
+                    std::mem::replace(best, group)
+                } else {
+                    group
+                };
+                self.add_duplicate(loser);
+            }
+        }
+    }
+
+    /// Adds a record in the first pass. Records may be in any order, but
+    /// memory usage is lowest for coordinate-sorted input, where mates are
+    /// close to each other.
+    pub fn add(&mut self, block: &BamBlock) {
+        let library = self.library(block);
+        let flag = &block.flag;
+
+        if flag.unmapped() {
+            self.metrics[library].unmapped_reads += 1;
+            return;
+        }
+        if flag.secondary_alignment() || flag.supplementary_alignment() {
+            self.metrics[library].secondary_or_supplementary_reads += 1;
+            return;
+        }
+        let end = unclipped_five_prime(block);
+        let score = base_quality_score(block);
+        let segment = segment_flags(block);
+
+        if !flag.read_paired() || flag.mate_unmapped() {
+            self.add_fragment(library, end, &block.read_name, segment, score);
+            return;
+        }
+        match self.pending.remove(&block.read_name) {
+            Some(mate) => {
+                self.add_pair(library, mate.end, end, &block.read_name, mate.score + score);
+            }
+            None => {
+                self.pending.insert(
+                    block.read_name.clone(),
+                    PendingMate {
+                        end,
+                        score,
+                        library,
+                        segment,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Completes the first pass and returns the duplication metrics of all
+    /// libraries that contain at least one read.
+    ///
+    /// Paired reads whose mate was never seen are treated as single reads.
+    pub fn finish(&mut self) -> Vec<DuplicationMetrics> {
+        let mut pending: Vec<_> = self.pending.drain().collect();
+        pending.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, mate) in pending {
+            self.add_fragment(mate.library, mate.end, &name, mate.segment, mate.score);
+        }
+
+        // Single reads at the position of a pair end are duplicates
+        let fragments: Vec<_> = self.fragments.drain().collect();
+        for (key, group) in fragments {
+            if self.pair_ends.contains(&key) {
+                self.metrics[key.0].unpaired_read_duplicates += 1;
+                self.add_duplicate(group);
+            }
+        }
+        self.pairs.clear();
+        self.pair_ends.clear();
+
+        self.metrics
+            .iter()
+            .filter(|m| {
+                m.unpaired_reads_examined
+                    + m.read_pairs_examined
+                    + m.unmapped_reads
+                    + m.secondary_or_supplementary_reads
+                    > 0
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns `true` if the record is a duplicate. Must be called after
+    /// `finish`.
+    pub fn is_duplicate(&self, block: &BamBlock) -> bool {
+        if block.flag.unmapped() {
+            return false;
+        }
+        self.duplicates
+            .contains(&(block.read_name.clone(), segment_flags(block)))
+    }
+
+    /// Sets or clears the duplicate flag (0x400) of a record.
+    pub fn mark(&self, block: &mut BamBlock) {
+        if self.is_duplicate(block) {
+            block.flag.0 |= 0x400;
+        } else {
+            block.flag.0 &= !0x400;
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Marks duplicates in two passes over the same BAM data and writes all
+/// records to `writer`.
+///
+/// # Arguments
+/// * `first` - Reader used for the first pass, which collects duplicates.
+/// * `second` - Reader over the same data used for the second pass.
+/// * `writer` - The output BAM writer, which already contains the header.
+/// * `remove_duplicates` - If `true`, duplicates are dropped instead of
+///   being flagged.
+///
+/// # Returns
+/// The duplication metrics of all libraries.
+///
+/// # Errors
+/// Returns an error if the header cannot be parsed or if reading or writing
+/// fails.
+pub fn bam_mark_duplicates<R1: Read, R2: Read, W: Write>(
+    first: &mut BamReader<R1>,
+    second: &mut BamReader<R2>,
+    writer: &mut BamWriter<W>,
+    remove_duplicates: bool,
+) -> Result<Vec<DuplicationMetrics>, Box<dyn Error>> {
+    let mut marker = DuplicateMarker::new(&first.get_header().parse()?);
+
+    while let Some(block) = first.read_block()? {
+        marker.add(&block);
+    }
+    let metrics = marker.finish();
+
+    while let Some(mut block) = second.read_block()? {
+        marker.mark(&mut block);
+        if remove_duplicates && block.flag.duplicate() {
+            continue;
+        }
+        writer.write_block(&block)?;
+    }
+    Ok(metrics)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Marks duplicates in the BAM file `input` and writes the result to
+/// `output`.
+///
+/// # Arguments
+/// * `input` - The file path or URL of the input BAM file.
+/// * `output` - The file path of the output BAM file.
+/// * `remove_duplicates` - If `true`, duplicates are dropped instead of
+///   being flagged.
+/// * `threads` - Number of threads used for BGZF decompression.
+/// * `command_line` - If given, a `@PG` line with this command line is added
+///   to the header of the output file.
+///
+/// # Returns
+/// The duplication metrics of all libraries.
+pub fn bam_mark_duplicates_file(
+    input: &str,
+    output: &str,
+    remove_duplicates: bool,
+    threads: usize,
+    command_line: Option<&str>,
+) -> Result<Vec<DuplicationMetrics>, Box<dyn Error>> {
+    let options = BamReaderOptions::with_threads(threads);
+    let mut first = BamFile::open(input, Some(options))?;
+    let mut second = BamFile::open(input, Some(options))?;
+
+    let mut header = first.reader.get_header().clone();
+    if let Some(command_line) = command_line {
+        let mut sam_header = header.parse()?;
+        sam_header.add_program(
+            "bam-mark-duplicates",
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(command_line),
+        );
+        header.set_sam_header(&sam_header);
+    }
+    let genome = first.reader.get_genome().clone();
+
+    let mut writer = BamFile::create(output, &header, &genome)?;
+    let metrics = bam_mark_duplicates(
+        &mut first.reader,
+        &mut second.reader,
+        &mut writer,
+        remove_duplicates,
+    )?;
+    writer.close()?;
+
+    Ok(metrics)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+
+    use crate::bam::{
+        BamAuxValue, BamAuxiliary, BamBlock, BamFile, BamHeader, BamReader, BamWriter,
+    };
+    use crate::bam_duplicates::{
+        bam_mark_duplicates, bam_mark_duplicates_file, DuplicateMarker, DuplicationMetrics,
+    };
+    use crate::bam_fixtures::{genome, read_cigar};
+    use crate::sam_header::SamHeader;
+
+    fn mark(blocks: &[BamBlock]) -> (Vec<DuplicationMetrics>, Vec<bool>) {
+        let mut marker = DuplicateMarker::new(&SamHeader::default());
+        for block in blocks {
+            marker.add(block);
+        }
+        let metrics = marker.finish();
+        let marked = blocks.iter().map(|b| marker.is_duplicate(b)).collect();
+        (metrics, marked)
+    }
+
+    #[test]
+    fn test_duplicates_fragments() {
+        let blocks = vec![
+            read_cigar("a", 0x0, 100, "10M", 20),
+            // Same unclipped start as `a`, but higher quality
+            read_cigar("b", 0x0, 102, "2S8M", 30),
+            // Different strand
+            read_cigar("c", 0x10, 100, "10M", 20),
+            // Same unclipped end as `c`
+            read_cigar("d", 0x10, 100, "8M2S", 10),
+            read_cigar("e", 0x4, -1, "", 30),
+        ];
+        let (metrics, marked) = mark(&blocks);
+
+        assert_eq!(marked, vec![true, false, false, true, false]);
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].unpaired_reads_examined, 4);
+        assert_eq!(metrics[0].unpaired_read_duplicates, 2);
+        assert_eq!(metrics[0].unmapped_reads, 1);
+        assert_eq!(metrics[0].percent_duplication(), 0.5);
+        assert_eq!(metrics[0].estimated_library_size(), None);
+    }
+
+    #[test]
+    fn test_duplicates_pairs() {
+        let mut blocks = Vec::new();
+        for (name, qual) in [("p1", 20), ("p2", 30), ("p3", 10)] {
+            blocks.push(read_cigar(name, 0x1 | 0x20 | 0x40, 100, "10M", qual));
+            blocks.push(read_cigar(name, 0x1 | 0x10 | 0x80, 300, "10M", qual));
+        }
+        // Different mate position
+        blocks.push(read_cigar("p4", 0x1 | 0x20 | 0x40, 100, "10M", 20));
+        blocks.push(read_cigar("p4", 0x1 | 0x10 | 0x80, 350, "10M", 20));
+        // Single read at a pair end is always a duplicate
+        blocks.push(read_cigar("f1", 0x0, 100, "10M", 40));
+        // Secondary alignment of a duplicate pair
+        blocks.push(read_cigar("p1", 0x1 | 0x100 | 0x40, 5000, "10M", 20));
+
+        let (metrics, marked) = mark(&blocks);
+
+        assert_eq!(
+            marked,
+            vec![true, true, false, false, true, true, false, false, true, true]
+        );
+        assert_eq!(metrics[0].read_pairs_examined, 4);
+        assert_eq!(metrics[0].read_pair_duplicates, 2);
+        assert_eq!(metrics[0].unpaired_reads_examined, 1);
+        assert_eq!(metrics[0].unpaired_read_duplicates, 1);
+        assert_eq!(metrics[0].secondary_or_supplementary_reads, 1);
+    }
+
+    #[test]
+    fn test_duplicates_libraries() {
+        let header: SamHeader = "@RG\tID:rg1\tLB:lib1\n@RG\tID:rg2\tLB:lib2\n"
+            .parse()
+            .unwrap();
+        let mut marker = DuplicateMarker::new(&header);
+
+        for (name, rg) in [("a", "rg1"), ("b", "rg2"), ("c", "rg1")] {
+            let mut block = read_cigar(name, 0x0, 100, "10M", 20);
+            block.auxiliary.push(BamAuxiliary {
+                tag: *b"RG",
+                value: BamAuxValue::Z(rg.to_string()),
+            });
+            marker.add(&block);
+        }
+        let metrics = marker.finish();
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].library, "lib1");
+        assert_eq!(metrics[0].unpaired_read_duplicates, 1);
+        assert_eq!(metrics[1].library, "lib2");
+        assert_eq!(metrics[1].unpaired_read_duplicates, 0);
+    }
+
+    #[test]
+    fn test_duplicates_library_size() {
+        let metrics = DuplicationMetrics {
+            read_pairs_examined: 10000,
+            read_pair_duplicates: 1000,
+            ..Default::default()
+        };
+        let x = metrics.estimated_library_size().unwrap() as f64;
+        let (n, c) = (10000.0, 9000.0);
+        assert!((c / x - 1.0 + (-n / x).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_duplicates_bam() {
+        let genome = genome(10000);
+        let header = BamHeader::from(&SamHeader::default());
+        let blocks = vec![
+            read_cigar("a", 0x0, 100, "10M", 20),
+            read_cigar("b", 0x0, 100, "10M", 30),
+            read_cigar("c", 0x0, 200, "10M", 30),
+        ];
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        for block in &blocks {
+            writer.write_block(block).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let mut first = BamReader::new(Cursor::new(bytes.clone()), None).unwrap();
+        let mut second = BamReader::new(Cursor::new(bytes), None).unwrap();
+
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        bam_mark_duplicates(&mut first, &mut second, &mut writer, false).unwrap();
+        let mut reader = BamReader::new(Cursor::new(writer.finish().unwrap()), None).unwrap();
+        let flags: Vec<_> = reader
+            .read_single_end()
+            .map(|r| r.unwrap().block.flag.duplicate())
+            .collect();
+        assert_eq!(flags, vec![true, false, false]);
+    }
+
+    #[test]
+    fn test_duplicates_bam_file() {
+        let genome = genome(10000);
+        let header = BamHeader::from(&SamHeader::default());
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        writer
+            .write_block(&read_cigar("a", 0x0, 100, "10M", 20))
+            .unwrap();
+        writer
+            .write_block(&read_cigar("b", 0x0, 100, "10M", 30))
+            .unwrap();
+
+        let dir = env::temp_dir();
+        let input = dir.join(format!("rustynetics-{}-dup-in.bam", process::id()));
+        let output = dir.join(format!("rustynetics-{}-dup-out.bam", process::id()));
+        fs::write(&input, writer.finish().unwrap()).unwrap();
+
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+        let metrics =
+            bam_mark_duplicates_file(input, output, true, 1, Some("bam-mark-duplicates")).unwrap();
+        assert_eq!(metrics[0].unpaired_read_duplicates, 1);
+
+        let mut bam = BamFile::open(output, None).unwrap();
+        let sam_header = bam.reader.get_header().parse().unwrap();
+        assert_eq!(sam_header.programs.len(), 1);
+        assert_eq!(sam_header.programs[0].id, "bam-mark-duplicates");
+        assert_eq!(
+            sam_header.programs[0].command_line.as_deref(),
+            Some("bam-mark-duplicates")
+        );
+        let names: Vec<_> = bam
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block.read_name)
+            .collect();
+        assert_eq!(names, vec!["b"]);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::bam::{BamAuxValue, BamAuxiliary, BamBlock, BamCigar, BamFlag, BamQual, BamSeq};
+use crate::genome::Genome;
+use crate::range::Range;
+use crate::read::Read;
+
+/* -------------------------------------------------------------------------- */
+
+// Alignment fixtures shared by the unit tests of the BAM modules
+
+/// Returns a genome with the single sequence `chr1` of the given length.
+pub(crate) fn genome(length: usize) -> Genome {
+    Genome::new(vec!["chr1".to_string()], vec![length])
+}
+
+/// Returns a record aligned to `chr1` with all bases of quality `qual` and a
+/// mapping quality of 60. Records with the unmapped flag have no reference,
+/// and the mates of paired records are placed on `chr1`.
+///
+/// # Arguments
+/// * `name` - The read name.
+/// * `flag` - The SAM flag.
+/// * `position` - The zero-based alignment start.
+/// * `cigar` - The CIGAR string, where `*` denotes an empty CIGAR.
+/// * `seq` - The read sequence.
+/// * `qual` - The base quality of all bases.
+pub(crate) fn read(
+    name: &str,
+    flag: u16,
+    position: i32,
+    cigar: &str,
+    seq: &str,
+    qual: u8,
+) -> BamBlock {
+    BamBlock {
+        ref_id: if flag & 0x4 != 0 { -1 } else { 0 },
+        position,
+        mapq: 60,
+        flag: BamFlag(flag),
+        l_seq: seq.len() as i32,
+        next_ref_id: if flag & 0x1 != 0 { 0 } else { -1 },
+        read_name: name.to_string(),
+        cigar: cigar.parse::<BamCigar>().unwrap(),
+        seq: BamSeq::encode(seq),
+        qual: BamQual(vec![qual; seq.len()]),
+        ..Default::default()
+    }
+}
+
+/// Returns a record as [`read`] whose sequence consists of `A`s and matches
+/// the query length of `cigar`.
+pub(crate) fn read_cigar(name: &str, flag: u16, position: i32, cigar: &str, qual: u8) -> BamBlock {
+    let length = cigar
+        .parse::<BamCigar>()
+        .unwrap()
+        .parse_cigar()
+        .filter(|c| matches!(c.type_, 'M' | 'I' | 'S' | '=' | 'X'))
+        .map(|c| c.n as usize)
+        .sum::<usize>();
+    read(name, flag, position, cigar, &"A".repeat(length), qual)
+}
+
+/// Returns a read named `r` with a mapping quality of 60, which is paired if
+/// `flag` has the paired bit set.
+///
+/// # Arguments
+/// * `seqname` - The sequence name.
+/// * `from` - The start of the read.
+/// * `to` - The end of the read.
+/// * `strand` - The strand of the read.
+/// * `flag` - The SAM flag.
+/// * `auxiliary` - The auxiliary fields as tag and value.
+pub(crate) fn mapped_read(
+    seqname: &str,
+    from: usize,
+    to: usize,
+    strand: char,
+    flag: u16,
+    auxiliary: Vec<(&[u8; 2], BamAuxValue)>,
+) -> Read {
+    let mut read = Read::new(
+        seqname,
+        Range::new(from, to),
+        strand,
+        60,
+        false,
+        flag & 0x1 != 0,
+    );
+    read.name = "r".to_string();
+    read.flag = BamFlag(flag);
+    read.auxiliary = auxiliary
+        .into_iter()
+        .map(|(tag, value)| BamAuxiliary { tag: *tag, value })
+        .collect();
+    read
+}
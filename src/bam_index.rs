@@ -0,0 +1,912 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::bam::{bam_import_genome, BamReader, BamReaderOptions};
+use crate::netfile::NetFile;
+
+/* -------------------------------------------------------------------------- */
+
+/// Bin size of the linear index in BAI files
+pub const BAI_MIN_SHIFT: u32 = 14;
+/// Number of levels of the binning scheme in BAI files
+pub const BAI_DEPTH: u32 = 5;
+/// Maximum reference sequence length supported by BAI files
+pub const BAI_MAX_LENGTH: usize = 1 << (BAI_MIN_SHIFT + 3 * BAI_DEPTH);
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns the id of the metadata pseudo-bin for a given binning depth.
+pub fn bam_index_pseudo_bin(depth: u32) -> u32 {
+    ((1 << (3 * (depth + 1))) - 1) / 7 + 1
+}
+
+/// Returns the id of the first bin on a given level of the binning scheme.
+pub fn bam_index_bin_first(level: u32) -> u32 {
+    ((1 << (3 * level)) - 1) / 7
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes the smallest bin that contains the region `[beg, end)`.
+///
+/// Unplaced records with `beg = -1` and `end = 0` are assigned to bin 4680,
+/// as required by the SAM specification.
+///
+/// # Arguments
+/// * `beg` - Start of the region (0-based, inclusive).
+/// * `end` - End of the region (0-based, exclusive).
+/// * `min_shift` - Number of bits of the smallest bin (14 for BAI files).
+/// * `depth` - Number of levels of the binning scheme (5 for BAI files).
+pub fn bam_reg2bin(beg: i64, end: i64, min_shift: u32, depth: u32) -> u32 {
+    let end = end.max(beg + 1) - 1;
+    let mut s = min_shift;
+
+    for level in (1..=depth).rev() {
+        if beg >> s == end >> s {
+            return (bam_index_bin_first(level) as i64 + (beg >> s)) as u32;
+        }
+        s += 3;
+    }
+    0
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes all bins that may contain records overlapping the region
+/// `[beg, end)`.
+///
+/// # Arguments
+/// * `beg` - Start of the region (0-based, inclusive).
+/// * `end` - End of the region (0-based, exclusive).
+/// * `min_shift` - Number of bits of the smallest bin (14 for BAI files).
+/// * `depth` - Number of levels of the binning scheme (5 for BAI files).
+pub fn bam_reg2bins(beg: u64, end: u64, min_shift: u32, depth: u32) -> Vec<u32> {
+    let mut bins = Vec::new();
+
+    if end <= beg {
+        return bins;
+    }
+    let end = end - 1;
+    let mut s = min_shift + depth * 3;
+    let mut t = 0u64;
+
+    for l in 0..=depth {
+        let b = t + (beg >> s);
+        let e = t + (end >> s);
+        for i in b..=e {
+            bins.push(i as u32);
+        }
+        s -= 3;
+        t += 1 << (l * 3);
+    }
+    bins
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A contiguous region of a BAM file given by two virtual file offsets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BamIndexChunk {
+    pub begin: u64,
+    pub end: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A bin of the hierarchical binning index.
+///
+/// The field `loffset` is only used by CSI indices and stores the smallest
+/// virtual offset of records that overlap the bin.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BamIndexBin {
+    pub bin: u32,
+    pub loffset: u64,
+    pub chunks: Vec<BamIndexChunk>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Statistics stored in the metadata pseudo-bin of each reference.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BamIndexMetadata {
+    pub ref_begin: u64,
+    pub ref_end: u64,
+    pub n_mapped: u64,
+    pub n_unmapped: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Index of a single reference sequence.
+///
+/// # Fields
+/// - `bins`: Bins of the hierarchical binning index, excluding the pseudo-bin.
+/// - `intervals`: Linear index, i.e. the smallest virtual offset of records
+///   overlapping each 16 kbp window.
+/// - `metadata`: Optional statistics from the pseudo-bin.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BamIndexReference {
+    pub bins: Vec<BamIndexBin>,
+    pub intervals: Vec<u64>,
+    pub metadata: Option<BamIndexMetadata>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Index of a coordinate-sorted BAM file.
+///
+/// # Fields
+/// - `min_shift`: Number of bits of the smallest bin.
+/// - `depth`: Number of levels of the binning scheme.
+/// - `references`: Index for each reference sequence in the BAM header.
+/// - `n_no_coordinate`: Optional number of unplaced unmapped reads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BamIndex {
+    pub min_shift: u32,
+    pub depth: u32,
+    pub references: Vec<BamIndexReference>,
+    pub n_no_coordinate: Option<u64>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for BamIndex {
+    fn default() -> Self {
+        BamIndex {
+            min_shift: BAI_MIN_SHIFT,
+            depth: BAI_DEPTH,
+            references: Vec::new(),
+            n_no_coordinate: None,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamIndex {
+    /// Reads a BAI or CSI index.
+    ///
+    /// # Arguments
+    /// * `reader` - A reader positioned at the beginning of the index.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the data is neither a valid BAI nor CSI index.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        let mut index = BamIndex::default();
+        let csi = match &magic {
+            b"BAI\x01" => false,
+            b"CSI\x01" => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "neither a BAI nor a CSI file",
+                ))
+            }
+        };
+        if csi {
+            index.min_shift = reader.read_i32::<LittleEndian>()? as u32;
+            index.depth = reader.read_i32::<LittleEndian>()? as u32;
+            let l_aux = reader.read_i32::<LittleEndian>()?;
+            io::copy(&mut (&mut reader).take(l_aux as u64), &mut io::sink())?;
+        }
+        let pseudo_bin = bam_index_pseudo_bin(index.depth);
+
+        let n_ref = reader.read_i32::<LittleEndian>()?;
+        for _ in 0..n_ref {
+            let mut reference = BamIndexReference::default();
+
+            let n_bin = reader.read_i32::<LittleEndian>()?;
+            for _ in 0..n_bin {
+                let bin = reader.read_u32::<LittleEndian>()?;
+                let loffset = if csi {
+                    reader.read_u64::<LittleEndian>()?
+                } else {
+                    0
+                };
+                let chunks = read_chunks(&mut reader)?;
+
+                if bin == pseudo_bin {
+                    reference.metadata = Some(metadata_from_chunks(&chunks)?);
+                } else {
+                    reference.bins.push(BamIndexBin {
+                        bin,
+                        loffset,
+                        chunks,
+                    });
+                }
+            }
+            if !csi {
+                let n_intv = reader.read_i32::<LittleEndian>()?;
+                reference.intervals = Vec::with_capacity(n_intv.max(0) as usize);
+                for _ in 0..n_intv {
+                    reference.intervals.push(reader.read_u64::<LittleEndian>()?);
+                }
+            }
+            index.references.push(reference);
+        }
+        // The number of unplaced reads is optional
+        index.n_no_coordinate = match reader.read_u64::<LittleEndian>() {
+            Ok(n) => Some(n),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(index)
+    }
+
+    /// Imports an index from a local file or URL.
+    ///
+    /// # Arguments
+    /// * `filename` - Path or URL of the index file.
+    pub fn import(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let file = NetFile::open(filename)?;
+        Ok(Self::read(io::BufReader::new(file))?)
+    }
+
+    /// Computes the list of chunks that must be read to retrieve all records
+    /// overlapping the region `[beg, end)`.
+    ///
+    /// Chunks ending before the smallest offset given by the linear index are
+    /// discarded, and the remaining chunks are sorted and merged.
+    ///
+    /// # Arguments
+    /// * `ref_id` - Index of the reference sequence.
+    /// * `beg` - Start of the region (0-based, inclusive).
+    /// * `end` - End of the region (0-based, exclusive).
+    pub fn query_chunks(&self, ref_id: usize, beg: usize, end: usize) -> Vec<BamIndexChunk> {
+        let reference = match self.references.get(ref_id) {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        let bins = bam_reg2bins(beg as u64, end as u64, self.min_shift, self.depth);
+
+        // Smallest virtual offset of records overlapping the region
+        let min_offset = if reference.intervals.is_empty() {
+            self.min_offset_from_bins(reference, beg)
+        } else {
+            let i = (beg >> self.min_shift).min(reference.intervals.len() - 1);
+            reference.intervals[i]
+        };
+
+        let mut chunks: Vec<BamIndexChunk> = reference
+            .bins
+            .iter()
+            .filter(|bin| bins.binary_search(&bin.bin).is_ok())
+            .flat_map(|bin| bin.chunks.iter())
+            .filter(|chunk| chunk.end > min_offset)
+            .cloned()
+            .collect();
+
+        chunks.sort();
+
+        let mut merged: Vec<BamIndexChunk> = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            match merged.last_mut() {
+                Some(last) if chunk.begin <= last.end => {
+                    last.end = last.end.max(chunk.end);
+                }
+                _ => merged.push(chunk),
+            }
+        }
+        merged
+    }
+
+    /// Returns the smallest virtual offset of records overlapping position
+    /// `beg`, using the `loffset` of the smallest existing bin containing it.
+    fn min_offset_from_bins(&self, reference: &BamIndexReference, beg: usize) -> u64 {
+        for level in (0..=self.depth).rev() {
+            let shift = self.min_shift + 3 * (self.depth - level);
+            let bin = bam_index_bin_first(level) + (beg >> shift) as u32;
+
+            if let Some(b) = reference.bins.iter().find(|b| b.bin == bin) {
+                return b.loffset;
+            }
+        }
+        0
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamIndex {
+    /// Computes the index of a coordinate-sorted BAM file.
+    ///
+    /// # Arguments
+    /// * `reader` - A reader positioned at the beginning of the BAM file.
+    /// * `min_shift` - Number of bits of the smallest bin.
+    /// * `depth` - Number of levels of the binning scheme.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the BAM file cannot be read, if it is not
+    /// sorted by coordinate, or if a record exceeds the maximum position
+    /// supported by the binning scheme.
+    pub fn build<R: Read>(reader: R, min_shift: u32, depth: u32) -> io::Result<Self> {
+        let options = BamReaderOptions {
+            read_name: false,
+            read_cigar: true,
+            read_sequence: false,
+            read_auxiliary: false,
+            read_qual: false,
+            threads: 1,
+        };
+        let mut bam_reader = BamReader::new(reader, Some(options))?;
+        let n_ref = bam_reader.get_genome().len();
+        let max_length = 1u64 << (min_shift + 3 * depth);
+
+        let mut index = BamIndex {
+            min_shift,
+            depth,
+            references: vec![BamIndexReference::default(); n_ref],
+            n_no_coordinate: Some(0),
+        };
+        let mut builder: Option<BamIndexBuilder> = None;
+        let mut last_ref_id = -1;
+        let mut last_position = -1;
+        let mut unplaced = false;
+
+        loop {
+            let begin = bam_reader.virtual_offset();
+            let block = match bam_reader.read_block()? {
+                Some(block) => block,
+                None => break,
+            };
+            let end = bam_reader.virtual_offset();
+
+            if block.ref_id < 0 {
+                unplaced = true;
+                index.n_no_coordinate = index.n_no_coordinate.map(|n| n + 1);
+                continue;
+            }
+            if unplaced
+                || block.ref_id < last_ref_id
+                || (block.ref_id == last_ref_id && block.position < last_position)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "BAM file is not sorted by coordinate (record `{}`)",
+                        block.read_name
+                    ),
+                ));
+            }
+            if block.ref_id as usize >= n_ref {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("record has invalid reference id `{}`", block.ref_id),
+                ));
+            }
+            if block.ref_id != last_ref_id {
+                if let Some(b) = builder.take() {
+                    index.references[last_ref_id as usize] = b.finish(depth);
+                }
+                builder = Some(BamIndexBuilder::default());
+            }
+            last_ref_id = block.ref_id;
+            last_position = block.position;
+
+            let from = block.position.max(0) as u64;
+            let to = from + block.cigar.alignment_length().max(1) as u64;
+
+            if to > max_length {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "record `{}` exceeds maximum position supported by the index",
+                        block.read_name
+                    ),
+                ));
+            }
+            let bin = bam_reg2bin(from as i64, to as i64, min_shift, depth);
+
+            builder.as_mut().unwrap().push(
+                bin,
+                from >> min_shift,
+                (to - 1) >> min_shift,
+                BamIndexChunk { begin, end },
+                block.flag.unmapped(),
+            );
+        }
+        if let Some(b) = builder.take() {
+            index.references[last_ref_id as usize] = b.finish(depth);
+        }
+
+        Ok(index)
+    }
+
+    /// Writes the index in BAI format.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the binning scheme is not compatible with
+    /// the BAI format or if writing fails.
+    pub fn write_bai<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        if self.min_shift != BAI_MIN_SHIFT || self.depth != BAI_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "binning scheme not supported by BAI format",
+            ));
+        }
+        writer.write_all(b"BAI\x01")?;
+        writer.write_i32::<LittleEndian>(self.references.len() as i32)?;
+
+        for reference in &self.references {
+            self.write_bins(&mut writer, reference, false)?;
+
+            writer.write_i32::<LittleEndian>(reference.intervals.len() as i32)?;
+            for &offset in &reference.intervals {
+                writer.write_u64::<LittleEndian>(offset)?;
+            }
+        }
+        if let Some(n) = self.n_no_coordinate {
+            writer.write_u64::<LittleEndian>(n)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the index in CSI format.
+    pub fn write_csi<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(b"CSI\x01")?;
+        writer.write_i32::<LittleEndian>(self.min_shift as i32)?;
+        writer.write_i32::<LittleEndian>(self.depth as i32)?;
+        writer.write_i32::<LittleEndian>(0)?;
+        writer.write_i32::<LittleEndian>(self.references.len() as i32)?;
+
+        for reference in &self.references {
+            self.write_bins(&mut writer, reference, true)?;
+        }
+        if let Some(n) = self.n_no_coordinate {
+            writer.write_u64::<LittleEndian>(n)?;
+        }
+        writer.flush()
+    }
+
+    fn write_bins<W: Write>(
+        &self,
+        writer: &mut W,
+        reference: &BamIndexReference,
+        csi: bool,
+    ) -> io::Result<()> {
+        let n_bin = reference.bins.len() + reference.metadata.is_some() as usize;
+
+        writer.write_i32::<LittleEndian>(n_bin as i32)?;
+
+        for bin in &reference.bins {
+            writer.write_u32::<LittleEndian>(bin.bin)?;
+            if csi {
+                writer.write_u64::<LittleEndian>(bin.loffset)?;
+            }
+            writer.write_i32::<LittleEndian>(bin.chunks.len() as i32)?;
+            for chunk in &bin.chunks {
+                writer.write_u64::<LittleEndian>(chunk.begin)?;
+                writer.write_u64::<LittleEndian>(chunk.end)?;
+            }
+        }
+        if let Some(metadata) = &reference.metadata {
+            writer.write_u32::<LittleEndian>(bam_index_pseudo_bin(self.depth))?;
+            if csi {
+                writer.write_u64::<LittleEndian>(0)?;
+            }
+            writer.write_i32::<LittleEndian>(2)?;
+            writer.write_u64::<LittleEndian>(metadata.ref_begin)?;
+            writer.write_u64::<LittleEndian>(metadata.ref_end)?;
+            writer.write_u64::<LittleEndian>(metadata.n_mapped)?;
+            writer.write_u64::<LittleEndian>(metadata.n_unmapped)?;
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Accumulates bins, linear index and metadata of a single reference
+/// sequence while scanning a sorted BAM file.
+#[derive(Default)]
+struct BamIndexBuilder {
+    bins: HashMap<u32, Vec<BamIndexChunk>>,
+    current: Option<(u32, BamIndexChunk)>,
+    intervals: Vec<u64>,
+    metadata: BamIndexMetadata,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamIndexBuilder {
+    fn push(&mut self, bin: u32, w_from: u64, w_to: u64, chunk: BamIndexChunk, unmapped: bool) {
+        if self.current.is_none() && self.bins.is_empty() {
+            self.metadata.ref_begin = chunk.begin;
+        }
+        self.metadata.ref_end = chunk.end;
+
+        if unmapped {
+            self.metadata.n_unmapped += 1;
+        } else {
+            self.metadata.n_mapped += 1;
+        }
+
+        // Extend the current chunk as long as records fall into the same bin
+        match &mut self.current {
+            Some((b, c)) if *b == bin => c.end = chunk.end,
+            _ => {
+                self.save_chunk();
+                self.current = Some((bin, chunk));
+            }
+        }
+
+        // Update linear index
+        if self.intervals.len() <= w_to as usize {
+            self.intervals.resize(w_to as usize + 1, u64::MAX);
+        }
+        for w in w_from..=w_to {
+            if self.intervals[w as usize] == u64::MAX {
+                self.intervals[w as usize] = chunk.begin;
+            }
+        }
+    }
+
+    fn save_chunk(&mut self) {
+        if let Some((bin, chunk)) = self.current.take() {
+            let chunks = self.bins.entry(bin).or_default();
+            match chunks.last_mut() {
+                Some(last) if last.end == chunk.begin => last.end = chunk.end,
+                _ => chunks.push(chunk),
+            }
+        }
+    }
+
+    fn finish(mut self, depth: u32) -> BamIndexReference {
+        self.save_chunk();
+
+        // Fill windows without records, which keeps queries valid
+        let mut previous = self
+            .intervals
+            .iter()
+            .cloned()
+            .find(|&v| v != u64::MAX)
+            .unwrap_or(0);
+        for v in self.intervals.iter_mut() {
+            if *v == u64::MAX {
+                *v = previous;
+            } else {
+                previous = *v;
+            }
+        }
+
+        let mut bins: Vec<BamIndexBin> = self
+            .bins
+            .into_iter()
+            .map(|(bin, chunks)| BamIndexBin {
+                bin,
+                loffset: 0,
+                chunks,
+            })
+            .collect();
+        bins.sort_by_key(|b| b.bin);
+
+        // Smallest offset of records overlapping each bin (CSI only)
+        for bin in bins.iter_mut() {
+            let level = (0..=depth)
+                .rev()
+                .find(|&l| bin.bin >= bam_index_bin_first(l))
+                .unwrap();
+            let w = ((bin.bin - bam_index_bin_first(level)) as u64) << (3 * (depth - level));
+            if !self.intervals.is_empty() {
+                bin.loffset = self.intervals[(w as usize).min(self.intervals.len() - 1)];
+            }
+        }
+        BamIndexReference {
+            bins,
+            intervals: self.intervals,
+            metadata: Some(self.metadata),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn read_chunks<R: Read>(reader: &mut R) -> io::Result<Vec<BamIndexChunk>> {
+    let n_chunk = reader.read_i32::<LittleEndian>()?;
+    let mut chunks = Vec::with_capacity(n_chunk.max(0) as usize);
+
+    for _ in 0..n_chunk {
+        let begin = reader.read_u64::<LittleEndian>()?;
+        let end = reader.read_u64::<LittleEndian>()?;
+        chunks.push(BamIndexChunk { begin, end });
+    }
+    Ok(chunks)
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn metadata_from_chunks(chunks: &[BamIndexChunk]) -> io::Result<BamIndexMetadata> {
+    if chunks.len() != 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid metadata pseudo-bin",
+        ));
+    }
+    Ok(BamIndexMetadata {
+        ref_begin: chunks[0].begin,
+        ref_end: chunks[0].end,
+        n_mapped: chunks[1].begin,
+        n_unmapped: chunks[1].end,
+    })
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes the index of a coordinate-sorted BAM file and saves it next to
+/// the BAM file.
+///
+/// A BAI index `<filename>.bai` is written if all reference sequences are
+/// shorter than 512 Mbp, otherwise a CSI index `<filename>.csi`.
+///
+/// # Arguments
+/// * `filename` - Path to the BAM file.
+///
+/// # Returns
+/// The name of the index file.
+pub fn index_bam(filename: &str) -> Result<String, Box<dyn Error>> {
+    let csi = bam_max_seq_length(filename)? > BAI_MAX_LENGTH;
+
+    index_bam_with(filename, csi, BAI_MIN_SHIFT)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes the index of a coordinate-sorted BAM file and saves it as
+/// `<filename>.bai` or `<filename>.csi`.
+///
+/// For CSI indices the number of levels is chosen such that the longest
+/// reference sequence is covered by the binning scheme.
+///
+/// # Arguments
+/// * `filename` - Path to the BAM file.
+/// * `csi` - Write a CSI instead of a BAI index.
+/// * `min_shift` - Number of bits of the smallest bin (CSI only).
+///
+/// # Returns
+/// The name of the index file.
+pub fn index_bam_with(filename: &str, csi: bool, min_shift: u32) -> Result<String, Box<dyn Error>> {
+    let reader = io::BufReader::new(File::open(filename)?);
+
+    if csi {
+        let max_length = bam_max_seq_length(filename)? as u64;
+        let mut depth = 0;
+        while (1u64 << (min_shift + 3 * depth)) < max_length {
+            depth += 1;
+        }
+        let index = BamIndex::build(reader, min_shift, depth)?;
+        let filename_index = format!("{}.csi", filename);
+
+        index.write_csi(BufWriter::new(File::create(&filename_index)?))?;
+
+        Ok(filename_index)
+    } else {
+        let index = BamIndex::build(reader, BAI_MIN_SHIFT, BAI_DEPTH)?;
+        let filename_index = format!("{}.bai", filename);
+
+        index.write_bai(BufWriter::new(File::create(&filename_index)?))?;
+
+        Ok(filename_index)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn bam_max_seq_length(filename: &str) -> Result<usize, Box<dyn Error>> {
+    let genome = bam_import_genome(filename)?;
+
+    Ok(genome.lengths.iter().cloned().max().unwrap_or(0))
+}
+
+/* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use crate::bam::{BamBlock, BamFile, BamReader, BamWriter};
+    use crate::bam_index::{bam_reg2bin, bam_reg2bins, BamIndex, BamIndexChunk, BamIndexMetadata};
+    use crate::range::Range;
+
+    fn sorted_bam() -> (Vec<u8>, Vec<BamBlock>) {
+        let mut bam = BamFile::open("tests/test_bam_2.bam", None).unwrap();
+        let header = bam.reader.get_header().clone();
+        let genome = bam.reader.get_genome().clone();
+        let mut blocks: Vec<_> = bam
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block)
+            .collect();
+
+        blocks.sort_by_key(|b| (b.ref_id as u32, b.position));
+
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        for block in &blocks {
+            writer.write_block(block).unwrap();
+        }
+        (writer.finish().unwrap(), blocks)
+    }
+
+    fn query_names(bytes: &[u8], index: &BamIndex, seqname: &str, range: Range) -> Vec<String> {
+        let mut reader = BamReader::new(Cursor::new(bytes), None).unwrap();
+        reader.set_index(index.clone());
+        reader
+            .query(seqname, range)
+            .map(|r| r.unwrap().block.read_name)
+            .collect()
+    }
+
+    #[test]
+    fn test_bam_reg2bin() {
+        assert_eq!(bam_reg2bin(0, 1, 14, 5), 4681);
+        assert_eq!(bam_reg2bin(-1, 0, 14, 5), 4680);
+        assert_eq!(bam_reg2bin(16383, 16385, 14, 5), 585);
+        assert_eq!(bam_reg2bin(0, 1 << 29, 14, 5), 0);
+    }
+
+    #[test]
+    fn test_bam_reg2bins() {
+        assert_eq!(bam_reg2bins(0, 1, 14, 5), vec![0, 1, 9, 73, 585, 4681]);
+        assert_eq!(
+            bam_reg2bins(16383, 16385, 14, 5),
+            vec![0, 1, 9, 73, 585, 4681, 4682]
+        );
+        assert!(bam_reg2bins(10, 10, 14, 5).is_empty());
+    }
+
+    #[test]
+    fn test_bam_index_read() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BAI\x01");
+        data.write_i32::<LittleEndian>(1).unwrap();
+        // Two bins, one of them is the pseudo-bin
+        data.write_i32::<LittleEndian>(2).unwrap();
+        data.write_u32::<LittleEndian>(4681).unwrap();
+        data.write_i32::<LittleEndian>(2).unwrap();
+        for offset in [10 << 16, 20 << 16, 20 << 16, 40 << 16] {
+            data.write_u64::<LittleEndian>(offset).unwrap();
+        }
+        data.write_u32::<LittleEndian>(37450).unwrap();
+        data.write_i32::<LittleEndian>(2).unwrap();
+        for value in [10 << 16, 40 << 16, 7, 1] {
+            data.write_u64::<LittleEndian>(value).unwrap();
+        }
+        // Linear index
+        data.write_i32::<LittleEndian>(1).unwrap();
+        data.write_u64::<LittleEndian>(10 << 16).unwrap();
+        data.write_u64::<LittleEndian>(3).unwrap();
+
+        let index = BamIndex::read(data.as_slice()).unwrap();
+
+        assert_eq!(index.references.len(), 1);
+        assert_eq!(index.references[0].bins.len(), 1);
+        assert_eq!(index.references[0].intervals, vec![10 << 16]);
+        assert_eq!(
+            index.references[0].metadata,
+            Some(BamIndexMetadata {
+                ref_begin: 10 << 16,
+                ref_end: 40 << 16,
+                n_mapped: 7,
+                n_unmapped: 1,
+            })
+        );
+        assert_eq!(index.n_no_coordinate, Some(3));
+
+        // Adjacent chunks are merged
+        assert_eq!(
+            index.query_chunks(0, 100, 200),
+            vec![BamIndexChunk {
+                begin: 10 << 16,
+                end: 40 << 16
+            }]
+        );
+        assert!(index.query_chunks(0, 20000, 30000).is_empty());
+        assert!(index.query_chunks(1, 100, 200).is_empty());
+    }
+
+    #[test]
+    fn test_bam_index_build() {
+        let (bytes, blocks) = sorted_bam();
+        let index = BamIndex::build(bytes.as_slice(), 14, 5).unwrap();
+        let genome = BamReader::new(bytes.as_slice(), None)
+            .unwrap()
+            .get_genome()
+            .clone();
+
+        let n_unplaced = blocks.iter().filter(|b| b.ref_id < 0).count() as u64;
+        let n_placed: u64 = index
+            .references
+            .iter()
+            .filter_map(|r| r.metadata)
+            .map(|m| m.n_mapped + m.n_unmapped)
+            .sum();
+
+        assert_eq!(index.n_no_coordinate, Some(n_unplaced));
+        assert_eq!(n_placed + n_unplaced, blocks.len() as u64);
+
+        // Round trip through BAI and CSI format
+        let mut bai = Vec::new();
+        index.write_bai(&mut bai).unwrap();
+        let index_bai = BamIndex::read(bai.as_slice()).unwrap();
+        for (a, b) in index_bai.references.iter().zip(index.references.iter()) {
+            assert_eq!(a.intervals, b.intervals);
+            assert_eq!(a.metadata, b.metadata);
+            assert_eq!(a.bins.len(), b.bins.len());
+            assert!(a
+                .bins
+                .iter()
+                .zip(b.bins.iter())
+                .all(|(x, y)| x.chunks == y.chunks));
+        }
+
+        let mut csi = Vec::new();
+        index.write_csi(&mut csi).unwrap();
+        let index_csi = BamIndex::read(csi.as_slice()).unwrap();
+        assert_eq!(index_csi.references[0].bins, index.references[0].bins);
+        assert!(index_csi.references[0].intervals.is_empty());
+
+        for (seqname, from, to) in [
+            ("chr1", 0, 250000000),
+            ("chr2", 50000000, 100000000),
+            ("chr11", 100000000, 100100000),
+            ("chrY_random", 0, 10),
+        ] {
+            let ref_id = genome.get_idx(seqname).unwrap() as i32;
+            let expected: Vec<_> = blocks
+                .iter()
+                .filter(|b| {
+                    b.ref_id == ref_id
+                        && (b.position as usize) < to
+                        && b.position as usize + b.cigar.alignment_length().max(1) > from
+                })
+                .map(|b| b.read_name.clone())
+                .collect();
+
+            assert_eq!(
+                query_names(&bytes, &index_bai, seqname, Range::new(from, to)),
+                expected
+            );
+            assert_eq!(
+                query_names(&bytes, &index_csi, seqname, Range::new(from, to)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_bam_index_unsorted() {
+        let bytes = std::fs::read("tests/test_bam_2.bam").unwrap();
+        assert!(BamIndex::build(bytes.as_slice(), 14, 5).is_err());
+    }
+}
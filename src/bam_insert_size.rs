@@ -0,0 +1,679 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::bam::{BamBlock, BamFile, BamReader, BamReaderOptions};
+
+/* -------------------------------------------------------------------------- */
+
+/// Relative orientation of the two mates of a pair, following the
+/// definitions of Picard `CollectInsertSizeMetrics`.
+///
+/// # Variants
+/// - `FR`: The forward mate starts left of the reverse mate (standard
+///   Illumina paired-end libraries).
+/// - `RF`: The reverse mate starts left of the forward mate (e.g. mate-pair
+///   libraries).
+/// - `Tandem`: Both mates are on the same strand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairOrientation {
+    FR,
+    RF,
+    Tandem,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl PairOrientation {
+    /// All orientations in the order used by tables and plots.
+    pub const ALL: [PairOrientation; 3] = [
+        PairOrientation::FR,
+        PairOrientation::RF,
+        PairOrientation::Tandem,
+    ];
+
+    /// Returns the orientation of two mates. The 5' end of a mate on the
+    /// reverse strand is the end of its alignment.
+    pub fn from_pair(block1: &BamBlock, block2: &BamBlock) -> Self {
+        let reverse1 = block1.flag.reverse_strand();
+        let reverse2 = block2.flag.reverse_strand();
+
+        if reverse1 == reverse2 {
+            return PairOrientation::Tandem;
+        }
+        let five_prime = |block: &BamBlock| {
+            if block.flag.reverse_strand() {
+                block.position as i64 + block.cigar.alignment_length() as i64
+            } else {
+                block.position as i64
+            }
+        };
+        let (forward, reverse) = if reverse1 {
+            (block2, block1)
+        } else {
+            (block1, block2)
+        };
+        if five_prime(forward) < five_prime(reverse) {
+            PairOrientation::FR
+        } else {
+            PairOrientation::RF
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            PairOrientation::FR => 0,
+            PairOrientation::RF => 1,
+            PairOrientation::Tandem => 2,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for PairOrientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PairOrientation::FR => write!(f, "FR"),
+            PairOrientation::RF => write!(f, "RF"),
+            PairOrientation::Tandem => write!(f, "TANDEM"),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Options for collecting insert sizes.
+///
+/// # Fields
+/// - `max_insert_size`: Pairs with larger insert sizes are only counted as
+///   exceeding the histogram.
+/// - `min_mapq`: Minimum mapping quality of both mates.
+/// - `skip_duplicates`: Ignore pairs where a mate is marked as duplicate.
+#[derive(Clone, Debug)]
+pub struct InsertSizeConfig {
+    pub max_insert_size: usize,
+    pub min_mapq: u8,
+    pub skip_duplicates: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for InsertSizeConfig {
+    fn default() -> Self {
+        InsertSizeConfig {
+            max_insert_size: 1000,
+            min_mapq: 0,
+            skip_duplicates: true,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Summary statistics of an insert size distribution. The median absolute
+/// deviation (MAD) is not scaled.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InsertSizeSummary {
+    pub n: u64,
+    pub min: usize,
+    pub max: usize,
+    pub mode: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub mad: f64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Nucleosome-related summary of a fragment length distribution, typically
+/// computed for ATAC-seq data.
+///
+/// Fragments are classified as nucleosome-free (< 147 bp), mono-nucleosomal
+/// (147-293 bp), di-nucleosomal (294-440 bp) and tri-nucleosomal or longer
+/// (>= 441 bp). Peaks are the most frequent fragment lengths within the
+/// mono- and di-nucleosomal ranges, and the nucleosome repeat length is
+/// their difference. The helical periodicity is the lag between 5 and 20 bp
+/// that maximizes the autocorrelation of the detrended histogram between 50
+/// and 300 bp, which is about 10.5 bp for good ATAC-seq libraries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NucleosomeSummary {
+    pub nucleosome_free: u64,
+    pub mono_nucleosome: u64,
+    pub di_nucleosome: u64,
+    pub tri_nucleosome: u64,
+    pub mono_nucleosome_peak: Option<usize>,
+    pub di_nucleosome_peak: Option<usize>,
+    pub repeat_length: Option<usize>,
+    pub helical_period: Option<usize>,
+    pub helical_autocorrelation: f64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Histogram of insert sizes of paired-end reads, separated by pair
+/// orientation.
+///
+/// The insert size of a pair is the absolute value of the template length
+/// (TLEN) field. Only primary alignments with both mates mapped to the same
+/// reference sequence are counted.
+#[derive(Clone, Debug)]
+pub struct InsertSizeHistogram {
+    pub config: InsertSizeConfig,
+    counts: [Vec<u64>; 3],
+    pub exceeding: u64,
+    pub skipped: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl InsertSizeHistogram {
+    /// Creates an empty histogram.
+    pub fn new(config: InsertSizeConfig) -> Self {
+        let n = config.max_insert_size + 1;
+        InsertSizeHistogram {
+            config,
+            counts: [vec![0; n], vec![0; n], vec![0; n]],
+            exceeding: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Adds a pair of mates to the histogram.
+    ///
+    /// # Returns
+    /// `true` if the pair was counted, either in the histogram or as
+    /// exceeding the maximum insert size.
+    pub fn add_pair(&mut self, block1: &BamBlock, block2: &BamBlock) -> bool {
+        let invalid = |block: &BamBlock| {
+            block.flag.unmapped()
+                || block.flag.secondary_alignment()
+                || block.flag.supplementary_alignment()
+                || block.mapq < self.config.min_mapq
+                || (self.config.skip_duplicates && block.flag.duplicate())
+        };
+        if invalid(block1) || invalid(block2) || block1.ref_id != block2.ref_id || block1.tlen == 0
+        {
+            self.skipped += 1;
+            return false;
+        }
+        let insert_size = block1.tlen.unsigned_abs() as usize;
+
+        if insert_size > self.config.max_insert_size {
+            self.exceeding += 1;
+        } else {
+            let orientation = PairOrientation::from_pair(block1, block2);
+            self.counts[orientation.index()][insert_size] += 1;
+        }
+        true
+    }
+
+    /// Collects the insert sizes of all remaining pairs of a BAM reader.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if a record cannot be read.
+    pub fn from_reader<R: Read>(
+        reader: &mut BamReader<R>,
+        config: InsertSizeConfig,
+    ) -> io::Result<Self> {
+        let mut histogram = InsertSizeHistogram::new(config);
+
+        for item in reader.read_paired_end() {
+            let pair = item?;
+            histogram.add_pair(&pair.block1, &pair.block2);
+        }
+        Ok(histogram)
+    }
+
+    /// Collects the insert sizes of all pairs in a BAM file.
+    ///
+    /// # Arguments
+    /// * `filename` - The file path or URL of the BAM file.
+    /// * `config` - Options for collecting insert sizes.
+    /// * `threads` - Number of threads used for BGZF decompression.
+    pub fn import(
+        filename: &str,
+        config: InsertSizeConfig,
+        threads: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Read names are required to match mates and cigars for the
+        // orientation of pairs
+        let options = BamReaderOptions {
+            read_name: true,
+            read_cigar: true,
+            read_sequence: false,
+            read_auxiliary: false,
+            read_qual: false,
+            threads,
+        };
+        let mut bam = BamFile::open(filename, Some(options))?;
+
+        Ok(Self::from_reader(&mut bam.reader, config)?)
+    }
+
+    /// Returns the histogram of pairs with the given orientation, indexed by
+    /// insert size.
+    pub fn histogram(&self, orientation: PairOrientation) -> &[u64] {
+        &self.counts[orientation.index()]
+    }
+
+    /// Returns the histogram of all pairs regardless of orientation.
+    pub fn histogram_total(&self) -> Vec<u64> {
+        (0..=self.config.max_insert_size)
+            .map(|i| self.counts.iter().map(|c| c[i]).sum())
+            .collect()
+    }
+
+    /// Returns the number of pairs with the given orientation within the
+    /// histogram.
+    pub fn count(&self, orientation: PairOrientation) -> u64 {
+        self.histogram(orientation).iter().sum()
+    }
+
+    /// Returns the dominant orientation, i.e. the one with most pairs, or
+    /// `None` if the histogram is empty.
+    pub fn dominant_orientation(&self) -> Option<PairOrientation> {
+        PairOrientation::ALL
+            .into_iter()
+            .filter(|&o| self.count(o) > 0)
+            .max_by_key(|&o| (self.count(o), std::cmp::Reverse(o.index())))
+    }
+
+    /// Computes summary statistics for pairs with the given orientation, or
+    /// for all pairs if `orientation` is `None`. Returns `None` if there are
+    /// no such pairs.
+    pub fn summary(&self, orientation: Option<PairOrientation>) -> Option<InsertSizeSummary> {
+        let counts = match orientation {
+            Some(o) => self.histogram(o).to_vec(),
+            None => self.histogram_total(),
+        };
+        histogram_summary(&counts)
+    }
+
+    /// Returns the median insert size of the dominant orientation as a
+    /// fragment length estimate, which for paired-end data replaces the
+    /// estimate from strand cross-correlation.
+    pub fn fragment_length(&self) -> Option<usize> {
+        self.summary(self.dominant_orientation())
+            .map(|s| s.median.round() as usize)
+    }
+
+    /// Computes the nucleosome summary of all pairs regardless of
+    /// orientation.
+    pub fn nucleosome_summary(&self) -> NucleosomeSummary {
+        nucleosome_summary(&self.histogram_total())
+    }
+
+    /// Writes the histogram as a tab-separated table with columns insert
+    /// size, FR, RF, TANDEM and total. Insert sizes without any pairs are
+    /// omitted.
+    pub fn write_table<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "insert_size\tFR\tRF\tTANDEM\ttotal")?;
+        for i in 0..=self.config.max_insert_size {
+            let n: Vec<u64> = self.counts.iter().map(|c| c[i]).collect();
+            let total: u64 = n.iter().sum();
+            if total > 0 {
+                writeln!(writer, "{}\t{}\t{}\t{}\t{}", i, n[0], n[1], n[2], total)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for InsertSizeHistogram {
+    /// Formats a report with pair counts per orientation, summary statistics
+    /// and the nucleosome summary.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Pairs [orientation, count]")?;
+        for o in PairOrientation::ALL {
+            writeln!(f, "{}\t{}", o, self.count(o))?;
+        }
+        writeln!(f, "exceeding\t{}", self.exceeding)?;
+        writeln!(f, "skipped\t{}", self.skipped)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "# Insert size statistics [orientation, n, min, max, mode, mean, median, mad]"
+        )?;
+        let rows = PairOrientation::ALL
+            .into_iter()
+            .map(|o| (o.to_string(), self.summary(Some(o))))
+            .chain(std::iter::once(("total".to_string(), self.summary(None))));
+        for (name, summary) in rows {
+            if let Some(s) = summary {
+                writeln!(
+                    f,
+                    "{}\t{}\t{}\t{}\t{}\t{:.2}\t{:.1}\t{:.1}",
+                    name, s.n, s.min, s.max, s.mode, s.mean, s.median, s.mad
+                )?;
+            }
+        }
+        writeln!(f)?;
+
+        let s = self.nucleosome_summary();
+        let total = s.nucleosome_free + s.mono_nucleosome + s.di_nucleosome + s.tri_nucleosome;
+        let percent = |n: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                100.0 * n as f64 / total as f64
+            }
+        };
+        let optional = |v: Option<usize>| v.map_or("NA".to_string(), |v| v.to_string());
+
+        writeln!(f, "# Nucleosome summary")?;
+        writeln!(
+            f,
+            "nucleosome_free\t{}\t{:.2}%",
+            s.nucleosome_free,
+            percent(s.nucleosome_free)
+        )?;
+        writeln!(
+            f,
+            "mono_nucleosome\t{}\t{:.2}%",
+            s.mono_nucleosome,
+            percent(s.mono_nucleosome)
+        )?;
+        writeln!(
+            f,
+            "di_nucleosome\t{}\t{:.2}%",
+            s.di_nucleosome,
+            percent(s.di_nucleosome)
+        )?;
+        writeln!(
+            f,
+            "tri_nucleosome\t{}\t{:.2}%",
+            s.tri_nucleosome,
+            percent(s.tri_nucleosome)
+        )?;
+        writeln!(
+            f,
+            "mono_nucleosome_peak\t{}",
+            optional(s.mono_nucleosome_peak)
+        )?;
+        writeln!(f, "di_nucleosome_peak\t{}", optional(s.di_nucleosome_peak))?;
+        writeln!(f, "repeat_length\t{}", optional(s.repeat_length))?;
+        writeln!(
+            f,
+            "helical_period\t{}\t{:.4}",
+            optional(s.helical_period),
+            s.helical_autocorrelation
+        )
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns the weighted median of `(value, count)` pairs.
+fn weighted_median(values: &mut [(f64, u64)]) -> f64 {
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let n: u64 = values.iter().map(|v| v.1).sum();
+    // Positions of the two middle elements (identical if n is odd)
+    let (k1, k2) = ((n - 1) / 2, n / 2);
+    let mut v1 = None;
+    let mut seen = 0;
+    for &(value, count) in values.iter() {
+        seen += count;
+        if v1.is_none() && seen > k1 {
+            v1 = Some(value);
+        }
+        if seen > k2 {
+            return (v1.unwrap() + value) / 2.0;
+        }
+    }
+    f64::NAN
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn histogram_summary(counts: &[u64]) -> Option<InsertSizeSummary> {
+    let n: u64 = counts.iter().sum();
+    if n == 0 {
+        return None;
+    }
+    let min = counts.iter().position(|&c| c > 0).unwrap();
+    let max = counts.iter().rposition(|&c| c > 0).unwrap();
+    let mode = (min..=max)
+        .max_by_key(|&i| (counts[i], std::cmp::Reverse(i)))
+        .unwrap();
+    let mean = counts
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| i as f64 * c as f64)
+        .sum::<f64>()
+        / n as f64;
+
+    let mut values: Vec<(f64, u64)> = (min..=max)
+        .filter(|&i| counts[i] > 0)
+        .map(|i| (i as f64, counts[i]))
+        .collect();
+    let median = weighted_median(&mut values);
+
+    let mut deviations: Vec<(f64, u64)> = values
+        .iter()
+        .map(|&(v, c)| ((v - median).abs(), c))
+        .collect();
+    let mad = weighted_median(&mut deviations);
+
+    Some(InsertSizeSummary {
+        n,
+        min,
+        max,
+        mode,
+        mean,
+        median,
+        mad,
+    })
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn nucleosome_summary(counts: &[u64]) -> NucleosomeSummary {
+    let sum = |from: usize, to: usize| -> u64 {
+        counts.iter().take(to.min(counts.len())).skip(from).sum()
+    };
+    let peak = |from: usize, to: usize| -> Option<usize> {
+        (from..to.min(counts.len()))
+            .filter(|&i| counts[i] > 0)
+            .max_by_key(|&i| (counts[i], std::cmp::Reverse(i)))
+    };
+    let mono_nucleosome_peak = peak(147, 294);
+    let di_nucleosome_peak = peak(294, 441);
+
+    let (helical_period, helical_autocorrelation) = match helical_periodicity(counts) {
+        Some((lag, r)) => (Some(lag), r),
+        None => (None, 0.0),
+    };
+
+    NucleosomeSummary {
+        nucleosome_free: sum(0, 147),
+        mono_nucleosome: sum(147, 294),
+        di_nucleosome: sum(294, 441),
+        tri_nucleosome: sum(441, counts.len()),
+        mono_nucleosome_peak,
+        di_nucleosome_peak,
+        repeat_length: match (mono_nucleosome_peak, di_nucleosome_peak) {
+            (Some(a), Some(b)) => Some(b - a),
+            _ => None,
+        },
+        helical_period,
+        helical_autocorrelation,
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns the lag in [5, 20] with maximal autocorrelation of the histogram
+/// between 50 and 300 bp, after subtracting a centered moving average of
+/// width 21 to remove the nucleosomal trend.
+fn helical_periodicity(counts: &[u64]) -> Option<(usize, f64)> {
+    let (from, to, w) = (50, 300, 10);
+    if counts.len() < to + w {
+        return None;
+    }
+    let residuals: Vec<f64> = (from..to)
+        .map(|i| {
+            let trend = counts[i - w..=i + w].iter().sum::<u64>() as f64 / (2 * w + 1) as f64;
+            counts[i] as f64 - trend
+        })
+        .collect();
+
+    let variance: f64 = residuals.iter().map(|r| r * r).sum();
+    if variance == 0.0 {
+        return None;
+    }
+    (5..=20)
+        .map(|lag| {
+            let c: f64 = residuals
+                .iter()
+                .zip(residuals.iter().skip(lag))
+                .map(|(a, b)| a * b)
+                .sum();
+            (lag, c / variance)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use crate::bam::{BamBlock, BamFlag};
+    use crate::bam_insert_size::{InsertSizeConfig, InsertSizeHistogram, PairOrientation};
+
+    fn pair(
+        position1: i32,
+        reverse1: bool,
+        position2: i32,
+        reverse2: bool,
+        tlen: i32,
+    ) -> (BamBlock, BamBlock) {
+        let block = |position: i32, reverse: bool| BamBlock {
+            position,
+            mapq: 60,
+            flag: BamFlag(0x1 | 0x2 | if reverse { 0x10 } else { 0 }),
+            cigar: "50M".parse().unwrap(),
+            tlen,
+            ..Default::default()
+        };
+        (block(position1, reverse1), block(position2, reverse2))
+    }
+
+    #[test]
+    fn test_pair_orientation() {
+        let (a, b) = pair(100, false, 200, true, 150);
+        assert_eq!(PairOrientation::from_pair(&a, &b), PairOrientation::FR);
+        let (a, b) = pair(100, true, 200, false, 150);
+        assert_eq!(PairOrientation::from_pair(&a, &b), PairOrientation::RF);
+        let (a, b) = pair(100, false, 200, false, 150);
+        assert_eq!(PairOrientation::from_pair(&a, &b), PairOrientation::Tandem);
+        // Overlapping mates where the reverse mate ends left of the forward start
+        let (a, b) = pair(100, true, 90, false, 60);
+        assert_eq!(PairOrientation::from_pair(&a, &b), PairOrientation::FR);
+    }
+
+    #[test]
+    fn test_insert_size_histogram() {
+        let mut histogram = InsertSizeHistogram::new(InsertSizeConfig {
+            max_insert_size: 500,
+            ..Default::default()
+        });
+        for tlen in [100, 100, 120, 180, 200] {
+            let (a, b) = pair(0, false, tlen - 50, true, tlen);
+            assert!(histogram.add_pair(&a, &b));
+        }
+        let (a, b) = pair(0, true, 250, false, 300);
+        histogram.add_pair(&a, &b);
+        let (a, b) = pair(0, false, 900, true, 950);
+        histogram.add_pair(&a, &b);
+        let (mut a, b) = pair(0, false, 100, true, 150);
+        a.flag.0 |= 0x400;
+        assert!(!histogram.add_pair(&a, &b));
+
+        assert_eq!(histogram.count(PairOrientation::FR), 5);
+        assert_eq!(histogram.count(PairOrientation::RF), 1);
+        assert_eq!(histogram.count(PairOrientation::Tandem), 0);
+        assert_eq!(histogram.exceeding, 1);
+        assert_eq!(histogram.skipped, 1);
+        assert_eq!(histogram.dominant_orientation(), Some(PairOrientation::FR));
+
+        let s = histogram.summary(Some(PairOrientation::FR)).unwrap();
+        assert_eq!((s.n, s.min, s.max, s.mode), (5, 100, 200, 100));
+        assert_eq!(s.median, 120.0);
+        assert_eq!(s.mad, 20.0);
+        assert_eq!(s.mean, 140.0);
+
+        let s = histogram.summary(None).unwrap();
+        assert_eq!(s.median, 150.0);
+        assert_eq!(histogram.fragment_length(), Some(120));
+
+        let nucleosomes = histogram.nucleosome_summary();
+        assert_eq!(nucleosomes.nucleosome_free, 3);
+        assert_eq!(nucleosomes.mono_nucleosome, 2);
+        assert_eq!(nucleosomes.di_nucleosome, 1);
+        assert_eq!(nucleosomes.mono_nucleosome_peak, Some(180));
+        assert_eq!(nucleosomes.repeat_length, Some(120));
+
+        let mut table = Vec::new();
+        histogram.write_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.starts_with("insert_size\tFR\tRF\tTANDEM\ttotal\n100\t2\t0\t0\t2\n"));
+        assert!(table.ends_with("300\t0\t1\t0\t1\n"));
+    }
+
+    #[test]
+    fn test_helical_periodicity() {
+        // Exponential decay modulated with a period of 10 bp
+        let counts: Vec<u64> = (0..1000)
+            .map(|i| {
+                let x = i as f64;
+                (10000.0
+                    * (-x / 200.0).exp()
+                    * (1.0 + 0.3 * (2.0 * std::f64::consts::PI * x / 10.0).cos()))
+                    as u64
+            })
+            .collect();
+        let mut histogram = InsertSizeHistogram::new(InsertSizeConfig {
+            max_insert_size: 999,
+            ..Default::default()
+        });
+        histogram.counts[0] = counts;
+
+        let s = histogram.nucleosome_summary();
+        assert_eq!(s.helical_period, Some(10));
+        assert!(s.helical_autocorrelation > 0.5);
+    }
+
+    #[test]
+    fn test_insert_size_file() {
+        let histogram =
+            InsertSizeHistogram::import("tests/test_bam_1.bam", InsertSizeConfig::default(), 1)
+                .unwrap();
+
+        assert_eq!(histogram.count(PairOrientation::FR), 1);
+        assert_eq!(histogram.fragment_length(), Some(39));
+    }
+}
@@ -0,0 +1,432 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::bam::{BamAuxValue, BamBlock, BamReader, BamWriter};
+use crate::genome::Genome;
+use crate::orderedstringset::OrderedStringSet;
+
+/* -------------------------------------------------------------------------- */
+
+/// The `MD` (mismatching positions) and `NM` (edit distance) tags of a
+/// record.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BamMdTags {
+    pub md: String,
+    pub nm: i64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamMdTags {
+    /// Computes the tags of a record from its CIGAR and sequence.
+    ///
+    /// Read bases equal to `=` always match. The base `N` mismatches any
+    /// reference base, and reference bases are compared case-insensitively.
+    ///
+    /// # Arguments
+    /// * `block` - A mapped record with CIGAR and sequence. Records without
+    ///   sequence are rejected, see `BamMdReference::compute`.
+    /// * `reference` - The reference sequence the record is aligned to.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` of kind `InvalidData` if the alignment exceeds
+    /// the reference or the sequence is shorter than the CIGAR.
+    pub fn compute(block: &BamBlock, reference: &[u8]) -> io::Result<Self> {
+        let seq = block.seq.decode(block.l_seq.max(0) as usize).into_bytes();
+
+        let mut tags = BamMdTags::default();
+        let mut matches = 0;
+        let mut ref_pos = block.position.max(0) as usize;
+        let mut query_pos = 0;
+
+        let out_of_range = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("alignment of `{}` exceeds the {}", block.read_name, what),
+            )
+        };
+
+        for op in block.cigar.parse_cigar() {
+            let n = op.n as usize;
+            match op.type_ {
+                'M' | '=' | 'X' => {
+                    let r = reference
+                        .get(ref_pos..ref_pos + n)
+                        .ok_or_else(|| out_of_range("reference"))?;
+                    let q = seq
+                        .get(query_pos..query_pos + n)
+                        .ok_or_else(|| out_of_range("read sequence"))?;
+                    for (&rb, &qb) in r.iter().zip(q) {
+                        let rb = rb.to_ascii_uppercase();
+                        if qb == b'=' || (qb == rb && qb != b'N') {
+                            matches += 1;
+                        } else {
+                            tags.md.push_str(&matches.to_string());
+                            tags.md.push(rb as char);
+                            tags.nm += 1;
+                            matches = 0;
+                        }
+                    }
+                    ref_pos += n;
+                    query_pos += n;
+                }
+                'I' => {
+                    tags.nm += n as i64;
+                    query_pos += n;
+                }
+                'D' => {
+                    let r = reference
+                        .get(ref_pos..ref_pos + n)
+                        .ok_or_else(|| out_of_range("reference"))?;
+                    tags.md.push_str(&matches.to_string());
+                    tags.md.push('^');
+                    tags.md
+                        .extend(r.iter().map(|b| b.to_ascii_uppercase() as char));
+                    tags.nm += n as i64;
+                    matches = 0;
+                    ref_pos += n;
+                }
+                'N' => ref_pos += n,
+                'S' => query_pos += n,
+                _ => {}
+            }
+        }
+        tags.md.push_str(&matches.to_string());
+
+        Ok(tags)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A record whose existing `MD` or `NM` tag differs from the computed value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BamMdMismatch {
+    pub read_name: String,
+    pub seqname: String,
+    pub position: usize,
+    pub expected: BamMdTags,
+    pub md: Option<String>,
+    pub nm: Option<i64>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for BamMdMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}:{}",
+            self.read_name,
+            self.seqname,
+            self.position + 1
+        )?;
+        if let Some(md) = &self.md {
+            if md != &self.expected.md {
+                write!(f, "\tMD:{} (expected {})", md, self.expected.md)?;
+            }
+        }
+        if let Some(nm) = self.nm {
+            if nm != self.expected.nm {
+                write!(f, "\tNM:{} (expected {})", nm, self.expected.nm)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Reference sequences indexed by the reference ids of a BAM file.
+pub struct BamMdReference<'a> {
+    seqnames: Vec<String>,
+    sequences: Vec<Option<&'a [u8]>>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<'a> BamMdReference<'a> {
+    /// Matches the sequences of `reference` to the reference sequences of a
+    /// BAM file.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if a sequence of the reference is shorter than
+    /// declared in the BAM header.
+    pub fn new(reference: &'a OrderedStringSet, genome: &Genome) -> io::Result<Self> {
+        let mut sequences = Vec::with_capacity(genome.len());
+        for i in 0..genome.len() {
+            let sequence = reference.sequences.get(&genome.seqnames[i]);
+            if let Some(sequence) = sequence {
+                if sequence.len() < genome.lengths[i] {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "reference sequence `{}` is shorter than declared in the BAM header",
+                            genome.seqnames[i]
+                        ),
+                    ));
+                }
+            }
+            sequences.push(sequence.map(|s| s.as_slice()));
+        }
+        Ok(BamMdReference {
+            seqnames: genome.seqnames.clone(),
+            sequences,
+        })
+    }
+
+    /// Computes the tags of a record.
+    ///
+    /// # Returns
+    /// `None` if the record is unmapped, has no CIGAR or sequence (`*`, e.g.
+    /// secondary alignments) or its reference sequence is not available.
+    pub fn compute(&self, block: &BamBlock) -> io::Result<Option<BamMdTags>> {
+        if block.flag.unmapped() || block.ref_id < 0 || block.cigar.0.is_empty() || block.l_seq == 0
+        {
+            return Ok(None);
+        }
+        match self.sequences.get(block.ref_id as usize).copied().flatten() {
+            Some(reference) => BamMdTags::compute(block, reference).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Computes the tags of a record and replaces existing `MD` and `NM`
+    /// fields.
+    ///
+    /// # Returns
+    /// `true` if the tags were set, `false` if they could not be computed
+    /// (see `compute`).
+    pub fn fill(&self, block: &mut BamBlock) -> io::Result<bool> {
+        let tags = match self.compute(block)? {
+            Some(tags) => tags,
+            None => return Ok(false),
+        };
+        let nm = if tags.nm <= u8::MAX as i64 {
+            BamAuxValue::CUnsigned(tags.nm as u8)
+        } else if tags.nm <= u16::MAX as i64 {
+            BamAuxValue::SUnsigned(tags.nm as u16)
+        } else {
+            BamAuxValue::IUnsigned(tags.nm as u32)
+        };
+        block.set_auxiliary(b"MD", BamAuxValue::Z(tags.md));
+        block.set_auxiliary(b"NM", nm);
+        Ok(true)
+    }
+
+    /// Verifies the existing `MD` and `NM` tags of a record. Missing tags are
+    /// not reported.
+    ///
+    /// # Returns
+    /// A `BamMdMismatch` if an existing tag differs from the computed value.
+    pub fn verify(&self, block: &BamBlock) -> io::Result<Option<BamMdMismatch>> {
+        let md = match block.get_auxiliary(b"MD") {
+            Some(BamAuxValue::Z(md)) => Some(md.clone()),
+            _ => None,
+        };
+        let nm = block.get_auxiliary(b"NM").and_then(|v| v.as_i64());
+
+        if md.is_none() && nm.is_none() {
+            return Ok(None);
+        }
+        let expected = match self.compute(block)? {
+            Some(tags) => tags,
+            None => return Ok(None),
+        };
+        let md_ok = md.as_ref().is_none_or(|md| md == &expected.md);
+        let nm_ok = nm.is_none_or(|nm| nm == expected.nm);
+
+        if md_ok && nm_ok {
+            return Ok(None);
+        }
+        Ok(Some(BamMdMismatch {
+            read_name: block.read_name.clone(),
+            seqname: self.seqnames[block.ref_id as usize].clone(),
+            position: block.position as usize,
+            expected,
+            md,
+            nm,
+        }))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Copies all records from `reader` to `writer` and fills in the `MD` and
+/// `NM` tags of all mapped records with available reference sequence.
+///
+/// # Returns
+/// The number of records whose tags were set.
+pub fn bam_fill_md<R: Read, W: Write>(
+    reader: &mut BamReader<R>,
+    writer: &mut BamWriter<W>,
+    reference: &OrderedStringSet,
+) -> io::Result<u64> {
+    let reference = BamMdReference::new(reference, reader.get_genome())?;
+    let mut n = 0;
+
+    while let Some(mut block) = reader.read_block()? {
+        if reference.fill(&mut block)? {
+            n += 1;
+        }
+        writer.write_block(&block)?;
+    }
+    Ok(n)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Verifies the `MD` and `NM` tags of all records.
+///
+/// # Returns
+/// The records whose existing tags are wrong.
+pub fn bam_verify_md<R: Read>(
+    reader: &mut BamReader<R>,
+    reference: &OrderedStringSet,
+) -> io::Result<Vec<BamMdMismatch>> {
+    let reference = BamMdReference::new(reference, reader.get_genome())?;
+    let mut mismatches = Vec::new();
+
+    while let Some(block) = reader.read_block()? {
+        if let Some(mismatch) = reference.verify(&block)? {
+            mismatches.push(mismatch);
+        }
+    }
+    Ok(mismatches)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bam::{BamAuxValue, BamBlock, BamHeader, BamReader, BamWriter};
+    use crate::bam_fixtures::{genome, read};
+    use crate::bam_md::{bam_fill_md, bam_verify_md, BamMdReference, BamMdTags};
+    use crate::orderedstringset::OrderedStringSet;
+
+    const REFERENCE: &[u8] = b"ACGTACGTacgtACGTACGT";
+
+    fn tags(block: &BamBlock) -> (String, i64) {
+        let tags = BamMdTags::compute(block, REFERENCE).unwrap();
+        (tags.md, tags.nm)
+    }
+
+    #[test]
+    fn test_md_tags() {
+        assert_eq!(
+            tags(&read("a", 0x0, 0, "8M", "ACGTACGT", 30)),
+            ("8".to_string(), 0)
+        );
+        assert_eq!(
+            tags(&read("b", 0x0, 0, "8M", "ACTTACGA", 30)),
+            ("2G4T0".to_string(), 2)
+        );
+        // Lower-case reference and soft clipping
+        assert_eq!(
+            tags(&read("c", 0x0, 8, "2S4M", "NNACGT", 30)),
+            ("4".to_string(), 0)
+        );
+        // Deletion followed by a mismatch, insertion and skipped region
+        assert_eq!(
+            tags(&read("d", 0x0, 0, "2M2D1M2I2M4N2M", "ACGTTCGTC", 30)),
+            ("2^GT0A3A0".to_string(), 6)
+        );
+        assert_eq!(
+            tags(&read("e", 0x0, 0, "3M", "A=N", 30)),
+            ("2G0".to_string(), 1)
+        );
+        assert!(BamMdTags::compute(&read("f", 0x0, 18, "4M", "ACGT", 30), REFERENCE).is_err());
+    }
+
+    #[test]
+    fn test_md_fill_and_verify() {
+        let genome = genome(REFERENCE.len());
+        let reference = OrderedStringSet::new(vec!["chr1".to_string()], vec![REFERENCE.to_vec()]);
+
+        let mut wrong = read("wrong", 0x0, 0, "4M", "ACTT", 30);
+        wrong.set_auxiliary(b"NM", BamAuxValue::I(0));
+        let mut unmapped = read("unmapped", 0x0, -1, "", "ACGT", 30);
+        unmapped.ref_id = -1;
+        unmapped.flag.0 = 0x4;
+        // Secondary alignment without sequence is left unchanged
+        let mut secondary = read("secondary", 0x0, 0, "4M", "", 30);
+        secondary.flag.0 = 0x100;
+        secondary.set_auxiliary(b"NM", BamAuxValue::I(3));
+
+        let mut writer = BamWriter::new(Vec::new(), &BamHeader::default(), &genome).unwrap();
+        for block in [
+            read("a", 0x0, 0, "4M", "ACGT", 30),
+            wrong,
+            unmapped,
+            secondary,
+        ] {
+            writer.write_block(&block).unwrap();
+        }
+        let buffer = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(Cursor::new(buffer.clone()), None).unwrap();
+        let mismatches = bam_verify_md(&mut reader, &reference).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].read_name, "wrong");
+        assert_eq!(mismatches[0].expected.nm, 1);
+        assert_eq!(
+            mismatches[0].to_string(),
+            "wrong\tchr1:1\tNM:0 (expected 1)"
+        );
+
+        let mut reader = BamReader::new(Cursor::new(buffer), None).unwrap();
+        let mut writer = BamWriter::new(Vec::new(), &BamHeader::default(), &genome).unwrap();
+        assert_eq!(
+            bam_fill_md(&mut reader, &mut writer, &reference).unwrap(),
+            2
+        );
+        let buffer = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(Cursor::new(buffer.clone()), None).unwrap();
+        let blocks: Vec<BamBlock> = reader.read_single_end().map(|r| r.unwrap().block).collect();
+        let md: Vec<Option<String>> = blocks
+            .iter()
+            .map(|b| match b.get_auxiliary(b"MD") {
+                Some(BamAuxValue::Z(md)) => Some(md.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            md,
+            vec![Some("4".to_string()), Some("2G1".to_string()), None, None]
+        );
+        assert_eq!(blocks[1].auxiliary.len(), 2);
+        assert_eq!(blocks[3].auxiliary.len(), 1);
+        assert_eq!(
+            blocks[3].get_auxiliary(b"NM").and_then(|v| v.as_i64()),
+            Some(3)
+        );
+
+        let md_reference = BamMdReference::new(&reference, &genome).unwrap();
+        assert!(blocks
+            .iter()
+            .all(|b| md_reference.verify(b).unwrap().is_none()));
+    }
+}
@@ -0,0 +1,786 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::bam::{
+    BamAuxValue, BamAuxiliary, BamBlock, BamFile, BamHeader, BamReader, BamReaderOptions, BamWriter,
+};
+use crate::bam_sort::{BamMergeEntry, BamSortOrder};
+use crate::genome::Genome;
+use crate::sam_header::{SamHeader, SamReadGroup};
+
+/* -------------------------------------------------------------------------- */
+
+/// Configuration of `bam_merge_files`.
+///
+/// # Fields
+/// - `order`: Sort order of the inputs. If `None`, the order is taken from
+///   the `@HD` lines of the inputs, which must all declare the same order,
+///   and inputs without a known sort order are concatenated.
+/// - `tag_read_groups`: If `true`, every record is tagged with a read group
+///   derived from the name of its source file, which is also added to the
+///   header.
+/// - `threads`: Number of threads used for BGZF decompression of each input.
+#[derive(Clone, Debug, Default)]
+pub struct BamMergeConfig {
+    pub order: Option<BamSortOrder>,
+    pub tag_read_groups: bool,
+    pub threads: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Merged header and the translation of every input into the merged header.
+///
+/// The merged reference dictionary contains all sequences of all inputs. If
+/// possible, the relative order of sequences within every input is preserved,
+/// which keeps coordinate-sorted inputs sorted after remapping reference ids.
+/// Read groups and programs of different inputs that share an id but differ
+/// in their attributes are renamed by appending a numeric suffix. Read groups
+/// with which inputs are tagged are always renamed if their id is taken.
+#[derive(Clone, Debug)]
+pub struct BamMergePlan {
+    pub header: SamHeader,
+    pub genome: Genome,
+    pub order: Option<BamSortOrder>,
+    ref_ids: Vec<Vec<i32>>,
+    read_groups: Vec<HashMap<String, String>>,
+    read_group_tags: Vec<Option<String>>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns `id` or, if `exists(id)`, the first `id.N` that does not exist.
+fn unique_id<F: Fn(&str) -> bool>(id: &str, exists: F) -> String {
+    let mut candidate = id.to_string();
+    let mut i = 1;
+    while exists(&candidate) {
+        candidate = format!("{}.{}", id, i);
+        i += 1;
+    }
+    candidate
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Merges the sequence dictionaries of all inputs.
+///
+/// Sequences are ordered such that the order within every input is preserved
+/// (a stable topological sort, preferring sequences that appear first). If the
+/// inputs disagree on the order, sequences are ordered by first appearance and
+/// `false` is returned as second value.
+fn merge_genomes(genomes: &[Genome]) -> Result<(Genome, bool), Box<dyn Error>> {
+    let mut names: Vec<String> = Vec::new();
+    let mut lengths: Vec<usize> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for genome in genomes {
+        for (name, &length) in genome.iter() {
+            match index.get(name) {
+                Some(&i) if lengths[i] != length => {
+                    return Err(format!(
+                        "sequence `{}` has different lengths in input files ({} and {})",
+                        name, lengths[i], length
+                    )
+                    .into());
+                }
+                Some(_) => (),
+                None => {
+                    index.insert(name.clone(), names.len());
+                    names.push(name.clone());
+                    lengths.push(length);
+                }
+            }
+        }
+    }
+
+    // Edges between consecutive sequences of every input
+    let mut successors = vec![Vec::new(); names.len()];
+    let mut in_degree = vec![0; names.len()];
+    for genome in genomes {
+        for pair in genome.seqnames.windows(2) {
+            let (i, j) = (index[&pair[0]], index[&pair[1]]);
+            successors[i].push(j);
+            in_degree[j] += 1;
+        }
+    }
+
+    let mut heap: BinaryHeap<Reverse<usize>> = (0..names.len())
+        .filter(|&i| in_degree[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut sorted = Vec::with_capacity(names.len());
+    while let Some(Reverse(i)) = heap.pop() {
+        sorted.push(i);
+        for &j in &successors[i] {
+            in_degree[j] -= 1;
+            if in_degree[j] == 0 {
+                heap.push(Reverse(j));
+            }
+        }
+    }
+
+    let consistent = sorted.len() == names.len();
+    if !consistent {
+        sorted = (0..names.len()).collect();
+    }
+    let genome = Genome::new(
+        sorted.iter().map(|&i| names[i].clone()).collect(),
+        sorted.iter().map(|&i| lengths[i]).collect(),
+    );
+    Ok((genome, consistent))
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamMergePlan {
+    /// Computes the merged header of a set of inputs.
+    ///
+    /// # Arguments
+    /// * `headers` - The parsed headers of all inputs.
+    /// * `genomes` - The reference sequences of all inputs.
+    /// * `read_group_tags` - For every input an optional read group id, with
+    ///   which all records of this input are tagged.
+    /// * `order` - The sort order of the inputs, see `BamMergeConfig`.
+    ///
+    /// # Errors
+    /// Returns an error if a sequence has different lengths in different
+    /// inputs, if coordinate-sorted inputs order their sequences
+    /// inconsistently, or if no `order` is given and the inputs declare
+    /// different sort orders.
+    pub fn new(
+        headers: &[SamHeader],
+        genomes: &[Genome],
+        read_group_tags: &[Option<String>],
+        order: Option<BamSortOrder>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if headers.len() != genomes.len() || headers.len() != read_group_tags.len() {
+            return Err("number of headers, genomes and read groups does not match".into());
+        }
+        let order = match order {
+            Some(order) => Some(order),
+            None => {
+                let sort_order = |h: &SamHeader| h.sort_order().unwrap_or("unknown").to_string();
+                let first = headers.first().map(sort_order);
+                if let Some(h) = headers.iter().find(|h| Some(sort_order(h)) != first) {
+                    return Err(format!(
+                        "input files have different sort orders `{}` and `{}`",
+                        first.unwrap(),
+                        sort_order(h)
+                    )
+                    .into());
+                }
+                first.and_then(|so| so.parse().ok())
+            }
+        };
+
+        let (genome, consistent) = merge_genomes(genomes)?;
+        if !consistent && order == Some(BamSortOrder::Coordinate) {
+            return Err("input files order their reference sequences inconsistently".into());
+        }
+        let ref_ids = genomes
+            .iter()
+            .map(|g| {
+                g.seqnames
+                    .iter()
+                    .map(|name| genome.get_idx(name).unwrap() as i32)
+                    .collect()
+            })
+            .collect();
+
+        let mut header = SamHeader {
+            hd: headers.first().and_then(|h| h.hd.clone()),
+            ..Default::default()
+        };
+        match order {
+            Some(order) => header.set_sort_order(order.as_str()),
+            None => {
+                if let Some(hd) = header.hd.as_mut() {
+                    hd.sort_order = Some("unsorted".to_string());
+                }
+            }
+        }
+
+        // Keep attributes of the first input that contains a sequence
+        for name in genome.seqnames.iter() {
+            let sequence = headers.iter().find_map(|h| h.get_sequence(name)).cloned();
+            header.sequences.push(sequence.unwrap_or_default());
+        }
+        header.set_genome(&genome);
+
+        let mut read_groups = Vec::with_capacity(headers.len());
+        for h in headers {
+            let mut renamed = HashMap::new();
+            for rg in &h.read_groups {
+                let id = match header.get_read_group(&rg.id) {
+                    Some(existing) if existing == rg => rg.id.clone(),
+                    Some(_) => {
+                        let id = unique_id(&rg.id, |id| header.get_read_group(id).is_some());
+                        header.add_read_group(SamReadGroup {
+                            id: id.clone(),
+                            ..rg.clone()
+                        });
+                        id
+                    }
+                    None => {
+                        header.add_read_group(rg.clone());
+                        rg.id.clone()
+                    }
+                };
+                renamed.insert(rg.id.clone(), id);
+            }
+            read_groups.push(renamed);
+        }
+
+        // Every input receives its own read group, even if file names or
+        // read groups of the inputs share the id
+        let mut read_group_tags = read_group_tags.to_vec();
+        for tag in read_group_tags.iter_mut().flatten() {
+            let id = unique_id(tag, |id| header.get_read_group(id).is_some());
+            header.add_read_group(SamReadGroup {
+                id: id.clone(),
+                sample: Some(tag.clone()),
+                ..Default::default()
+            });
+            *tag = id;
+        }
+
+        for h in headers {
+            let mut renamed: HashMap<String, String> = HashMap::new();
+            // Programs are visited in file order, in which PP usually refers
+            // to a preceding line
+            for pg in &h.programs {
+                let mut pg = pg.clone();
+                if let Some(pp) = pg.previous_id.as_mut() {
+                    if let Some(id) = renamed.get(pp) {
+                        *pp = id.clone();
+                    }
+                }
+                let id = match header.get_program(&pg.id) {
+                    Some(existing) if *existing == pg => pg.id.clone(),
+                    Some(_) => unique_id(&pg.id, |id| header.get_program(id).is_some()),
+                    None => pg.id.clone(),
+                };
+                if header.get_program(&id).is_none() {
+                    renamed.insert(pg.id.clone(), id.clone());
+                    pg.id = id;
+                    header.programs.push(pg);
+                } else {
+                    renamed.insert(pg.id.clone(), id);
+                }
+            }
+            for comment in &h.comments {
+                if !header.comments.contains(comment) {
+                    header.comments.push(comment.clone());
+                }
+            }
+        }
+
+        Ok(BamMergePlan {
+            header,
+            genome,
+            order,
+            ref_ids,
+            read_groups,
+            read_group_tags,
+        })
+    }
+
+    /// Returns the merged header as `BamHeader`.
+    pub fn bam_header(&self) -> BamHeader {
+        BamHeader::from(&self.header)
+    }
+
+    /// Translates a record of input `input` into the merged header, i.e.
+    /// remaps reference ids and renames or sets its read group.
+    pub fn translate(&self, input: usize, block: &mut BamBlock) {
+        let ref_ids = &self.ref_ids[input];
+        if block.ref_id >= 0 {
+            block.ref_id = ref_ids[block.ref_id as usize];
+        }
+        if block.next_ref_id >= 0 {
+            block.next_ref_id = ref_ids[block.next_ref_id as usize];
+        }
+        if let Some(tag) = &self.read_group_tags[input] {
+            block.auxiliary.retain(|aux| &aux.tag != b"RG");
+            block.auxiliary.push(BamAuxiliary {
+                tag: *b"RG",
+                value: BamAuxValue::Z(tag.clone()),
+            });
+        } else {
+            for aux in block.auxiliary.iter_mut() {
+                if &aux.tag == b"RG" {
+                    if let BamAuxValue::Z(rg) = &mut aux.value {
+                        if let Some(id) = self.read_groups[input].get(rg.as_str()) {
+                            *rg = id.clone();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Merges all records of `readers` into `writer`.
+///
+/// Sorted inputs are merged such that the output has the sort order of the
+/// plan, otherwise inputs are concatenated. Records with equal sort keys are
+/// written in the order of the inputs.
+///
+/// # Arguments
+/// * `plan` - The merge plan computed from the headers of `readers`.
+/// * `readers` - The input BAM readers.
+/// * `writer` - The output BAM writer, which already contains the merged header.
+///
+/// # Errors
+/// Returns an error if reading or writing fails.
+pub fn bam_merge<R: Read, W: Write>(
+    plan: &BamMergePlan,
+    readers: &mut [BamReader<R>],
+    writer: &mut BamWriter<W>,
+) -> Result<(), Box<dyn Error>> {
+    let order = match plan.order {
+        Some(order) => order,
+        None => {
+            for (i, reader) in readers.iter_mut().enumerate() {
+                while let Some(mut block) = reader.read_block()? {
+                    plan.translate(i, &mut block);
+                    writer.write_block(&block)?;
+                }
+            }
+            return Ok(());
+        }
+    };
+
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (chunk, reader) in readers.iter_mut().enumerate() {
+        if let Some(mut block) = reader.read_block()? {
+            plan.translate(chunk, &mut block);
+            heap.push(BamMergeEntry {
+                block,
+                chunk,
+                order,
+            });
+        }
+    }
+    while let Some(entry) = heap.pop() {
+        writer.write_block(&entry.block)?;
+
+        if let Some(mut block) = readers[entry.chunk].read_block()? {
+            plan.translate(entry.chunk, &mut block);
+            heap.push(BamMergeEntry {
+                block,
+                chunk: entry.chunk,
+                order,
+            });
+        }
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Merges the BAM files `inputs` into `output`.
+///
+/// # Arguments
+/// * `inputs` - The file paths or URLs of the input BAM files.
+/// * `output` - The file path of the merged BAM file.
+/// * `config` - The merge configuration.
+/// * `command_line` - If given, a `@PG` line with this command line is added
+///   to the header of the output file.
+///
+/// # Errors
+/// Returns an error if the headers cannot be reconciled or if reading or
+/// writing fails.
+pub fn bam_merge_files(
+    inputs: &[&str],
+    output: &str,
+    config: &BamMergeConfig,
+    command_line: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let mut readers = Vec::with_capacity(inputs.len());
+    let mut headers = Vec::with_capacity(inputs.len());
+    let mut genomes = Vec::with_capacity(inputs.len());
+    let mut read_group_tags = Vec::with_capacity(inputs.len());
+
+    for filename in inputs {
+        let options = BamReaderOptions::with_threads(config.threads);
+        let bam = BamFile::open(filename, Some(options))?;
+
+        headers.push(bam.reader.get_header().parse()?);
+        genomes.push(bam.reader.get_genome().clone());
+        read_group_tags.push(if config.tag_read_groups {
+            Some(bam_merge_read_group_name(filename))
+        } else {
+            None
+        });
+        readers.push(bam.reader);
+    }
+
+    let mut plan = BamMergePlan::new(&headers, &genomes, &read_group_tags, config.order)?;
+    if let Some(command_line) = command_line {
+        plan.header.add_program(
+            "bam-merge",
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(command_line),
+        );
+    }
+
+    let mut writer = BamFile::create(output, &plan.bam_header(), &plan.genome)?;
+    bam_merge(&plan, &mut readers, &mut writer)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns the read group id used for tagging records of a file, which is
+/// the file name without directory and `.bam` extension.
+pub fn bam_merge_read_group_name(filename: &str) -> String {
+    let name = Path::new(filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| filename.to_string());
+
+    match name.strip_suffix(".bam") {
+        Some(stem) if !stem.is_empty() => stem.to_string(),
+        _ => name,
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+
+    use crate::bam::{
+        BamAuxValue, BamAuxiliary, BamBlock, BamFile, BamHeader, BamReader, BamWriter,
+    };
+    use crate::bam_merge::{
+        bam_merge, bam_merge_files, bam_merge_read_group_name, merge_genomes, BamMergeConfig,
+        BamMergePlan,
+    };
+    use crate::bam_sort::{bam_sort, bam_sort_header, BamSortConfig, BamSortOrder};
+    use crate::genome::Genome;
+    use crate::sam_header::SamHeader;
+
+    fn genome(names: &[&str]) -> Genome {
+        Genome::new(
+            names.iter().map(|s| s.to_string()).collect(),
+            names.iter().map(|_| 100).collect(),
+        )
+    }
+
+    #[test]
+    fn test_bam_merge_genomes() {
+        let (g, consistent) = merge_genomes(&[
+            genome(&["chr1", "chr2", "chr3"]),
+            genome(&["chr1", "chrX", "chr3"]),
+        ])
+        .unwrap();
+        assert!(consistent);
+        assert_eq!(g.seqnames, vec!["chr1", "chr2", "chrX", "chr3"]);
+
+        let (g, consistent) =
+            merge_genomes(&[genome(&["chr1", "chr2"]), genome(&["chr2", "chr1"])]).unwrap();
+        assert!(!consistent);
+        assert_eq!(g.seqnames, vec!["chr1", "chr2"]);
+
+        let g2 = Genome::new(vec!["chr1".to_string()], vec![50]);
+        assert!(merge_genomes(&[genome(&["chr1"]), g2]).is_err());
+    }
+
+    #[test]
+    fn test_bam_merge_plan() {
+        let h1: SamHeader = "@HD\tVN:1.6\tSO:coordinate\n\
+            @SQ\tSN:chr1\tLN:100\n@SQ\tSN:chr2\tLN:100\n\
+            @RG\tID:rg1\tSM:a\n@RG\tID:rg2\tSM:b\n\
+            @PG\tID:bwa\tPN:bwa\tVN:1\n"
+            .parse()
+            .unwrap();
+        let h2: SamHeader = "@HD\tVN:1.6\tSO:coordinate\n\
+            @SQ\tSN:chr2\tLN:100\n\
+            @RG\tID:rg1\tSM:c\n@RG\tID:rg2\tSM:b\n\
+            @PG\tID:bwa\tPN:bwa\tVN:2\n@PG\tID:dedup\tPN:dedup\tPP:bwa\n"
+            .parse()
+            .unwrap();
+        let genomes = vec![h1.genome(), h2.genome()];
+
+        let plan = BamMergePlan::new(&[h1, h2], &genomes, &[None, None], None).unwrap();
+
+        assert_eq!(plan.order, Some(BamSortOrder::Coordinate));
+        assert_eq!(plan.genome.seqnames, vec!["chr1", "chr2"]);
+
+        // rg1 collides, rg2 is identical in both files
+        let ids: Vec<_> = plan
+            .header
+            .read_groups
+            .iter()
+            .map(|rg| rg.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["rg1", "rg2", "rg1.1"]);
+        assert_eq!(
+            plan.header
+                .get_read_group("rg1.1")
+                .unwrap()
+                .sample
+                .as_deref(),
+            Some("c")
+        );
+
+        let chain: Vec<_> = plan
+            .header
+            .program_chain("dedup")
+            .iter()
+            .map(|pg| pg.id.as_str())
+            .collect();
+        assert_eq!(chain, vec!["bwa.1", "dedup"]);
+
+        let mut block = BamBlock {
+            ref_id: 0,
+            next_ref_id: 0,
+            auxiliary: vec![BamAuxiliary {
+                tag: *b"RG",
+                value: BamAuxValue::Z("rg1".to_string()),
+            }],
+            ..Default::default()
+        };
+        plan.translate(1, &mut block);
+        assert_eq!(block.ref_id, 1);
+        assert_eq!(block.next_ref_id, 1);
+        assert_eq!(block.read_group(), Some("rg1.1"));
+
+        // Inconsistent reference orders cannot be merged by coordinate
+        let h3: SamHeader = "@SQ\tSN:chr2\tLN:100\n@SQ\tSN:chr1\tLN:100\n"
+            .parse()
+            .unwrap();
+        let h4: SamHeader = "@SQ\tSN:chr1\tLN:100\n@SQ\tSN:chr2\tLN:100\n"
+            .parse()
+            .unwrap();
+        let genomes = vec![h3.genome(), h4.genome()];
+        let headers = vec![h3, h4];
+        assert!(BamMergePlan::new(
+            &headers,
+            &genomes,
+            &[None, None],
+            Some(BamSortOrder::Coordinate)
+        )
+        .is_err());
+        assert!(BamMergePlan::new(&headers, &genomes, &[None, None], None).is_ok());
+
+        // Inputs must declare the same sort order unless it is given
+        let h5: SamHeader = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:100\n"
+            .parse()
+            .unwrap();
+        let h6: SamHeader = "@HD\tVN:1.6\tSO:queryname\n@SQ\tSN:chr1\tLN:100\n"
+            .parse()
+            .unwrap();
+        let h7: SamHeader = "@SQ\tSN:chr1\tLN:100\n".parse().unwrap();
+        let genomes = vec![h5.genome(), h6.genome()];
+        let tags = [None, None];
+        assert!(BamMergePlan::new(&[h5.clone(), h6], &genomes, &tags, None).is_err());
+        assert!(BamMergePlan::new(&[h5.clone(), h7.clone()], &genomes, &tags, None).is_err());
+        let plan =
+            BamMergePlan::new(&[h5, h7], &genomes, &tags, Some(BamSortOrder::Coordinate)).unwrap();
+        assert_eq!(plan.order, Some(BamSortOrder::Coordinate));
+    }
+
+    #[test]
+    fn test_bam_merge_plan_read_group_tags() {
+        // The first input already has a read group `sample` of another sample
+        let h1: SamHeader = "@SQ\tSN:chr1\tLN:100\n@RG\tID:sample\tSM:x\tLB:l1\n"
+            .parse()
+            .unwrap();
+        let h2: SamHeader = "@SQ\tSN:chr1\tLN:100\n".parse().unwrap();
+        let h3: SamHeader = "@SQ\tSN:chr1\tLN:100\n".parse().unwrap();
+        let genomes = vec![h1.genome(), h2.genome(), h3.genome()];
+
+        // Inputs a/sample.bam, b/sample.bam and c/other.bam
+        let tags: Vec<_> = ["a/sample.bam", "b/sample.bam", "c/other.bam"]
+            .iter()
+            .map(|f| Some(bam_merge_read_group_name(f)))
+            .collect();
+        let plan = BamMergePlan::new(&[h1, h2, h3], &genomes, &tags, None).unwrap();
+
+        let ids: Vec<_> = plan
+            .header
+            .read_groups
+            .iter()
+            .map(|rg| rg.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["sample", "sample.1", "sample.2", "other"]);
+        assert_eq!(
+            plan.header
+                .get_read_group("sample")
+                .unwrap()
+                .sample
+                .as_deref(),
+            Some("x")
+        );
+
+        let mut tagged = Vec::new();
+        for input in 0..3 {
+            let mut block = BamBlock::default();
+            plan.translate(input, &mut block);
+            tagged.push(block.read_group().unwrap().to_string());
+        }
+        assert_eq!(tagged, vec!["sample.1", "sample.2", "other"]);
+    }
+
+    #[test]
+    fn test_bam_merge() {
+        // Sort the test file by coordinate
+        let mut bam = BamFile::open("tests/test_bam_2.bam", None).unwrap();
+        let header = bam_sort_header(bam.reader.get_header(), BamSortOrder::Coordinate).unwrap();
+        let genome = bam.reader.get_genome().clone();
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        bam_sort(&mut bam.reader, &mut writer, &BamSortConfig::default()).unwrap();
+        let mut reader = BamReader::new(Cursor::new(writer.finish().unwrap()), None).unwrap();
+        let blocks: Vec<_> = reader.read_single_end().map(|r| r.unwrap().block).collect();
+
+        // Split records into two files, where the second file only contains
+        // the sequences it uses
+        let mut used = vec![false; genome.len()];
+        for block in blocks.iter().skip(1).step_by(2) {
+            if block.ref_id >= 0 {
+                used[block.ref_id as usize] = true;
+            }
+        }
+        let mut genome2 = Genome::default();
+        for (i, (name, &length)) in genome.iter().enumerate() {
+            if used[i] {
+                genome2.add_sequence(name.clone(), length).unwrap();
+            }
+        }
+        let mut header2 = header.parse().unwrap();
+        header2.set_genome(&genome2);
+        let header2 = BamHeader::from(&header2);
+
+        let mut writer1 = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        let mut writer2 = BamWriter::new(Vec::new(), &header2, &genome2).unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            if i % 2 == 0 {
+                writer1.write_block(block).unwrap();
+            } else {
+                let mut block = block.clone();
+                let remap = |id: i32| {
+                    if id < 0 {
+                        id
+                    } else {
+                        genome2
+                            .get_idx(&genome.seqnames[id as usize])
+                            .map_or(-1, |i| i as i32)
+                    }
+                };
+                block.ref_id = remap(block.ref_id);
+                block.next_ref_id = remap(block.next_ref_id);
+                writer2.write_block(&block).unwrap();
+            }
+        }
+        let mut readers = vec![
+            BamReader::new(Cursor::new(writer1.finish().unwrap()), None).unwrap(),
+            BamReader::new(Cursor::new(writer2.finish().unwrap()), None).unwrap(),
+        ];
+        let headers: Vec<_> = readers
+            .iter()
+            .map(|r| r.get_header().parse().unwrap())
+            .collect();
+        let genomes: Vec<_> = readers.iter().map(|r| r.get_genome().clone()).collect();
+        let tags = vec![Some("a".to_string()), Some("b".to_string())];
+
+        let plan = BamMergePlan::new(&headers, &genomes, &tags, None).unwrap();
+        assert_eq!(plan.genome.seqnames, genome.seqnames);
+
+        let mut writer = BamWriter::new(Vec::new(), &plan.bam_header(), &plan.genome).unwrap();
+        bam_merge(&plan, &mut readers, &mut writer).unwrap();
+        let mut reader = BamReader::new(Cursor::new(writer.finish().unwrap()), None).unwrap();
+        let merged: Vec<_> = reader.read_single_end().map(|r| r.unwrap().block).collect();
+
+        assert_eq!(merged.len(), blocks.len());
+        for pair in merged.windows(2) {
+            assert_ne!(
+                BamSortOrder::Coordinate.compare(&pair[0], &pair[1]),
+                Ordering::Greater
+            );
+        }
+        let key = |b: &BamBlock| {
+            (
+                b.read_name.clone(),
+                b.flag.0,
+                b.ref_id,
+                b.position,
+                b.next_ref_id,
+            )
+        };
+        let mut keys1: Vec<_> = blocks.iter().map(key).collect();
+        let mut keys2: Vec<_> = merged.iter().map(key).collect();
+        keys1.sort();
+        keys2.sort();
+        assert_eq!(keys1, keys2);
+
+        let n_b = merged
+            .iter()
+            .filter(|b| b.read_group() == Some("b"))
+            .count();
+        assert_eq!(n_b, blocks.len() / 2);
+        assert!(reader
+            .get_header()
+            .parse()
+            .unwrap()
+            .get_read_group("a")
+            .is_some());
+    }
+
+    #[test]
+    fn test_bam_merge_files() {
+        let output = env::temp_dir().join(format!("rustynetics-{}-merged.bam", process::id()));
+        let output = output.to_str().unwrap();
+        let inputs = ["tests/test_bam_2.bam", "tests/test_bam_2.bam"];
+        let config = BamMergeConfig::default();
+        bam_merge_files(&inputs, output, &config, Some("bam-merge")).unwrap();
+
+        let mut bam = BamFile::open(output, None).unwrap();
+        let header = bam.reader.get_header().parse().unwrap();
+        let program = header.programs.last().unwrap();
+        assert_eq!(program.command_line.as_deref(), Some("bam-merge"));
+        assert_eq!(bam.reader.read_single_end().count(), 2 * 4964);
+
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_bam_merge_read_group_name() {
+        assert_eq!(bam_merge_read_group_name("data/sample1.bam"), "sample1");
+        assert_eq!(bam_merge_read_group_name("sample2"), "sample2");
+    }
+}
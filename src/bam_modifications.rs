@@ -0,0 +1,602 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, Read};
+
+use crate::bam::{BamAuxValue, BamBlock, BamReader};
+use crate::bam_pileup::BAM_PILEUP_EXCLUDE_FLAGS;
+use crate::fastq::complement_base;
+use crate::genome::Genome;
+use crate::granges::GRanges;
+use crate::meta::MetaData;
+use crate::orderedstringset::OrderedStringSet;
+use crate::track::MutableTrack;
+use crate::track_simple::SimpleTrack;
+
+/* -------------------------------------------------------------------------- */
+
+/// A base modification call of a single read, as given by the `MM` and `ML`
+/// tags.
+///
+/// # Fields
+/// - `canonical`: The unmodified base, e.g. `C` for 5mC, in the orientation
+///   of the sequenced molecule.
+/// - `strand`: `+` if the modification is on the sequenced strand, `-` if it
+///   is on the opposite strand (duplex data).
+/// - `code`: The modification code, e.g. `m` (5mC), `h` (5hmC), `a` (6mA)
+///   or a ChEBI number.
+/// - `query_position`: Position of the base within the stored sequence,
+///   which is reverse-complemented for reverse-strand records.
+/// - `ref_position`: Position on the reference, or `None` if the base is
+///   not aligned (insertions, soft clips).
+/// - `probability`: Probability of the modification. Bases skipped in the
+///   `MM` tag are reported with probability zero, unless the tag marks them
+///   as unknown (`?`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct BamBaseModification {
+    pub canonical: u8,
+    pub strand: char,
+    pub code: String,
+    pub query_position: usize,
+    pub ref_position: Option<usize>,
+    pub probability: f64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamBlock {
+    /// Parses the base modification calls stored in the `MM` and `ML` tags
+    /// (or the older `Mm` and `Ml` tags).
+    ///
+    /// Probabilities are converted from the 8-bit `ML` values as
+    /// `(ML + 0.5) / 256`. If the `ML` tag is missing, all listed
+    /// modifications have probability one.
+    ///
+    /// # Returns
+    /// All calls ordered as in the `MM` tag, or an empty vector if the record
+    /// has no `MM` tag.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` of kind `InvalidData` if the tags are malformed
+    /// or inconsistent with the sequence, e.g. because the record was hard
+    /// clipped.
+    pub fn base_modifications(&self) -> io::Result<Vec<BamBaseModification>> {
+        let invalid = |msg: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid base modifications of `{}`: {}",
+                    self.read_name, msg
+                ),
+            )
+        };
+        let mm = match self.get_auxiliary(b"MM").or(self.get_auxiliary(b"Mm")) {
+            Some(BamAuxValue::Z(mm)) => mm,
+            Some(_) => return Err(invalid("MM tag is not a string".to_string())),
+            None => return Ok(Vec::new()),
+        };
+        let ml: Option<&[u8]> = match self.get_auxiliary(b"ML").or(self.get_auxiliary(b"Ml")) {
+            Some(BamAuxValue::BUint8(ml)) => Some(ml),
+            Some(_) => return Err(invalid("ML tag is not an uint8 array".to_string())),
+            None => None,
+        };
+        let l_seq = self.l_seq.max(0) as usize;
+        if let Some(mn) = self.get_auxiliary(b"MN").and_then(|v| v.as_i64()) {
+            if mn != l_seq as i64 {
+                return Err(invalid("sequence length does not match MN tag".to_string()));
+            }
+        }
+        let reverse = self.flag.reverse_strand();
+
+        // The MM tag refers to the sequence of the original molecule
+        let mut seq = self.seq.decode(l_seq).into_bytes();
+        if reverse {
+            seq = seq.iter().rev().map(|&b| complement_base(b)).collect();
+        }
+        let ref_positions = self.query_ref_positions();
+
+        let mut modifications = Vec::new();
+        let mut ml_idx = 0;
+
+        for entry in mm.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut fields = entry.split(',');
+            let head = fields.next().unwrap_or_default().as_bytes();
+            if head.len() < 3 || (head[1] != b'+' && head[1] != b'-') {
+                return Err(invalid(format!("invalid MM entry `{}`", entry)));
+            }
+            let canonical = head[0].to_ascii_uppercase();
+            let strand = head[1] as char;
+            let (codes, implicit) = match head[head.len() - 1] {
+                b'?' => (&head[2..head.len() - 1], false),
+                b'.' => (&head[2..head.len() - 1], true),
+                _ => (&head[2..], true),
+            };
+            let codes: Vec<String> = if codes.iter().all(u8::is_ascii_digit) {
+                vec![String::from_utf8_lossy(codes).to_string()]
+            } else {
+                codes.iter().map(|&c| (c as char).to_string()).collect()
+            };
+            let candidates: Vec<usize> = (0..seq.len())
+                .filter(|&i| canonical == b'N' || seq[i] == canonical)
+                .collect();
+
+            let mut call = |i: usize, code: &str, probability: f64| {
+                let query_position = if reverse { l_seq - 1 - i } else { i };
+                modifications.push(BamBaseModification {
+                    canonical,
+                    strand,
+                    code: code.to_string(),
+                    query_position,
+                    ref_position: ref_positions.get(query_position).copied().flatten(),
+                    probability,
+                });
+            };
+
+            let mut ci = 0;
+            for delta in fields {
+                let delta = delta
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| invalid(format!("invalid MM entry `{}`", entry)))?;
+                if ci + delta >= candidates.len() {
+                    return Err(invalid("MM tag exceeds the sequence".to_string()));
+                }
+                if implicit {
+                    for &i in &candidates[ci..ci + delta] {
+                        for code in &codes {
+                            call(i, code, 0.0);
+                        }
+                    }
+                }
+                ci += delta;
+                for code in &codes {
+                    let probability = match ml {
+                        Some(ml) => {
+                            let v = *ml
+                                .get(ml_idx)
+                                .ok_or_else(|| invalid("ML tag is too short".to_string()))?;
+                            (v as f64 + 0.5) / 256.0
+                        }
+                        None => 1.0,
+                    };
+                    ml_idx += 1;
+                    call(candidates[ci], code, probability);
+                }
+                ci += 1;
+            }
+            if implicit {
+                for &i in &candidates[ci..] {
+                    for code in &codes {
+                        call(i, code, 0.0);
+                    }
+                }
+            }
+        }
+        Ok(modifications)
+    }
+
+    /// Returns the reference position of every base of the stored sequence,
+    /// or `None` for bases that are not aligned.
+    fn query_ref_positions(&self) -> Vec<Option<usize>> {
+        let mut positions = Vec::with_capacity(self.l_seq.max(0) as usize);
+        let mut ref_pos = self.position.max(0) as usize;
+        let mapped = !self.flag.unmapped() && self.ref_id >= 0;
+
+        for op in self.cigar.parse_cigar() {
+            let n = op.n as usize;
+            match op.type_ {
+                'M' | '=' | 'X' => {
+                    positions.extend((ref_pos..ref_pos + n).map(|p| mapped.then_some(p)));
+                    ref_pos += n;
+                }
+                'I' | 'S' => positions.extend(std::iter::repeat_n(None, n)),
+                'D' | 'N' => ref_pos += n,
+                _ => {}
+            }
+        }
+        positions
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Options for the aggregation of modification calls at CpG sites.
+///
+/// # Fields
+/// - `code`: The modification code of 5mC calls, usually `m`.
+/// - `threshold`: Calls with a probability of at least `threshold` are
+///   counted as methylated, calls with a probability of at most
+///   `1 - threshold` as unmethylated. Calls in between are ignored.
+/// - `min_mapq`: Minimum mapping quality of a record.
+/// - `exclude_flags`: Records with any of these flags set are skipped. By
+///   default, these are unmapped, secondary, QC-failed, duplicate and
+///   supplementary records.
+/// - `combine_strands`: If `true`, calls on both strands of a CpG are
+///   reported at the position of the C on the forward strand.
+#[derive(Clone, Debug)]
+pub struct CpGMethylationConfig {
+    pub code: String,
+    pub threshold: f64,
+    pub min_mapq: u8,
+    pub exclude_flags: u16,
+    pub combine_strands: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for CpGMethylationConfig {
+    fn default() -> Self {
+        CpGMethylationConfig {
+            code: "m".to_string(),
+            threshold: 0.5,
+            min_mapq: 0,
+            // Supplementary records would count the calls of a read twice
+            exclude_flags: BAM_PILEUP_EXCLUDE_FLAGS | 0x800,
+            combine_strands: true,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Number of methylated and total calls at CpG sites.
+///
+/// Only calls of the canonical base `C` on the sequenced strand are
+/// considered. A call is placed at a CpG if the aligned reference base is
+/// the C (forward-strand records) or the G (reverse-strand records) of a
+/// CpG dinucleotide.
+pub struct CpGMethylation {
+    pub genome: Genome,
+    config: CpGMethylationConfig,
+    // Methylated and total calls per sequence and position
+    counts: Vec<BTreeMap<usize, (u64, u64)>>,
+    // Number of records with malformed MM/ML tags
+    malformed: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl CpGMethylation {
+    pub fn new(genome: Genome, config: CpGMethylationConfig) -> Self {
+        let counts = vec![BTreeMap::new(); genome.len()];
+        CpGMethylation {
+            genome,
+            config,
+            counts,
+            malformed: 0,
+        }
+    }
+
+    /// Returns the number of records that were skipped because of
+    /// malformed `MM`/`ML` tags.
+    pub fn n_malformed(&self) -> u64 {
+        self.malformed
+    }
+
+    /// Adds the modification calls of a record. Records with malformed
+    /// `MM`/`ML` tags are skipped and counted, see `n_malformed`.
+    ///
+    /// # Arguments
+    /// * `block` - The record, which must contain CIGAR, sequence and
+    ///   auxiliary data.
+    /// * `reference` - The reference sequence the record is aligned to.
+    pub fn add(&mut self, block: &BamBlock, reference: &[u8]) -> io::Result<()> {
+        if block.ref_id < 0
+            || block.ref_id as usize >= self.counts.len()
+            || block.flag.unmapped()
+            || block.flag.0 & self.config.exclude_flags != 0
+            || block.mapq < self.config.min_mapq
+        {
+            return Ok(());
+        }
+        let reverse = block.flag.reverse_strand();
+        let base = |p: usize| reference.get(p).map(u8::to_ascii_uppercase);
+
+        let modifications = match block.base_modifications() {
+            Ok(modifications) => modifications,
+            Err(_) => {
+                self.malformed += 1;
+                return Ok(());
+            }
+        };
+
+        for m in modifications {
+            if m.canonical != b'C' || m.strand != '+' || m.code != self.config.code {
+                continue;
+            }
+            let p = match m.ref_position {
+                Some(p) => p,
+                None => continue,
+            };
+            let position = if !reverse {
+                if base(p) != Some(b'C') || base(p + 1) != Some(b'G') {
+                    continue;
+                }
+                p
+            } else {
+                if p == 0 || base(p) != Some(b'G') || base(p - 1) != Some(b'C') {
+                    continue;
+                }
+                if self.config.combine_strands {
+                    p - 1
+                } else {
+                    p
+                }
+            };
+            let methylated = if m.probability >= self.config.threshold {
+                1
+            } else if m.probability <= 1.0 - self.config.threshold {
+                0
+            } else {
+                continue;
+            };
+            let entry = self.counts[block.ref_id as usize]
+                .entry(position)
+                .or_insert((0, 0));
+            entry.0 += methylated;
+            entry.1 += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the methylation level of all CpGs with at least one call.
+    /// The metadata column "values" contains the fraction of methylated
+    /// calls (as required by `export_bedgraph`), the columns "methylated"
+    /// and "coverage" the number of calls. If strands are combined, ranges
+    /// cover the CpG dinucleotide, otherwise the C on the given strand.
+    pub fn granges(&self) -> Result<GRanges, Box<dyn Error>> {
+        let width = if self.config.combine_strands { 2 } else { 1 };
+
+        let mut seqnames = Vec::new();
+        let mut from = Vec::new();
+        let mut to = Vec::new();
+        let mut values = Vec::new();
+        let mut methylated = Vec::new();
+        let mut coverage = Vec::new();
+
+        for (i, counts) in self.counts.iter().enumerate() {
+            for (&position, &(m, n)) in counts {
+                seqnames.push(self.genome.seqnames[i].clone());
+                from.push(position);
+                to.push(position + width);
+                values.push(m as f64 / n as f64);
+                methylated.push(m as i64);
+                coverage.push(n as i64);
+            }
+        }
+        let mut granges = GRanges::new(seqnames, from, to, vec![]);
+        granges.meta.add("values", MetaData::FloatArray(values))?;
+        granges
+            .meta
+            .add("methylated", MetaData::IntArray(methylated))?;
+        granges.meta.add("coverage", MetaData::IntArray(coverage))?;
+        Ok(granges)
+    }
+
+    /// Returns a track with the fraction of methylated calls among all calls
+    /// at CpGs within each bin. Bins without calls are set to NaN.
+    ///
+    /// # Errors
+    /// Returns an error if `bin_size` is zero.
+    pub fn track(&self, name: &str, bin_size: usize) -> Result<SimpleTrack, Box<dyn Error>> {
+        if bin_size == 0 {
+            return Err("bin size must be positive".into());
+        }
+        let mut track =
+            SimpleTrack::alloc(name.to_string(), self.genome.clone(), f64::NAN, bin_size);
+
+        for (i, counts) in self.counts.iter().enumerate() {
+            let mut seq = track.get_sequence_mut(&self.genome.seqnames[i])?;
+            let mut bins: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+            for (&position, &(m, n)) in counts {
+                let entry = bins.entry(position / bin_size).or_insert((0, 0));
+                entry.0 += m;
+                entry.1 += n;
+            }
+            for (bin, (m, n)) in bins {
+                if bin < seq.n_bins() {
+                    seq.set_bin(bin, m as f64 / n as f64);
+                }
+            }
+        }
+        Ok(track)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Aggregates the 5mC calls of all records at CpG sites.
+///
+/// # Arguments
+/// * `reader` - The BAM reader, which must read CIGAR, sequence and
+///   auxiliary data.
+/// * `reference` - The reference sequences. Records on sequences missing in
+///   the reference are skipped.
+/// * `config` - Filters and thresholds.
+pub fn bam_cpg_methylation<R: Read>(
+    reader: &mut BamReader<R>,
+    reference: &OrderedStringSet,
+    config: CpGMethylationConfig,
+) -> Result<CpGMethylation, Box<dyn Error>> {
+    let genome = reader.get_genome().clone();
+    let sequences: Vec<Option<&Vec<u8>>> = genome
+        .seqnames
+        .iter()
+        .map(|name| reference.sequences.get(name))
+        .collect();
+
+    let mut methylation = CpGMethylation::new(genome, config);
+
+    while let Some(block) = reader.read_block()? {
+        if block.ref_id < 0 {
+            continue;
+        }
+        if let Some(Some(sequence)) = sequences.get(block.ref_id as usize) {
+            methylation.add(&block, sequence)?;
+        }
+    }
+    Ok(methylation)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use crate::bam::{BamAuxValue, BamBlock};
+    use crate::bam_fixtures::{genome, read};
+    use crate::bam_modifications::{CpGMethylation, CpGMethylationConfig};
+    use crate::track::Track;
+
+    fn modified_read(
+        flag: u16,
+        position: i32,
+        cigar: &str,
+        seq: &str,
+        mm: &str,
+        ml: Vec<u8>,
+    ) -> BamBlock {
+        let mut block = read("r", flag, position, cigar, seq, 30);
+        block.set_auxiliary(b"MM", BamAuxValue::Z(mm.to_string()));
+        block.set_auxiliary(b"ML", BamAuxValue::BUint8(ml));
+        block
+    }
+
+    #[test]
+    fn test_base_modifications() {
+        // C positions: 1, 4, 6; position 4 is inserted
+        let block = modified_read(
+            0x0,
+            10,
+            "4M1I3M",
+            "ACGTCACG",
+            "C+m?,1,0;C+h?,2;",
+            vec![255, 0, 127],
+        );
+        let mods = block.base_modifications().unwrap();
+
+        assert_eq!(mods.len(), 3);
+        assert_eq!((mods[0].query_position, mods[0].ref_position), (4, None));
+        assert_eq!(mods[0].code, "m");
+        assert!(mods[0].probability > 0.99);
+        assert_eq!(
+            (mods[1].query_position, mods[1].ref_position),
+            (6, Some(15))
+        );
+        assert!(mods[1].probability < 0.01);
+        assert_eq!(mods[2].code, "h");
+        assert_eq!(mods[2].query_position, 6);
+        assert!((mods[2].probability - 0.5).abs() < 0.01);
+
+        // Implicit mode reports skipped bases as unmodified
+        let block = modified_read(0x0, 10, "8M", "ACGTCACG", "C+m,1;", vec![200]);
+        let mods = block.base_modifications().unwrap();
+        let calls: Vec<(usize, bool)> = mods
+            .iter()
+            .map(|m| (m.query_position, m.probability > 0.5))
+            .collect();
+        assert_eq!(calls, vec![(1, false), (4, true), (6, false)]);
+
+        // Reverse strand: the original molecule is CGTGACGT
+        let block = modified_read(0x10, 10, "8M", "ACGTCACG", "C+m?,1;", vec![255]);
+        let mods = block.base_modifications().unwrap();
+        assert_eq!(mods.len(), 1);
+        assert_eq!(mods[0].query_position, 2);
+
+        let block = modified_read(0x0, 10, "8M", "ACGTCACG", "C+m?,3;", vec![255]);
+        assert!(block.base_modifications().is_err());
+    }
+
+    #[test]
+    fn test_cpg_methylation() {
+        //                     0123456789
+        let reference = b"TTACGTTCGA";
+        let genome = genome(reference.len());
+
+        let mut methylation = CpGMethylation::new(genome, CpGMethylationConfig::default());
+
+        // Forward read covering both CpGs (C at 3 and 7)
+        methylation
+            .add(
+                &modified_read(0x0, 2, "8M", "ACGTTCGA", "C+m?,0,0;", vec![250, 10]),
+                reference,
+            )
+            .unwrap();
+        // Reverse read, the G of the first CpG at position 4 is the only C of the molecule
+        methylation
+            .add(
+                &modified_read(0x10, 2, "6M", "ACGTTC", "C+m?,0;", vec![250]),
+                reference,
+            )
+            .unwrap();
+
+        let granges = methylation.granges().unwrap();
+        assert_eq!(granges.num_rows(), 2);
+        assert_eq!((granges.ranges[0].from, granges.ranges[0].to), (3, 5));
+        assert_eq!(
+            granges.meta.get_column_int("coverage").unwrap(),
+            &vec![2, 1]
+        );
+        assert_eq!(
+            granges.meta.get_column_float("values").unwrap(),
+            &vec![1.0, 0.0]
+        );
+
+        let track = methylation.track("methylation", 5).unwrap();
+        let seq = track.get_sequence("chr1").unwrap();
+        assert_eq!(seq.at_bin(0), 1.0);
+        assert_eq!(seq.at_bin(1), 0.0);
+        assert!(methylation.track("methylation", 0).is_err());
+    }
+
+    #[test]
+    fn test_cpg_methylation_skips_invalid_records() {
+        let reference = b"TTACGTTCGA";
+        let genome = genome(reference.len());
+
+        let mut methylation = CpGMethylation::new(genome, CpGMethylationConfig::default());
+
+        // The MM tag skips more C bases than the sequence contains
+        methylation
+            .add(
+                &modified_read(0x0, 2, "8M", "ACGTTCGA", "C+m?,5;", vec![250]),
+                reference,
+            )
+            .unwrap();
+        // Supplementary alignments are excluded by default
+        methylation
+            .add(
+                &modified_read(0x800, 2, "8M", "ACGTTCGA", "C+m?,0;", vec![250]),
+                reference,
+            )
+            .unwrap();
+        methylation
+            .add(
+                &modified_read(0x0, 2, "8M", "ACGTTCGA", "C+m?,0;", vec![250]),
+                reference,
+            )
+            .unwrap();
+
+        assert_eq!(methylation.n_malformed(), 1);
+        let granges = methylation.granges().unwrap();
+        assert_eq!(granges.meta.get_column_int("coverage").unwrap(), &vec![1]);
+    }
+}
@@ -0,0 +1,627 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io::{self, Read, Seek};
+use std::rc::Rc;
+
+use futures::executor::block_on_stream;
+
+use crate::bam::{BamBlock, BamReader, CigarBlock};
+use crate::genome::Genome;
+use crate::range::Range;
+
+/* -------------------------------------------------------------------------- */
+
+/// Flags of records that are skipped by default, i.e. unmapped (`0x4`),
+/// secondary (`0x100`), QC-failed (`0x200`) and duplicate (`0x400`) records.
+/// These are the defaults of `samtools mpileup`.
+pub const BAM_PILEUP_EXCLUDE_FLAGS: u16 = 0x704;
+
+/* -------------------------------------------------------------------------- */
+
+/// Filters applied while computing a pileup.
+///
+/// # Fields
+/// - `min_base_quality`: Minimum quality of a base. Bases with lower quality
+///   are not reported. Deletions and skipped regions have no quality and are
+///   always reported.
+/// - `min_mapq`: Minimum mapping quality of a record.
+/// - `include_flags`: Records must have all of these flags set.
+/// - `exclude_flags`: Records with any of these flags set are skipped.
+#[derive(Clone, Debug)]
+pub struct BamPileupConfig {
+    pub min_base_quality: u8,
+    pub min_mapq: u8,
+    pub include_flags: u16,
+    pub exclude_flags: u16,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for BamPileupConfig {
+    fn default() -> Self {
+        BamPileupConfig {
+            min_base_quality: 13,
+            min_mapq: 0,
+            include_flags: 0,
+            exclude_flags: BAM_PILEUP_EXCLUDE_FLAGS,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamPileupConfig {
+    /// Returns `true` if the record passes the flag and mapping quality
+    /// filters. Unmapped records are never accepted.
+    pub fn accept(&self, block: &BamBlock) -> bool {
+        let flag = block.flag.0;
+
+        block.ref_id >= 0
+            && !block.flag.unmapped()
+            && flag & self.include_flags == self.include_flags
+            && flag & self.exclude_flags == 0
+            && block.mapq >= self.min_mapq
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A single record covering a pileup position.
+///
+/// # Fields
+/// - `block`: The record, shared among all columns it covers.
+/// - `query_position`: Position of the base within the stored sequence, or
+///   `None` if the record has a deletion or skipped region at this position.
+/// - `base`: The base as ASCII character in reference orientation, or `*` for
+///   deletions and skipped regions.
+/// - `qual`: Quality of the base, zero for deletions and skipped regions and
+///   `0xff` if the record has no qualities.
+/// - `mapq`: Mapping quality of the record.
+/// - `reverse`: Whether the record is aligned to the reverse strand.
+/// - `is_deletion`: Whether the position is deleted (`D`) in the record.
+/// - `is_refskip`: Whether the position is skipped (`N`), e.g. by an intron.
+/// - `indel`: Length of an insertion (positive) or deletion (negative) that
+///   directly follows this position, and zero otherwise.
+#[derive(Clone, Debug)]
+pub struct BamPileupRead {
+    pub block: Rc<BamBlock>,
+    pub query_position: Option<usize>,
+    pub base: u8,
+    pub qual: u8,
+    pub mapq: u8,
+    pub reverse: bool,
+    pub is_deletion: bool,
+    pub is_refskip: bool,
+    pub indel: i32,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamPileupRead {
+    /// Returns the inserted bases if an insertion follows this position.
+    pub fn inserted_bases(&self) -> Option<String> {
+        match self.query_position {
+            Some(qp) if self.indel > 0 => {
+                let seq = self.block.seq.decode(self.block.l_seq as usize);
+                seq.get(qp + 1..qp + 1 + self.indel as usize)
+                    .map(|s| s.to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// All records covering a single reference position.
+#[derive(Clone, Debug)]
+pub struct BamPileupColumn {
+    pub ref_id: usize,
+    pub seqname: String,
+    pub position: usize,
+    pub reads: Vec<BamPileupRead>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamPileupColumn {
+    /// Returns the number of records with a base or deletion at this
+    /// position. Records with a skipped region are not counted.
+    pub fn depth(&self) -> usize {
+        self.reads.iter().filter(|r| !r.is_refskip).count()
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+// Position of a record's alignment at the current pileup position. Since the
+// pileup only moves forward, the CIGAR operation is advanced incrementally.
+struct BamPileupCursor {
+    block: Rc<BamBlock>,
+    ops: Vec<CigarBlock>,
+    end: usize,
+    idx: usize,
+    ref_start: usize,
+    query_start: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamPileupCursor {
+    fn new(block: BamBlock) -> Self {
+        let ops = block.cigar.parse_cigar().collect();
+        let ref_start = block.position as usize;
+        let end = ref_start + block.cigar.alignment_length();
+        BamPileupCursor {
+            block: Rc::new(block),
+            ops,
+            end,
+            idx: 0,
+            ref_start,
+            query_start: 0,
+        }
+    }
+
+    fn consumes_reference(type_: char) -> bool {
+        matches!(type_, 'M' | 'D' | 'N' | '=' | 'X')
+    }
+
+    fn consumes_query(type_: char) -> bool {
+        matches!(type_, 'M' | 'I' | 'S' | '=' | 'X')
+    }
+
+    fn at(&mut self, position: usize) -> Option<BamPileupRead> {
+        while let Some(op) = self.ops.get(self.idx) {
+            let consumes_reference = Self::consumes_reference(op.type_);
+            if consumes_reference && position < self.ref_start + op.n as usize {
+                break;
+            }
+            if consumes_reference {
+                self.ref_start += op.n as usize;
+            }
+            if Self::consumes_query(op.type_) {
+                self.query_start += op.n as usize;
+            }
+            self.idx += 1;
+        }
+        let op = self.ops.get(self.idx)?;
+        let offset = position.checked_sub(self.ref_start)?;
+
+        let mut read = BamPileupRead {
+            block: self.block.clone(),
+            query_position: None,
+            base: b'*',
+            qual: 0,
+            mapq: self.block.mapq,
+            reverse: self.block.flag.reverse_strand(),
+            is_deletion: false,
+            is_refskip: false,
+            indel: 0,
+        };
+        match op.type_ {
+            'D' => read.is_deletion = true,
+            'N' => read.is_refskip = true,
+            _ => {
+                let qp = self.query_start + offset;
+                let t = b"=ACMGRSVTWYHKDBN";
+                read.query_position = Some(qp);
+                read.base = match self.block.seq.0.get(qp / 2) {
+                    Some(byte) if qp.is_multiple_of(2) => t[(byte >> 4) as usize],
+                    Some(byte) => t[(byte & 0xf) as usize],
+                    None => b'N',
+                };
+                read.qual = self.block.qual.0.get(qp).copied().unwrap_or(0xff);
+
+                // Check for an indel following the last base of this operation
+                if offset + 1 == op.n as usize {
+                    if let Some(next) = self.ops[self.idx + 1..].iter().find(|c| c.type_ != 'P') {
+                        match next.type_ {
+                            'I' => read.indel = next.n,
+                            'D' => read.indel = -next.n,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        Some(read)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Iterator over the pileup of coordinate-sorted BAM records.
+///
+/// For every reference position covered by at least one record that passes
+/// the filters, a `BamPileupColumn` is returned. Positions are visited in
+/// increasing order and records are held in memory only while they overlap
+/// the current position.
+///
+/// # Errors
+/// Yields an `io::Error` of kind `InvalidData` if the records are not sorted
+/// by coordinate.
+pub struct BamPileup<I: Iterator<Item = io::Result<BamBlock>>> {
+    blocks: I,
+    genome: Genome,
+    config: BamPileupConfig,
+    next: Option<BamBlock>,
+    last: Option<(i32, i32)>,
+    active: Vec<BamPileupCursor>,
+    ref_id: usize,
+    seqname: String,
+    position: usize,
+    done: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<I: Iterator<Item = io::Result<BamBlock>>> BamPileup<I> {
+    /// Creates a pileup over a stream of records.
+    ///
+    /// # Arguments
+    /// * `blocks` - Coordinate-sorted records, which must contain CIGAR,
+    ///   sequence and quality data.
+    /// * `genome` - The reference sequences of the records.
+    /// * `config` - Filters applied to records and bases.
+    pub fn new(blocks: I, genome: Genome, config: BamPileupConfig) -> Self {
+        BamPileup {
+            blocks,
+            genome,
+            config,
+            next: None,
+            last: None,
+            active: Vec::new(),
+            ref_id: 0,
+            seqname: String::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the next record that passes the filters.
+    fn fetch(&mut self) -> io::Result<Option<BamBlock>> {
+        for result in self.blocks.by_ref() {
+            let block = result?;
+
+            // Records without reference are placed at the end
+            if block.ref_id < 0 {
+                continue;
+            }
+            if self.last > Some((block.ref_id, block.position)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "BAM records are not sorted by coordinate at `{}`",
+                        block.read_name
+                    ),
+                ));
+            }
+            self.last = Some((block.ref_id, block.position));
+
+            if block.ref_id as usize >= self.genome.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid reference id `{}`", block.ref_id),
+                ));
+            }
+            if self.config.accept(&block) && block.cigar.alignment_length() > 0 {
+                return Ok(Some(block));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Adds all records starting at or before the current position.
+    fn load(&mut self) -> io::Result<()> {
+        loop {
+            if self.next.is_none() {
+                self.next = self.fetch()?;
+            }
+            match &self.next {
+                Some(block)
+                    if block.ref_id as usize == self.ref_id
+                        && block.position as usize <= self.position =>
+                {
+                    let block = self.next.take().unwrap();
+                    self.active.push(BamPileupCursor::new(block));
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn next_column(&mut self) -> io::Result<Option<BamPileupColumn>> {
+        loop {
+            let position = self.position;
+            self.active.retain(|cursor| cursor.end > position);
+
+            // Jump to the start of the next record
+            if self.active.is_empty() {
+                if self.next.is_none() {
+                    self.next = self.fetch()?;
+                }
+                match &self.next {
+                    Some(block) => {
+                        if self.ref_id != block.ref_id as usize || self.seqname.is_empty() {
+                            self.ref_id = block.ref_id as usize;
+                            self.seqname = self.genome.seqnames[self.ref_id].clone();
+                        }
+                        self.position = block.position as usize;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            self.load()?;
+
+            let position = self.position;
+            let min_base_quality = self.config.min_base_quality;
+            let reads: Vec<BamPileupRead> = self
+                .active
+                .iter_mut()
+                .filter_map(|cursor| cursor.at(position))
+                .filter(|read| read.query_position.is_none() || read.qual >= min_base_quality)
+                .collect();
+
+            self.position += 1;
+
+            if !reads.is_empty() {
+                return Ok(Some(BamPileupColumn {
+                    ref_id: self.ref_id,
+                    seqname: self.seqname.clone(),
+                    position,
+                    reads,
+                }));
+            }
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<I: Iterator<Item = io::Result<BamBlock>>> Iterator for BamPileup<I> {
+    type Item = io::Result<BamPileupColumn>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_column() {
+            Ok(Some(column)) => Some(Ok(column)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: Read> BamReader<R> {
+    /// Computes the pileup of all records in a coordinate-sorted BAM file.
+    ///
+    /// CIGAR, sequence and quality data are read regardless of the
+    /// `BamReaderOptions`, which remain unchanged.
+    ///
+    /// # Arguments
+    /// * `config` - Filters applied to records and bases.
+    ///
+    /// # Returns
+    /// An iterator over `io::Result<BamPileupColumn>`.
+    pub fn pileup(
+        &mut self,
+        config: BamPileupConfig,
+    ) -> BamPileup<impl Iterator<Item = io::Result<BamBlock>> + '_> {
+        let mut options = *self.get_options();
+        options.read_cigar = true;
+        options.read_sequence = true;
+        options.read_qual = true;
+
+        let genome = self.get_genome().clone();
+
+        BamPileup::new(
+            std::iter::from_fn(move || self.read_block_with_options(&options).transpose()),
+            genome,
+            config,
+        )
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: Read + Seek> BamReader<R> {
+    /// Computes the pileup of a genomic region using the index of the BAM
+    /// file (see `set_index`). As for `pileup`, CIGAR, sequence and quality
+    /// data are read regardless of the `BamReaderOptions`.
+    ///
+    /// # Arguments
+    /// * `seqname` - Name of the reference sequence.
+    /// * `range` - The 0-based, half-open region on the reference sequence.
+    /// * `config` - Filters applied to records and bases.
+    ///
+    /// # Returns
+    /// An iterator over `io::Result<BamPileupColumn>` with all columns
+    /// within the region.
+    pub fn pileup_region<'a>(
+        &'a mut self,
+        seqname: &'a str,
+        range: Range,
+        config: BamPileupConfig,
+    ) -> impl Iterator<Item = io::Result<BamPileupColumn>> + 'a {
+        let mut options = *self.get_options();
+        options.read_cigar = true;
+        options.read_sequence = true;
+        options.read_qual = true;
+
+        let genome = self.get_genome().clone();
+        let blocks = block_on_stream(Box::pin(
+            self.query_stream_with_options(seqname, range, options),
+        ))
+        .map(|r| r.map(|r| r.block));
+
+        BamPileup::new(blocks, genome, config).filter(move |column| match column {
+            Ok(column) => column.position >= range.from && column.position < range.to,
+            Err(_) => true,
+        })
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bam::{BamBlock, BamHeader, BamReader, BamReaderOptions, BamWriter};
+    use crate::bam_fixtures::{genome, read};
+    use crate::bam_pileup::{BamPileup, BamPileupColumn, BamPileupConfig};
+    use crate::genome::Genome;
+
+    fn pileup(blocks: Vec<BamBlock>, config: BamPileupConfig) -> Vec<BamPileupColumn> {
+        BamPileup::new(blocks.into_iter().map(Ok), genome(100), config)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    fn bases(column: &BamPileupColumn) -> String {
+        column.reads.iter().map(|r| r.base as char).collect()
+    }
+
+    #[test]
+    fn test_pileup() {
+        let blocks = vec![
+            read("a", 0x0, 10, "2S3M2I2M1D2M", "NNACGTTGTCA", 30),
+            read("b", 0x10, 12, "2M3N2M", "GCAT", 30),
+            read("c", 0x0, 12, "4M", "GAAA", 5),
+            read("d", 0x400, 12, "4M", "GGGG", 30),
+            read("e", 0x0, 40, "2M", "CC", 30),
+        ];
+        let columns = pileup(blocks, BamPileupConfig::default());
+
+        let positions: Vec<usize> = columns.iter().map(|c| c.position).collect();
+        assert_eq!(positions, vec![10, 11, 12, 13, 14, 15, 16, 17, 18, 40, 41]);
+
+        // `c` has low base quality and `d` is a duplicate
+        assert_eq!(bases(&columns[2]), "GG");
+        assert!(columns.iter().all(|c| c.seqname == "chr1"));
+
+        // Insertion after position 12 in `a`
+        assert_eq!(columns[2].reads[0].indel, 2);
+        assert_eq!(columns[2].reads[0].inserted_bases().unwrap(), "TT");
+        assert_eq!(columns[2].reads[0].query_position, Some(4));
+
+        // Deletion at position 15 in `a`, skipped region in `b`
+        assert_eq!(columns[4].reads[0].indel, -1);
+        assert!(columns[5].reads[0].is_deletion);
+        assert!(columns[5].reads[1].is_refskip);
+        assert_eq!(columns[5].depth(), 1);
+        assert_eq!(bases(&columns[6]), "C*");
+        assert_eq!(bases(&columns[7]), "AA");
+        assert!(columns[7].reads[1].reverse);
+        assert_eq!(bases(&columns[8]), "T");
+    }
+
+    #[test]
+    fn test_pileup_filters() {
+        let blocks = vec![
+            read("a", 0x1 | 0x40, 10, "2M", "AC", 30),
+            read("b", 0x1 | 0x80, 10, "2M", "GT", 10),
+            read("c", 0x0, 10, "2M", "TT", 30),
+        ];
+        let config = BamPileupConfig {
+            min_base_quality: 0,
+            include_flags: 0x1,
+            exclude_flags: 0x80,
+            ..Default::default()
+        };
+        let columns = pileup(blocks, config);
+
+        assert_eq!(columns.len(), 2);
+        assert_eq!(bases(&columns[0]), "A");
+        assert_eq!(bases(&columns[1]), "C");
+    }
+
+    #[test]
+    fn test_pileup_unsorted() {
+        let blocks = vec![
+            read("a", 0x0, 20, "2M", "AC", 30),
+            read("b", 0x0, 10, "2M", "GT", 30),
+        ];
+        let result = BamPileup::new(
+            blocks.into_iter().map(Ok),
+            genome(100),
+            BamPileupConfig::default(),
+        )
+        .collect::<Result<Vec<_>, _>>();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_pileup_reader() {
+        let genome = Genome::new(vec!["chr1".to_string(), "chr2".to_string()], vec![100, 100]);
+        let mut blocks = vec![
+            read("a", 0x0, 10, "3M", "ACG", 30),
+            read("b", 0x0, 5, "2M", "TT", 30),
+        ];
+        blocks[1].ref_id = 1;
+
+        let mut writer = BamWriter::new(Vec::new(), &BamHeader::default(), &genome).unwrap();
+        for block in &blocks {
+            writer.write_block(block).unwrap();
+        }
+        let buffer = writer.finish().unwrap();
+
+        // Sequences are read for the pileup without changing the options
+        let options = BamReaderOptions {
+            read_cigar: false,
+            read_sequence: false,
+            read_qual: false,
+            ..Default::default()
+        };
+        let mut reader = BamReader::new(Cursor::new(buffer), Some(options)).unwrap();
+        let columns = reader
+            .pileup(BamPileupConfig::default())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(!reader.get_options().read_sequence);
+        assert_eq!(columns[0].reads[0].base, b'A');
+
+        let sites: Vec<(String, usize)> = columns
+            .iter()
+            .map(|c| (c.seqname.clone(), c.position))
+            .collect();
+        assert_eq!(
+            sites,
+            vec![
+                ("chr1".to_string(), 10),
+                ("chr1".to_string(), 11),
+                ("chr1".to_string(), 12),
+                ("chr2".to_string(), 5),
+                ("chr2".to_string(), 6),
+            ]
+        );
+    }
+}
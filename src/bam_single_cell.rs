@@ -0,0 +1,488 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use flate2::read::MultiGzDecoder;
+use futures::executor::block_on_stream;
+
+use crate::bam::BamFile;
+use crate::bgzf::BgzfWriter;
+use crate::coverage::{CoverageConfig, OptionCoverage};
+use crate::genome::Genome;
+use crate::log;
+use crate::read::Read;
+use crate::track_generic::GenericMutableTrack;
+use crate::track_simple::SimpleTrack;
+
+/* -------------------------------------------------------------------------- */
+
+/// Imports cell barcodes from a text file with one barcode per line (e.g. the
+/// `barcodes.tsv.gz` of Cell Ranger). An optional second column assigns each
+/// barcode to a group, e.g. a cluster. Barcodes without group are assigned to
+/// a group named after the barcode itself. Empty lines and lines starting with
+/// `#` are skipped.
+///
+/// # Returns
+/// A map from barcodes to groups.
+///
+/// # Errors
+/// Returns an error if the file cannot be read.
+pub fn import_barcodes(filename: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let file = File::open(filename)?;
+    let reader: Box<dyn BufRead> = if filename.ends_with(".gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut barcodes = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let barcode = match fields.next() {
+            Some(barcode) if !barcode.starts_with('#') => barcode,
+            _ => continue,
+        };
+        let group = fields.next().unwrap_or(barcode);
+        barcodes.insert(barcode.to_string(), group.to_string());
+    }
+    Ok(barcodes)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes a separate coverage track for each group of cells.
+///
+/// Each read is assigned to the group of its cell barcode (`CB` tag). Reads
+/// without cell barcode or with a barcode missing in `groups` are skipped.
+/// Since a full-genome track is allocated for every group, `groups` must list
+/// the cells explicitly; map each barcode to itself to obtain one track per
+/// cell. Tracks are normalized individually if `config.normalize_track` is
+/// `cpm` or `rpkm`.
+///
+/// # Arguments
+/// * `reads` - The reads, which are added without further filtering.
+/// * `genome` - The genome of the tracks.
+/// * `groups` - A map from cell barcodes to groups.
+/// * `fraglen` - Length to which single-end reads are extended.
+/// * `config` - Binning, splicing and normalization of the tracks.
+///
+/// # Returns
+/// A map from group names to coverage tracks.
+///
+/// # Errors
+/// Returns an error if `groups` is empty.
+pub fn coverage_by_group(
+    reads: impl Iterator<Item = Read>,
+    genome: &Genome,
+    groups: &HashMap<String, String>,
+    fraglen: usize,
+    config: &CoverageConfig,
+) -> Result<BTreeMap<String, SimpleTrack>, Box<dyn Error>> {
+    if groups.is_empty() {
+        return Err("no cell barcodes given for computing coverage by group".into());
+    }
+    let mut tracks: BTreeMap<String, (SimpleTrack, usize)> = BTreeMap::new();
+
+    for read in reads {
+        let group = match read.cell_barcode() {
+            Some(barcode) => match groups.get(barcode) {
+                Some(group) => group.as_str(),
+                None => continue,
+            },
+            None => continue,
+        };
+        if !tracks.contains_key(group) {
+            let track = SimpleTrack::alloc(
+                group.to_string(),
+                genome.clone(),
+                config.initial_value,
+                config.bin_size,
+            );
+            tracks.insert(group.to_string(), (track, 0));
+        }
+        let (track, n) = tracks.get_mut(group).unwrap();
+        let mut track = GenericMutableTrack::wrap(track);
+
+        *n += if config.spliced {
+            track.add_spliced_reads(
+                std::iter::once(read),
+                &config.binning_method,
+                config.count_deletions,
+            )
+        } else {
+            track.add_reads(std::iter::once(read), fraglen, &config.binning_method)
+        };
+    }
+
+    let mut result = BTreeMap::new();
+
+    for (group, (mut track, n)) in tracks {
+        let c = match config.normalize_track.as_str() {
+            "rpkm" => 1_000_000.0 / (n as f64 * config.bin_size as f64),
+            "cpm" => 1_000_000.0 / n as f64,
+            _ => 1.0,
+        };
+        if c != 1.0 {
+            GenericMutableTrack::wrap(&mut track).map(|_name, _i, x| c * x)?;
+        }
+        result.insert(group, track);
+    }
+    Ok(result)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes coverage tracks per cell or per group of cells from a single-cell
+/// BAM file.
+///
+/// Reads are filtered according to the options (see `bam_coverage`). If the
+/// options contain no barcode whitelist, the barcodes of `groups` are used as
+/// whitelist.
+///
+/// # Arguments
+/// * `filename` - The BAM file with `CB` (and optionally `UB`) tags.
+/// * `groups` - A map from cell barcodes to groups, see `import_barcodes`.
+///   Must not be empty.
+/// * `fraglen` - Length to which single-end reads are extended.
+/// * `options` - Coverage options.
+///
+/// # Returns
+/// A map from group names to coverage tracks.
+pub fn bam_coverage_by_group(
+    filename: &str,
+    groups: &HashMap<String, String>,
+    fraglen: usize,
+    options: Vec<OptionCoverage>,
+) -> Result<BTreeMap<String, SimpleTrack>, Box<dyn Error>> {
+    if groups.is_empty() {
+        return Err("no cell barcodes given for computing coverage by group".into());
+    }
+    let mut config = CoverageConfig::default();
+
+    for option in options {
+        config.insert_option(option);
+    }
+    if config.filter_barcodes.is_empty() {
+        config.filter_barcodes = groups.keys().cloned().collect();
+    }
+
+    log!(config.logger, "Reading tags from `{}`", filename);
+    let mut bam = BamFile::open(filename, Some(config.bam_reader_options()))?;
+    let genome = bam.reader.get_genome().clone();
+
+    let reads = config.read_stream(&mut bam.reader);
+    let reads = config.filter_read_stream(reads);
+
+    let mut err_opt = None;
+    let reads_iter = block_on_stream(reads).map_while(|item| match item {
+        Ok(read) => Some(read),
+        Err(err) => {
+            err_opt = Some(err);
+            None
+        }
+    });
+
+    let tracks = coverage_by_group(reads_iter, &genome, groups, fraglen, &config)?;
+
+    if let Some(err) = err_opt {
+        return Err(Box::new(err));
+    }
+    log!(config.logger, "Computed coverage of {} groups", tracks.len());
+
+    Ok(tracks)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Writes fragments of paired-end reads in the fragment file format of Cell
+/// Ranger ATAC, i.e. tab-separated lines with sequence name, start, end, cell
+/// barcode and the number of read pairs supporting the fragment.
+///
+/// Identical fragments of a cell are collapsed. Reads that are not paired or
+/// have no cell barcode (`CB` tag) are skipped. Fragments are buffered and
+/// sorted per sequence, so reads must be grouped by sequence name, as in
+/// coordinate-sorted BAM files.
+///
+/// # Arguments
+/// * `writer` - The output.
+/// * `reads` - Joined read pairs.
+/// * `tn5_shift` - If `true`, the fragment start is shifted by +4 and the end
+///   by -5 to account for the Tn5 insertion.
+///
+/// # Returns
+/// The number of written fragments.
+pub fn write_fragments<W: Write>(
+    writer: &mut W,
+    reads: impl Iterator<Item = Read>,
+    tn5_shift: bool,
+) -> io::Result<usize> {
+    let mut seqname = String::new();
+    let mut fragments: BTreeMap<(usize, usize, String), usize> = BTreeMap::new();
+    let mut n = 0;
+
+    let mut flush =
+        |writer: &mut W, seqname: &str, fragments: &mut BTreeMap<_, _>| -> io::Result<()> {
+            for ((from, to, barcode), count) in std::mem::take(fragments) {
+                writeln!(writer, "{}\t{}\t{}\t{}\t{}", seqname, from, to, barcode, count)?;
+                n += 1;
+            }
+            Ok(())
+        };
+
+    for read in reads {
+        if !read.paired_end {
+            continue;
+        }
+        let barcode = match read.cell_barcode() {
+            Some(barcode) => barcode.to_string(),
+            None => continue,
+        };
+        let (from, to) = if tn5_shift {
+            (read.range.from + 4, read.range.to.saturating_sub(5))
+        } else {
+            (read.range.from, read.range.to)
+        };
+        if to <= from {
+            continue;
+        }
+        if read.seqname != seqname {
+            flush(writer, &seqname, &mut fragments)?;
+            seqname = read.seqname.clone();
+        }
+        *fragments.entry((from, to, barcode)).or_insert(0) += 1;
+    }
+    flush(writer, &seqname, &mut fragments)?;
+
+    Ok(n)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Exports the fragments of a paired-end single-cell BAM file, see
+/// `write_fragments`.
+///
+/// Read pairs are joined and filtered according to the options (see
+/// `bam_coverage`). Files ending in `.gz` are BGZF compressed, so that they
+/// can be indexed with tabix.
+///
+/// # Returns
+/// The number of written fragments.
+pub fn bam_export_fragments(
+    filename_in: &str,
+    filename_out: &str,
+    tn5_shift: bool,
+    options: Vec<OptionCoverage>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut config = CoverageConfig::default();
+
+    for option in options {
+        config.insert_option(option);
+    }
+
+    log!(config.logger, "Reading tags from `{}`", filename_in);
+    // Cell barcodes are always required
+    let mut bam_options = config.bam_reader_options();
+    bam_options.read_auxiliary = true;
+
+    let mut bam = BamFile::open(filename_in, Some(bam_options))?;
+
+    let reads = Box::pin(bam.reader.read_simple_stream(true, false));
+    let reads = config.filter_read_stream(reads);
+
+    let mut err_opt = None;
+    let reads_iter = block_on_stream(reads).map_while(|item| match item {
+        Ok(read) => Some(read),
+        Err(err) => {
+            err_opt = Some(err);
+            None
+        }
+    });
+
+    let file = File::create(filename_out)?;
+    let n = if filename_out.ends_with(".gz") {
+        let mut writer = BgzfWriter::new(BufWriter::new(file));
+        let n = write_fragments(&mut writer, reads_iter, tn5_shift)?;
+        writer.finish()?.flush()?;
+        n
+    } else {
+        let mut writer = BufWriter::new(file);
+        let n = write_fragments(&mut writer, reads_iter, tn5_shift)?;
+        writer.flush()?;
+        n
+    };
+
+    if let Some(err) = err_opt {
+        return Err(Box::new(err));
+    }
+    log!(config.logger, "Wrote {} fragments to `{}`", n, filename_out);
+
+    Ok(n)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use futures::executor::block_on_stream;
+    use futures::stream;
+
+    use crate::bam::BamAuxValue;
+    use crate::bam_fixtures::mapped_read;
+    use crate::bam_single_cell::{coverage_by_group, write_fragments};
+    use crate::coverage::CoverageConfig;
+    use crate::genome::Genome;
+    use crate::read::Read;
+    use crate::read_stream::ReadStream;
+    use crate::track::Track;
+
+    fn cell_read(seqname: &str, from: usize, to: usize, strand: char, cb: &str, ub: &str) -> Read {
+        let auxiliary = [(b"CB", cb), (b"UB", ub)]
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(tag, value)| (tag, BamAuxValue::Z(value.to_string())))
+            .collect();
+        mapped_read(seqname, from, to, strand, 0x1, auxiliary)
+    }
+
+    #[test]
+    fn test_filter_barcodes_and_umi_duplicates() {
+        let mut reads = vec![
+            cell_read("chr1", 10, 20, '+', "AAA", "U1"),
+            cell_read("chr1", 10, 25, '+', "AAA", "U1"),
+            cell_read("chr1", 10, 20, '+', "AAA", "U2"),
+            cell_read("chr1", 10, 20, '+', "CCC", "U1"),
+            cell_read("chr1", 5, 20, '-', "AAA", "U1"),
+            cell_read("chr1", 8, 20, '-', "AAA", "U1"),
+            cell_read("chr1", 10, 20, '+', "GGG", "U1"),
+            cell_read("chr1", 10, 20, '+', "", ""),
+            cell_read("chr2", 10, 20, '+', "AAA", "U1"),
+        ];
+        for r in reads.iter_mut() {
+            r.paired_end = false;
+        }
+        // Read pairs are ordered by their second mate, and duplicates must share both ends
+        reads.insert(8, cell_read("chr1", 12, 40, '*', "AAA", "U3"));
+        reads.insert(9, cell_read("chr1", 30, 35, '*', "CCC", "U3"));
+        reads.insert(10, cell_read("chr1", 12, 40, '*', "AAA", "U3"));
+        reads.insert(11, cell_read("chr1", 12, 45, '*', "AAA", "U3"));
+        let whitelist: HashSet<String> = ["AAA", "CCC"].iter().map(|s| s.to_string()).collect();
+
+        let reads = Box::pin(stream::iter(reads.into_iter().map(Ok)));
+        let reads = ReadStream::filter_barcodes(reads, None, &whitelist);
+        let reads = ReadStream::filter_umi_duplicates(reads, None, true);
+
+        let result: Vec<(String, usize, usize, String)> = block_on_stream(reads)
+            .map(|r| r.unwrap())
+            .map(|r| {
+                let cb = r.cell_barcode().unwrap().to_string();
+                (r.seqname, r.range.from, r.range.to, cb)
+            })
+            .collect();
+
+        assert_eq!(
+            result,
+            vec![
+                ("chr1".to_string(), 10, 20, "AAA".to_string()),
+                ("chr1".to_string(), 10, 20, "AAA".to_string()),
+                ("chr1".to_string(), 10, 20, "CCC".to_string()),
+                ("chr1".to_string(), 5, 20, "AAA".to_string()),
+                ("chr1".to_string(), 12, 40, "AAA".to_string()),
+                ("chr1".to_string(), 30, 35, "CCC".to_string()),
+                ("chr1".to_string(), 12, 45, "AAA".to_string()),
+                ("chr2".to_string(), 10, 20, "AAA".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coverage_by_group() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![100]);
+        let reads = vec![
+            cell_read("chr1", 0, 20, '+', "AAA", ""),
+            cell_read("chr1", 10, 30, '+', "CCC", ""),
+            cell_read("chr1", 50, 60, '+', "GGG", ""),
+            cell_read("chr1", 50, 60, '+', "TTT", ""),
+        ];
+        let groups: HashMap<String, String> = [("AAA", "c1"), ("CCC", "c1"), ("GGG", "c2")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let tracks = coverage_by_group(
+            reads.clone().into_iter(),
+            &genome,
+            &groups,
+            0,
+            &CoverageConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(tracks.keys().collect::<Vec<_>>(), vec!["c1", "c2"]);
+        let seq = tracks["c1"].get_sequence("chr1").unwrap();
+        assert_eq!(
+            (0..4).map(|i| seq.at_bin(i)).collect::<Vec<f64>>(),
+            vec![1.0, 2.0, 1.0, 0.0]
+        );
+        assert_eq!(tracks["c2"].get_sequence("chr1").unwrap().at_bin(5), 1.0);
+
+        assert!(coverage_by_group(
+            reads.into_iter(),
+            &genome,
+            &HashMap::new(),
+            0,
+            &CoverageConfig::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_write_fragments() {
+        let mut single_end = cell_read("chr1", 0, 50, '+', "AAA", "");
+        single_end.paired_end = false;
+        let reads = vec![
+            cell_read("chr1", 100, 200, '*', "CCC", ""),
+            cell_read("chr1", 10, 80, '*', "AAA", ""),
+            cell_read("chr1", 100, 200, '*', "CCC", ""),
+            cell_read("chr1", 100, 200, '*', "AAA", ""),
+            cell_read("chr1", 0, 50, '*', "", ""),
+            single_end,
+            cell_read("chr2", 20, 40, '*', "AAA", ""),
+        ];
+
+        let mut buffer = Vec::new();
+        let n = write_fragments(&mut buffer, reads.into_iter(), true).unwrap();
+
+        assert_eq!(n, 4);
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "chr1\t14\t75\tAAA\t1\n\
+             chr1\t104\t195\tAAA\t1\n\
+             chr1\t104\t195\tCCC\t2\n\
+             chr2\t24\t35\tAAA\t1\n"
+        );
+    }
+}
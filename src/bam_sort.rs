@@ -0,0 +1,543 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use flate2::Compression;
+
+use crate::bam::{
+    BamAuxValue, BamAuxiliary, BamBlock, BamFile, BamHeader, BamReader, BamReaderOptions, BamWriter,
+};
+use crate::genome::Genome;
+
+/* -------------------------------------------------------------------------- */
+
+/// Order of records in a sorted BAM file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BamSortOrder {
+    /// Sort by reference id, position and strand. Unmapped reads without
+    /// reference are placed at the end.
+    Coordinate,
+    /// Sort by read name, with first mates before second mates.
+    QueryName,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamSortOrder {
+    /// Returns the value of the `SO` tag in the `@HD` header line.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BamSortOrder::Coordinate => "coordinate",
+            BamSortOrder::QueryName => "queryname",
+        }
+    }
+
+    /// Compares two records with respect to the sort order.
+    pub fn compare(&self, a: &BamBlock, b: &BamBlock) -> Ordering {
+        match self {
+            BamSortOrder::Coordinate => (a.ref_id as u32)
+                .cmp(&(b.ref_id as u32))
+                .then(a.position.cmp(&b.position))
+                .then(a.flag.reverse_strand().cmp(&b.flag.reverse_strand())),
+            BamSortOrder::QueryName => a
+                .read_name
+                .cmp(&b.read_name)
+                .then((a.flag.0 & 0xc0).cmp(&(b.flag.0 & 0xc0))),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for BamSortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for BamSortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "coordinate" => Ok(BamSortOrder::Coordinate),
+            "queryname" => Ok(BamSortOrder::QueryName),
+            _ => Err(format!("invalid sort order `{}`", s)),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Configuration of `bam_sort`.
+///
+/// # Fields
+/// - `order`: The sort order.
+/// - `memory_limit`: Approximate number of bytes used for records held in
+///   memory. If exceeded, records are sorted and spilled to a temporary file.
+/// - `tmp_dir`: Directory for temporary files.
+/// - `threads`: Number of threads used for BGZF decompression of the input.
+#[derive(Clone, Debug)]
+pub struct BamSortConfig {
+    pub order: BamSortOrder,
+    pub memory_limit: usize,
+    pub tmp_dir: PathBuf,
+    pub threads: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for BamSortConfig {
+    fn default() -> Self {
+        BamSortConfig {
+            order: BamSortOrder::Coordinate,
+            memory_limit: 768 * 1024 * 1024,
+            tmp_dir: std::env::temp_dir(),
+            threads: 1,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Approximate number of bytes a record occupies in memory.
+fn bam_block_memory(block: &BamBlock) -> usize {
+    let aux: usize = block
+        .auxiliary
+        .iter()
+        .map(|aux| match &aux.value {
+            BamAuxValue::Z(s) | BamAuxValue::H(s) => s.len(),
+            BamAuxValue::BInt8(v) => v.len(),
+            BamAuxValue::BUint8(v) => v.len(),
+            BamAuxValue::BInt16(v) => 2 * v.len(),
+            BamAuxValue::BUint16(v) => 2 * v.len(),
+            BamAuxValue::BInt32(v) => 4 * v.len(),
+            BamAuxValue::BUint32(v) => 4 * v.len(),
+            BamAuxValue::BFloat32(v) => 4 * v.len(),
+            _ => 0,
+        })
+        .sum();
+
+    std::mem::size_of::<BamBlock>()
+        + block.read_name.len()
+        + 4 * block.cigar.0.len()
+        + block.seq.0.len()
+        + block.qual.0.len()
+        + block.auxiliary.len() * std::mem::size_of::<BamAuxiliary>()
+        + aux
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Counter for unique names of temporary files within this process.
+static BAM_SORT_CHUNK_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary files that are removed when dropped.
+struct BamSortChunks {
+    filenames: Vec<PathBuf>,
+}
+
+impl BamSortChunks {
+    /// Creates a new temporary file in `dir`. Files are created exclusively,
+    /// so that concurrent sorts never share or overwrite chunks and existing
+    /// files or symlinks are never followed.
+    fn create(&mut self, dir: &Path) -> io::Result<File> {
+        loop {
+            let filename = dir.join(format!(
+                "rustynetics-sort-{}-{}.bam",
+                process::id(),
+                BAM_SORT_CHUNK_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+            ));
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&filename)
+            {
+                Ok(file) => {
+                    self.filenames.push(filename);
+                    return Ok(file);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for BamSortChunks {
+    fn drop(&mut self) {
+        for filename in &self.filenames {
+            let _ = fs::remove_file(filename);
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Smallest unmerged record of a sorted input (a temporary chunk or an input
+/// file), ordered for use in a `BinaryHeap`.
+pub(crate) struct BamMergeEntry {
+    pub(crate) block: BamBlock,
+    pub(crate) chunk: usize,
+    pub(crate) order: BamSortOrder,
+}
+
+impl PartialEq for BamMergeEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for BamMergeEntry {}
+
+impl PartialOrd for BamMergeEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BamMergeEntry {
+    // Reversed, so that the max-heap returns the smallest record. Ties are
+    // broken by chunk index to keep the sort stable.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order
+            .compare(&other.block, &self.block)
+            .then(other.chunk.cmp(&self.chunk))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns a copy of `header` where the `SO` tag of the `@HD` line is set to
+/// the given sort order.
+///
+/// # Errors
+/// Returns an error if the header text cannot be parsed.
+pub fn bam_sort_header(
+    header: &BamHeader,
+    order: BamSortOrder,
+) -> Result<BamHeader, Box<dyn Error>> {
+    let mut sam_header = header.parse()?;
+    sam_header.set_sort_order(order.as_str());
+
+    let mut result = header.clone();
+    result.set_sam_header(&sam_header);
+
+    Ok(result)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Sorts all records of `reader` and writes them to `writer`.
+///
+/// Records are collected in memory until `config.memory_limit` is reached.
+/// Each full buffer is sorted and written to a temporary BAM file in
+/// `config.tmp_dir`, and all temporary files are merged in a final pass.
+/// The reader must be created with options that read all fields of a
+/// record, otherwise the missing fields are lost.
+///
+/// # Arguments
+/// * `reader` - The input BAM reader.
+/// * `writer` - The output BAM writer, which already contains the header.
+/// * `config` - The sort configuration.
+///
+/// # Errors
+/// Returns an error if reading the input or writing temporary or output
+/// files fails.
+pub fn bam_sort<R: Read, W: Write>(
+    reader: &mut BamReader<R>,
+    writer: &mut BamWriter<W>,
+    config: &BamSortConfig,
+) -> Result<(), Box<dyn Error>> {
+    let header = reader.get_header().clone();
+    let genome = reader.get_genome().clone();
+    let order = config.order;
+
+    let mut chunks = BamSortChunks {
+        filenames: Vec::new(),
+    };
+    let mut buffer = Vec::new();
+    let mut memory = 0;
+
+    while let Some(block) = reader.read_block()? {
+        memory += bam_block_memory(&block);
+        buffer.push(block);
+
+        if memory >= config.memory_limit {
+            let file = chunks.create(&config.tmp_dir)?;
+            write_chunk(file, &header, &genome, &mut buffer, order)?;
+            memory = 0;
+        }
+    }
+
+    buffer.sort_by(|a, b| order.compare(a, b));
+
+    if chunks.filenames.is_empty() {
+        for block in &buffer {
+            writer.write_block(block)?;
+        }
+        return Ok(());
+    }
+
+    // Remaining records form the last chunk, which is kept in memory
+    let mut readers = Vec::with_capacity(chunks.filenames.len());
+    for filename in &chunks.filenames {
+        let file = BufReader::new(File::open(filename)?);
+        readers.push(BamReader::new(file, None)?);
+    }
+    let mut memory_chunk = buffer.into_iter();
+
+    let mut heap = BinaryHeap::with_capacity(readers.len() + 1);
+    for (chunk, reader) in readers.iter_mut().enumerate() {
+        if let Some(block) = reader.read_block()? {
+            heap.push(BamMergeEntry {
+                block,
+                chunk,
+                order,
+            });
+        }
+    }
+    if let Some(block) = memory_chunk.next() {
+        heap.push(BamMergeEntry {
+            block,
+            chunk: readers.len(),
+            order,
+        });
+    }
+
+    while let Some(entry) = heap.pop() {
+        writer.write_block(&entry.block)?;
+
+        let next = if entry.chunk < readers.len() {
+            readers[entry.chunk].read_block()?
+        } else {
+            memory_chunk.next()
+        };
+        if let Some(block) = next {
+            heap.push(BamMergeEntry {
+                block,
+                chunk: entry.chunk,
+                order,
+            });
+        }
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn write_chunk(
+    file: File,
+    header: &BamHeader,
+    genome: &Genome,
+    buffer: &mut Vec<BamBlock>,
+    order: BamSortOrder,
+) -> Result<(), Box<dyn Error>> {
+    buffer.sort_by(|a, b| order.compare(a, b));
+
+    // Temporary files are read only once, so favor speed over size
+    let file = BufWriter::new(file);
+    let mut writer = BamWriter::with_compression(file, header, genome, Compression::fast())?;
+    for block in buffer.drain(..) {
+        writer.write_block(&block)?;
+    }
+    writer.finish()?.flush()?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Sorts the BAM file `input` and writes the result to `output`. The `SO`
+/// tag of the header is updated accordingly.
+///
+/// # Arguments
+/// * `input` - The file path or URL of the input BAM file.
+/// * `output` - The file path of the sorted BAM file.
+/// * `config` - The sort configuration.
+/// * `command_line` - If given, a `@PG` line with this command line is added
+///   to the header of the output file.
+///
+/// # Errors
+/// Returns an error if the input cannot be read or the output cannot be
+/// written.
+pub fn bam_sort_file(
+    input: &str,
+    output: &str,
+    config: &BamSortConfig,
+    command_line: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let options = BamReaderOptions::with_threads(config.threads);
+    let mut bam = BamFile::open(input, Some(options))?;
+
+    let mut header = bam_sort_header(bam.reader.get_header(), config.order)?;
+    if let Some(command_line) = command_line {
+        let mut sam_header = header.parse()?;
+        sam_header.add_program(
+            "bam-sort",
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(command_line),
+        );
+        header.set_sam_header(&sam_header);
+    }
+    let genome = bam.reader.get_genome().clone();
+
+    let mut writer = BamFile::create(output, &header, &genome)?;
+    bam_sort(&mut bam.reader, &mut writer, config)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+
+    use crate::bam::{BamBlock, BamFile, BamReader, BamWriter};
+    use crate::bam_index::{BamIndex, BAI_DEPTH, BAI_MIN_SHIFT};
+    use crate::bam_sort::{bam_sort, bam_sort_file, bam_sort_header, BamSortConfig, BamSortOrder};
+
+    fn sort_test_bam(config: &BamSortConfig) -> (Vec<u8>, Vec<BamBlock>) {
+        let mut bam = BamFile::open("tests/test_bam_2.bam", None).unwrap();
+        let header = bam_sort_header(bam.reader.get_header(), config.order).unwrap();
+        let genome = bam.reader.get_genome().clone();
+
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        bam_sort(&mut bam.reader, &mut writer, config).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(Cursor::new(bytes.clone()), None).unwrap();
+        let blocks = reader.read_single_end().map(|r| r.unwrap().block).collect();
+        (bytes, blocks)
+    }
+
+    #[test]
+    fn test_bam_sort_coordinate() {
+        let config = BamSortConfig::default();
+        let (bytes, blocks) = sort_test_bam(&config);
+
+        assert_eq!(blocks.len(), 4964);
+        for pair in blocks.windows(2) {
+            assert_ne!(
+                BamSortOrder::Coordinate.compare(&pair[0], &pair[1]),
+                Ordering::Greater
+            );
+        }
+        let reader = BamReader::new(Cursor::new(bytes.clone()), None).unwrap();
+        let header = reader.get_header().parse().unwrap();
+        assert_eq!(header.sort_order(), Some("coordinate"));
+
+        // The result can be indexed
+        assert!(BamIndex::build(Cursor::new(bytes), BAI_MIN_SHIFT, BAI_DEPTH).is_ok());
+
+        // Spilling to temporary files gives the same result
+        let config = BamSortConfig {
+            memory_limit: 64 * 1024,
+            ..Default::default()
+        };
+        let (_, blocks_spilled) = sort_test_bam(&config);
+
+        assert_eq!(blocks.len(), blocks_spilled.len());
+        for (a, b) in blocks.iter().zip(blocks_spilled.iter()) {
+            assert_eq!(a.read_name, b.read_name);
+            assert_eq!(a.flag.0, b.flag.0);
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.seq.0, b.seq.0);
+            assert_eq!(a.qual.0, b.qual.0);
+        }
+    }
+
+    #[test]
+    fn test_bam_sort_concurrent() {
+        let config = BamSortConfig {
+            memory_limit: 64 * 1024,
+            ..Default::default()
+        };
+        // Concurrent sorts in the same process must not share temporary files
+        let (a, b) = std::thread::scope(|s| {
+            let a = s.spawn(|| sort_test_bam(&config).1);
+            let b = s.spawn(|| sort_test_bam(&config).1);
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        assert_eq!(a.len(), 4964);
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.read_name, b.read_name);
+            assert_eq!(a.position, b.position);
+        }
+    }
+
+    #[test]
+    fn test_bam_sort_queryname() {
+        let config = BamSortConfig {
+            order: BamSortOrder::QueryName,
+            memory_limit: 64 * 1024,
+            ..Default::default()
+        };
+        let (bytes, blocks) = sort_test_bam(&config);
+
+        assert_eq!(blocks.len(), 4964);
+        for pair in blocks.windows(2) {
+            assert_ne!(
+                BamSortOrder::QueryName.compare(&pair[0], &pair[1]),
+                Ordering::Greater
+            );
+        }
+        let reader = BamReader::new(Cursor::new(bytes), None).unwrap();
+        let header = reader.get_header().parse().unwrap();
+        assert_eq!(header.sort_order(), Some("queryname"));
+    }
+
+    #[test]
+    fn test_bam_sort_file() {
+        let output = env::temp_dir().join(format!("rustynetics-{}-sorted.bam", process::id()));
+        let output = output.to_str().unwrap();
+        let config = BamSortConfig::default();
+        bam_sort_file("tests/test_bam_2.bam", output, &config, Some("bam-sort")).unwrap();
+
+        let mut bam = BamFile::open(output, None).unwrap();
+        let header = bam.reader.get_header().parse().unwrap();
+        assert_eq!(header.sort_order(), Some("coordinate"));
+        let program = header.programs.last().unwrap();
+        assert_eq!(program.command_line.as_deref(), Some("bam-sort"));
+        assert_eq!(header.program_leaves(), vec![program]);
+        assert_eq!(bam.reader.read_single_end().count(), 4964);
+
+        fs::remove_file(output).unwrap();
+    }
+}
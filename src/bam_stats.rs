@@ -0,0 +1,436 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+use serde::Serialize;
+
+use crate::bam::{BamBlock, BamFile, BamReader, BamReaderOptions};
+use crate::genome::Genome;
+
+/* -------------------------------------------------------------------------- */
+
+/// Number of records per flag category, following the definitions of
+/// `samtools flagstat`. Statistics on pairs only consider primary
+/// alignments.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BamFlagStats {
+    pub total: u64,
+    pub primary: u64,
+    pub secondary: u64,
+    pub supplementary: u64,
+    pub duplicates: u64,
+    pub primary_duplicates: u64,
+    pub mapped: u64,
+    pub primary_mapped: u64,
+    pub paired: u64,
+    pub read1: u64,
+    pub read2: u64,
+    pub properly_paired: u64,
+    pub with_mate_mapped: u64,
+    pub singletons: u64,
+    pub mate_on_other_chromosome: u64,
+    pub mate_on_other_chromosome_mapq5: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamFlagStats {
+    fn add(&mut self, block: &BamBlock) {
+        let flag = &block.flag;
+        let mapped = !flag.unmapped();
+
+        self.total += 1;
+        if flag.secondary_alignment() {
+            self.secondary += 1;
+        } else if flag.supplementary_alignment() {
+            self.supplementary += 1;
+        } else {
+            self.primary += 1;
+            if flag.duplicate() {
+                self.primary_duplicates += 1;
+            }
+            if mapped {
+                self.primary_mapped += 1;
+            }
+            if flag.read_paired() {
+                self.paired += 1;
+                if flag.first_in_pair() {
+                    self.read1 += 1;
+                }
+                if flag.second_in_pair() {
+                    self.read2 += 1;
+                }
+                if mapped && flag.read_mapped_proper_paired() {
+                    self.properly_paired += 1;
+                }
+                if mapped && flag.mate_unmapped() {
+                    self.singletons += 1;
+                }
+                if mapped && !flag.mate_unmapped() {
+                    self.with_mate_mapped += 1;
+                    if block.next_ref_id != block.ref_id {
+                        self.mate_on_other_chromosome += 1;
+                        if block.mapq >= 5 {
+                            self.mate_on_other_chromosome_mapq5 += 1;
+                        }
+                    }
+                }
+            }
+        }
+        if flag.duplicate() {
+            self.duplicates += 1;
+        }
+        if mapped {
+            self.mapped += 1;
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Number of mapped and unmapped records of a reference sequence, following
+/// the definitions of `samtools idxstats`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BamReferenceStats {
+    pub name: String,
+    pub length: usize,
+    pub mapped: u64,
+    pub unmapped: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Summary statistics of the records of a BAM file.
+///
+/// # Fields
+/// - `qc_passed`: Flag statistics of records passing quality controls.
+/// - `qc_failed`: Flag statistics of records failing quality controls (0x200).
+/// - `references`: Mapped and unmapped records per reference sequence.
+/// - `unplaced`: Number of unmapped records without reference sequence.
+/// - `mapq`: Histogram of mapping qualities of mapped primary alignments.
+/// - `read_lengths`: Histogram of read lengths of primary alignments.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BamStats {
+    pub qc_passed: BamFlagStats,
+    pub qc_failed: BamFlagStats,
+    pub references: Vec<BamReferenceStats>,
+    pub unplaced: u64,
+    pub mapq: Vec<u64>,
+    pub read_lengths: BTreeMap<usize, u64>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BamStats {
+    /// Creates empty statistics for the reference sequences of `genome`.
+    pub fn new(genome: &Genome) -> Self {
+        BamStats {
+            references: genome
+                .iter()
+                .map(|(name, &length)| BamReferenceStats {
+                    name: name.clone(),
+                    length,
+                    ..Default::default()
+                })
+                .collect(),
+            mapq: vec![0; 256],
+            ..Default::default()
+        }
+    }
+
+    /// Adds a single record to the statistics.
+    pub fn add(&mut self, block: &BamBlock) {
+        let flag = &block.flag;
+
+        if flag.not_passing_filters() {
+            self.qc_failed.add(block);
+        } else {
+            self.qc_passed.add(block);
+        }
+
+        match self.references.get_mut(block.ref_id as usize) {
+            Some(reference) if block.ref_id >= 0 => {
+                if flag.unmapped() {
+                    reference.unmapped += 1;
+                } else {
+                    reference.mapped += 1;
+                }
+            }
+            _ => self.unplaced += 1,
+        }
+
+        if !flag.secondary_alignment() && !flag.supplementary_alignment() {
+            if !flag.unmapped() {
+                self.mapq[block.mapq as usize] += 1;
+            }
+            *self.read_lengths.entry(block.l_seq as usize).or_insert(0) += 1;
+        }
+    }
+
+    /// Computes the statistics of all remaining records of a BAM reader.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if a record cannot be read.
+    pub fn from_reader<R: Read>(reader: &mut BamReader<R>) -> io::Result<Self> {
+        let mut stats = BamStats::new(reader.get_genome());
+
+        while let Some(block) = reader.read_block()? {
+            stats.add(&block);
+        }
+        Ok(stats)
+    }
+
+    /// Computes the statistics of a BAM file.
+    ///
+    /// # Arguments
+    /// * `filename` - The file path or URL of the BAM file.
+    /// * `threads` - Number of threads used for BGZF decompression.
+    pub fn import(filename: &str, threads: usize) -> Result<Self, Box<dyn Error>> {
+        // Only fixed-length fields are required
+        let options = BamReaderOptions {
+            read_name: false,
+            read_cigar: false,
+            read_sequence: false,
+            read_auxiliary: false,
+            read_qual: false,
+            threads,
+        };
+        let mut bam = BamFile::open(filename, Some(options))?;
+
+        Ok(Self::from_reader(&mut bam.reader)?)
+    }
+
+    /// Serializes the statistics as JSON.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Formats the flag statistics in the format of `samtools flagstat`.
+    pub fn flagstat(&self) -> String {
+        let (p, f) = (&self.qc_passed, &self.qc_failed);
+        let percent = |n: u64, d: u64| {
+            if d == 0 {
+                "N/A".to_string()
+            } else {
+                format!("{:.2}%", 100.0 * n as f64 / d as f64)
+            }
+        };
+        let rows = [
+            (
+                p.total,
+                f.total,
+                "in total (QC-passed reads + QC-failed reads)",
+                None,
+            ),
+            (p.primary, f.primary, "primary", None),
+            (p.secondary, f.secondary, "secondary", None),
+            (p.supplementary, f.supplementary, "supplementary", None),
+            (p.duplicates, f.duplicates, "duplicates", None),
+            (
+                p.primary_duplicates,
+                f.primary_duplicates,
+                "primary duplicates",
+                None,
+            ),
+            (p.mapped, f.mapped, "mapped", Some((p.total, f.total))),
+            (
+                p.primary_mapped,
+                f.primary_mapped,
+                "primary mapped",
+                Some((p.primary, f.primary)),
+            ),
+            (p.paired, f.paired, "paired in sequencing", None),
+            (p.read1, f.read1, "read1", None),
+            (p.read2, f.read2, "read2", None),
+            (
+                p.properly_paired,
+                f.properly_paired,
+                "properly paired",
+                Some((p.paired, f.paired)),
+            ),
+            (
+                p.with_mate_mapped,
+                f.with_mate_mapped,
+                "with itself and mate mapped",
+                None,
+            ),
+            (
+                p.singletons,
+                f.singletons,
+                "singletons",
+                Some((p.paired, f.paired)),
+            ),
+            (
+                p.mate_on_other_chromosome,
+                f.mate_on_other_chromosome,
+                "with mate mapped to a different chr",
+                None,
+            ),
+            (
+                p.mate_on_other_chromosome_mapq5,
+                f.mate_on_other_chromosome_mapq5,
+                "with mate mapped to a different chr (mapQ>=5)",
+                None,
+            ),
+        ];
+
+        let mut s = String::new();
+        for (n_passed, n_failed, label, total) in rows {
+            s.push_str(&format!("{} + {} {}", n_passed, n_failed, label));
+            if let Some((t_passed, t_failed)) = total {
+                s.push_str(&format!(
+                    " ({} : {})",
+                    percent(n_passed, t_passed),
+                    percent(n_failed, t_failed)
+                ));
+            }
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Formats the per-reference counts in the format of `samtools idxstats`,
+    /// i.e. name, length, mapped and unmapped records separated by tabs.
+    pub fn idxstats(&self) -> String {
+        let mut s = String::new();
+        for r in &self.references {
+            s.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                r.name, r.length, r.mapped, r.unmapped
+            ));
+        }
+        s.push_str(&format!("*\t0\t0\t{}\n", self.unplaced));
+        s
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for BamStats {
+    /// Formats the full report, consisting of flag statistics, per-reference
+    /// counts and both histograms.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Flag statistics")?;
+        write!(f, "{}", self.flagstat())?;
+        writeln!(f)?;
+        writeln!(f, "# Reference statistics [name, length, mapped, unmapped]")?;
+        write!(f, "{}", self.idxstats())?;
+        writeln!(f)?;
+        writeln!(f, "# MAPQ histogram [mapq, count]")?;
+        for (mapq, &n) in self.mapq.iter().enumerate() {
+            if n > 0 {
+                writeln!(f, "{}\t{}", mapq, n)?;
+            }
+        }
+        writeln!(f)?;
+        writeln!(f, "# Read length histogram [length, count]")?;
+        for (length, n) in &self.read_lengths {
+            writeln!(f, "{}\t{}", length, n)?;
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use crate::bam::{BamBlock, BamFlag};
+    use crate::bam_stats::BamStats;
+    use crate::genome::Genome;
+
+    #[test]
+    fn test_bam_stats_file() {
+        let stats = BamStats::import("tests/test_bam_1.bam", 1).unwrap();
+
+        assert_eq!(stats.qc_passed.total, 12);
+        assert_eq!(stats.qc_passed.mapped, 12);
+        assert_eq!(stats.qc_passed.paired, 2);
+        assert_eq!(stats.qc_passed.properly_paired, 2);
+        assert_eq!(stats.qc_failed.total, 0);
+        assert_eq!(stats.references[0].name, "ref");
+        assert_eq!(stats.references[0].mapped, 6);
+        assert_eq!(stats.references[1].mapped, 6);
+        assert_eq!(stats.mapq[30], 12);
+        assert_eq!(stats.read_lengths.values().sum::<u64>(), 12);
+
+        assert!(stats.flagstat().starts_with("12 + 0 in total"));
+        assert!(stats.idxstats().ends_with("*\t0\t0\t0\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json().unwrap()).unwrap();
+        assert_eq!(json["qc_passed"]["total"], 12);
+        assert_eq!(json["references"][1]["name"], "ref2");
+    }
+
+    #[test]
+    fn test_bam_stats_flags() {
+        let genome = Genome::new(vec!["chr1".to_string(), "chr2".to_string()], vec![100, 100]);
+        let mut stats = BamStats::new(&genome);
+
+        let block = |flag: u16, ref_id: i32, next_ref_id: i32, mapq: u8| BamBlock {
+            ref_id,
+            next_ref_id,
+            mapq,
+            l_seq: 10,
+            flag: BamFlag(flag),
+            ..Default::default()
+        };
+        // Proper pair
+        stats.add(&block(0x1 | 0x2 | 0x40, 0, 0, 60));
+        stats.add(&block(0x1 | 0x2 | 0x80, 0, 0, 60));
+        // Mate on other chromosome with low mapping quality
+        stats.add(&block(0x1 | 0x40, 0, 1, 3));
+        // Singleton and its placed unmapped mate
+        stats.add(&block(0x1 | 0x8 | 0x40, 1, 1, 60));
+        stats.add(&block(0x1 | 0x4 | 0x80, 1, 1, 0));
+        // Secondary duplicate, QC-failed and unplaced unmapped read
+        stats.add(&block(0x100 | 0x400, 0, -1, 60));
+        stats.add(&block(0x200, 1, -1, 60));
+        stats.add(&block(0x4, -1, -1, 0));
+
+        let p = &stats.qc_passed;
+        assert_eq!(p.total, 7);
+        assert_eq!(p.primary, 6);
+        assert_eq!(p.secondary, 1);
+        assert_eq!(p.duplicates, 1);
+        assert_eq!(p.primary_duplicates, 0);
+        assert_eq!(p.mapped, 5);
+        assert_eq!(p.primary_mapped, 4);
+        assert_eq!(p.paired, 5);
+        assert_eq!(p.properly_paired, 2);
+        assert_eq!(p.with_mate_mapped, 3);
+        assert_eq!(p.singletons, 1);
+        assert_eq!(p.mate_on_other_chromosome, 1);
+        assert_eq!(p.mate_on_other_chromosome_mapq5, 0);
+        assert_eq!(stats.qc_failed.total, 1);
+
+        assert_eq!(stats.references[0].mapped, 4);
+        assert_eq!(stats.references[1].mapped, 2);
+        assert_eq!(stats.references[1].unmapped, 1);
+        assert_eq!(stats.unplaced, 1);
+        assert_eq!(stats.mapq[60], 4);
+        assert_eq!(stats.mapq[3], 1);
+        assert_eq!(stats.read_lengths[&10], 7);
+    }
+}
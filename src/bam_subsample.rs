@@ -0,0 +1,343 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::io::{self, Read, Write};
+
+use crate::bam::{BamBlock, BamFile, BamReader, BamReaderOptions, BamWriter};
+
+/* -------------------------------------------------------------------------- */
+
+/// Reproducible random selection of reads.
+///
+/// Whether a read is kept depends only on its name and the seed, so that
+/// mates are either both kept or both removed, and the same reads are
+/// selected in every run.
+#[derive(Clone, Copy, Debug)]
+pub struct Subsampler {
+    threshold: u64,
+    seed: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Subsampler {
+    /// Creates a subsampler that keeps the given fraction of reads. Fractions
+    /// of one or larger keep all reads.
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        let threshold = if fraction >= 1.0 {
+            u64::MAX
+        } else if fraction <= 0.0 {
+            0
+        } else {
+            (fraction * u64::MAX as f64) as u64
+        };
+        Subsampler { threshold, seed }
+    }
+
+    /// Creates a subsampler that keeps approximately `target` out of `total`
+    /// reads.
+    pub fn with_count(target: u64, total: u64, seed: u64) -> Self {
+        if total == 0 {
+            return Subsampler::new(1.0, seed);
+        }
+        Subsampler::new(target as f64 / total as f64, seed)
+    }
+
+    /// Returns the expected fraction of kept reads.
+    pub fn fraction(&self) -> f64 {
+        self.threshold as f64 / u64::MAX as f64
+    }
+
+    fn hash(&self, name: &[u8]) -> u64 {
+        // FNV-1a followed by the SplitMix64 finalizer
+        let mut h = 0xcbf29ce484222325 ^ self.seed;
+        for &b in name {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^ (h >> 31)
+    }
+
+    /// Returns `true` if the read with the given name is kept.
+    pub fn keep(&self, name: &str) -> bool {
+        self.threshold == u64::MAX || self.hash(name.as_bytes()) < self.threshold
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Returns `true` if the record is the primary alignment that represents its
+/// template, i.e. the first mate of a pair or a single-end read.
+fn is_template(block: &BamBlock) -> bool {
+    !block.flag.secondary_alignment()
+        && !block.flag.supplementary_alignment()
+        && (!block.flag.read_paired() || block.flag.first_in_pair())
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Counts the templates (single-end reads or read pairs) in a BAM file, i.e.
+/// the number of primary alignments that are not the second mate of a pair.
+pub fn bam_count_templates<R: Read>(reader: &mut BamReader<R>) -> io::Result<u64> {
+    let mut n = 0;
+    while let Some(block) = reader.read_block()? {
+        if is_template(&block) {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Copies a random subset of records, selected by read name, from a BAM
+/// reader to a BAM writer. All records of a template, including secondary and
+/// supplementary alignments, are either kept or removed together.
+///
+/// # Returns
+/// The number of kept templates and the total number of templates.
+pub fn bam_subsample<R: Read, W: Write>(
+    reader: &mut BamReader<R>,
+    writer: &mut BamWriter<W>,
+    subsampler: &Subsampler,
+) -> io::Result<(u64, u64)> {
+    let mut n = 0;
+    let mut m = 0;
+    while let Some(block) = reader.read_block()? {
+        let keep = subsampler.keep(&block.read_name);
+        if is_template(&block) {
+            n += 1;
+            if keep {
+                m += 1;
+            }
+        }
+        if keep {
+            writer.write_block(&block)?;
+        }
+    }
+    Ok((m, n))
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Subsamples a BAM file either to a fraction of templates or to
+/// approximately `count` templates. The latter requires an additional pass
+/// over the input to count all templates.
+///
+/// # Arguments
+/// * `filename_in` - The input BAM file.
+/// * `filename_out` - The output BAM file.
+/// * `fraction` - Fraction of templates to keep, ignored if `count` is given.
+/// * `count` - Approximate number of templates to keep.
+/// * `seed` - Seed of the random selection.
+/// * `threads` - Number of threads used for BGZF decompression.
+/// * `command_line` - If given, a `@PG` line with this command line is added
+///   to the header of the output file.
+///
+/// # Returns
+/// The number of kept templates and the total number of templates.
+pub fn bam_subsample_file(
+    filename_in: &str,
+    filename_out: &str,
+    fraction: f64,
+    count: Option<u64>,
+    seed: u64,
+    threads: usize,
+    command_line: Option<&str>,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    let options = BamReaderOptions::with_threads(threads);
+
+    let subsampler = match count {
+        Some(count) => {
+            let mut bam = BamFile::open(filename_in, Some(options))?;
+            let total = bam_count_templates(&mut bam.reader)?;
+            Subsampler::with_count(count, total, seed)
+        }
+        None => Subsampler::new(fraction, seed),
+    };
+
+    let mut bam = BamFile::open(filename_in, Some(options))?;
+    let mut header = bam.reader.get_header().clone();
+    if let Some(command_line) = command_line {
+        let mut sam_header = header.parse()?;
+        sam_header.add_program(
+            "bam-subsample",
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(command_line),
+        );
+        header.set_sam_header(&sam_header);
+    }
+    let genome = bam.reader.get_genome().clone();
+    let mut writer = BamFile::create(filename_out, &header, &genome)?;
+
+    let result = bam_subsample(&mut bam.reader, &mut writer, &subsampler)?;
+    writer.close()?;
+
+    Ok(result)
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+
+    use futures::executor::block_on_stream;
+    use futures::stream;
+
+    use crate::bam::{BamBlock, BamFile, BamFlag, BamHeader, BamReader, BamWriter};
+    use crate::bam_fixtures::mapped_read;
+    use crate::bam_subsample::{bam_subsample, bam_subsample_file, Subsampler};
+    use crate::genome::Genome;
+    use crate::read::Read;
+    use crate::read_stream::ReadStream;
+    use crate::sam_header::SamHeader;
+
+    #[test]
+    fn test_subsampler() {
+        let names: Vec<String> = (0..10000).map(|i| format!("read{}", i)).collect();
+        let subsampler = Subsampler::new(0.2, 1);
+        let n = names.iter().filter(|name| subsampler.keep(name)).count();
+
+        assert!(n > 1800 && n < 2200);
+        // Selection is reproducible and depends on the seed
+        assert_eq!(n, names.iter().filter(|name| subsampler.keep(name)).count());
+        let other = Subsampler::new(0.2, 2);
+        assert!(names
+            .iter()
+            .any(|name| subsampler.keep(name) != other.keep(name)));
+        // Reads kept at a smaller fraction are also kept at larger fractions
+        let larger = Subsampler::new(0.5, 1);
+        assert!(names
+            .iter()
+            .all(|name| !subsampler.keep(name) || larger.keep(name)));
+
+        assert!(names.iter().all(|name| Subsampler::new(1.0, 1).keep(name)));
+        assert!(!names.iter().any(|name| Subsampler::new(0.0, 1).keep(name)));
+        assert_eq!(Subsampler::with_count(10, 40, 0).fraction(), 0.25);
+    }
+
+    #[test]
+    fn test_subsample_read_stream() {
+        let reads: Vec<Read> = (0..1000)
+            .flat_map(|i| {
+                (0..2).map(move |_| {
+                    let mut read = mapped_read("chr1", 0, 10, '+', 0x1, vec![]);
+                    read.name = format!("pair{}", i);
+                    read
+                })
+            })
+            .collect();
+
+        let reads = Box::pin(stream::iter(reads.into_iter().map(Ok)));
+        let reads = ReadStream::subsample(reads, None, 0.3, 42);
+        let names: Vec<String> = block_on_stream(reads).map(|r| r.unwrap().name).collect();
+
+        // Both mates are kept
+        assert_eq!(names.len() % 2, 0);
+        for pair in names.chunks(2) {
+            assert_eq!(pair[0], pair[1]);
+        }
+        assert!(names.len() > 400 && names.len() < 800);
+    }
+
+    #[test]
+    fn test_bam_subsample() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![1000]);
+        let header = BamHeader::from(&SamHeader::default());
+
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        for i in 0..100 {
+            for flag in [0x1 | 0x40, 0x1 | 0x80, 0x1 | 0x40 | 0x800] {
+                let block = BamBlock {
+                    ref_id: 0,
+                    position: i,
+                    flag: BamFlag(flag),
+                    next_ref_id: -1,
+                    read_name: format!("pair{}", i),
+                    ..Default::default()
+                };
+                writer.write_block(&block).unwrap();
+            }
+        }
+        let data = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(Cursor::new(data), None).unwrap();
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        let (m, n) = bam_subsample(&mut reader, &mut writer, &Subsampler::new(0.5, 7)).unwrap();
+        let data = writer.finish().unwrap();
+
+        assert_eq!(n, 100);
+        assert!(m > 30 && m < 70);
+
+        let mut reader = BamReader::new(Cursor::new(data), None).unwrap();
+        let mut k = 0;
+        while reader.read_block().unwrap().is_some() {
+            k += 1;
+        }
+        assert_eq!(k, 3 * m);
+    }
+
+    #[test]
+    fn test_bam_subsample_file() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![1000]);
+        let header = BamHeader::from(&SamHeader::default());
+
+        let dir = env::temp_dir();
+        let input = dir.join(format!("rustynetics-{}-subsample-in.bam", process::id()));
+        let output = dir.join(format!("rustynetics-{}-subsample-out.bam", process::id()));
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+
+        let mut writer = BamFile::create(input, &header, &genome).unwrap();
+        for i in 0..100 {
+            let block = BamBlock {
+                ref_id: 0,
+                position: i,
+                next_ref_id: -1,
+                read_name: format!("read{}", i),
+                ..Default::default()
+            };
+            writer.write_block(&block).unwrap();
+        }
+        writer.close().unwrap();
+
+        let (m, n) =
+            bam_subsample_file(input, output, 0.0, Some(50), 3, 1, Some("bam-subsample")).unwrap();
+        assert_eq!(n, 100);
+
+        let mut bam = BamFile::open(output, None).unwrap();
+        let sam_header = bam.reader.get_header().parse().unwrap();
+        assert_eq!(sam_header.programs.len(), 1);
+        assert_eq!(
+            sam_header.programs[0].command_line.as_deref(),
+            Some("bam-subsample")
+        );
+        assert_eq!(bam.reader.read_single_end().count() as u64, m);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
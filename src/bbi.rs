@@ -35,6 +35,7 @@ use core::pin::Pin;
 use futures::executor::block_on_stream;
 use futures_core::stream::Stream;
 
+use crate::genome::Genome;
 use crate::utility_io::indent_fmt;
 
 /* -------------------------------------------------------------------------- */
@@ -103,15 +104,15 @@ fn compress_slice(data: &[u8]) -> io::Result<Vec<u8>> {
 /* -------------------------------------------------------------------------- */
 
 #[derive(Clone, Copy, Default, Debug)]
-struct BbiZoomRecord {
-    chrom_id: u32,
-    start: u32,
-    end: u32,
-    valid: u32,
-    min: f32,
-    max: f32,
-    sum: f32,
-    sum_squares: f32,
+pub(crate) struct BbiZoomRecord {
+    pub(crate) chrom_id: u32,
+    pub(crate) start: u32,
+    pub(crate) end: u32,
+    pub(crate) valid: u32,
+    pub(crate) min: f32,
+    pub(crate) max: f32,
+    pub(crate) sum: f32,
+    pub(crate) sum_squares: f32,
 }
 
 /* -------------------------------------------------------------------------- */
@@ -132,7 +133,24 @@ impl BbiZoomRecord {
         self.sum_squares += (x * x) as f32;
     }
 
-    fn read<E: ByteOrder, T: Read + Seek>(&mut self, reader: &mut T) -> io::Result<()> {
+    /// Adds a value that covers `n` bases, so that `valid` counts bases and
+    /// sums are weighted by the number of bases.
+    pub(crate) fn add_interval(&mut self, x: f64, n: u32) {
+        if x.is_nan() || n == 0 {
+            return;
+        }
+        if self.min.is_nan() || self.min > x as f32 {
+            self.min = x as f32;
+        }
+        if self.max.is_nan() || self.max < x as f32 {
+            self.max = x as f32;
+        }
+        self.valid += n;
+        self.sum += (x * n as f64) as f32;
+        self.sum_squares += (x * x * n as f64) as f32;
+    }
+
+    pub(crate) fn read<E: ByteOrder, T: Read>(&mut self, reader: &mut T) -> io::Result<()> {
         self.chrom_id = reader.read_u32::<E>()?;
         self.start = reader.read_u32::<E>()?;
         self.end = reader.read_u32::<E>()?;
@@ -144,7 +162,7 @@ impl BbiZoomRecord {
         Ok(())
     }
 
-    fn write<E: ByteOrder, T: Write + Seek>(&self, writer: &mut T) -> io::Result<()> {
+    pub(crate) fn write<E: ByteOrder, T: Write>(&self, writer: &mut T) -> io::Result<()> {
         writer.write_u32::<E>(self.chrom_id)?;
         writer.write_u32::<E>(self.start)?;
         writer.write_u32::<E>(self.end)?;
@@ -986,7 +1004,7 @@ impl BVertex {
             }
         } else {
             self.is_leaf = 0;
-            while i < data.items_per_block as usize && from + i < to {
+            while self.children.len() < data.items_per_block as usize && from + i < to {
                 self.keys.push(data.keys[from + i].clone());
                 let mut child = BVertex {
                     is_leaf: 0,
@@ -1025,12 +1043,12 @@ impl BVertex {
 
         writer.write_u8(is_leaf)?;
         writer.write_u8(padding)?;
-        writer.write_u16::<byteorder::LittleEndian>(n_vals)?;
+        writer.write_u16::<E>(n_vals)?;
 
         for i in 0..self.keys.len() {
             writer.write_all(&self.keys[i])?;
             offsets.push(writer.seek(io::SeekFrom::Current(0))?);
-            writer.write_u64::<byteorder::LittleEndian>(0)?;
+            writer.write_u64::<E>(0)?;
         }
         for i in 0..self.keys.len() {
             let offset = writer.seek(io::SeekFrom::Current(0))? as u64;
@@ -1077,7 +1095,7 @@ impl BTree {
                 children: Vec::new(),
             },
         };
-        if data.item_count == 1 {
+        if data.item_count <= 1 {
             tree.root
                 .build_tree(data, 0, data.item_count as usize, 0)
                 .unwrap();
@@ -1092,6 +1110,82 @@ impl BTree {
         tree
     }
 
+    /// Searches the B+ tree stored at `offset` for `key` and returns the
+    /// values of all matching items. Keys shorter than the key size of the
+    /// tree are padded with zeros.
+    pub fn find<E: ByteOrder, R: Read + Seek>(
+        file: &mut R,
+        offset: u64,
+        key: &[u8],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        file.seek(SeekFrom::Start(offset))?;
+
+        let magic = file.read_u32::<E>()?;
+        if magic != CIRTREE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid tree"));
+        }
+        file.read_u32::<E>()?; // items per block
+        let key_size = file.read_u32::<E>()? as usize;
+        let value_size = file.read_u32::<E>()? as usize;
+        file.read_u64::<E>()?; // item count
+        file.read_u64::<E>()?; // padding
+
+        let mut values = Vec::new();
+        if key.len() > key_size {
+            return Ok(values);
+        }
+        let mut key = key.to_vec();
+        key.resize(key_size, 0);
+
+        let root = file.stream_position()?;
+        Self::find_rec::<E, R>(file, root, &key, value_size, &mut values)?;
+
+        Ok(values)
+    }
+
+    fn find_rec<E: ByteOrder, R: Read + Seek>(
+        file: &mut R,
+        offset: u64,
+        key: &[u8],
+        value_size: usize,
+        values: &mut Vec<Vec<u8>>,
+    ) -> io::Result<()> {
+        file.seek(SeekFrom::Start(offset))?;
+
+        let is_leaf = file.read_u8()?;
+        file.read_u8()?; // padding
+        let n_vals = file.read_u16::<E>()? as usize;
+
+        let mut k = vec![0; key.len()];
+        if is_leaf != 0 {
+            let mut v = vec![0; value_size];
+            for _ in 0..n_vals {
+                file.read_exact(&mut k)?;
+                file.read_exact(&mut v)?;
+                if k == key {
+                    values.push(v.clone());
+                }
+            }
+        } else {
+            let mut children = Vec::with_capacity(n_vals);
+            for _ in 0..n_vals {
+                file.read_exact(&mut k)?;
+                children.push((k.clone(), file.read_u64::<E>()?));
+            }
+            // Items with equal keys may be spread over neighboring children
+            for i in 0..children.len() {
+                if children[i].0.as_slice() > key {
+                    break;
+                }
+                if i + 1 < children.len() && children[i + 1].0.as_slice() < key {
+                    continue;
+                }
+                Self::find_rec::<E, R>(file, children[i].1, key, value_size, values)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn write<E: ByteOrder, W: Write + Seek>(&self, writer: &mut W) -> io::Result<()> {
         let magic = CIRTREE_MAGIC;
 
@@ -1140,6 +1234,39 @@ impl Default for BData {
 /* -------------------------------------------------------------------------- */
 
 impl BData {
+    /// Interprets the data as chromosome list, where keys are chromosome
+    /// names and values consist of chromosome index and length.
+    pub fn genome<E: ByteOrder>(&self) -> io::Result<Genome> {
+        let mut genome = Genome::new(
+            vec![String::new(); self.keys.len()],
+            vec![0; self.keys.len()],
+        );
+
+        for i in 0..self.keys.len() {
+            if self.values[i].len() != 8 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid chromosome list",
+                ));
+            }
+
+            let idx = (&self.values[i][0..4]).read_u32::<E>()? as usize;
+
+            if idx >= self.keys.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid chromosome index",
+                ));
+            }
+            genome.seqnames[idx] = String::from_utf8_lossy(&self.keys[i])
+                .trim_end_matches('\x00')
+                .to_string();
+            genome.lengths[idx] = (&self.values[i][4..8]).read_u32::<E>()? as usize;
+        }
+
+        Ok(genome)
+    }
+
     pub fn key_offsets(&self) -> &[i64] {
         &self.ptr_keys
     }
@@ -1181,9 +1308,9 @@ impl BData {
         let n_vals = file.read_u16::<E>()?;
         for _ in 0..n_vals {
             let mut key = vec![0; self.key_size as usize];
-            let position = file.read_u64::<E>()?;
 
             file.read_exact(&mut key)?;
+            let position = file.read_u64::<E>()?;
 
             // save current position and jump to child vertex
             let current_position = file.seek(SeekFrom::Current(0))?;
@@ -1518,6 +1645,23 @@ impl BbiHeader {
         self.sum_squares += x * x;
     }
 
+    /// Adds a value that covers `n` bases to the summary, where sums are
+    /// weighted by the number of bases.
+    pub fn summary_add_interval(&mut self, x: f64, n: u64) {
+        if x.is_nan() || n == 0 {
+            return;
+        }
+        if self.min_val.is_nan() || self.min_val > x {
+            self.min_val = x;
+        }
+        if self.max_val.is_nan() || self.max_val < x {
+            self.max_val = x;
+        }
+        self.n_bases_covered += n;
+        self.sum_data += x * n as f64;
+        self.sum_squares += x * x * n as f64;
+    }
+
     fn read<E: ByteOrder, R: Read + Seek>(&mut self, file: &mut R, magic: u32) -> io::Result<()> {
         self.magic = file.read_u32::<E>()?;
 
@@ -1867,22 +2011,36 @@ pub struct RVertex {
 /* -------------------------------------------------------------------------- */
 
 impl RVertex {
+    /// Returns an empty leaf vertex.
+    pub fn new_leaf() -> Self {
+        RVertex {
+            is_leaf: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Appends an entry for a data block covering `[from, to)` on the given
+    /// chromosome to a leaf vertex. Offset and size of the block are set
+    /// when it is written with `write_block`.
+    pub fn add_block(&mut self, chrom_id: u32, from: u32, to: u32) {
+        self.chr_idx_start.push(chrom_id);
+        self.chr_idx_end.push(chrom_id);
+        self.base_start.push(from);
+        self.base_end.push(to);
+        self.data_offset.push(0);
+        self.sizes.push(0);
+        self.ptr_data_offset.push(0);
+        self.ptr_sizes.push(0);
+        self.n_children += 1;
+    }
+
     pub fn read_block<R: Read + Seek>(
         &self,
         reader: &mut R,
         bwf: &BbiFile,
         i: usize,
     ) -> io::Result<Vec<u8>> {
-        let mut block = vec![0u8; self.sizes[i] as usize];
-
-        reader.seek(SeekFrom::Start(self.data_offset[i]))?;
-        reader.read_exact(&mut block)?;
-
-        if bwf.header.uncompress_buf_size != 0 {
-            block = uncompress_slice(&block)?;
-        }
-
-        Ok(block)
+        bwf.read_block(reader, self.data_offset[i], self.sizes[i])
     }
 
     pub fn write_block<E: ByteOrder, W: Write + Seek>(
@@ -2079,15 +2237,7 @@ impl RVertexGenerator {
                     vertex.is_leaf = 1;
                     blocks = Vec::new();
                 }
-                vertex.chr_idx_start  .push(chrom_id   as u32);
-                vertex.chr_idx_end    .push(chrom_id   as u32);
-                vertex.base_start     .push(chunk.from as u32);
-                vertex.base_end       .push(chunk.to   as u32);
-                vertex.data_offset    .push(0);
-                vertex.sizes          .push(0);
-                vertex.ptr_data_offset.push(0);
-                vertex.ptr_sizes      .push(0);
-                vertex.n_children += 1;
+                vertex.add_block(chrom_id as u32, chunk.from as u32, chunk.to as u32);
 
                 blocks.push(chunk.block);
             }
@@ -2126,15 +2276,7 @@ impl RVertexGenerator {
                     vertex.is_leaf = 1;
                     blocks = Vec::new();
                 }
-                vertex.chr_idx_start  .push(chrom_id   as u32);
-                vertex.chr_idx_end    .push(chrom_id   as u32);
-                vertex.base_start     .push(chunk.from as u32);
-                vertex.base_end       .push(chunk.to   as u32);
-                vertex.data_offset    .push(0);
-                vertex.sizes          .push(0);
-                vertex.ptr_data_offset.push(0);
-                vertex.ptr_sizes      .push(0);
-                vertex.n_children += 1;
+                vertex.add_block(chrom_id as u32, chunk.from as u32, chunk.to as u32);
 
                 blocks.push(chunk.block);
             }
@@ -2318,6 +2460,26 @@ impl Default for BbiFile {
 /* -------------------------------------------------------------------------- */
 
 impl BbiFile {
+    /// Reads the data block of `size` bytes at `offset` and uncompresses it
+    /// if the file is compressed.
+    pub fn read_block<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offset: u64,
+        size: u64,
+    ) -> io::Result<Vec<u8>> {
+        let mut block = vec![0u8; size as usize];
+
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut block)?;
+
+        if self.header.uncompress_buf_size != 0 {
+            block = uncompress_slice(&block)?;
+        }
+
+        Ok(block)
+    }
+
     fn read_index<E: ByteOrder, R: Read + Seek>(&mut self, reader: &mut R) -> io::Result<()> {
         reader.seek(SeekFrom::Start(self.header.index_offset))?;
         self.index.read::<E, R>(reader)
@@ -2471,6 +2633,86 @@ impl BbiFile {
         }
     }
 
+    /// Returns the uncompressed data blocks whose index entries overlap the
+    /// region `[from, to)` on the given chromosome. Blocks are returned as
+    /// stored and may contain records outside of the region.
+    pub fn query_blocks_stream<'a, E: ByteOrder, R: Read + Seek>(
+        &'a mut self,
+        reader: &'a mut R,
+        chrom_id: u32,
+        from: u32,
+        to: u32,
+    ) -> impl Stream<Item = io::Result<Vec<u8>>> + 'a {
+        stream! {
+            if self.index.root.is_none() {
+                if let Err(err) = self.read_index::<E, R>(reader) {
+                    yield Err(err); return ();
+                }
+            }
+
+            let traverser = RTreeTraverser::new(&self.index, chrom_id, from, to);
+
+            for r in traverser {
+                match r.vertex.read_block::<R>(reader, self, r.idx) {
+                    Err(err) => { yield Err(err); return (); },
+                    Ok(block) => yield Ok(block),
+                }
+            }
+        }
+    }
+
+    /// Returns all records that overlap the region `[from, to)` on the given
+    /// chromosome without merging them into bins. Records are read from
+    /// zoom level `zoom_idx`, or from the raw data if `zoom_idx` is `None`,
+    /// in which case each record has a `valid` count of one.
+    pub fn query_records<E: ByteOrder, R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        zoom_idx: Option<usize>,
+        chrom_id: u32,
+        from: u32,
+        to: u32,
+    ) -> io::Result<Vec<BbiSummaryRecord>> {
+        let index = match zoom_idx {
+            None => {
+                if self.index.root.is_none() {
+                    self.read_index::<E, R>(reader)?;
+                }
+                &self.index
+            }
+            Some(i) => {
+                if self.index_zoom[i].root.is_none() {
+                    self.read_zoom_index::<E, R>(reader, i)?;
+                }
+                &self.index_zoom[i]
+            }
+        };
+        let mut records = Vec::new();
+
+        for r in RTreeTraverser::new(index, chrom_id, from, to) {
+            let block = r.vertex.read_block::<R>(reader, self, r.idx)?;
+
+            if zoom_idx.is_none() {
+                let decoder = BbiRawBlockDecoder::new::<E>(&block)?;
+                for item in decoder.decode() {
+                    records.push(item.read::<E>().0);
+                }
+            } else {
+                let decoder = BbiZoomBlockDecoder::new(&block)?;
+                for mut item in decoder.decode() {
+                    records.push(item.read::<E>()?.0);
+                }
+            }
+        }
+        records.retain(|record| {
+            record.chrom_id == chrom_id as i32
+                && record.from < to as i32
+                && record.to > from as i32
+        });
+
+        Ok(records)
+    }
+
     pub fn query_stream<'a, E: ByteOrder, R: Read + Seek>(
         &'a mut self,
         reader: &'a mut R,
@@ -2603,3 +2845,164 @@ impl BbiFile {
         Ok(())
     }
 }
+
+/* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::File;
+    use std::io::Cursor;
+
+    use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+
+    use crate::bbi::{BData, BTree, BbiFile, CIRTREE_MAGIC};
+
+    fn chrom_data(n: usize, items_per_block: u32) -> BData {
+        let mut data = BData {
+            key_size: 5,
+            value_size: 8,
+            ..Default::default()
+        };
+        for i in 0..n {
+            let mut value = Vec::new();
+            value.write_u32::<LittleEndian>(i as u32).unwrap();
+            value
+                .write_u32::<LittleEndian>(1000 * (i as u32 + 1))
+                .unwrap();
+            data.add(format!("chr{:02}", i).into_bytes(), value)
+                .unwrap();
+        }
+        data.items_per_block = items_per_block;
+        data
+    }
+
+    // Writes a B+ tree with the layout of bptFileBulkIndexToOpenFile() from
+    // the UCSC Genome Browser sources, i.e. vertices are stored level by
+    // level and padded to the block size.
+    fn ucsc_tree(data: &BData) -> Vec<u8> {
+        let block_size = data.items_per_block as usize;
+        let key_size = data.key_size as usize;
+        let item_count = data.keys.len();
+
+        let mut levels = 1;
+        let mut n = item_count;
+        while n > block_size {
+            n = n.div_ceil(block_size);
+            levels += 1;
+        }
+
+        let mut buffer = Vec::new();
+        buffer.write_u32::<LittleEndian>(CIRTREE_MAGIC).unwrap();
+        buffer
+            .write_u32::<LittleEndian>(data.items_per_block)
+            .unwrap();
+        buffer.write_u32::<LittleEndian>(data.key_size).unwrap();
+        buffer.write_u32::<LittleEndian>(data.value_size).unwrap();
+        buffer.write_u64::<LittleEndian>(item_count as u64).unwrap();
+        buffer.write_u64::<LittleEndian>(0).unwrap();
+
+        let index_block_size = 4 + block_size * (key_size + 8);
+        let leaf_block_size = 4 + block_size * (key_size + data.value_size as usize);
+
+        for level in (1..levels).rev() {
+            let slot_size = block_size.pow(level as u32);
+            let node_size = slot_size * block_size;
+            let next_block_size = if level == 1 {
+                leaf_block_size
+            } else {
+                index_block_size
+            };
+            let mut next_child = buffer.len() + item_count.div_ceil(node_size) * index_block_size;
+
+            for i in (0..item_count).step_by(node_size) {
+                let n = block_size.min((item_count - i).div_ceil(slot_size));
+                buffer.extend_from_slice(&[0, 0]);
+                buffer.write_u16::<LittleEndian>(n as u16).unwrap();
+                for j in 0..n {
+                    buffer.extend_from_slice(&data.keys[i + j * slot_size]);
+                    buffer.write_u64::<LittleEndian>(next_child as u64).unwrap();
+                    next_child += next_block_size;
+                }
+                buffer.resize(buffer.len() + (block_size - n) * (key_size + 8), 0);
+            }
+        }
+        for i in (0..item_count).step_by(block_size) {
+            let n = block_size.min(item_count - i);
+            buffer.extend_from_slice(&[1, 0]);
+            buffer.write_u16::<LittleEndian>(n as u16).unwrap();
+            for j in i..i + n {
+                buffer.extend_from_slice(&data.keys[j]);
+                buffer.extend_from_slice(&data.values[j]);
+            }
+            buffer.resize(
+                buffer.len() + (block_size - n) * (key_size + data.value_size as usize),
+                0,
+            );
+        }
+        buffer
+    }
+
+    fn roundtrip<E: ByteOrder>(data: &BData) -> BData {
+        let mut buffer = Cursor::new(Vec::new());
+        data.write::<E, _>(&mut buffer).unwrap();
+
+        for (key, value) in data.keys.iter().zip(data.values.iter()) {
+            assert_eq!(
+                BTree::find::<E, _>(&mut buffer, 0, key).unwrap(),
+                vec![value.clone()]
+            );
+        }
+
+        let mut result = BData::default();
+        buffer.set_position(0);
+        result.read::<E, _>(&mut buffer).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_bdata_read_ucsc() {
+        // Chromosome list of a bigWig file written by UCSC tools
+        let mut file = File::open("tests/test_bigwig_1.bw").unwrap();
+        let mut bwf = BbiFile::default();
+        bwf.open::<LittleEndian, _>(&mut file, 0x888FFC26).unwrap();
+
+        let genome = bwf.chrom_data.genome::<LittleEndian>().unwrap();
+        assert_eq!(genome.seqnames, vec!["test1", "test2"]);
+        assert_eq!(
+            BTree::find::<LittleEndian, _>(&mut file, bwf.header.ct_offset, b"test2").unwrap(),
+            vec![bwf.chrom_data.values[1].clone()]
+        );
+
+        // Index vertices store the key before the offset of the child
+        let data = chrom_data(5, 2);
+        let mut buffer = Cursor::new(ucsc_tree(&data));
+        let mut result = BData::default();
+        result.read::<LittleEndian, _>(&mut buffer).unwrap();
+
+        assert_eq!(result.keys, data.keys);
+        assert_eq!(result.values, data.values);
+        assert_eq!(
+            BTree::find::<LittleEndian, _>(&mut buffer, 0, b"chr03").unwrap(),
+            vec![data.values[3].clone()]
+        );
+    }
+
+    #[test]
+    fn test_bdata_write() {
+        // Index vertices are limited to `items_per_block` children, which
+        // requires several levels here
+        for n in [0, 1, 2, 5, 9, 30] {
+            let data = chrom_data(n, 2);
+            for result in [
+                roundtrip::<LittleEndian>(&data),
+                roundtrip::<BigEndian>(&data),
+            ] {
+                assert_eq!(result.item_count, n as u64);
+                assert_eq!(result.keys, data.keys);
+                assert_eq!(result.values, data.values);
+            }
+        }
+    }
+}
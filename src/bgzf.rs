@@ -18,9 +18,28 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use flate2::read::MultiGzDecoder;
-use std::io::{self, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/* -------------------------------------------------------------------------- */
+
+/// Maximum number of uncompressed bytes stored in a single BGZF block.
+///
+/// The value leaves enough room so that even incompressible data fits into
+/// the 64 KiB limit imposed by the 16-bit `BSIZE` field.
+pub const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// Empty BGZF block that marks the end of a BGZF file.
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
 
 /* -------------------------------------------------------------------------- */
 
@@ -42,14 +61,170 @@ pub struct BgzfExtra {
 
 /* -------------------------------------------------------------------------- */
 
-/// BGZF reader structure that wraps around a GzDecoder.
+/// BGZF block as stored in the compressed file, before inflation.
+#[derive(Debug)]
+struct BgzfRawBlock {
+    extra: BgzfExtra,
+    address: u64,
+    size: u64,
+    cdata: Vec<u8>,
+    crc32: u32,
+    isize: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Inflates the compressed data of a BGZF block into `block` and verifies
+/// its size and checksum.
+fn inflate_block(cdata: &[u8], crc32: u32, isize: usize, block: &mut Vec<u8>) -> io::Result<()> {
+    block.clear();
+    block.reserve(isize);
+    DeflateDecoder::new(cdata).read_to_end(block)?;
+
+    if block.len() != isize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "BGZF block has invalid size",
+        ));
+    }
+    let mut crc = Crc::new();
+    crc.update(block);
+    if crc.sum() != crc32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "BGZF block has invalid checksum",
+        ));
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Block that is being inflated by the thread pool.
+#[derive(Debug)]
+struct BgzfPendingBlock {
+    extra: BgzfExtra,
+    address: u64,
+    size: u64,
+    result: mpsc::Receiver<io::Result<Vec<u8>>>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+type BgzfJob = (BgzfRawBlock, mpsc::SyncSender<io::Result<Vec<u8>>>);
+
+/// Pool of worker threads that inflate BGZF blocks.
+///
+/// Every job carries its own result channel, so that the reader can collect
+/// inflated blocks in file order, independent of the order in which workers
+/// finish.
+#[derive(Debug)]
+struct BgzfThreadPool {
+    sender: Option<mpsc::Sender<BgzfJob>>,
+    workers: Vec<thread::JoinHandle<()>>,
+    pending: VecDeque<io::Result<BgzfPendingBlock>>,
+    read_ahead: usize,
+    eof: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BgzfThreadPool {
+    fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<BgzfJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..threads)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    // Release the lock before inflating the block
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => break,
+                    };
+                    let (raw, result) = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    let mut block = Vec::new();
+                    let r = inflate_block(&raw.cdata, raw.crc32, raw.isize, &mut block);
+                    // The reader might have discarded the block after a seek
+                    let _ = result.send(r.map(|_| block));
+                })
+            })
+            .collect();
+
+        BgzfThreadPool {
+            sender: Some(sender),
+            workers,
+            pending: VecDeque::new(),
+            read_ahead: 4 * threads,
+            eof: false,
+        }
+    }
+
+    fn submit(&mut self, raw: BgzfRawBlock) -> io::Result<()> {
+        let (result_sender, result_receiver) = mpsc::sync_channel(1);
+        let pending = BgzfPendingBlock {
+            extra: raw.extra.clone(),
+            address: raw.address,
+            size: raw.size,
+            result: result_receiver,
+        };
+        self.sender
+            .as_ref()
+            .ok_or_else(|| io::Error::other("BGZF thread pool is shut down"))?
+            .send((raw, result_sender))
+            .map_err(|_| io::Error::other("BGZF worker threads terminated"))?;
+        self.pending.push_back(Ok(pending));
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+        self.eof = false;
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Drop for BgzfThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel terminates all workers
+        self.sender.take();
+        self.pending.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// BGZF reader that decompresses one BGZF block at a time.
+///
+/// `BgzfReader` parses the gzip header of every BGZF block and inflates the
+/// compressed data separately. This allows to keep track of virtual file
+/// offsets, which consist of the offset of the current block in the
+/// compressed file (upper 48 bits) and the offset within the uncompressed
+/// block (lower 16 bits). If the underlying reader implements `Seek`, the
+/// reader can be positioned at arbitrary virtual offsets.
 ///
-/// `BgzfReader` is a wrapper around `MultiGzDecoder` that provides
-/// additional functionality for reading BGZF compressed files, specifically
-/// the ability to extract BGZF-specific extra fields and metadata.
+/// A reader created with `with_threads` reads ahead several compressed
+/// blocks and inflates them on a pool of worker threads, while blocks are
+/// still returned in file order.
 #[derive(Debug)]
 pub struct BgzfReader<R: Read> {
-    decoder: MultiGzDecoder<R>,
+    reader: R,
+    extra: Option<BgzfExtra>,
+    cdata: Vec<u8>,
+    block: Vec<u8>,
+    block_offset: usize,
+    block_address: u64,
+    next_address: u64,
+    read_address: u64,
+    pool: Option<BgzfThreadPool>,
 }
 
 /* -------------------------------------------------------------------------- */
@@ -66,52 +241,249 @@ impl<R: Read> BgzfReader<R> {
     /// A `Result` containing a new `BgzfReader` instance if successful,
     /// or an `io::Error` if initialization fails.
     pub fn new(reader: R) -> io::Result<BgzfReader<R>> {
-        let decoder = MultiGzDecoder::new(reader);
-        Ok(BgzfReader { decoder })
+        Ok(BgzfReader {
+            reader,
+            extra: None,
+            cdata: Vec::new(),
+            block: Vec::new(),
+            block_offset: 0,
+            block_address: 0,
+            next_address: 0,
+            read_address: 0,
+            pool: None,
+        })
+    }
+
+    /// Creates a new `BgzfReader` that inflates blocks on `threads` worker
+    /// threads.
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: The reader from which BGZF-compressed data is read.
+    /// - `threads`: The number of worker threads. With zero or one thread
+    ///   blocks are inflated on the calling thread.
+    pub fn with_threads(reader: R, threads: usize) -> io::Result<BgzfReader<R>> {
+        let mut bgzf_reader = Self::new(reader)?;
+        if threads > 1 {
+            bgzf_reader.pool = Some(BgzfThreadPool::new(threads));
+        }
+        Ok(bgzf_reader)
     }
 
     /// Extracts the BGZF-specific extra fields from the compressed data.
     ///
-    /// This function reads the gzip header of the BGZF-compressed data and
-    /// retrieves the extra fields if they are present. These fields contain
-    /// metadata specific to the BGZF format.
+    /// This function returns the extra fields stored in the gzip header of
+    /// the most recently read BGZF block. These fields contain metadata
+    /// specific to the BGZF format.
     ///
     /// # Returns
     ///
     /// A `Result` containing a `BgzfExtra` struct with BGZF metadata,
-    /// or an `io::Error` if the header or extra fields cannot be read.
+    /// or an `io::Error` if no block has been read yet.
     pub fn get_extra(&mut self) -> io::Result<BgzfExtra> {
-        let header = self
-            .decoder
-            .header()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to read gzip header"))?;
-
-        if let Some(extra) = header.extra() {
-            if extra.len() != 6 {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "No extra information available",
-                ));
+        self.extra
+            .clone()
+            .ok_or_else(|| io::Error::other("No extra information available"))
+    }
+
+    /// Returns the virtual file offset of the next byte to be read.
+    ///
+    /// If the current block is exhausted, the offset points to the beginning
+    /// of the next block.
+    pub fn virtual_offset(&self) -> u64 {
+        if self.block_offset < self.block.len() {
+            (self.block_address << 16) | self.block_offset as u64
+        } else {
+            self.next_address << 16
+        }
+    }
+
+    /// Reads the next compressed BGZF block from the underlying reader.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if the end of the stream has been reached.
+    fn read_raw_block(&mut self, mut cdata: Vec<u8>) -> io::Result<Option<BgzfRawBlock>> {
+        let mut header = [0u8; 12];
+
+        // Check for a clean end of the stream
+        let n = loop {
+            match self.reader.read(&mut header[..1]) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
             }
+        };
+        if n == 0 {
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut header[1..])?;
+
+        if header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 || header[3] & 4 == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid BGZF block header",
+            ));
+        }
+        let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let mut extra = vec![0u8; xlen];
+        self.reader.read_exact(&mut extra)?;
 
-            let mut cursor = io::Cursor::new(extra);
+        // Search the BC subfield that stores the block size
+        let mut bgzf_extra = None;
+        let mut cursor = io::Cursor::new(&extra);
+        while (cursor.position() as usize) + 4 <= xlen {
             let si1 = cursor.read_u8()?;
             let si2 = cursor.read_u8()?;
             let slen = cursor.read_u16::<LittleEndian>()?;
-            let bsize = cursor.read_u16::<LittleEndian>()?;
+            if si1 == 66 && si2 == 67 && slen == 2 {
+                let bsize = cursor.read_u16::<LittleEndian>()?;
+                bgzf_extra = Some(BgzfExtra {
+                    si1,
+                    si2,
+                    slen,
+                    bsize,
+                });
+            } else {
+                cursor.set_position(cursor.position() + slen as u64);
+            }
+        }
+        let bgzf_extra = bgzf_extra
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing BGZF block size"))?;
 
-            Ok(BgzfExtra {
-                si1,
-                si2,
-                slen,
-                bsize,
-            })
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "No extra information available",
-            ))
+        let block_size = bgzf_extra.bsize as usize + 1;
+        if block_size < xlen + 20 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid BGZF block size",
+            ));
+        }
+        cdata.resize(block_size - xlen - 20, 0);
+        self.reader.read_exact(&mut cdata)?;
+
+        let crc32 = self.reader.read_u32::<LittleEndian>()?;
+        let isize = self.reader.read_u32::<LittleEndian>()? as usize;
+
+        let address = self.read_address;
+        self.read_address += block_size as u64;
+
+        Ok(Some(BgzfRawBlock {
+            extra: bgzf_extra,
+            address,
+            size: block_size as u64,
+            cdata,
+            crc32,
+            isize,
+        }))
+    }
+
+    /// Reads and inflates the next BGZF block.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(false)` if the end of the stream has been reached, `Ok(true)`
+    /// otherwise.
+    fn read_block(&mut self) -> io::Result<bool> {
+        if self.pool.is_some() {
+            return self.read_block_parallel();
+        }
+        let cdata = std::mem::take(&mut self.cdata);
+
+        let raw = match self.read_raw_block(cdata)? {
+            Some(raw) => raw,
+            None => {
+                self.block.clear();
+                self.block_offset = 0;
+                self.block_address = self.next_address;
+                return Ok(false);
+            }
+        };
+        inflate_block(&raw.cdata, raw.crc32, raw.isize, &mut self.block)?;
+
+        self.cdata = raw.cdata;
+        self.extra = Some(raw.extra);
+        self.block_offset = 0;
+        self.block_address = raw.address;
+        self.next_address = raw.address + raw.size;
+
+        Ok(true)
+    }
+
+    /// Variant of `read_block` that keeps the thread pool busy by reading
+    /// ahead compressed blocks.
+    fn read_block_parallel(&mut self) -> io::Result<bool> {
+        // Fill the queue of pending blocks
+        loop {
+            let pool = self.pool.as_mut().unwrap();
+            if pool.eof || pool.pending.len() >= pool.read_ahead {
+                break;
+            }
+            match self.read_raw_block(Vec::new()) {
+                Ok(Some(raw)) => self.pool.as_mut().unwrap().submit(raw)?,
+                Ok(None) => self.pool.as_mut().unwrap().eof = true,
+                Err(e) => {
+                    // Report the error once all preceding blocks are consumed
+                    let pool = self.pool.as_mut().unwrap();
+                    pool.pending.push_back(Err(e));
+                    pool.eof = true;
+                }
+            }
+        }
+        let pending = match self.pool.as_mut().unwrap().pending.pop_front() {
+            Some(pending) => pending?,
+            None => {
+                self.block.clear();
+                self.block_offset = 0;
+                self.block_address = self.next_address;
+                return Ok(false);
+            }
+        };
+        self.block = pending
+            .result
+            .recv()
+            .map_err(|_| io::Error::other("BGZF worker thread terminated"))??;
+        self.extra = Some(pending.extra);
+        self.block_offset = 0;
+        self.block_address = pending.address;
+        self.next_address = pending.address + pending.size;
+
+        Ok(true)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// Moves the reader to the given virtual file offset.
+    ///
+    /// # Parameters
+    ///
+    /// - `offset`: The virtual file offset, i.e. the offset of a BGZF block
+    ///   in the compressed file shifted by 16 bits plus the offset within the
+    ///   uncompressed block.
+    pub fn seek_virtual(&mut self, offset: u64) -> io::Result<()> {
+        let block_address = offset >> 16;
+        let block_offset = (offset & 0xffff) as usize;
+
+        if block_address != self.block_address || self.block.is_empty() {
+            self.reader.seek(SeekFrom::Start(block_address))?;
+            self.next_address = block_address;
+            self.read_address = block_address;
+            // Blocks read ahead are no longer valid
+            if let Some(pool) = self.pool.as_mut() {
+                pool.clear();
+            }
+            self.read_block()?;
+        }
+        if block_offset > self.block.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "virtual offset beyond end of BGZF block",
+            ));
         }
+        self.block_offset = block_offset;
+
+        Ok(())
     }
 }
 
@@ -132,10 +504,182 @@ impl<R: Read> Read for BgzfReader<R> {
     ///
     /// The number of bytes read into `buf`, or an `io::Error` if reading fails.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.decoder.read(buf)
+        // Skip exhausted and empty blocks
+        while self.block_offset >= self.block.len() {
+            if !self.read_block()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.block_offset);
+
+        buf[..n].copy_from_slice(&self.block[self.block_offset..self.block_offset + n]);
+        self.block_offset += n;
+
+        Ok(n)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// BGZF writer that compresses data into independent gzip blocks.
+///
+/// Data written to a `BgzfWriter` is buffered until `BGZF_BLOCK_SIZE` bytes
+/// are available, which are then deflated into a single BGZF block. The
+/// empty EOF block is appended when the writer is finished or dropped.
+#[derive(Debug)]
+pub struct BgzfWriter<W: Write> {
+    writer: Option<W>,
+    buffer: Vec<u8>,
+    address: u64,
+    level: Compression,
+    finished: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write> BgzfWriter<W> {
+    /// Creates a new `BgzfWriter` using the default compression level.
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The writer to which BGZF blocks are written.
+    pub fn new(writer: W) -> BgzfWriter<W> {
+        Self::with_compression(writer, Compression::default())
+    }
+
+    /// Creates a new `BgzfWriter` with the given compression level.
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: The writer to which BGZF blocks are written.
+    /// - `level`: The deflate compression level used for every block.
+    pub fn with_compression(writer: W, level: Compression) -> BgzfWriter<W> {
+        BgzfWriter {
+            writer: Some(writer),
+            buffer: Vec::with_capacity(BGZF_BLOCK_SIZE),
+            address: 0,
+            level,
+            finished: false,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+
+    /// Returns the virtual file offset of the next byte to be written.
+    pub fn virtual_offset(&self) -> u64 {
+        (self.address << 16) | self.buffer.len() as u64
+    }
+
+    /// Compresses and writes all buffered data as a single BGZF block.
+    ///
+    /// Nothing is written if the buffer is empty. Use this method to force a
+    /// block boundary, for instance after the BAM header.
+    pub fn flush_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let writer = self.writer.as_mut().unwrap();
+
+        self.address += write_block(writer, &self.buffer, self.level)? as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes all pending data followed by the BGZF EOF marker.
+    ///
+    /// The underlying writer remains available and calling this method more
+    /// than once has no further effect.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.flush_block()?;
+
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(&BGZF_EOF)?;
+        writer.flush()?;
+
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Finishes the BGZF stream and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer.take().unwrap())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write> Write for BgzfWriter<W> {
+    /// Buffers data and emits a BGZF block whenever the buffer is full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("BGZF stream already finished"));
+        }
+        let n = buf.len().min(BGZF_BLOCK_SIZE - self.buffer.len());
+
+        self.buffer.extend_from_slice(&buf[..n]);
+
+        if self.buffer.len() == BGZF_BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Writes all buffered data as a block and flushes the underlying writer.
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_block()?;
+        self.writer.as_mut().unwrap().flush()
     }
 }
 
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Compresses `data` and writes it as a single BGZF block. Returns the size
+/// of the compressed block.
+fn write_block<W: Write>(writer: &mut W, data: &[u8], level: Compression) -> io::Result<usize> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len()), level);
+    encoder.write_all(data)?;
+    let mut cdata = encoder.finish()?;
+
+    // Store data uncompressed if deflate would exceed the block size limit
+    if cdata.len() + 26 > 0x10000 {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(data)?;
+        cdata = encoder.finish()?;
+    }
+
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    // Gzip header with BGZF extra subfield
+    writer.write_all(&[0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff])?;
+    writer.write_u16::<LittleEndian>(6)?;
+    writer.write_all(b"BC")?;
+    writer.write_u16::<LittleEndian>(2)?;
+    writer.write_u16::<LittleEndian>((cdata.len() + 25) as u16)?;
+    writer.write_all(&cdata)?;
+    writer.write_u32::<LittleEndian>(crc.sum())?;
+    writer.write_u32::<LittleEndian>(data.len() as u32)?;
+
+    Ok(cdata.len() + 26)
+}
+
 /* -------------------------------------------------------------------------- */
 /* -------------------------------------------------------------------------- */
 
@@ -145,8 +689,9 @@ mod tests {
     use byteorder::LittleEndian;
     use byteorder::ReadBytesExt;
     use std::fs::File;
+    use std::io::{Cursor, Read, Write};
 
-    use crate::bgzf::{BgzfExtra, BgzfReader};
+    use crate::bgzf::{BgzfExtra, BgzfReader, BgzfWriter, BGZF_BLOCK_SIZE, BGZF_EOF};
     use crate::netfile::NetFile;
 
     #[test]
@@ -188,4 +733,79 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_bgzf_writer() {
+        let data: Vec<u8> = (0..3 * BGZF_BLOCK_SIZE + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        assert!(bytes.ends_with(&BGZF_EOF));
+
+        let mut reader = BgzfReader::new(bytes.as_slice()).unwrap();
+        let mut result = Vec::new();
+        reader.read_to_end(&mut result).unwrap();
+
+        assert_eq!(result, data);
+        assert_eq!(
+            reader.get_extra().unwrap(),
+            BgzfExtra {
+                si1: 66,
+                si2: 67,
+                slen: 2,
+                bsize: 27
+            }
+        );
+    }
+
+    #[test]
+    fn test_bgzf_reader_threads() {
+        let data: Vec<u8> = (0..20 * BGZF_BLOCK_SIZE + 123)
+            .map(|i| ((i * 7) % 253) as u8)
+            .collect();
+
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BgzfReader::with_threads(Cursor::new(bytes.clone()), 4).unwrap();
+        let mut result = Vec::new();
+        reader.read_to_end(&mut result).unwrap();
+
+        assert_eq!(result, data);
+
+        // Virtual offsets must agree with the single-threaded reader
+        let mut reader1 = BgzfReader::new(Cursor::new(bytes.clone())).unwrap();
+        let mut reader2 = BgzfReader::with_threads(Cursor::new(bytes.clone()), 3).unwrap();
+        let mut buf1 = vec![0u8; 10007];
+        let mut buf2 = vec![0u8; 10007];
+        let mut offsets = Vec::new();
+        for _ in 0..50 {
+            offsets.push(reader1.virtual_offset());
+            assert_eq!(reader1.virtual_offset(), reader2.virtual_offset());
+            reader1.read_exact(&mut buf1).unwrap();
+            reader2.read_exact(&mut buf2).unwrap();
+            assert_eq!(buf1, buf2);
+        }
+
+        // Seek backwards and forwards while blocks are read ahead
+        for (i, &offset) in offsets.iter().enumerate().rev().step_by(7) {
+            reader2.seek_virtual(offset).unwrap();
+            reader2.read_exact(&mut buf2).unwrap();
+            assert_eq!(buf2, data[i * 10007..(i + 1) * 10007]);
+        }
+
+        // Corrupted data is reported after all preceding blocks
+        let mut corrupted = bytes.clone();
+        let n = corrupted.len();
+        corrupted[n - 40] ^= 0xff;
+        let mut reader = BgzfReader::with_threads(corrupted.as_slice(), 2).unwrap();
+        let mut result = Vec::new();
+        assert!(reader.read_to_end(&mut result).is_err());
+        assert_eq!(result, data[..20 * BGZF_BLOCK_SIZE]);
+    }
 }
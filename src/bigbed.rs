@@ -0,0 +1,1235 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use async_stream::stream;
+use futures::executor::block_on_stream;
+use futures::executor::BlockingStream;
+use futures::StreamExt;
+use futures_core::stream::Stream;
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::bbi::{BData, BTree, BbiFile, BbiHeader, RTree, RVertex};
+use crate::genome::Genome;
+use crate::granges::GRanges;
+use crate::meta::{Meta, MetaData};
+use crate::netfile::NetFile;
+use crate::range::Range;
+
+/* -------------------------------------------------------------------------- */
+
+const BIGBED_MAGIC: u32 = 0x8789F2EB;
+
+/// Names of the standard BED fields in the order defined by the BED format.
+pub const BED_FIELD_NAMES: [&str; 12] = [
+    "chrom",
+    "chromStart",
+    "chromEnd",
+    "name",
+    "score",
+    "strand",
+    "thickStart",
+    "thickEnd",
+    "itemRgb",
+    "blockCount",
+    "blockSizes",
+    "chromStarts",
+];
+
+/* -------------------------------------------------------------------------- */
+
+pub fn is_bigbed_file(filename: &str) -> Result<bool, Box<dyn Error>> {
+    let mut file = NetFile::open(filename)?;
+
+    let magic = file.read_u32::<LittleEndian>()?;
+
+    Ok(BIGBED_MAGIC == magic)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A single field of an autoSql table declaration, e.g.
+/// `uint chromStart; "Start position in chromosome"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoSqlField {
+    pub kind: String,
+    pub name: String,
+    pub comment: String,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl AutoSqlField {
+    pub fn new(kind: &str, name: &str, comment: &str) -> Self {
+        AutoSqlField {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            comment: comment.to_string(),
+        }
+    }
+
+    /// Returns `true` if values of this field are stored as integers in a
+    /// `Meta` column.
+    pub fn is_int(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "int" | "uint" | "short" | "ushort" | "byte" | "ubyte" | "bigint"
+        )
+    }
+
+    /// Returns `true` if values of this field are stored as floats in a
+    /// `Meta` column.
+    pub fn is_float(&self) -> bool {
+        matches!(self.kind.as_str(), "float" | "double")
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// An autoSql table declaration, which describes the columns of a bigBed
+/// file. Only simple declarations are supported, i.e. a table name, a
+/// comment and a list of fields with type, name and comment. Array types
+/// such as `int[blockCount]` are kept as strings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoSql {
+    pub name: String,
+    pub comment: String,
+    pub fields: Vec<AutoSqlField>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl AutoSql {
+    /// Returns the declaration of the first `n` standard BED fields, where
+    /// `n` must be between 3 and 12.
+    pub fn bed(n: usize) -> Self {
+        let kinds = [
+            ("string", "Reference sequence chromosome or scaffold"),
+            ("uint", "Start position in chromosome"),
+            ("uint", "End position in chromosome"),
+            ("string", "Name of item"),
+            ("uint", "Score from 0-1000"),
+            ("char[1]", "+ or -"),
+            (
+                "uint",
+                "Start of where display should be thick (start codon)",
+            ),
+            ("uint", "End of where display should be thick (stop codon)"),
+            ("uint", "Used as itemRgb as of 2004-11-22"),
+            ("int", "Number of blocks"),
+            ("int[blockCount]", "Comma separated list of block sizes"),
+            ("int[blockCount]", "Start positions relative to chromStart"),
+        ];
+        let n = n.clamp(3, 12);
+        AutoSql {
+            name: format!("bed{}", n),
+            comment: "Browser extensible data".to_string(),
+            fields: BED_FIELD_NAMES
+                .iter()
+                .zip(kinds.iter())
+                .take(n)
+                .map(|(name, (kind, comment))| AutoSqlField::new(kind, name, comment))
+                .collect(),
+        }
+    }
+
+    /// Returns the number of leading fields that are standard BED fields.
+    pub fn defined_field_count(&self) -> usize {
+        self.fields
+            .iter()
+            .zip(BED_FIELD_NAMES.iter())
+            .take_while(|(field, &name)| field.name == name)
+            .count()
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for AutoSql {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_end_matches('\0');
+        let rest = s
+            .strip_prefix("table")
+            .ok_or("autoSql declaration must start with `table`")?;
+
+        let open = rest.find('(').ok_or("missing `(` in autoSql declaration")?;
+        let close = rest
+            .rfind(')')
+            .ok_or("missing `)` in autoSql declaration")?;
+        if close < open {
+            return Err("invalid autoSql declaration".to_string());
+        }
+
+        // Table name followed by a quoted comment
+        let head = rest[..open].trim();
+        let (name, comment) = match head.find(char::is_whitespace) {
+            Some(i) => (&head[..i], head[i..].trim()),
+            None => (head, ""),
+        };
+
+        let mut fields = Vec::new();
+        for line in rest[open + 1..close].lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (declaration, comment) = match line.find(';') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => return Err(format!("missing `;` in autoSql field `{}`", line)),
+            };
+            let tokens: Vec<&str> = declaration.split_whitespace().collect();
+            if tokens.len() < 2 {
+                return Err(format!("invalid autoSql field `{}`", line));
+            }
+            fields.push(AutoSqlField::new(
+                tokens[0],
+                tokens[tokens.len() - 1],
+                comment.trim_matches('"'),
+            ));
+        }
+        if fields.len() < 3 {
+            return Err("autoSql declaration must have at least three fields".to_string());
+        }
+
+        Ok(AutoSql {
+            name: name.to_string(),
+            comment: comment.trim_matches('"').to_string(),
+            fields,
+        })
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for AutoSql {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "table {}", self.name)?;
+        writeln!(f, "\"{}\"", self.comment)?;
+        writeln!(f, "    (")?;
+        for field in &self.fields {
+            let declaration = format!("{} {};", field.kind, field.name);
+            writeln!(f, "    {:<24}\"{}\"", declaration, field.comment)?;
+        }
+        writeln!(f, "    )")
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A bigBed item, i.e. a BED row where all fields after the end position are
+/// stored as a single tab-separated string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigBedRecord {
+    pub chrom: String,
+    pub from: usize,
+    pub to: usize,
+    pub rest: String,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigBedRecord {
+    /// Returns the field with index `i`, where the first three fields are
+    /// chromosome, start and end position.
+    pub fn field(&self, i: usize) -> Option<String> {
+        match i {
+            0 => Some(self.chrom.clone()),
+            1 => Some(self.from.to_string()),
+            2 => Some(self.to.to_string()),
+            _ => self.rest.split('\t').nth(i - 3).map(String::from),
+        }
+    }
+
+    /// Returns the fields after the end position.
+    pub fn fields(&self) -> Vec<&str> {
+        if self.rest.is_empty() {
+            Vec::new()
+        } else {
+            self.rest.split('\t').collect()
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for BigBedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t{}", self.chrom, self.from, self.to)?;
+        if !self.rest.is_empty() {
+            write!(f, "\t{}", self.rest)?;
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+pub enum OptionBigBed {
+    BlockSize(usize),
+    ItemsPerSlot(usize),
+    ExtraIndex(Vec<String>),
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[derive(Clone, Debug)]
+pub struct BigBedParameters {
+    pub block_size: usize,
+    pub items_per_slot: usize,
+    pub extra_index: Vec<String>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigBedParameters {
+    pub fn insert_option(&mut self, option: OptionBigBed) {
+        match option {
+            OptionBigBed::BlockSize(x) => self.block_size = x,
+            OptionBigBed::ItemsPerSlot(x) => self.items_per_slot = x,
+            OptionBigBed::ExtraIndex(x) => self.extra_index = x,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for BigBedParameters {
+    fn default() -> Self {
+        BigBedParameters {
+            block_size: 256,
+            items_per_slot: 512,
+            extra_index: vec![],
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Extra B+ tree index on a single field, which maps field values to the
+/// data blocks containing the corresponding items.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BigBedExtraIndex {
+    pub field_id: u16,
+    pub offset: u64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+pub enum BigBedFile {}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigBedFile {
+    pub fn new_reader(filename: &str) -> Result<BigBedReader<NetFile>, Box<dyn Error>> {
+        let file = NetFile::open(filename)?;
+
+        BigBedReader::new(file)
+    }
+
+    pub fn new_writer(
+        filename: &str,
+        genome: Genome,
+        autosql: AutoSql,
+        parameters: Vec<OptionBigBed>,
+    ) -> Result<BigBedWriter<File>, Box<dyn Error>> {
+        let file = File::create(filename)?;
+
+        BigBedWriter::new(file, genome, autosql, parameters)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[derive(Clone, Debug)]
+pub struct BigBedReader<R: Read + Seek> {
+    reader: R,
+    bbf: BbiFile,
+    genome: Genome,
+    autosql: AutoSql,
+    extra_index: Vec<BigBedExtraIndex>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: Read + Seek> BigBedReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut bbf = BbiFile::default();
+
+        reader.seek(SeekFrom::Start(0))?;
+        bbf.open::<LittleEndian, R>(&mut reader, BIGBED_MAGIC)?;
+
+        let genome = bbf.chrom_data.genome::<LittleEndian>()?;
+        let autosql = Self::read_autosql(&mut reader, &bbf.header)?;
+        let extra_index = Self::read_extra_index(&mut reader, &bbf.header)?;
+
+        Ok(BigBedReader {
+            reader,
+            bbf,
+            genome,
+            autosql,
+            extra_index,
+        })
+    }
+
+    fn read_extra_index(reader: &mut R, header: &BbiHeader) -> io::Result<Vec<BigBedExtraIndex>> {
+        let mut extra_index = Vec::new();
+        if header.extension_offset == 0 {
+            return Ok(extra_index);
+        }
+        reader.seek(SeekFrom::Start(header.extension_offset))?;
+
+        reader.read_u16::<LittleEndian>()?; // extension size
+        let n = reader.read_u16::<LittleEndian>()?;
+        let offset = reader.read_u64::<LittleEndian>()?;
+
+        reader.seek(SeekFrom::Start(offset))?;
+        for _ in 0..n {
+            let kind = reader.read_u16::<LittleEndian>()?;
+            let field_count = reader.read_u16::<LittleEndian>()?;
+            let offset = reader.read_u64::<LittleEndian>()?;
+            reader.read_u32::<LittleEndian>()?; // reserved
+
+            let mut field_ids = Vec::with_capacity(field_count as usize);
+            for _ in 0..field_count {
+                field_ids.push(reader.read_u16::<LittleEndian>()?);
+                reader.read_u16::<LittleEndian>()?; // reserved
+            }
+            // Only B+ tree indices on single fields are defined
+            if kind == 0 && field_count == 1 {
+                extra_index.push(BigBedExtraIndex {
+                    field_id: field_ids[0],
+                    offset,
+                });
+            }
+        }
+        Ok(extra_index)
+    }
+
+    fn read_autosql(reader: &mut R, header: &BbiHeader) -> Result<AutoSql, Box<dyn Error>> {
+        // Files without autoSql declaration use standard BED fields
+        if header.sql_offset == 0 {
+            return Ok(AutoSql::bed(header.field_count as usize));
+        }
+        reader.seek(SeekFrom::Start(header.sql_offset))?;
+
+        let mut text = Vec::new();
+        loop {
+            let c = reader.read_u8()?;
+            if c == 0 {
+                break;
+            }
+            text.push(c);
+        }
+        Ok(String::from_utf8_lossy(&text).parse::<AutoSql>()?)
+    }
+
+    pub fn genome(&self) -> &Genome {
+        &self.genome
+    }
+
+    pub fn header(&self) -> &BbiHeader {
+        &self.bbf.header
+    }
+
+    pub fn autosql(&self) -> &AutoSql {
+        &self.autosql
+    }
+
+    /// Returns the names of all fields with an extra index.
+    pub fn extra_index_fields(&self) -> Vec<&str> {
+        self.extra_index
+            .iter()
+            .filter_map(|index| self.autosql.fields.get(index.field_id as usize))
+            .map(|field| field.name.as_str())
+            .collect()
+    }
+
+    /// Returns all items where `field` equals `value` using the extra index
+    /// on `field`.
+    ///
+    /// # Errors
+    /// Returns an error if the file has no extra index on `field`.
+    pub fn search_records(
+        &mut self,
+        field: &str,
+        value: &str,
+    ) -> Result<Vec<BigBedRecord>, Box<dyn Error>> {
+        let index = self
+            .extra_index
+            .iter()
+            .find(|index| {
+                self.autosql
+                    .fields
+                    .get(index.field_id as usize)
+                    .is_some_and(|f| f.name == field)
+            })
+            .copied()
+            .ok_or(format!("bigBed file has no index on field `{}`", field))?;
+
+        let mut blocks: Vec<(u64, u64)> =
+            BTree::find::<LittleEndian, R>(&mut self.reader, index.offset, value.as_bytes())?
+                .iter()
+                .map(|v| {
+                    (
+                        LittleEndian::read_u64(&v[0..8]),
+                        LittleEndian::read_u64(&v[8..16]),
+                    )
+                })
+                .collect();
+        blocks.sort_unstable();
+        blocks.dedup();
+
+        let mut records = Vec::new();
+        for (offset, size) in blocks {
+            let block = self.bbf.read_block(&mut self.reader, offset, size)?;
+
+            for item in BigBedBlockDecoder::new(&block) {
+                let (chrom_id, from, to, rest) = item?;
+                let chrom = self
+                    .genome
+                    .seqnames
+                    .get(chrom_id as usize)
+                    .ok_or("invalid chromosome index in bigBed item")?;
+                let record = BigBedRecord {
+                    chrom: chrom.clone(),
+                    from: from as usize,
+                    to: to as usize,
+                    rest,
+                };
+                if record.field(index.field_id as usize).as_deref() == Some(value) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Returns all items where `field` equals `value` as `GRanges`, see
+    /// `search_records` and `query_granges`.
+    pub fn search(&mut self, field: &str, value: &str) -> Result<GRanges, Box<dyn Error>> {
+        let records = self.search_records(field, value)?;
+        self.records_to_granges(records)
+    }
+
+    /// Returns all items that overlap the region `[from, to)` on every
+    /// chromosome matching the regular expression `seq_regex`.
+    pub fn query_stream<'a>(
+        &'a mut self,
+        seq_regex: &'a str,
+        from: usize,
+        to: usize,
+    ) -> impl Stream<Item = io::Result<BigBedRecord>> + 'a {
+        stream! {
+
+            let re = match regex::Regex::new(&format!("^{}$", seq_regex)) {
+                Ok(re) => re,
+                Err(e) => {
+                    yield Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+                    return;
+                }
+            };
+            let from = from.min(u32::MAX as usize) as u32;
+            let to = to.min(u32::MAX as usize) as u32;
+
+            for seqname in &self.genome.seqnames {
+                if !re.is_match(seqname) {
+                    continue;
+                }
+                let idx = match self.genome.get_idx(seqname) {
+                    Some(idx) => idx as u32,
+                    None => continue,
+                };
+
+                let mut iterator = Box::pin(self.bbf.query_blocks_stream::<LittleEndian, R>(&mut self.reader, idx, from, to));
+
+                while let Some(item) = iterator.next().await {
+
+                    let block = match item {
+                        Ok(block) => block,
+                        Err(e) => { yield Err(e); return; }
+                    };
+
+                    for record in BigBedBlockDecoder::new(&block) {
+                        match record {
+                            Err(e) => { yield Err(e); return; }
+                            Ok((chrom_id, start, end, rest)) => {
+                                // Items of length zero (insertions) overlap a
+                                // region if they are located within it
+                                if chrom_id != idx || start >= to || (end <= from && !(start == end && start == from)) {
+                                    continue;
+                                }
+                                yield Ok(BigBedRecord {
+                                    chrom: seqname.clone(),
+                                    from: start as usize,
+                                    to: end as usize,
+                                    rest,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn query<'a>(
+        &'a mut self,
+        seq_regex: &'a str,
+        from: usize,
+        to: usize,
+    ) -> BlockingStream<impl Stream<Item = io::Result<BigBedRecord>> + 'a> {
+        let s = Box::pin(self.query_stream(seq_regex, from, to));
+
+        block_on_stream(s)
+    }
+
+    /// Returns all items that overlap the region `[from, to)` on chromosomes
+    /// matching `seq_regex` as `GRanges`. Fields are converted to typed
+    /// `Meta` columns according to the autoSql declaration, where integer
+    /// types become `IntArray`, `float` and `double` become `FloatArray`,
+    /// and all other types `StringArray`. A `strand` field is stored as the
+    /// strand of the ranges.
+    pub fn query_granges(
+        &mut self,
+        seq_regex: &str,
+        from: usize,
+        to: usize,
+    ) -> Result<GRanges, Box<dyn Error>> {
+        let records = self
+            .query(seq_regex, from, to)
+            .collect::<io::Result<Vec<_>>>()?;
+        self.records_to_granges(records)
+    }
+
+    fn records_to_granges(&self, records: Vec<BigBedRecord>) -> Result<GRanges, Box<dyn Error>> {
+        let fields = self.autosql.fields[3..].to_vec();
+        let strand_idx = fields.iter().position(|f| f.name == "strand");
+
+        let mut seqnames = Vec::new();
+        let mut ranges = Vec::new();
+        let mut strand = Vec::new();
+        let mut columns: Vec<MetaData> = fields
+            .iter()
+            .map(|f| {
+                if f.is_int() {
+                    MetaData::IntArray(Vec::new())
+                } else if f.is_float() {
+                    MetaData::FloatArray(Vec::new())
+                } else {
+                    MetaData::StringArray(Vec::new())
+                }
+            })
+            .collect();
+
+        for record in &records {
+            let values = record.fields();
+
+            if values.len() != fields.len() {
+                return Err(format!(
+                    "bigBed item `{}` has {} fields, but autoSql declares {}",
+                    record,
+                    values.len() + 3,
+                    fields.len() + 3
+                )
+                .into());
+            }
+            for (j, (value, column)) in values.iter().zip(columns.iter_mut()).enumerate() {
+                if Some(j) == strand_idx {
+                    continue;
+                }
+                let invalid = || format!("invalid value `{}` in field `{}`", value, fields[j].name);
+                match column {
+                    MetaData::IntArray(v) => v.push(value.parse().map_err(|_| invalid())?),
+                    MetaData::FloatArray(v) => v.push(value.parse().map_err(|_| invalid())?),
+                    MetaData::StringArray(v) => v.push(value.to_string()),
+                    _ => unreachable!(),
+                }
+            }
+            strand.push(match strand_idx {
+                Some(j) => values[j].chars().next().unwrap_or('*'),
+                None => '*',
+            });
+            seqnames.push(record.chrom.clone());
+            ranges.push(Range::new(record.from, record.to));
+        }
+
+        let mut granges = GRanges {
+            seqnames,
+            ranges,
+            strand,
+            meta: Meta::default(),
+        };
+
+        for (j, (field, column)) in fields.iter().zip(columns).enumerate() {
+            if Some(j) != strand_idx {
+                granges.meta.add(&field.name, column)?;
+            }
+        }
+        Ok(granges)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Iterates over the items of an uncompressed bigBed data block, each
+/// consisting of chromosome index, start, end and a zero-terminated string
+/// with the remaining fields.
+struct BigBedBlockDecoder<'a> {
+    buffer: &'a [u8],
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<'a> BigBedBlockDecoder<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        BigBedBlockDecoder { buffer }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<'a> Iterator for BigBedBlockDecoder<'a> {
+    type Item = io::Result<(u32, u32, u32, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        if self.buffer.len() < 13 {
+            self.buffer = &[];
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated bigBed item",
+            )));
+        }
+        let chrom_id = LittleEndian::read_u32(&self.buffer[0..4]);
+        let start = LittleEndian::read_u32(&self.buffer[4..8]);
+        let end = LittleEndian::read_u32(&self.buffer[8..12]);
+
+        let rest = &self.buffer[12..];
+        let n = match rest.iter().position(|&c| c == 0) {
+            Some(n) => n,
+            None => {
+                self.buffer = &[];
+                return Some(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unterminated bigBed item",
+                )));
+            }
+        };
+        let rest_str = String::from_utf8_lossy(&rest[..n]).to_string();
+        self.buffer = &rest[n + 1..];
+
+        Some(Ok((chrom_id, start, end, rest_str)))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Writer for bigBed files. Items must be written sorted by chromosome, in
+/// the order of the genome, and by start position. Zoom levels are not
+/// written, which genome browsers only use to speed up display of large
+/// regions.
+#[derive(Clone, Debug)]
+pub struct BigBedWriter<W: Write + Seek> {
+    writer: W,
+    bbf: BbiFile,
+    genome: Genome,
+    autosql: AutoSql,
+    parameters: BigBedParameters,
+    leaves: Vec<RVertex>,
+    vertex: RVertex,
+    block: Vec<u8>,
+    block_items: usize,
+    block_range: (u32, u32, u32),
+    last: Option<(u32, u32)>,
+    intervals: Vec<(u32, u32)>,
+    n_items: u64,
+    extra_fields: Vec<usize>,
+    extra_keys: Vec<Vec<String>>,
+    extra_items: Vec<Vec<(String, u64, u64)>>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write + Seek> BigBedWriter<W> {
+    pub fn new(
+        writer: W,
+        genome: Genome,
+        autosql: AutoSql,
+        parameters_arg: Vec<OptionBigBed>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut parameters = BigBedParameters::default();
+
+        for parameter in parameters_arg {
+            parameters.insert_option(parameter);
+        }
+        if parameters.block_size == 0 || parameters.items_per_slot == 0 {
+            return Err("block size and items per slot must be positive".into());
+        }
+
+        let mut extra_fields = Vec::new();
+        for name in &parameters.extra_index {
+            let i = autosql
+                .fields
+                .iter()
+                .position(|f| &f.name == name)
+                .ok_or(format!("cannot index unknown field `{}`", name))?;
+            extra_fields.push(i);
+        }
+
+        let mut bbf = BbiFile::default();
+        bbf.header.magic = BIGBED_MAGIC;
+        bbf.header.field_count = autosql.fields.len() as u16;
+        bbf.header.defined_field_count = autosql.defined_field_count() as u16;
+        bbf.header.uncompress_buf_size = 1;
+        bbf.chrom_data.value_size = 8;
+
+        let mut bbw = BigBedWriter {
+            writer,
+            bbf,
+            genome,
+            autosql,
+            parameters,
+            leaves: Vec::new(),
+            vertex: RVertex::new_leaf(),
+            block: Vec::new(),
+            block_items: 0,
+            block_range: (0, 0, 0),
+            last: None,
+            intervals: Vec::new(),
+            n_items: 0,
+            extra_keys: vec![Vec::new(); extra_fields.len()],
+            extra_items: vec![Vec::new(); extra_fields.len()],
+            extra_fields,
+        };
+
+        bbw.bbf.create::<LittleEndian, W>(&mut bbw.writer)?;
+
+        Ok(bbw)
+    }
+
+    pub fn autosql(&self) -> &AutoSql {
+        &self.autosql
+    }
+
+    /// Writes a single item.
+    ///
+    /// # Errors
+    /// Returns an error if the chromosome is not part of the genome, items
+    /// are not sorted, or the number of fields does not match the autoSql
+    /// declaration.
+    pub fn write(&mut self, record: &BigBedRecord) -> Result<(), Box<dyn Error>> {
+        let idx = self.genome.get_idx(&record.chrom).ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Sequence '{}' not found", record.chrom),
+        ))? as u32;
+
+        if record.from > record.to || record.to > u32::MAX as usize {
+            return Err(format!("invalid bigBed item `{}`", record).into());
+        }
+        let (from, to) = (record.from as u32, record.to as u32);
+
+        let n_fields = record.fields().len() + 3;
+        if n_fields != self.autosql.fields.len() {
+            return Err(format!(
+                "bigBed item `{}` has {} fields, but autoSql declares {}",
+                record,
+                n_fields,
+                self.autosql.fields.len()
+            )
+            .into());
+        }
+        if let Some(last) = self.last {
+            if (idx, from) < last {
+                return Err(format!("bigBed items are not sorted at `{}`", record).into());
+            }
+            if idx != last.0 {
+                self.flush_block()?;
+                self.add_coverage();
+            }
+        }
+        if self.block_items == self.parameters.items_per_slot {
+            self.flush_block()?;
+        }
+
+        if self.block_items == 0 {
+            self.block_range = (idx, from, to);
+        }
+        self.block_range.2 = self.block_range.2.max(to);
+        self.block.extend_from_slice(&idx.to_le_bytes());
+        self.block.extend_from_slice(&from.to_le_bytes());
+        self.block.extend_from_slice(&to.to_le_bytes());
+        self.block.extend_from_slice(record.rest.as_bytes());
+        self.block.push(0);
+        self.block_items += 1;
+
+        for (keys, &i) in self.extra_keys.iter_mut().zip(self.extra_fields.iter()) {
+            keys.push(record.field(i).unwrap_or_default());
+        }
+
+        self.intervals.push((from, to));
+        self.last = Some((idx, from));
+        self.n_items += 1;
+
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        if self.block_items == 0 {
+            return Ok(());
+        }
+        if self.vertex.n_children as usize == self.parameters.block_size {
+            let vertex = std::mem::replace(&mut self.vertex, RVertex::new_leaf());
+            self.leaves.push(vertex);
+        }
+        let (idx, from, to) = self.block_range;
+        let i = self.vertex.n_children as usize;
+
+        self.vertex.add_block(idx, from, to);
+        self.vertex.write_block::<LittleEndian, W>(
+            &mut self.writer,
+            &mut self.bbf,
+            i,
+            &self.block,
+        )?;
+        self.bbf.header.n_blocks += 1;
+
+        let (offset, size) = (self.vertex.data_offset[i], self.vertex.sizes[i]);
+        for (keys, items) in self.extra_keys.iter_mut().zip(self.extra_items.iter_mut()) {
+            items.extend(keys.drain(..).map(|key| (key, offset, size)));
+        }
+
+        self.block.clear();
+        self.block_items = 0;
+        Ok(())
+    }
+
+    /// Adds the coverage of the items of the current chromosome to the
+    /// summary statistics of the file.
+    fn add_coverage(&mut self) {
+        let mut events: Vec<(u32, i64)> = Vec::with_capacity(2 * self.intervals.len());
+        for &(from, to) in &self.intervals {
+            events.push((from, 1));
+            events.push((to, -1));
+        }
+        events.sort_unstable();
+        self.intervals.clear();
+
+        let header = &mut self.bbf.header;
+        let mut depth = 0;
+        let mut position = 0;
+        for (x, delta) in events {
+            if depth > 0 && x > position {
+                let n = (x - position) as u64;
+                let d = depth as f64;
+                if header.min_val.is_nan() || header.min_val > d {
+                    header.min_val = d;
+                }
+                if header.max_val.is_nan() || header.max_val < d {
+                    header.max_val = d;
+                }
+                header.n_bases_covered += n;
+                header.sum_data += d * n as f64;
+                header.sum_squares += d * d * n as f64;
+            }
+            depth += delta;
+            position = x;
+        }
+    }
+
+    /// Writes a B+ tree for each extra index, followed by the list of extra
+    /// indices and the extension header that points to it.
+    fn write_extra_index(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.extra_fields.is_empty() {
+            return Ok(());
+        }
+        let mut offsets = Vec::with_capacity(self.extra_fields.len());
+
+        for items in &mut self.extra_items {
+            items.sort_unstable();
+            items.dedup();
+
+            let mut data = BData::default();
+            data.key_size = items
+                .iter()
+                .map(|item| item.0.len())
+                .max()
+                .unwrap_or(0)
+                .max(1) as u32;
+            data.value_size = 16;
+
+            for (key, offset, size) in items.iter() {
+                let mut k = key.as_bytes().to_vec();
+                k.resize(data.key_size as usize, 0);
+                let mut v = Vec::with_capacity(16);
+                v.extend_from_slice(&offset.to_le_bytes());
+                v.extend_from_slice(&size.to_le_bytes());
+                data.add(k, v)?;
+            }
+            data.items_per_block = (self.parameters.block_size as u32).max(2);
+
+            offsets.push(self.writer.stream_position()?);
+            data.write::<LittleEndian, W>(&mut self.writer)?;
+        }
+
+        // List of extra indices, each consisting of type, number of fields,
+        // offset of the B+ tree and a single field id
+        let list_offset = self.writer.stream_position()?;
+        for (&i, &offset) in self.extra_fields.iter().zip(offsets.iter()) {
+            self.writer.write_all(&0u16.to_le_bytes())?;
+            self.writer.write_all(&1u16.to_le_bytes())?;
+            self.writer.write_all(&offset.to_le_bytes())?;
+            self.writer.write_all(&[0; 4])?;
+            self.writer.write_all(&(i as u16).to_le_bytes())?;
+            self.writer.write_all(&[0; 2])?;
+        }
+
+        // Extension header of 64 bytes
+        self.bbf.header.extension_offset = self.writer.stream_position()?;
+        self.writer.write_all(&64u16.to_le_bytes())?;
+        self.writer
+            .write_all(&(self.extra_fields.len() as u16).to_le_bytes())?;
+        self.writer.write_all(&list_offset.to_le_bytes())?;
+        self.writer.write_all(&[0; 52])?;
+
+        self.bbf
+            .header
+            .write_offsets::<LittleEndian, W>(&mut self.writer)?;
+
+        Ok(())
+    }
+
+    /// Writes the index, chromosome list, autoSql declaration and summary.
+    /// Must be called after all items have been written.
+    pub fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        self.flush_block()?;
+        self.add_coverage();
+
+        // Write index
+        let mut tree = RTree::default();
+        tree.block_size = self.parameters.block_size as u32;
+        tree.n_items_per_slot = self.parameters.items_per_slot as u32;
+
+        let vertex = std::mem::take(&mut self.vertex);
+        if vertex.n_children > 0 {
+            self.leaves.push(vertex);
+        }
+        let leaves = std::mem::take(&mut self.leaves);
+        if leaves.is_empty() {
+            tree.root = Some(RVertex::new_leaf());
+        } else {
+            tree.build_tree(leaves)?;
+        }
+        self.bbf.index = tree;
+        self.bbf.write_index::<LittleEndian, W>(&mut self.writer)?;
+
+        // Write chromosome list, sorted by name as required for searching
+        // the B+ tree
+        for name in &self.genome.seqnames {
+            if self.bbf.chrom_data.key_size < (name.len() + 1) as u32 {
+                self.bbf.chrom_data.key_size = (name.len() + 1) as u32;
+            }
+        }
+        let mut indices: Vec<usize> = (0..self.genome.len()).collect();
+        indices.sort_by(|&i, &j| self.genome.seqnames[i].cmp(&self.genome.seqnames[j]));
+
+        for idx in indices {
+            let name = &self.genome.seqnames[idx];
+            let mut key = vec![0; self.bbf.chrom_data.key_size as usize];
+            let mut value = vec![0; self.bbf.chrom_data.value_size as usize];
+            key[..name.len()].copy_from_slice(name.as_bytes());
+            value[..4].copy_from_slice(&(idx as u32).to_le_bytes());
+            value[4..8].copy_from_slice(&(self.genome.lengths[idx] as u32).to_le_bytes());
+
+            self.bbf.chrom_data.add(key, value)?;
+        }
+        self.bbf
+            .write_chrom_list::<LittleEndian, W>(&mut self.writer)?;
+
+        // Write autoSql declaration
+        self.bbf.header.sql_offset = self.writer.stream_position()?;
+        self.writer.write_all(self.autosql.to_string().as_bytes())?;
+        self.writer.write_all(&[0])?;
+        self.bbf
+            .header
+            .write_offsets::<LittleEndian, W>(&mut self.writer)?;
+
+        self.write_extra_index()?;
+
+        // The data section of bigBed files starts with the number of items
+        // instead of the number of blocks
+        self.bbf.header.n_blocks = self.n_items;
+        self.bbf
+            .header
+            .write_n_blocks::<LittleEndian, W>(&mut self.writer)?;
+
+        self.writer.seek(SeekFrom::End(0))?;
+        self.bbf
+            .header
+            .write_summary::<LittleEndian, W>(&mut self.writer)?;
+        self.writer
+            .write_all(&self.bbf.header.magic.to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bigbed::{AutoSql, BigBedReader, BigBedRecord, BigBedWriter, OptionBigBed};
+    use crate::genome::Genome;
+
+    #[test]
+    fn test_autosql() {
+        let text = "table narrowPeak\n\"BED6+4 Peaks\"\n(\n    string chrom;        \"Reference sequence\"\n    uint   chromStart;   \"Start\"\n    uint   chromEnd;     \"End\"\n    string name;         \"Name\"\n    uint   score;        \"Score\"\n    char[1] strand;      \"+ or -\"\n    float  signalValue;  \"Signal\"\n    int    peak;         \"Peak\"\n)\n";
+        let autosql = text.parse::<AutoSql>().unwrap();
+
+        assert_eq!(autosql.name, "narrowPeak");
+        assert_eq!(autosql.comment, "BED6+4 Peaks");
+        assert_eq!(autosql.fields.len(), 8);
+        assert_eq!(autosql.fields[5].kind, "char[1]");
+        assert_eq!(autosql.fields[6].name, "signalValue");
+        assert!(autosql.fields[6].is_float());
+        assert!(autosql.fields[7].is_int());
+        assert_eq!(autosql.defined_field_count(), 6);
+        assert_eq!(autosql.to_string().parse::<AutoSql>().unwrap(), autosql);
+
+        assert_eq!(AutoSql::bed(12).fields[11].name, "chromStarts");
+        assert_eq!(AutoSql::bed(12).defined_field_count(), 12);
+        assert!("table x\n\"\"\n(\nstring chrom;\n)"
+            .parse::<AutoSql>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_bigbed_write_read() {
+        let genome = Genome::new(
+            vec!["chr2".to_string(), "chr1".to_string()],
+            vec![100000, 200000],
+        );
+        let record = |chrom: &str, from: usize, to: usize, i: usize| BigBedRecord {
+            chrom: chrom.to_string(),
+            from,
+            to,
+            rest: format!("item{}\t{}\t+", i, i % 1000),
+        };
+        let mut records = Vec::new();
+        for i in 0..1000 {
+            records.push(record("chr2", 10 * i, 10 * i + 25, i));
+        }
+        for i in 0..500 {
+            records.push(record("chr1", 100 * i, 100 * i + 50, 1000 + i));
+        }
+
+        let mut bbw = BigBedWriter::new(
+            Cursor::new(Vec::new()),
+            genome.clone(),
+            AutoSql::bed(6),
+            vec![OptionBigBed::ItemsPerSlot(16), OptionBigBed::BlockSize(4)],
+        )
+        .unwrap();
+        for r in &records {
+            bbw.write(r).unwrap();
+        }
+        // Items must be sorted
+        assert!(bbw.write(&record("chr2", 0, 10, 0)).is_err());
+        bbw.close().unwrap();
+
+        let data = bbw.writer.into_inner();
+        let mut bbr = BigBedReader::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(bbr.genome(), &genome);
+        assert_eq!(bbr.autosql(), &AutoSql::bed(6));
+        assert_eq!(bbr.header().field_count, 6);
+        assert_eq!(bbr.header().defined_field_count, 6);
+        assert_eq!(bbr.header().n_blocks, 1500);
+
+        let all: Vec<BigBedRecord> = bbr.query(".*", 0, usize::MAX).map(|r| r.unwrap()).collect();
+        assert_eq!(all, records);
+
+        let result: Vec<BigBedRecord> = bbr.query("chr2", 1000, 1030).map(|r| r.unwrap()).collect();
+        assert_eq!(result, records[98..103].to_vec());
+
+        let result: Vec<BigBedRecord> = bbr.query("chr1", 1050, 1100).map(|r| r.unwrap()).collect();
+        assert!(result.is_empty());
+
+        // Items on chr2 overlap and cover [0, 10015)
+        assert_eq!(bbr.header().n_bases_covered, 10015 + 25000);
+        assert_eq!(bbr.header().max_val, 3.0);
+    }
+
+    #[test]
+    fn test_bigbed_extra_index() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![100000]);
+
+        let mut bbw = BigBedWriter::new(
+            Cursor::new(Vec::new()),
+            genome,
+            AutoSql::bed(6),
+            vec![
+                OptionBigBed::BlockSize(4),
+                OptionBigBed::ItemsPerSlot(8),
+                OptionBigBed::ExtraIndex(vec!["name".to_string()]),
+            ],
+        )
+        .unwrap();
+        for i in 0..500 {
+            // Names repeat every 100 items and span many blocks
+            let record = BigBedRecord {
+                chrom: "chr1".to_string(),
+                from: 10 * i,
+                to: 10 * i + 5,
+                rest: format!("gene{}\t{}\t-", i % 100, i),
+            };
+            bbw.write(&record).unwrap();
+        }
+        bbw.close().unwrap();
+
+        let data = bbw.writer.into_inner();
+        let mut bbr = BigBedReader::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(bbr.extra_index_fields(), vec!["name"]);
+
+        let records = bbr.search_records("name", "gene7").unwrap();
+        let from: Vec<usize> = records.iter().map(|r| r.from).collect();
+        assert_eq!(from, vec![70, 1070, 2070, 3070, 4070]);
+
+        let granges = bbr.search("name", "gene42").unwrap();
+        assert_eq!(granges.num_rows(), 5);
+        assert_eq!(granges.ranges[0].from, 420);
+        assert_eq!(granges.strand[0], '-');
+
+        assert!(bbr.search_records("name", "gene100").unwrap().is_empty());
+        assert!(bbr.search("score", "1").is_err());
+    }
+}
@@ -19,11 +19,16 @@
 // SOFTWARE.
 
 use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::process;
 use std::result::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use async_stream::stream;
 use futures::executor::block_on_stream;
@@ -31,12 +36,13 @@ use futures::executor::BlockingStream;
 use futures::StreamExt;
 use futures_core::stream::Stream;
 
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::bbi::{
-    BbiFile, BbiHeader, BbiHeaderZoom, BbiQueryType, BbiSummaryRecord, BbiSummaryStatistics, RTree,
-    RVertex, RVertexGenerator,
+    BbiFile, BbiHeader, BbiHeaderZoom, BbiQueryType, BbiSummaryRecord, BbiSummaryStatistics,
+    BbiZoomRecord, RTree, RVertex, RVertexGenerator,
 };
+use crate::bbi::{BBI_MAX_ZOOM_LEVELS, BBI_RES_INCREMENT};
 use crate::bbi::{BBI_TYPE_BED_GRAPH, BBI_TYPE_FIXED, BBI_TYPE_VARIABLE};
 use crate::genome::Genome;
 use crate::netfile::NetFile;
@@ -193,15 +199,25 @@ impl BigWigFile {
 
         BigWigWriter::new(file, genome, parameters)
     }
+
+    pub fn new_stream_writer(
+        filename: &str,
+        genome: Genome,
+        parameters: Vec<OptionBigWig>,
+    ) -> Result<BigWigStreamWriter<BufWriter<File>>, Box<dyn Error>> {
+        let file = BufWriter::new(File::create(filename)?);
+
+        BigWigStreamWriter::new(file, genome, parameters)
+    }
 }
 
 /* -------------------------------------------------------------------------- */
 
 #[derive(Clone, Debug)]
 pub struct BigWigReader<R: Read + Seek> {
-    reader: R,
-    bwf: BbiFile,
-    genome: Genome,
+    pub(crate) reader: R,
+    pub(crate) bwf: BbiFile,
+    pub(crate) genome: Genome,
 }
 
 /* -------------------------------------------------------------------------- */
@@ -230,31 +246,7 @@ impl<R: Read + Seek> BigWigReader<R> {
     }
 
     fn initialize<E: ByteOrder>(mut self) -> io::Result<Self> {
-        self.genome.seqnames = vec![String::new(); self.bwf.chrom_data.keys.len()];
-        self.genome.lengths = vec![0; self.bwf.chrom_data.keys.len()];
-
-        for i in 0..self.bwf.chrom_data.keys.len() {
-            if self.bwf.chrom_data.values[i].len() != 8 {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "invalid chromosome list",
-                ));
-            }
-
-            let idx = (&self.bwf.chrom_data.values[i][0..4]).read_u32::<E>()? as usize;
-
-            if idx >= self.bwf.chrom_data.keys.len() {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "invalid chromosome index",
-                ));
-            }
-            self.genome.seqnames[idx] = String::from_utf8_lossy(&self.bwf.chrom_data.keys[i])
-                .trim_end_matches('\x00')
-                .to_string();
-            self.genome.lengths[idx] =
-                (&self.bwf.chrom_data.values[i][4..8]).read_u32::<E>()? as usize;
-        }
+        self.genome = self.bwf.chrom_data.genome::<E>()?;
 
         Ok(self)
     }
@@ -506,6 +498,22 @@ impl<W: Write + Seek> BigWigWriter<W> {
         self.leaves.clear();
     }
 
+    fn build_index(&mut self) -> io::Result<RTree> {
+        let mut tree = RTree::default();
+        tree.block_size = self.parameters.block_size as u32;
+        tree.n_items_per_slot = self.parameters.items_per_slot as u32;
+
+        let leaves = self.get_leaves_sorted();
+        if leaves.is_empty() {
+            tree.root = Some(RVertex::new_leaf());
+        } else {
+            tree.build_tree(leaves)?;
+        }
+        self.reset_leaf_map();
+
+        Ok(tree)
+    }
+
     pub fn parameters(&self) -> &BigWigParameters {
         return &self.parameters;
     }
@@ -627,28 +635,12 @@ impl<W: Write + Seek> BigWigWriter<W> {
     }
 
     pub fn write_index(&mut self) -> Result<(), Box<dyn Error>> {
-        let mut tree = RTree::default();
-        tree.block_size = self.parameters.block_size as u32;
-        tree.n_items_per_slot = self.parameters.items_per_slot as u32;
-
-        let leaves = self.get_leaves_sorted();
-        tree.build_tree(leaves)?;
-
-        self.reset_leaf_map();
-        self.bwf.index = tree;
+        self.bwf.index = self.build_index()?;
         Ok(self.bwf.write_index::<LittleEndian, W>(&mut self.writer)?)
     }
 
     pub fn write_index_zoom(&mut self, i: usize) -> Result<(), Box<dyn Error>> {
-        let mut tree = RTree::default();
-        tree.block_size = self.parameters.block_size as u32;
-        tree.n_items_per_slot = self.parameters.items_per_slot as u32;
-
-        let leaves = self.get_leaves_sorted();
-        tree.build_tree(leaves)?;
-
-        self.reset_leaf_map();
-        self.bwf.index_zoom[i] = tree;
+        self.bwf.index_zoom[i] = self.build_index()?;
         Ok(self
             .bwf
             .write_index_zoom::<LittleEndian, W>(&mut self.writer, i)?)
@@ -672,20 +664,25 @@ impl<W: Write + Seek> BigWigWriter<W> {
             }
         }
 
-        for name in &self.genome.seqnames {
+        // Chromosomes are sorted by name as required for searching the B+
+        // tree, which has more than one level if there are more chromosomes
+        // than fit into a block
+        let mut indices: Vec<usize> = (0..self.genome.len()).collect();
+        indices.sort_by(|&i, &j| self.genome.seqnames[i].cmp(&self.genome.seqnames[j]));
+
+        for idx in indices {
+            let name = &self.genome.seqnames[idx];
             let mut key = vec![0; self.bwf.chrom_data.key_size as usize];
             let mut value = vec![0; self.bwf.chrom_data.value_size as usize];
             key[..name.len()].copy_from_slice(name.as_bytes());
-
-            let idx = self.genome.get_idx(name).ok_or(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Sequence '{}' not found", name),
-            ))?;
             value[..4].copy_from_slice(&(idx as u32).to_le_bytes());
-            value[4..8].copy_from_slice(&(self.genome.lengths[idx as usize] as u32).to_le_bytes());
+            value[4..8].copy_from_slice(&(self.genome.lengths[idx] as u32).to_le_bytes());
 
             self.bwf.chrom_data.add(key, value)?;
         }
+        self.bwf.chrom_data.items_per_block = (self.parameters.block_size as u32)
+            .min(self.bwf.chrom_data.items_per_block)
+            .max(2);
 
         self.bwf
             .write_chrom_list::<LittleEndian, W>(&mut self.writer)?;
@@ -708,9 +705,581 @@ impl<W: Write + Seek> BigWigWriter<W> {
     }
 }
 
+/* -------------------------------------------------------------------------- */
+
+static BIGWIG_ZOOM_BUFFER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Zoom records of a single reduction level. Records are spooled to a
+/// temporary file while raw data is written and read back when the zoom
+/// level is written, so that memory usage does not grow with the data. The
+/// file is created exclusively and removed when the buffer is dropped.
+#[derive(Debug)]
+struct BigWigZoomBuffer {
+    reduction_level: u32,
+    record: BbiZoomRecord,
+    n_records: u64,
+    path: PathBuf,
+    file: BufWriter<File>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigWigZoomBuffer {
+    fn new(reduction_level: u32) -> io::Result<Self> {
+        let (path, file) = loop {
+            let id = BIGWIG_ZOOM_BUFFER_ID.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("rustynetics-{}-{}.zoom", process::id(), id));
+            // Never reuse an existing file, which might be owned by someone else
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => break (path, BufWriter::new(file)),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error),
+            }
+        };
+
+        Ok(BigWigZoomBuffer {
+            reduction_level,
+            record: BbiZoomRecord::default(),
+            n_records: 0,
+            path,
+            file,
+        })
+    }
+
+    /// Adds value `x` covering `[from, to)` to the zoom records, which span
+    /// multiples of the reduction level clipped at the chromosome `length`.
+    fn add(&mut self, chrom_id: u32, from: u32, to: u32, x: f64, length: u32) -> io::Result<()> {
+        let r = self.reduction_level;
+        let mut p = from;
+        while p < to {
+            let start = p - p % r;
+            if self.record.valid > 0
+                && (self.record.chrom_id != chrom_id || self.record.start != start)
+            {
+                self.flush()?;
+            }
+            if self.record.valid == 0 {
+                self.record = BbiZoomRecord {
+                    chrom_id,
+                    start,
+                    end: start.saturating_add(r).min(length),
+                    min: f32::NAN,
+                    max: f32::NAN,
+                    ..Default::default()
+                };
+            }
+            let q = to.min(start.saturating_add(r));
+            self.record.add_interval(x, q - p);
+            p = q;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.record.valid > 0 {
+            self.record.write::<LittleEndian, _>(&mut self.file)?;
+            self.record.valid = 0;
+            self.n_records += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns a reader for all records added so far. The reader shares the
+    /// file handle of the buffer, so no further records may be added.
+    fn records(&mut self) -> io::Result<BufReader<File>> {
+        self.flush()?;
+        self.file.flush()?;
+        let mut file = self.file.get_ref().try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(BufReader::new(file))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Drop for BigWigZoomBuffer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Items of the data block that is currently filled by a stream writer.
+#[derive(Clone, Debug, Default)]
+struct BigWigStreamBlock {
+    kind: u8,
+    chrom_id: u32,
+    start: u32,
+    end: u32,
+    step: u32,
+    span: u32,
+    item_count: u16,
+    data: Vec<u8>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigWigStreamBlock {
+    fn accepts(
+        &self,
+        kind: u8,
+        chrom_id: u32,
+        from: u32,
+        step: u32,
+        span: u32,
+        items_per_slot: usize,
+    ) -> bool {
+        if self.item_count == 0 {
+            return true;
+        }
+        // Fixed step blocks cannot have gaps
+        let contiguous =
+            kind != BBI_TYPE_FIXED || from == self.start + self.item_count as u32 * step;
+
+        (self.item_count as usize) < items_per_slot
+            && self.kind == kind
+            && self.chrom_id == chrom_id
+            && self.step == step
+            && self.span == span
+            && contiguous
+    }
+
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(24 + self.data.len());
+
+        buffer.write_u32::<LittleEndian>(self.chrom_id)?;
+        buffer.write_u32::<LittleEndian>(self.start)?;
+        buffer.write_u32::<LittleEndian>(self.end)?;
+        buffer.write_u32::<LittleEndian>(self.step)?;
+        buffer.write_u32::<LittleEndian>(self.span)?;
+        buffer.write_u8(self.kind)?;
+        buffer.write_u8(0)?;
+        buffer.write_u16::<LittleEndian>(self.item_count)?;
+        buffer.extend_from_slice(&self.data);
+
+        Ok(buffer)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Writer for bigWig files that accepts data one item at a time, so that
+/// large bedGraph files or base-resolution tracks can be converted without
+/// holding whole chromosomes in memory. Items on a chromosome must be sorted
+/// by position and must not overlap, and all items on a chromosome must be
+/// written consecutively. Zoom levels are computed while data is written and
+/// stored in temporary files until the writer is closed.
+#[derive(Debug)]
+pub struct BigWigStreamWriter<W: Write + Seek> {
+    bww: BigWigWriter<W>,
+    block: BigWigStreamBlock,
+    vertex: RVertex,
+    chrom: Option<usize>,
+    position: usize,
+    done: Vec<bool>,
+    zoom: Vec<BigWigZoomBuffer>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write + Seek> BigWigStreamWriter<W> {
+    /// Creates a new stream writer. If no reduction levels are given, a
+    /// default set of zoom levels is computed from the chromosome lengths.
+    ///
+    /// # Errors
+    /// Returns an error if a reduction level is not positive or if the
+    /// header or temporary files cannot be written.
+    pub fn new(
+        writer: W,
+        genome: Genome,
+        mut parameters_arg: Vec<OptionBigWig>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut items_per_slot = BigWigParameters::default().items_per_slot;
+        let mut has_reduction_levels = false;
+
+        for p in &parameters_arg {
+            match p {
+                OptionBigWig::ItemsPerSlot(x) => items_per_slot = *x,
+                OptionBigWig::ReductionLevels(_) => has_reduction_levels = true,
+                _ => (),
+            }
+        }
+        if !has_reduction_levels {
+            parameters_arg.push(OptionBigWig::ReductionLevels(
+                bigwig_automatic_reduction_levels(&genome, 1, items_per_slot),
+            ));
+        }
+
+        let done = vec![false; genome.len()];
+        let bww = BigWigWriter::new(writer, genome, parameters_arg)?;
+
+        let mut zoom = Vec::with_capacity(bww.parameters.reduction_levels.len());
+        for &reduction_level in &bww.parameters.reduction_levels {
+            if reduction_level <= 0 {
+                return Err(format!("invalid reduction level `{}`", reduction_level).into());
+            }
+            zoom.push(BigWigZoomBuffer::new(reduction_level as u32)?);
+        }
+
+        Ok(BigWigStreamWriter {
+            bww,
+            block: BigWigStreamBlock::default(),
+            vertex: RVertex::new_leaf(),
+            chrom: None,
+            position: 0,
+            done,
+            zoom,
+        })
+    }
+
+    pub fn parameters(&self) -> &BigWigParameters {
+        self.bww.parameters()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.bww.writer
+    }
+
+    /// Writes value `x` for the region `[from, to)` as a bedGraph item. NaN
+    /// values are skipped.
+    pub fn write_interval(
+        &mut self,
+        seqname: &str,
+        from: usize,
+        to: usize,
+        x: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        if x.is_nan() {
+            return Ok(());
+        }
+        let idx = self.seek(seqname, from, to)?;
+
+        let mut buffer = [0u8; 12];
+        LittleEndian::write_u32(&mut buffer[0..4], from as u32);
+        LittleEndian::write_u32(&mut buffer[4..8], to as u32);
+        LittleEndian::write_f32(&mut buffer[8..12], x as f32);
+
+        self.push(idx, BBI_TYPE_BED_GRAPH, from, to, 0, 0, x, &buffer)
+    }
+
+    /// Writes a fixed step run, where value `i` covers
+    /// `[start + i*step, start + i*step + span)`. NaN values are skipped.
+    ///
+    /// # Errors
+    /// Returns an error if `span` is zero or larger than `step`, or if the
+    /// run does not follow the previously written items.
+    pub fn write_fixed_step(
+        &mut self,
+        seqname: &str,
+        start: usize,
+        step: usize,
+        span: usize,
+        values: &[f64],
+    ) -> Result<(), Box<dyn Error>> {
+        if span == 0 || span > step {
+            return Err(format!(
+                "invalid fixed step run with step `{}` and span `{}`",
+                step, span
+            )
+            .into());
+        }
+        for (i, &x) in values.iter().enumerate() {
+            if x.is_nan() {
+                continue;
+            }
+            let from = start + i * step;
+            let idx = self.seek(seqname, from, from + span)?;
+
+            let mut buffer = [0u8; 4];
+            LittleEndian::write_f32(&mut buffer, x as f32);
+
+            self.push(
+                idx,
+                BBI_TYPE_FIXED,
+                from,
+                from + span,
+                step,
+                span,
+                x,
+                &buffer,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes a variable step run of `(position, value)` pairs, where each
+    /// value covers `span` bases. NaN values are skipped.
+    ///
+    /// # Errors
+    /// Returns an error if `span` is zero or if positions are not sorted.
+    pub fn write_variable_step(
+        &mut self,
+        seqname: &str,
+        span: usize,
+        items: &[(usize, f64)],
+    ) -> Result<(), Box<dyn Error>> {
+        if span == 0 {
+            return Err("invalid variable step run with span `0`".into());
+        }
+        for &(from, x) in items {
+            if x.is_nan() {
+                continue;
+            }
+            let idx = self.seek(seqname, from, from + span)?;
+
+            let mut buffer = [0u8; 8];
+            LittleEndian::write_u32(&mut buffer[0..4], from as u32);
+            LittleEndian::write_f32(&mut buffer[4..8], x as f32);
+
+            self.push(
+                idx,
+                BBI_TYPE_VARIABLE,
+                from,
+                from + span,
+                0,
+                span,
+                x,
+                &buffer,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining data, the index, all zoom levels and the
+    /// chromosome list.
+    pub fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        self.flush_block()?;
+        self.flush_vertex();
+        self.bww.write_index()?;
+
+        for i in 0..self.zoom.len() {
+            self.bww.start_zoom_data(i)?;
+            self.write_zoom(i)?;
+            self.bww.write_index_zoom(i)?;
+        }
+        self.bww.close()?;
+        self.bww.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Checks that an item on `seqname` covering `[from, to)` follows the
+    /// previously written items and returns the chromosome index.
+    fn seek(&mut self, seqname: &str, from: usize, to: usize) -> Result<usize, Box<dyn Error>> {
+        let idx = self
+            .bww
+            .genome
+            .get_idx(seqname)
+            .ok_or(format!("Sequence '{}' not found", seqname))?;
+
+        if from >= to || to > self.bww.genome.lengths[idx] {
+            return Err(format!("invalid region {}:{}-{}", seqname, from, to).into());
+        }
+        if self.chrom != Some(idx) {
+            if self.done[idx] {
+                return Err(format!("items on sequence `{}` are not consecutive", seqname).into());
+            }
+            if let Some(chrom) = self.chrom {
+                self.done[chrom] = true;
+            }
+            self.flush_block()?;
+            self.chrom = Some(idx);
+            self.position = 0;
+        }
+        if from < self.position {
+            return Err(format!(
+                "items must be sorted and must not overlap, but {}:{}-{} starts before position {}",
+                seqname, from, to, self.position
+            )
+            .into());
+        }
+        self.position = to;
+
+        Ok(idx)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        idx: usize,
+        kind: u8,
+        from: usize,
+        to: usize,
+        step: usize,
+        span: usize,
+        x: f64,
+        item: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let items_per_slot = self.bww.parameters.items_per_slot;
+
+        if !self.block.accepts(
+            kind,
+            idx as u32,
+            from as u32,
+            step as u32,
+            span as u32,
+            items_per_slot,
+        ) {
+            self.flush_block()?;
+        }
+        if self.block.item_count == 0 {
+            self.block.kind = kind;
+            self.block.chrom_id = idx as u32;
+            self.block.start = from as u32;
+            self.block.step = step as u32;
+            self.block.span = span as u32;
+        }
+        self.block.data.extend_from_slice(item);
+        self.block.item_count += 1;
+        self.block.end = to as u32;
+
+        self.bww
+            .bwf
+            .header
+            .summary_add_interval(x, (to - from) as u64);
+
+        let length = self.bww.genome.lengths[idx] as u32;
+        for zoom in &mut self.zoom {
+            zoom.add(idx as u32, from as u32, to as u32, x, length)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the current leaf vertex to the leaves of the index. Each leaf
+    /// contains blocks of a single chromosome.
+    fn flush_vertex(&mut self) {
+        if self.vertex.n_children > 0 {
+            let chrom_id = self.vertex.chr_idx_start[0] as usize;
+            let vertex = mem::replace(&mut self.vertex, RVertex::new_leaf());
+            self.bww.leaves.entry(chrom_id).or_default().push(vertex);
+        }
+    }
+
+    /// Writes `block` covering `[from, to)` on chromosome `chrom_id` and
+    /// adds it to the current leaf vertex.
+    fn write_block(
+        &mut self,
+        chrom_id: u32,
+        from: u32,
+        to: u32,
+        block: &Vec<u8>,
+    ) -> io::Result<()> {
+        if self.vertex.n_children as usize == self.bww.parameters.block_size
+            || (self.vertex.n_children > 0 && self.vertex.chr_idx_start[0] != chrom_id)
+        {
+            self.flush_vertex();
+        }
+        let i = self.vertex.n_children as usize;
+
+        self.vertex.add_block(chrom_id, from, to);
+        self.vertex.write_block::<LittleEndian, W>(
+            &mut self.bww.writer,
+            &mut self.bww.bwf,
+            i,
+            block,
+        )
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        if self.block.item_count == 0 {
+            return Ok(());
+        }
+        let block = mem::take(&mut self.block);
+
+        self.write_block(block.chrom_id, block.start, block.end, &block.encode()?)?;
+        self.bww.bwf.header.n_blocks += 1;
+
+        Ok(())
+    }
+
+    /// Reads back the records of zoom level `i` and writes them in blocks
+    /// of `items_per_slot` records.
+    fn write_zoom(&mut self, i: usize) -> io::Result<()> {
+        let items_per_slot = self.bww.parameters.items_per_slot;
+        let mut reader = self.zoom[i].records()?;
+        let mut records: Vec<BbiZoomRecord> = Vec::with_capacity(items_per_slot);
+
+        for _ in 0..self.zoom[i].n_records {
+            let mut record = BbiZoomRecord::default();
+            record.read::<LittleEndian, _>(&mut reader)?;
+
+            if !records.is_empty()
+                && (records.len() == items_per_slot || records[0].chrom_id != record.chrom_id)
+            {
+                self.write_zoom_block(i, &records)?;
+                records.clear();
+            }
+            records.push(record);
+        }
+        self.write_zoom_block(i, &records)?;
+        self.flush_vertex();
+
+        Ok(())
+    }
+
+    fn write_zoom_block(&mut self, i: usize, records: &[BbiZoomRecord]) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut block = Vec::with_capacity(32 * records.len());
+        for record in records {
+            record.write::<LittleEndian, _>(&mut block)?;
+        }
+        let (from, to) = (records[0].start, records[records.len() - 1].end);
+
+        self.write_block(records[0].chrom_id, from, to, &block)?;
+        self.bww.bwf.header.zoom_headers[i].n_blocks += 1;
+
+        Ok(())
+    }
+}
+
 /* Utility functions
  * -------------------------------------------------------------------------- */
 
+/// Returns a default set of reduction levels for data with the given bin
+/// size. Levels start at 100 bases or `BBI_RES_INCREMENT` bins and grow by
+/// the same factor until the longest chromosome fits into a single block of
+/// `items_per_slot` records.
+pub fn bigwig_automatic_reduction_levels(
+    genome: &Genome,
+    bin_size: usize,
+    items_per_slot: usize,
+) -> Vec<i32> {
+    let c = (BBI_RES_INCREMENT as usize) * bin_size;
+    let mut n = Vec::new();
+
+    // Get length of longest track
+    let l = genome
+        .lengths
+        .iter()
+        .map(|&length| length / bin_size)
+        .max()
+        .unwrap_or(0);
+
+    // Initial zoom level
+    let mut r = std::cmp::max(100, c);
+
+    // Compute number of zoom levels
+    while n.len() <= BBI_MAX_ZOOM_LEVELS {
+        if l / r > items_per_slot {
+            n.push(r as i32);
+            r *= c;
+        } else {
+            break;
+        }
+    }
+
+    n
+}
+
 pub fn bigwig_read_genome<R: Read + Seek>(file: R) -> Result<Genome, Box<dyn Error>> {
     let reader = BigWigReader::new(file)?;
     Ok(reader.genome().clone())
@@ -729,10 +1298,21 @@ pub fn bigwig_import_genome(filename: &str) -> Result<Genome, Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
 
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+    use std::sync::atomic::Ordering;
+
     use approx::assert_relative_eq;
     use byteorder::LittleEndian;
 
-    use crate::bigwig::BigWigFile;
+    use crate::bbi::{BBI_TYPE_BED_GRAPH, BBI_TYPE_FIXED};
+    use crate::bigwig::{
+        BigWigFile, BigWigReader, BigWigStreamWriter, BigWigZoomBuffer, OptionBigWig,
+        BIGWIG_ZOOM_BUFFER_ID,
+    };
+    use crate::genome::Genome;
     use crate::netfile::NetFile;
 
     #[test]
@@ -774,4 +1354,133 @@ mod tests {
             assert_relative_eq!(sum_max, 49.5, epsilon = 1e-6);
         }
     }
+
+    #[test]
+    fn test_bigwig_stream_writer() {
+        let genome = Genome::new(
+            vec!["chr1".to_string(), "chr2".to_string()],
+            vec![10000, 5000],
+        );
+
+        let mut bww = BigWigStreamWriter::new(
+            Cursor::new(Vec::new()),
+            genome.clone(),
+            vec![
+                OptionBigWig::BlockSize(4),
+                OptionBigWig::ItemsPerSlot(8),
+                OptionBigWig::ReductionLevels(vec![100, 400]),
+            ],
+        )
+        .unwrap();
+
+        // Chromosomes do not have to follow the order of the genome
+        for i in 0..200 {
+            bww.write_interval("chr2", 10 * i, 10 * i + 10, i as f64)
+                .unwrap();
+        }
+        let mut values: Vec<f64> = (0..50).map(|i| i as f64).collect();
+        values[20] = f64::NAN;
+        bww.write_fixed_step("chr1", 0, 10, 10, &values).unwrap();
+        bww.write_variable_step("chr1", 5, &[(1000, 1.0), (2000, 2.0)])
+            .unwrap();
+
+        assert!(bww.write_interval("chr1", 1500, 1510, 1.0).is_err());
+        assert!(bww.write_interval("chr2", 4000, 4010, 1.0).is_err());
+        assert!(bww.write_interval("chr1", 9995, 10005, 1.0).is_err());
+        bww.close().unwrap();
+
+        let data = bww.bww.writer.into_inner();
+        let mut bwr = BigWigReader::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(bwr.genome(), &genome);
+        assert_eq!(bwr.header().zoom_levels, 2);
+        assert_eq!(bwr.header().n_bases_covered, 2000 + 490 + 10);
+
+        let raw: Vec<_> = bwr.query("chr2", 0, 5000, 0).map(|r| r.unwrap()).collect();
+        assert_eq!(raw.len(), 200);
+        assert!(raw.iter().all(|r| r.data_type == BBI_TYPE_BED_GRAPH));
+        assert_eq!(raw[42].data.from, 420);
+        assert_eq!(raw[42].data.statistics.sum, 42.0);
+
+        let raw: Vec<_> = bwr.query("chr1", 0, 10000, 0).map(|r| r.unwrap()).collect();
+        assert_eq!(raw.len(), 51);
+        assert_eq!(raw[20].data_type, BBI_TYPE_FIXED);
+        assert_eq!(raw[20].data.from, 210);
+        assert_eq!((raw[49].data.from, raw[49].data.to), (1000, 1005));
+
+        // Zoom records contain base-weighted sums over windows of 100 bases
+        let zoom: Vec<_> = bwr
+            .query("chr2", 0, 2000, 100)
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(zoom.len(), 20);
+        for (k, r) in zoom.iter().enumerate() {
+            assert_eq!(r.data.from, 100 * k as i32);
+            assert_eq!(r.data.statistics.valid, 100.0);
+            assert_relative_eq!(
+                r.data.statistics.sum / r.data.statistics.valid,
+                10.0 * k as f64 + 4.5,
+                epsilon = 1e-6
+            );
+        }
+        let zoom: Vec<_> = bwr
+            .query("chr2", 0, 2000, 400)
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(zoom.len(), 5);
+        assert_eq!(zoom[0].data.statistics.max, 39.0);
+    }
+
+    #[test]
+    fn test_bigwig_many_chromosomes() {
+        // The chromosome B+ tree has more than one level if the number of
+        // chromosomes exceeds the block size
+        let seqnames: Vec<String> = (1..=11).map(|i| format!("chr{}", i)).collect();
+        let genome = Genome::new(seqnames.clone(), vec![1000; seqnames.len()]);
+
+        let mut bww = BigWigStreamWriter::new(
+            Cursor::new(Vec::new()),
+            genome.clone(),
+            vec![
+                OptionBigWig::BlockSize(4),
+                OptionBigWig::ReductionLevels(vec![]),
+            ],
+        )
+        .unwrap();
+        for (i, seqname) in seqnames.iter().enumerate() {
+            bww.write_interval(seqname, 100, 200, i as f64).unwrap();
+        }
+        bww.close().unwrap();
+
+        let data = bww.bww.writer.into_inner();
+        let mut bwr = BigWigReader::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(bwr.genome(), &genome);
+        for (i, seqname) in seqnames.iter().enumerate() {
+            let raw: Vec<_> = bwr.query(seqname, 0, 1000, 0).map(|r| r.unwrap()).collect();
+            assert_eq!(raw.len(), 1);
+            assert_eq!(raw[0].data.chrom, *seqname);
+            assert_eq!(raw[0].data.statistics.sum, i as f64);
+        }
+    }
+
+    #[test]
+    fn test_bigwig_zoom_buffer_file() {
+        let buffer = BigWigZoomBuffer::new(10).unwrap();
+        let path = buffer.path.clone();
+        assert!(path.exists());
+
+        // Existing files are skipped instead of being truncated
+        let id = BIGWIG_ZOOM_BUFFER_ID.load(Ordering::Relaxed);
+        let taken = env::temp_dir().join(format!("rustynetics-{}-{}.zoom", process::id(), id));
+        fs::write(&taken, b"foreign").unwrap();
+        let other = BigWigZoomBuffer::new(10).unwrap();
+        assert_ne!(other.path, taken);
+        assert_eq!(fs::read(&taken).unwrap(), b"foreign");
+        fs::remove_file(&taken).unwrap();
+
+        drop(buffer);
+        drop(other);
+        assert!(!path.exists());
+    }
 }
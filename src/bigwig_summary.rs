@@ -0,0 +1,465 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek};
+use std::str::FromStr;
+
+use byteorder::LittleEndian;
+
+use crate::bbi::BbiSummaryRecord;
+use crate::bigwig::BigWigReader;
+use crate::granges::GRanges;
+use crate::meta::MetaData;
+
+/* -------------------------------------------------------------------------- */
+
+/// Minimum number of zoom records that a region must span before zoom levels
+/// are used to summarize it.
+const ZOOM_MIN_RECORDS: usize = 4;
+
+/* -------------------------------------------------------------------------- */
+
+/// Summary statistics of a region, following the definitions of
+/// bigWigAverageOverBed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BigWigSummaryStat {
+    /// Average over covered bases
+    Mean,
+    /// Average over all bases, where bases without data count as zero
+    Mean0,
+    Min,
+    Max,
+    /// Fraction of bases with data
+    Coverage,
+    /// Sum of values over all covered bases
+    Sum,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigWigSummaryStat {
+    pub const ALL: [BigWigSummaryStat; 6] = [
+        BigWigSummaryStat::Mean,
+        BigWigSummaryStat::Mean0,
+        BigWigSummaryStat::Min,
+        BigWigSummaryStat::Max,
+        BigWigSummaryStat::Coverage,
+        BigWigSummaryStat::Sum,
+    ];
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for BigWigSummaryStat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BigWigSummaryStat::Mean => "mean",
+            BigWigSummaryStat::Mean0 => "mean0",
+            BigWigSummaryStat::Min => "min",
+            BigWigSummaryStat::Max => "max",
+            BigWigSummaryStat::Coverage => "coverage",
+            BigWigSummaryStat::Sum => "sum",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for BigWigSummaryStat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BigWigSummaryStat::ALL
+            .iter()
+            .find(|stat| stat.to_string() == s)
+            .copied()
+            .ok_or(format!("invalid summary statistic `{}`", s))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Summary of the bigWig data within a single region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BigWigRegionSummary {
+    /// Length of the region
+    pub size: usize,
+    /// Number of bases with data
+    pub covered: f64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl BigWigRegionSummary {
+    pub(crate) fn new(size: usize) -> Self {
+        BigWigRegionSummary {
+            size,
+            covered: 0.0,
+            sum: 0.0,
+            min: f64::NAN,
+            max: f64::NAN,
+        }
+    }
+
+    /// Adds data covering `n` bases with the given sum over all bases.
+    pub(crate) fn add(&mut self, n: f64, sum: f64, min: f64, max: f64) {
+        if n <= 0.0 {
+            return;
+        }
+        self.covered += n;
+        self.sum += sum;
+        if self.min.is_nan() || min < self.min {
+            self.min = min;
+        }
+        if self.max.is_nan() || max > self.max {
+            self.max = max;
+        }
+    }
+
+    /// Returns the value of the given statistic. Mean, minimum and maximum
+    /// are NaN if the region contains no data.
+    pub fn get(&self, stat: BigWigSummaryStat) -> f64 {
+        match stat {
+            BigWigSummaryStat::Mean => {
+                if self.covered > 0.0 {
+                    self.sum / self.covered
+                } else {
+                    f64::NAN
+                }
+            }
+            BigWigSummaryStat::Mean0 => self.sum / self.size as f64,
+            BigWigSummaryStat::Min => self.min,
+            BigWigSummaryStat::Max => self.max,
+            BigWigSummaryStat::Coverage => self.covered / self.size as f64,
+            BigWigSummaryStat::Sum => self.sum,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: Read + Seek> BigWigReader<R> {
+    /// Returns the number of bases represented by a single valid count of
+    /// zoom records, or `None` if zoom levels cannot be used for exact
+    /// summaries. Zoom records of files written by `BigWigWriter` count bins
+    /// instead of bases, which is detected by comparing the coarsest zoom
+    /// level with the number of bases covered given in the header.
+    fn zoom_unit(&mut self) -> Result<Option<f64>, Box<dyn Error>> {
+        let zoom_idx = match self.coarsest_zoom_level(usize::MAX) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        let mut valid = 0.0;
+        for (i, &length) in self.genome.lengths.iter().enumerate() {
+            for record in self.bwf.query_records::<LittleEndian, R>(
+                &mut self.reader,
+                Some(zoom_idx),
+                i as u32,
+                0,
+                length as u32,
+            )? {
+                valid += record.statistics.valid;
+            }
+        }
+        if valid == 0.0 {
+            return Ok(None);
+        }
+        let unit = self.bwf.header.n_bases_covered as f64 / valid;
+
+        if unit >= 1.0 && (unit - unit.round()).abs() < 1e-6 {
+            Ok(Some(unit.round()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the index of the zoom level with the largest reduction level
+    /// that spans at least `ZOOM_MIN_RECORDS` records within `length` bases.
+    fn coarsest_zoom_level(&self, length: usize) -> Option<usize> {
+        self.bwf
+            .header
+            .zoom_headers
+            .iter()
+            .enumerate()
+            .filter(|(_, zoom)| zoom.reduction_level > 0)
+            .filter(|(_, zoom)| {
+                (zoom.reduction_level as usize).saturating_mul(ZOOM_MIN_RECORDS) <= length
+            })
+            .max_by_key(|(_, zoom)| zoom.reduction_level)
+            .map(|(i, _)| i)
+    }
+
+    /// Adds raw data within `[from, to)` to `summary`, clipping records at
+    /// the region boundaries.
+    fn summarize_raw(
+        &mut self,
+        summary: &mut BigWigRegionSummary,
+        chrom_id: usize,
+        from: usize,
+        to: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        if from >= to {
+            return Ok(());
+        }
+        for record in self.bwf.query_records::<LittleEndian, R>(
+            &mut self.reader,
+            None,
+            chrom_id as u32,
+            from as u32,
+            to as u32,
+        )? {
+            let n = (record.to as usize).min(to) as f64 - (record.from as usize).max(from) as f64;
+            // Raw records contain a single value
+            let x = record.statistics.sum;
+            summary.add(n, n * x, x, x);
+        }
+        Ok(())
+    }
+
+    /// Summarizes the data within `[from, to)` on the given chromosome.
+    /// Zoom records that are fully contained in the region are used if
+    /// `zoom_unit` is given, while raw data is used for the remaining bases
+    /// at the region boundaries, so that results are exact up to the single
+    /// precision of zoom records.
+    fn summarize_region(
+        &mut self,
+        chrom_id: usize,
+        from: usize,
+        to: usize,
+        zoom_unit: Option<f64>,
+    ) -> Result<BigWigRegionSummary, Box<dyn Error>> {
+        let mut summary = BigWigRegionSummary::new(to - from);
+
+        let zoom_idx = zoom_unit.and(self.coarsest_zoom_level(to - from));
+
+        if let (Some(zoom_idx), Some(unit)) = (zoom_idx, zoom_unit) {
+            let records: Vec<BbiSummaryRecord> = self
+                .bwf
+                .query_records::<LittleEndian, R>(
+                    &mut self.reader,
+                    Some(zoom_idx),
+                    chrom_id as u32,
+                    from as u32,
+                    to as u32,
+                )?
+                .into_iter()
+                .filter(|r| r.from as usize >= from && r.to as usize <= to)
+                .collect();
+
+            if let (Some(first), Some(last)) = (records.first(), records.last()) {
+                let (inner_from, inner_to) = (first.from as usize, last.to as usize);
+
+                for r in &records {
+                    let s = &r.statistics;
+                    summary.add(s.valid * unit, s.sum * unit, s.min, s.max);
+                }
+                self.summarize_raw(&mut summary, chrom_id, from, inner_from)?;
+                self.summarize_raw(&mut summary, chrom_id, inner_to, to)?;
+
+                return Ok(summary);
+            }
+        }
+        self.summarize_raw(&mut summary, chrom_id, from, to)?;
+
+        Ok(summary)
+    }
+
+    /// Computes summaries of the data within each region of `granges`,
+    /// similar to bigWigAverageOverBed.
+    ///
+    /// # Arguments
+    /// * `granges` - Regions to summarize.
+    /// * `use_zoom` - Use zoom levels for large regions whenever the file
+    ///   allows exact summaries from zoom records.
+    ///
+    /// # Errors
+    /// Returns an error if a sequence of `granges` is not part of the file or
+    /// if reading fails.
+    pub fn summarize_regions(
+        &mut self,
+        granges: &GRanges,
+        use_zoom: bool,
+    ) -> Result<Vec<BigWigRegionSummary>, Box<dyn Error>> {
+        let zoom_unit = if use_zoom { self.zoom_unit()? } else { None };
+
+        let mut result = Vec::with_capacity(granges.num_rows());
+        for i in 0..granges.num_rows() {
+            let chrom_id = self
+                .genome
+                .get_idx(&granges.seqnames[i])
+                .ok_or(format!("Sequence '{}' not found", granges.seqnames[i]))?;
+            let (from, to) = (granges.ranges[i].from, granges.ranges[i].to);
+
+            result.push(self.summarize_region(chrom_id, from, to, zoom_unit)?);
+        }
+        Ok(result)
+    }
+
+    /// Summarizes the data within each region of `granges` and adds the
+    /// result as a float column named after `stat`, replacing any existing
+    /// column of the same name. Zoom levels are used whenever possible, see
+    /// `summarize_regions`.
+    pub fn summarize(
+        &mut self,
+        granges: &mut GRanges,
+        stat: BigWigSummaryStat,
+    ) -> Result<(), Box<dyn Error>> {
+        let summaries = self.summarize_regions(granges, true)?;
+        let values = summaries.iter().map(|s| s.get(stat)).collect();
+
+        let name = stat.to_string();
+        granges.meta.delete_meta(&name);
+        granges.meta.add(&name, MetaData::FloatArray(values))?;
+
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+
+    use approx::assert_relative_eq;
+
+    use crate::bigwig::{BigWigReader, BigWigStreamWriter, OptionBigWig};
+    use crate::bigwig_summary::BigWigSummaryStat;
+    use crate::genome::Genome;
+    use crate::granges::GRanges;
+    use crate::meta::MetaData;
+    use crate::track::MutableTrack;
+    use crate::track_simple::SimpleTrack;
+
+    fn regions() -> GRanges {
+        GRanges::new(
+            vec!["chr1".into(), "chr1".into(), "chr1".into(), "chr1".into()],
+            vec![0, 155, 1240, 5000],
+            vec![10000, 3777, 1250, 6000],
+            vec!['*', '*', '*', '*'],
+        )
+    }
+
+    #[test]
+    fn test_bigwig_summarize_stream() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![10000]);
+
+        // Values i % 13 at [i*5, i*5+3) for positions below 4000
+        let mut bww = BigWigStreamWriter::new(
+            Cursor::new(Vec::new()),
+            genome,
+            vec![OptionBigWig::ReductionLevels(vec![50, 200])],
+        )
+        .unwrap();
+        for i in 0..800 {
+            bww.write_interval("chr1", 5 * i, 5 * i + 3, (i % 13) as f64)
+                .unwrap();
+        }
+        bww.close().unwrap();
+
+        let expected = |from: usize, to: usize| {
+            let (mut covered, mut sum) = (0.0, 0.0);
+            for i in 0..800 {
+                let n = (5 * i + 3).min(to) as f64 - (5 * i).max(from) as f64;
+                if n > 0.0 {
+                    covered += n;
+                    sum += n * (i % 13) as f64;
+                }
+            }
+            (covered, sum)
+        };
+
+        let mut bwr = BigWigReader::new(Cursor::new(bww.into_inner().into_inner())).unwrap();
+        let granges = regions();
+
+        let exact = bwr.summarize_regions(&granges, false).unwrap();
+        let zoom = bwr.summarize_regions(&granges, true).unwrap();
+
+        for i in 0..granges.num_rows() {
+            let (covered, sum) = expected(granges.ranges[i].from, granges.ranges[i].to);
+            for s in [&exact[i], &zoom[i]] {
+                assert_eq!(s.covered, covered);
+                assert_relative_eq!(s.sum, sum, epsilon = 1e-3);
+            }
+        }
+        assert_eq!(exact[0].max, 12.0);
+        assert_eq!(exact[0].get(BigWigSummaryStat::Coverage), 0.24);
+        assert_eq!(exact[2].get(BigWigSummaryStat::Min), 1.0);
+        assert!(exact[3].get(BigWigSummaryStat::Mean).is_nan());
+        assert_eq!(exact[3].get(BigWigSummaryStat::Mean0), 0.0);
+
+        let mut granges = regions();
+        bwr.summarize(&mut granges, BigWigSummaryStat::Mean0)
+            .unwrap();
+        match granges.meta.get_column("mean0").unwrap() {
+            MetaData::FloatArray(v) => {
+                assert_relative_eq!(v[0], expected(0, 10000).1 / 10000.0, epsilon = 1e-6)
+            }
+            _ => panic!("expected FloatArray"),
+        }
+    }
+
+    #[test]
+    fn test_bigwig_summarize_binned() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![10000]);
+
+        let mut track = SimpleTrack::alloc("".to_string(), genome, f64::NAN, 10);
+        {
+            let mut seq = track.get_sequence_mut("chr1").unwrap();
+            for i in 0..500 {
+                seq.set(10 * i, (i % 7) as f64);
+            }
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        track
+            .write_bigwig(
+                &mut buffer,
+                vec![OptionBigWig::ReductionLevels(vec![40, 160])],
+            )
+            .unwrap();
+
+        let mut bwr = BigWigReader::new(Cursor::new(buffer.into_inner())).unwrap();
+        let granges = regions();
+
+        // Zoom records of binned tracks count bins, which must be accounted
+        // for when combining them with raw data
+        let exact = bwr.summarize_regions(&granges, false).unwrap();
+        let zoom = bwr.summarize_regions(&granges, true).unwrap();
+
+        assert_eq!(exact[0].covered, 5000.0);
+        assert_eq!(
+            exact[0].sum,
+            10.0 * (0..500).map(|i| (i % 7) as f64).sum::<f64>()
+        );
+        for i in 0..granges.num_rows() {
+            assert_eq!(zoom[i].covered, exact[i].covered);
+            assert_relative_eq!(zoom[i].sum, exact[i].sum, epsilon = 1e-3);
+            assert_eq!(zoom[i].min.to_bits(), exact[i].min.to_bits());
+            assert_eq!(zoom[i].max.to_bits(), exact[i].max.to_bits());
+        }
+    }
+}
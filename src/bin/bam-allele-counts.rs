@@ -0,0 +1,149 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_allele_counts::{bam_allele_counts_file, import_allele_sites};
+use rustynetics::bam_pileup::{BamPileupConfig, BAM_PILEUP_EXCLUDE_FLAGS};
+use rustynetics::granges_table::OptionPrintStrand;
+
+/* -------------------------------------------------------------------------- */
+
+fn parse_flags(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse::<u16>(),
+    };
+    result.map_err(|_| format!("invalid flags `{}`", s))
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn allele_counts(
+    filename_sites: &str,
+    filename_in: &str,
+    filename_out: Option<&String>,
+    config: BamPileupConfig,
+    threads: usize,
+) -> Result<(), Box<dyn Error>> {
+    let sites = import_allele_sites(filename_sites)?;
+    let counts = bam_allele_counts_file(filename_in, &sites, config, threads)?;
+
+    let mut writer: Box<dyn Write> = match filename_out {
+        Some(filename) => Box::new(BufWriter::new(File::create(filename)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    counts.write_table(&mut writer, &[&OptionPrintStrand(false)])?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Allele Counts")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Count reference and alternative alleles at SNV sites per read group")
+        .arg(
+            Arg::new("min-base-quality")
+                .short('q')
+                .long("min-base-quality")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("13")
+                .help("Minimum base quality"),
+        )
+        .arg(
+            Arg::new("min-mapq")
+                .short('Q')
+                .long("min-mapq")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("0")
+                .help("Minimum mapping quality"),
+        )
+        .arg(
+            Arg::new("include-flags")
+                .short('f')
+                .long("include-flags")
+                .value_parser(parse_flags)
+                .default_value("0")
+                .help("Only count reads with all of these flags set"),
+        )
+        .arg(
+            Arg::new("exclude-flags")
+                .short('F')
+                .long("exclude-flags")
+                .value_parser(parse_flags)
+                .help("Skip reads with any of these flags set [default: 0x704]"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("sites")
+                .help("Variant sites (VCF, BED with ref/alt columns or GRanges table)")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The coordinate-sorted input BAM file")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output table [default: stdout]")
+                .index(3),
+        )
+        .get_matches();
+
+    let config = BamPileupConfig {
+        min_base_quality: *matches.get_one::<u8>("min-base-quality").unwrap(),
+        min_mapq: *matches.get_one::<u8>("min-mapq").unwrap(),
+        include_flags: *matches.get_one::<u16>("include-flags").unwrap(),
+        exclude_flags: matches
+            .get_one::<u16>("exclude-flags")
+            .copied()
+            .unwrap_or(BAM_PILEUP_EXCLUDE_FLAGS),
+    };
+    let threads = *matches.get_one::<usize>("threads").unwrap();
+
+    let filename_sites = matches.get_one::<String>("sites").unwrap();
+    let filename_in = matches.get_one::<String>("input").unwrap();
+    let filename_out = matches.get_one::<String>("output");
+
+    if let Err(e) = allele_counts(filename_sites, filename_in, filename_out, config, threads) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam::{BamFile, BamReaderOptions};
+use rustynetics::bam_bisulfite::{bam_bisulfite_methylation, BisulfiteConfig, MethylationContext};
+use rustynetics::granges_table::OptionPrintStrand;
+use rustynetics::orderedstringset::OrderedStringSet;
+
+/* -------------------------------------------------------------------------- */
+
+struct Config {
+    filename_reference: String,
+    filename_in: String,
+    prefix: String,
+    contexts: Vec<MethylationContext>,
+    bin_size: usize,
+    threads: usize,
+    bisulfite: BisulfiteConfig,
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn extract(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut reference = OrderedStringSet::empty();
+    reference.import_fasta(&config.filename_reference)?;
+
+    let options = BamReaderOptions::with_threads(config.threads);
+    let mut bam = BamFile::open(&config.filename_in, Some(options))?;
+
+    let methylation =
+        bam_bisulfite_methylation(&mut bam.reader, &reference, config.bisulfite.clone())?;
+
+    for &context in &config.contexts {
+        let track = methylation.track(context, context.as_str(), config.bin_size)?;
+        track.export_bigwig(&format!("{}.{}.bw", config.prefix, context), vec![])?;
+    }
+    methylation.granges()?.export_table(
+        &format!("{}.sites.table.gz", config.prefix),
+        true,
+        &[&OptionPrintStrand(true)],
+    )?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Bisulfite Extract")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Extract CpG/CHG/CHH methylation levels from bisulfite sequencing alignments")
+        .arg(
+            Arg::new("contexts")
+                .short('c')
+                .long("contexts")
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(MethylationContext))
+                .default_value("CG,CHG,CHH")
+                .help("Contexts for which bigWig tracks are written"),
+        )
+        .arg(
+            Arg::new("min-base-quality")
+                .short('q')
+                .long("min-base-quality")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("0")
+                .help("Minimum base quality"),
+        )
+        .arg(
+            Arg::new("min-mapq")
+                .short('Q')
+                .long("min-mapq")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("0")
+                .help("Minimum mapping quality"),
+        )
+        .arg(
+            Arg::new("include-overlap")
+                .long("include-overlap")
+                .action(clap::ArgAction::SetTrue)
+                .help("Count positions where mates overlap twice"),
+        )
+        .arg(
+            Arg::new("bin-size")
+                .long("bin-size")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("1")
+                .help("Bin size of bigWig tracks"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("reference")
+                .help("The reference FASTA file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::new("prefix")
+                .help("Prefix of output files (<prefix>.<context>.bw and <prefix>.sites.table.gz)")
+                .required(true)
+                .index(3),
+        )
+        .get_matches();
+
+    let config = Config {
+        filename_reference: matches.get_one::<String>("reference").unwrap().clone(),
+        filename_in: matches.get_one::<String>("input").unwrap().clone(),
+        prefix: matches.get_one::<String>("prefix").unwrap().clone(),
+        contexts: matches
+            .get_many::<MethylationContext>("contexts")
+            .unwrap()
+            .copied()
+            .collect(),
+        bin_size: *matches.get_one::<usize>("bin-size").unwrap(),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+        bisulfite: BisulfiteConfig {
+            min_base_quality: *matches.get_one::<u8>("min-base-quality").unwrap(),
+            min_mapq: *matches.get_one::<u8>("min-mapq").unwrap(),
+            ignore_overlap: !matches.get_flag("include-overlap"),
+            ..Default::default()
+        },
+    };
+
+    if let Err(e) = extract(&config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
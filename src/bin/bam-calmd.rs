@@ -0,0 +1,131 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::env;
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam::{BamFile, BamReaderOptions};
+use rustynetics::bam_md::{bam_fill_md, bam_verify_md};
+use rustynetics::orderedstringset::OrderedStringSet;
+
+/* -------------------------------------------------------------------------- */
+
+struct Config {
+    filename_reference: String,
+    filename_in: String,
+    filename_out: Option<String>,
+    verify: bool,
+    threads: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn calmd(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut reference = OrderedStringSet::empty();
+    reference.import_fasta(&config.filename_reference)?;
+
+    let options = BamReaderOptions::with_threads(config.threads);
+    let mut bam = BamFile::open(&config.filename_in, Some(options))?;
+
+    if config.verify {
+        let mismatches = bam_verify_md(&mut bam.reader, &reference)?;
+        for mismatch in &mismatches {
+            println!("{}", mismatch);
+        }
+        if !mismatches.is_empty() {
+            return Err(format!("{} records have wrong MD/NM tags", mismatches.len()).into());
+        }
+        return Ok(());
+    }
+    let filename_out = config.filename_out.as_ref().ok_or("no output file given")?;
+
+    // Record this program in the header
+    let mut header = bam.reader.get_header().clone();
+    let mut sam_header = header.parse()?;
+    let command_line = env::args().collect::<Vec<_>>().join(" ");
+    sam_header.add_program("bam-calmd", Some("1.0"), Some(&command_line));
+    header.set_sam_header(&sam_header);
+
+    let genome = bam.reader.get_genome().clone();
+    let mut writer = BamFile::create(filename_out, &header, &genome)?;
+
+    bam_fill_md(&mut bam.reader, &mut writer, &reference)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM CalMD")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Compute MD and NM tags from a FASTA reference or verify existing tags")
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .action(clap::ArgAction::SetTrue)
+                .help("Only report records with wrong MD/NM tags"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("reference")
+                .help("The reference FASTA file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output BAM file")
+                .required_unless_present("verify")
+                .index(3),
+        )
+        .get_matches();
+
+    let config = Config {
+        filename_reference: matches.get_one::<String>("reference").unwrap().clone(),
+        filename_in: matches.get_one::<String>("input").unwrap().clone(),
+        filename_out: matches.get_one::<String>("output").cloned(),
+        verify: matches.get_flag("verify"),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+    };
+
+    if let Err(e) = calmd(&config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
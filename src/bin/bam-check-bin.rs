@@ -26,27 +26,12 @@ use std::process;
 use clap::{Arg, Command};
 
 use rustynetics::bam::{BamReader, BamReaderOptions};
+use rustynetics::bam_index::{bam_reg2bin, BAI_DEPTH, BAI_MIN_SHIFT};
 
 /* -------------------------------------------------------------------------- */
 
-fn reg2bin(beg: i32, mut end: i32) -> i32 {
-    end -= 1;
-    if beg >> 14 == end >> 14 {
-        return ((1 << 15) - 1) / 7 + (beg >> 14);
-    }
-    if beg >> 17 == end >> 17 {
-        return ((1 << 12) - 1) / 7 + (beg >> 17);
-    }
-    if beg >> 20 == end >> 20 {
-        return ((1 << 9) - 1) / 7 + (beg >> 20);
-    }
-    if beg >> 23 == end >> 23 {
-        return ((1 << 6) - 1) / 7 + (beg >> 23);
-    }
-    if beg >> 26 == end >> 26 {
-        return ((1 << 3) - 1) / 7 + (beg >> 26);
-    }
-    0
+fn reg2bin(beg: i32, end: i32) -> i32 {
+    bam_reg2bin(beg as i64, end as i64, BAI_MIN_SHIFT, BAI_DEPTH) as i32
 }
 
 /* -------------------------------------------------------------------------- */
@@ -61,6 +46,7 @@ fn check_bin(filename_in: &str, verbose: bool) -> Result<(), Box<dyn Error>> {
         read_sequence: true,
         read_auxiliary: false,
         read_qual: false,
+        threads: 1,
     };
 
     let mut bam_reader = BamReader::new(reader, Some(options))?;
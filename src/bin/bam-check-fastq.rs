@@ -283,6 +283,7 @@ fn open_bam_reader(
             read_sequence: false,
             read_auxiliary: false,
             read_qual: false,
+            threads: 1,
         }),
     )?;
 
@@ -0,0 +1,168 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, ArgAction, Command};
+
+use rustynetics::bam_single_cell::{bam_coverage_by_group, import_barcodes};
+use rustynetics::coverage::OptionCoverage;
+use rustynetics::infologger::Logger;
+
+/* -------------------------------------------------------------------------- */
+
+fn coverage_by_group(
+    filename_in: &str,
+    prefix: &str,
+    filename_groups: &str,
+    fraglen: usize,
+    options: Vec<OptionCoverage>,
+) -> Result<(), Box<dyn Error>> {
+    let groups = import_barcodes(filename_groups)?;
+    let tracks = bam_coverage_by_group(filename_in, &groups, fraglen, options)?;
+
+    for (group, track) in tracks {
+        track.export_bigwig(&format!("{}.{}.bw", prefix, group), vec![])?;
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Coverage By Cell")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Compute coverage tracks per cell or per cluster of cells from single-cell alignments")
+        .arg(
+            Arg::new("groups")
+                .short('g')
+                .long("groups")
+                .required(true)
+                .help("File with cell barcodes and optional cluster names, one track is computed per cell if cluster names are missing"),
+        )
+        .arg(
+            Arg::new("bin-size")
+                .long("bin-size")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10")
+                .help("Track bin size"),
+        )
+        .arg(
+            Arg::new("normalize-track")
+                .long("normalize-track")
+                .value_parser(["cpm", "rpkm"])
+                .help("Normalize each track with the specified method"),
+        )
+        .arg(
+            Arg::new("fragment-length")
+                .long("fragment-length")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+                .help("Extend single-end reads to the given length"),
+        )
+        .arg(
+            Arg::new("filter-mapq")
+                .long("filter-mapq")
+                .value_parser(clap::value_parser!(i64))
+                .default_value("0")
+                .help("Filter reads for minimum mapping quality"),
+        )
+        .arg(
+            Arg::new("filter-duplicates")
+                .long("filter-duplicates")
+                .action(ArgAction::SetTrue)
+                .help("Remove reads marked as duplicates"),
+        )
+        .arg(
+            Arg::new("filter-umi-duplicates")
+                .long("filter-umi-duplicates")
+                .action(ArgAction::SetTrue)
+                .help("Remove reads with the same cell barcode, UMI (UB tag) and 5' position"),
+        )
+        .arg(
+            Arg::new("paired-as-single-end")
+                .long("paired-as-single-end")
+                .action(ArgAction::SetTrue)
+                .help("Treat paired reads as single end reads"),
+        )
+        .arg(
+            Arg::new("spliced")
+                .long("spliced")
+                .action(ArgAction::SetTrue)
+                .help("Only add coverage on aligned read segments, e.g. to skip introns of RNA-seq reads"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Verbose output"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file with CB (and UB) tags")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("prefix")
+                .help("Prefix of output files (<prefix>.<group>.bw)")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let mut options = vec![
+        OptionCoverage::BinSize(*matches.get_one::<usize>("bin-size").unwrap()),
+        OptionCoverage::FilterMapQ(*matches.get_one::<i64>("filter-mapq").unwrap()),
+        OptionCoverage::FilterDuplicates(matches.get_flag("filter-duplicates")),
+        OptionCoverage::FilterUmiDuplicates(matches.get_flag("filter-umi-duplicates")),
+        OptionCoverage::PairedAsSingleEnd(matches.get_flag("paired-as-single-end")),
+        OptionCoverage::Spliced(matches.get_flag("spliced")),
+        OptionCoverage::Threads(*matches.get_one::<usize>("threads").unwrap()),
+    ];
+    if let Some(method) = matches.get_one::<String>("normalize-track") {
+        options.push(OptionCoverage::NormalizeTrack(method.clone()));
+    }
+    if matches.get_flag("verbose") {
+        options.push(OptionCoverage::Logger(Logger::new_stderr()));
+    }
+
+    if let Err(e) = coverage_by_group(
+        matches.get_one::<String>("input").unwrap(),
+        matches.get_one::<String>("prefix").unwrap(),
+        matches.get_one::<String>("groups").unwrap(),
+        *matches.get_one::<usize>("fragment-length").unwrap(),
+        options,
+    ) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
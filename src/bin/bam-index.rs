@@ -0,0 +1,77 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_index::{index_bam, index_bam_with};
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Index")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Create a BAI or CSI index for a coordinate-sorted BAM file")
+        .arg(
+            Arg::new("csi")
+                .short('c')
+                .long("csi")
+                .action(clap::ArgAction::SetTrue)
+                .help(
+                    "Create a CSI index (default: BAI if all sequences are shorter than 512 Mbp)",
+                ),
+        )
+        .arg(
+            Arg::new("min-shift")
+                .short('m')
+                .long("min-shift")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("14")
+                .help("Size of the smallest bin in bits for CSI indices"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    let filename_in = matches
+        .get_one::<String>("input")
+        .expect("Input file is required");
+    let min_shift = *matches.get_one::<u32>("min-shift").unwrap();
+
+    let result = if matches.get_flag("csi") {
+        index_bam_with(filename_in, true, min_shift)
+    } else {
+        index_bam(filename_in)
+    };
+
+    match result {
+        Ok(filename_index) => println!("Wrote index `{}`", filename_index),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+
+use clap::{Arg, Command};
+use plotters::prelude::*;
+
+use rustynetics::bam_insert_size::{InsertSizeConfig, InsertSizeHistogram, PairOrientation};
+
+/* -------------------------------------------------------------------------- */
+
+fn save_table(histogram: &InsertSizeHistogram, filename: &str) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    histogram.write_table(&mut writer)?;
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn save_plot(histogram: &InsertSizeHistogram, filename: &str) -> Result<(), Box<dyn Error>> {
+    // Create a 800x500 image for the plot
+    let root = BitMapBackend::new(filename, (800, 500)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let n = histogram.config.max_insert_size + 1;
+    let max_y = PairOrientation::ALL
+        .iter()
+        .flat_map(|&o| histogram.histogram(o).iter())
+        .cloned()
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Insert size distribution", ("sans-serif", 20))
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .margin(10)
+        .build_cartesian_2d(0..n, 0..max_y)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Insert size")
+        .y_desc("Number of pairs")
+        .draw()?;
+
+    for (o, color) in PairOrientation::ALL.into_iter().zip([BLACK, BLUE, RED]) {
+        if histogram.count(o) == 0 {
+            continue;
+        }
+        let points = histogram.histogram(o).iter().cloned().enumerate();
+        chart
+            .draw_series(LineSeries::new(points, &color))?
+            .label(o.to_string())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    // Ensure the plot is saved
+    root.present()?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Insert Size")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Compute the insert size distribution of paired-end reads, including pair orientations and nucleosome periodicity")
+        .arg(
+            Arg::new("max-insert-size")
+                .long("max-insert-size")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1000")
+                .help("Maximum insert size of the histogram"),
+        )
+        .arg(
+            Arg::new("min-mapq")
+                .long("min-mapq")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("0")
+                .help("Minimum mapping quality of both mates"),
+        )
+        .arg(
+            Arg::new("keep-duplicates")
+                .long("keep-duplicates")
+                .action(clap::ArgAction::SetTrue)
+                .help("Include pairs marked as duplicates"),
+        )
+        .arg(
+            Arg::new("table")
+                .long("table")
+                .num_args(1)
+                .help("Save histogram as tab-separated table to the given file"),
+        )
+        .arg(
+            Arg::new("plot")
+                .long("plot")
+                .num_args(1)
+                .help("Save histogram plot as PNG to the given file"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    let filename_in = matches.get_one::<String>("input").unwrap();
+    let threads = *matches.get_one::<usize>("threads").unwrap();
+
+    let config = InsertSizeConfig {
+        max_insert_size: *matches.get_one::<usize>("max-insert-size").unwrap(),
+        min_mapq: *matches.get_one::<u8>("min-mapq").unwrap(),
+        skip_duplicates: !matches.get_flag("keep-duplicates"),
+    };
+
+    let histogram = InsertSizeHistogram::import(filename_in, config, threads).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    print!("{}", histogram);
+
+    if let Some(filename) = matches.get_one::<String>("table") {
+        if let Err(e) = save_table(&histogram, filename) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+    if let Some(filename) = matches.get_one::<String>("plot") {
+        if let Err(e) = save_plot(&histogram, filename) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
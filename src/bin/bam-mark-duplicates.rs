@@ -0,0 +1,120 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_duplicates::{bam_mark_duplicates_file, DuplicationMetrics};
+
+/* -------------------------------------------------------------------------- */
+
+struct Config {
+    filename_in: String,
+    filename_out: String,
+    filename_metrics: Option<String>,
+    remove_duplicates: bool,
+    threads: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn mark_duplicates(config: &Config) -> Result<(), Box<dyn Error>> {
+    // Record this program in the header
+    let command_line = env::args().collect::<Vec<_>>().join(" ");
+
+    let metrics = bam_mark_duplicates_file(
+        &config.filename_in,
+        &config.filename_out,
+        config.remove_duplicates,
+        config.threads,
+        Some(&command_line),
+    )?;
+
+    let mut out: Box<dyn Write> = match &config.filename_metrics {
+        Some(filename) => Box::new(File::create(filename)?),
+        None => Box::new(io::stderr()),
+    };
+    writeln!(out, "{}", DuplicationMetrics::table_header())?;
+    for m in &metrics {
+        writeln!(out, "{}", m)?;
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Mark Duplicates")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Mark duplicate reads and read pairs based on unclipped 5' positions")
+        .arg(
+            Arg::new("metrics")
+                .short('M')
+                .long("metrics")
+                .help("Write duplication metrics to this file [default: stderr]"),
+        )
+        .arg(
+            Arg::new("remove-duplicates")
+                .short('r')
+                .long("remove-duplicates")
+                .action(clap::ArgAction::SetTrue)
+                .help("Remove duplicates instead of setting the duplicate flag"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output BAM file")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let config = Config {
+        filename_in: matches.get_one::<String>("input").unwrap().clone(),
+        filename_out: matches.get_one::<String>("output").unwrap().clone(),
+        filename_metrics: matches.get_one::<String>("metrics").cloned(),
+        remove_duplicates: matches.get_flag("remove-duplicates"),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+    };
+
+    if let Err(e) = mark_duplicates(&config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::env;
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_merge::{bam_merge_files, BamMergeConfig};
+use rustynetics::bam_sort::BamSortOrder;
+
+/* -------------------------------------------------------------------------- */
+
+fn merge(inputs: &[&str], output: &str, config: &BamMergeConfig) -> Result<(), Box<dyn Error>> {
+    // Record this program in the header
+    let command_line = env::args().collect::<Vec<_>>().join(" ");
+
+    bam_merge_files(inputs, output, config, Some(&command_line))
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Merge")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Merge sorted BAM files, reconciling reference sequences, read groups and programs")
+        .arg(
+            Arg::new("sort-order")
+                .short('s')
+                .long("sort-order")
+                .value_parser(["coordinate", "queryname"])
+                .help(
+                    "Sort order of the input files [default: sort order of the first input file]",
+                ),
+        )
+        .arg(
+            Arg::new("tag-read-groups")
+                .short('r')
+                .long("tag-read-groups")
+                .action(clap::ArgAction::SetTrue)
+                .help("Tag every record with a read group named after its input file"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression of each input file"),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The merged output BAM file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("inputs")
+                .help("The input BAM files")
+                .required(true)
+                .num_args(1..)
+                .index(2),
+        )
+        .get_matches();
+
+    let output = matches.get_one::<String>("output").unwrap();
+    let inputs: Vec<&str> = matches
+        .get_many::<String>("inputs")
+        .unwrap()
+        .map(|s| s.as_str())
+        .collect();
+
+    let config = BamMergeConfig {
+        order: matches
+            .get_one::<String>("sort-order")
+            .map(|s| s.parse::<BamSortOrder>().unwrap()),
+        tag_read_groups: matches.get_flag("tag-read-groups"),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+    };
+
+    if let Err(e) = merge(&inputs, output, &config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam::{BamFile, BamReaderOptions};
+use rustynetics::bam_modifications::{bam_cpg_methylation, CpGMethylationConfig};
+use rustynetics::orderedstringset::OrderedStringSet;
+
+/* -------------------------------------------------------------------------- */
+
+struct Config {
+    filename_reference: String,
+    filename_in: String,
+    filename_out: String,
+    bin_size: usize,
+    threads: usize,
+    methylation: CpGMethylationConfig,
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn methylation(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut reference = OrderedStringSet::empty();
+    reference.import_fasta(&config.filename_reference)?;
+
+    let options = BamReaderOptions::with_threads(config.threads);
+    let mut bam = BamFile::open(&config.filename_in, Some(options))?;
+
+    let methylation = bam_cpg_methylation(&mut bam.reader, &reference, config.methylation.clone())?;
+    if methylation.n_malformed() > 0 {
+        eprintln!(
+            "Skipped {} records with malformed MM/ML tags",
+            methylation.n_malformed()
+        );
+    }
+
+    let filename = config.filename_out.to_lowercase();
+    if filename.ends_with(".bw") || filename.ends_with(".bigwig") {
+        let track = methylation.track("methylation", config.bin_size)?;
+        track.export_bigwig(&config.filename_out, vec![])?;
+    } else {
+        let compress = filename.ends_with(".gz");
+        methylation
+            .granges()?
+            .export_bedgraph(&config.filename_out, compress)?;
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Methylation")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Aggregate 5mC calls from MM/ML tags into per-CpG methylation levels")
+        .arg(
+            Arg::new("code")
+                .long("code")
+                .default_value("m")
+                .help("Modification code of 5mC calls in the MM tag"),
+        )
+        .arg(
+            Arg::new("threshold")
+                .long("threshold")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("0.5")
+                .help("Minimum probability of methylated and unmethylated calls"),
+        )
+        .arg(
+            Arg::new("min-mapq")
+                .short('Q')
+                .long("min-mapq")
+                .value_parser(clap::value_parser!(u8))
+                .default_value("0")
+                .help("Minimum mapping quality"),
+        )
+        .arg(
+            Arg::new("separate-strands")
+                .long("separate-strands")
+                .action(clap::ArgAction::SetTrue)
+                .help("Report both strands of a CpG separately"),
+        )
+        .arg(
+            Arg::new("bin-size")
+                .long("bin-size")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("1")
+                .help("Bin size of bigWig output"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("reference")
+                .help("The reference FASTA file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output bigWig (.bw) or bedGraph file")
+                .required(true)
+                .index(3),
+        )
+        .get_matches();
+
+    let config = Config {
+        filename_reference: matches.get_one::<String>("reference").unwrap().clone(),
+        filename_in: matches.get_one::<String>("input").unwrap().clone(),
+        filename_out: matches.get_one::<String>("output").unwrap().clone(),
+        bin_size: *matches.get_one::<usize>("bin-size").unwrap(),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+        methylation: CpGMethylationConfig {
+            code: matches.get_one::<String>("code").unwrap().clone(),
+            threshold: *matches.get_one::<f64>("threshold").unwrap(),
+            min_mapq: *matches.get_one::<u8>("min-mapq").unwrap(),
+            combine_strands: !matches.get_flag("separate-strands"),
+            ..Default::default()
+        },
+    };
+
+    if let Err(e) = methylation(&config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
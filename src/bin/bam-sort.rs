@@ -0,0 +1,128 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_sort::{bam_sort_file, BamSortConfig, BamSortOrder};
+
+/* -------------------------------------------------------------------------- */
+
+fn parse_memory(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let (digits, factor) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<usize>()
+        .map(|n| n * factor)
+        .map_err(|_| format!("invalid memory size `{}`", s))
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn sort(
+    filename_in: &str,
+    filename_out: &str,
+    config: &BamSortConfig,
+) -> Result<(), Box<dyn Error>> {
+    // Record this program in the header
+    let command_line = env::args().collect::<Vec<_>>().join(" ");
+
+    bam_sort_file(filename_in, filename_out, config, Some(&command_line))
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Sort")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Sort a BAM file by coordinate or read name")
+        .arg(
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .action(clap::ArgAction::SetTrue)
+                .help("Sort by read name instead of coordinate"),
+        )
+        .arg(
+            Arg::new("memory")
+                .short('m')
+                .long("memory")
+                .value_parser(parse_memory)
+                .default_value("768M")
+                .help("Approximate memory used for sorting before spilling to temporary files [suffixes K, M, G]"),
+        )
+        .arg(
+            Arg::new("tmp-dir")
+                .short('T')
+                .long("tmp-dir")
+                .help("Directory for temporary files [default: system temporary directory]"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The sorted output BAM file")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let filename_in = matches.get_one::<String>("input").unwrap();
+    let filename_out = matches.get_one::<String>("output").unwrap();
+
+    let mut config = BamSortConfig {
+        memory_limit: *matches.get_one::<usize>("memory").unwrap(),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+        ..Default::default()
+    };
+    if matches.get_flag("name") {
+        config.order = BamSortOrder::QueryName;
+    }
+    if let Some(tmp_dir) = matches.get_one::<String>("tmp-dir") {
+        config.tmp_dir = PathBuf::from(tmp_dir);
+    }
+
+    if let Err(e) = sort(filename_in, filename_out, &config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
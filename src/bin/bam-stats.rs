@@ -0,0 +1,94 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_stats::BamStats;
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Stats")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Summarize flags, per-reference counts, mapping qualities and read lengths of a BAM file")
+        .arg(
+            Arg::new("json")
+                .short('j')
+                .long("json")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print statistics in JSON format"),
+        )
+        .arg(
+            Arg::new("flagstat")
+                .long("flagstat")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["json", "idxstats"])
+                .help("Only print flag statistics (samtools flagstat format)"),
+        )
+        .arg(
+            Arg::new("idxstats")
+                .long("idxstats")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("json")
+                .help("Only print per-reference counts (samtools idxstats format)"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    let filename_in = matches.get_one::<String>("input").unwrap();
+    let threads = *matches.get_one::<usize>("threads").unwrap();
+
+    let stats = BamStats::import(filename_in, threads).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+
+    if matches.get_flag("json") {
+        match stats.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    } else if matches.get_flag("flagstat") {
+        print!("{}", stats.flagstat());
+    } else if matches.get_flag("idxstats") {
+        print!("{}", stats.idxstats());
+    } else {
+        print!("{}", stats);
+    }
+}
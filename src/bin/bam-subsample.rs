@@ -0,0 +1,127 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::env;
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bam_subsample::bam_subsample_file;
+
+/* -------------------------------------------------------------------------- */
+
+struct Config {
+    filename_in: String,
+    filename_out: String,
+    fraction: f64,
+    count: Option<u64>,
+    seed: u64,
+    threads: usize,
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn subsample(config: &Config) -> Result<(), Box<dyn Error>> {
+    // Record this program in the header
+    let command_line = env::args().collect::<Vec<_>>().join(" ");
+
+    let (m, n) = bam_subsample_file(
+        &config.filename_in,
+        &config.filename_out,
+        config.fraction,
+        config.count,
+        config.seed,
+        config.threads,
+        Some(&command_line),
+    )?;
+
+    eprintln!("Kept {} out of {} reads or read pairs", m, n);
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM Subsample")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Reproducibly subsample reads of a BAM file, keeping mates together")
+        .arg(
+            Arg::new("fraction")
+                .short('f')
+                .long("fraction")
+                .value_parser(clap::value_parser!(f64))
+                .required_unless_present("count")
+                .conflicts_with("count")
+                .help("Fraction of reads or read pairs to keep"),
+        )
+        .arg(
+            Arg::new("count")
+                .short('n')
+                .long("count")
+                .value_parser(clap::value_parser!(u64))
+                .help("Approximate number of reads or read pairs to keep"),
+        )
+        .arg(
+            Arg::new("seed")
+                .short('s')
+                .long("seed")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0")
+                .help("Seed of the random selection"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output BAM file")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let config = Config {
+        filename_in: matches.get_one::<String>("input").unwrap().clone(),
+        filename_out: matches.get_one::<String>("output").unwrap().clone(),
+        fraction: matches.get_one::<f64>("fraction").copied().unwrap_or(1.0),
+        count: matches.get_one::<u64>("count").copied(),
+        seed: *matches.get_one::<u64>("seed").unwrap(),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
+    };
+
+    if let Err(e) = subsample(&config) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -27,9 +27,12 @@ use std::process;
 use clap::{Arg, ArgAction, Command};
 use plotters::prelude::*;
 
+use rustynetics::bam::BamFlag;
 use rustynetics::bam_coverage::bam_coverage;
+use rustynetics::bam_single_cell::import_barcodes;
 use rustynetics::bigwig::OptionBigWig;
 use rustynetics::coverage::OptionCoverage;
+use rustynetics::read_filter::{AuxFilter, AuxPredicate};
 use rustynetics::track_generic::GenericTrack;
 
 /* -------------------------------------------------------------------------- */
@@ -229,8 +232,19 @@ fn import_fraglen(config: &Config, filename: &str) -> Option<usize> {
 /// - `--filter-duplicates`: Remove reads marked as duplicates.
 /// - `--filter-paired-end`: Remove all single-end reads.
 /// - `--filter-single-end`: Remove all paired-end reads.
+/// - `--filter-include-flags`: Only use reads with all of the given BAM flags set (number or names such as `PROPER_PAIR`).
+/// - `--filter-exclude-flags`: Remove reads with any of the given BAM flags set (number or names such as `SECONDARY,QCFAIL`).
+/// - `--filter-auxiliary`: Only use reads satisfying a condition on an auxiliary field, e.g. `NM<=3` or `!XS` (can be repeated).
+/// - `--filter-read-groups`: Only use reads from the given read groups (comma-separated list).
+/// - `--filter-barcodes`: Remove reads whose cell barcode (`CB` tag) is not listed in the given file.
+/// - `--filter-umi-duplicates`: Remove reads with the same cell barcode, UMI (`UB` tag) and 5' position.
+/// - `--subsample-fraction`: Randomly keep the given fraction of reads, keeping mates together.
+/// - `--subsample-count`: Randomly keep approximately the given number of reads per input file.
+/// - `--subsample-seed`: Seed for subsampling reads (default: 0).
 /// - `--filter-chromosomes`: Exclude reads from specific chromosomes (comma-separated list).
 /// - `--binning-method`: Specify the method used for binning data (valid values: `simple`, `default`, `overlap`, `mean overlap`).
+/// - `--spliced`: Only add coverage on aligned read segments, skipping introns (`N` in the CIGAR string).
+/// - `--count-deletions`: Count deletions as covered when using `--spliced`.
 /// - `--bin-size`: Size of the bins for track (default: 10).
 /// - `--normalize-track`: Method used to normalize the track (`rpkm` or `cpm`).
 /// - `--pseudocounts`: Pseudocounts added to treatment and control signal (default: `0.0,0.0`).
@@ -246,6 +260,7 @@ fn import_fraglen(config: &Config, filename: &str) -> Option<usize> {
 /// - `--save-fraglen`: Save the estimated fragment length to a file.
 /// - `--save-crosscorrelation`: Save cross-correlation data between forward and reverse strands.
 /// - `--save-crosscorrelation-plot`: Save a plot of the cross-correlation data.
+/// - `-t, --threads`: Number of threads used for BGZF decompression (default: 1).
 /// - `-v, --verbose`: Set the verbosity level (use `-v` or `-vv` for higher verbosity).
 ///
 /// ### Files:
@@ -310,6 +325,45 @@ fn main() {
             .long("filter-single-end")
             .action(ArgAction::SetTrue)
             .help("Remove all paired end reads"))
+        .arg(Arg::new("filter-include-flags")
+            .long("filter-include-flags")
+            .num_args(1)
+            .help("Only use reads with all of the given flags set [number or comma separated names, e.g. PROPER_PAIR]"))
+        .arg(Arg::new("filter-exclude-flags")
+            .long("filter-exclude-flags")
+            .num_args(1)
+            .help("Remove reads with any of the given flags set [number or comma separated names, e.g. SECONDARY,SUPPLEMENTARY,QCFAIL]"))
+        .arg(Arg::new("filter-auxiliary")
+            .long("filter-auxiliary")
+            .num_args(1)
+            .action(ArgAction::Append)
+            .help("Only use reads satisfying a condition on an auxiliary field, e.g. `NM<=3`, `!XS` or `XT==U` [can be given multiple times]"))
+        .arg(Arg::new("filter-read-groups")
+            .long("filter-read-groups")
+            .num_args(1)
+            .help("Only use reads from the given read groups [comma separated list]"))
+        .arg(Arg::new("filter-barcodes")
+            .long("filter-barcodes")
+            .num_args(1)
+            .help("Remove all reads whose cell barcode (CB tag) is not listed in the given file"))
+        .arg(Arg::new("filter-umi-duplicates")
+            .long("filter-umi-duplicates")
+            .action(ArgAction::SetTrue)
+            .help("Remove reads with the same cell barcode, UMI (UB tag) and 5' position"))
+        .arg(Arg::new("subsample-fraction")
+            .long("subsample-fraction")
+            .num_args(1)
+            .help("Randomly keep the given fraction of reads [mates are kept together]"))
+        .arg(Arg::new("subsample-count")
+            .long("subsample-count")
+            .num_args(1)
+            .conflicts_with("subsample-fraction")
+            .help("Randomly keep approximately the given number of reads per input file [requires an additional pass over each file]"))
+        .arg(Arg::new("subsample-seed")
+            .long("subsample-seed")
+            .num_args(1)
+            .default_value("0")
+            .help("Seed for subsampling reads [default: 0]"))
         .arg(Arg::new("filter-chromosomes")
             .long("filter-chromosomes")
             .num_args(1)
@@ -323,6 +377,15 @@ fn main() {
             .long("binning-method")
             .num_args(1)
             .help("Binning method"))
+        .arg(Arg::new("spliced")
+            .long("spliced")
+            .action(ArgAction::SetTrue)
+            .help("Only add coverage on aligned read segments, e.g. to skip introns of RNA-seq reads [reads are not extended]"))
+        .arg(Arg::new("count-deletions")
+            .long("count-deletions")
+            .action(ArgAction::SetTrue)
+            .requires("spliced")
+            .help("Count deletions as covered when computing spliced coverage"))
         .arg(Arg::new("bin-size")
             .long("bin-size")
             .num_args(1)
@@ -397,6 +460,12 @@ fn main() {
             .action(ArgAction::SetTrue)
             .help("Save crosscorrelation plot"))
         // generic options
+        .arg(Arg::new("threads")
+            .short('t')
+            .long("threads")
+            .num_args(1)
+            .default_value("1")
+            .help("Number of threads used for BGZF decompression [default: 1]"))
         .arg(Arg::new("verbose")
             .short('v')
             .action(ArgAction::Count)
@@ -590,6 +659,77 @@ fn main() {
         process::exit(1);
     }
 
+    if let Some(opt_flags) = matches.get_one::<String>("filter-include-flags") {
+        let flags = opt_flags.parse::<BamFlag>().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        options_list.push(OptionCoverage::FilterIncludeFlags(flags.0));
+    }
+
+    if let Some(opt_flags) = matches.get_one::<String>("filter-exclude-flags") {
+        let flags = opt_flags.parse::<BamFlag>().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        options_list.push(OptionCoverage::FilterExcludeFlags(flags.0));
+    }
+
+    let mut filter_auxiliary: Vec<AuxFilter> = Vec::new();
+    if let Some(opt_filter_auxiliary) = matches.get_many::<String>("filter-auxiliary") {
+        for expr in opt_filter_auxiliary {
+            filter_auxiliary.push(expr.parse().unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1);
+            }));
+        }
+    }
+    if let Some(opt_read_groups) = matches.get_one::<String>("filter-read-groups") {
+        filter_auxiliary.push(AuxFilter::new(
+            b"RG",
+            AuxPredicate::In(opt_read_groups.split(',').map(String::from).collect()),
+        ));
+    }
+    options_list.push(OptionCoverage::FilterAuxiliary(filter_auxiliary));
+
+    if let Some(opt_filter_barcodes) = matches.get_one::<String>("filter-barcodes") {
+        let barcodes = import_barcodes(opt_filter_barcodes).unwrap_or_else(|err| {
+            eprintln!("Error reading barcodes: {}", err);
+            process::exit(1);
+        });
+        options_list.push(OptionCoverage::FilterBarcodes(
+            barcodes.into_keys().collect(),
+        ));
+    }
+
+    if let Some(opt_fraction) = matches.get_one::<String>("subsample-fraction") {
+        let fraction: f64 = opt_fraction.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid subsampling fraction");
+            process::exit(1);
+        });
+        if fraction <= 0.0 || fraction > 1.0 {
+            eprintln!("Subsampling fraction must be in (0, 1]");
+            process::exit(1);
+        }
+        options_list.push(OptionCoverage::SubsampleFraction(fraction));
+    }
+
+    if let Some(opt_count) = matches.get_one::<String>("subsample-count") {
+        let count: usize = opt_count.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid subsampling count");
+            process::exit(1);
+        });
+        options_list.push(OptionCoverage::SubsampleCount(count));
+    }
+
+    if let Some(opt_seed) = matches.get_one::<String>("subsample-seed") {
+        let seed: u64 = opt_seed.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid subsampling seed");
+            process::exit(1);
+        });
+        options_list.push(OptionCoverage::SubsampleSeed(seed));
+    }
+
     if let Some(opt_filter_chroms) = matches.get_one::<String>("filter-chromosomes") {
         options_list.push(OptionCoverage::FilterChroms(
             opt_filter_chroms.split(',').map(String::from).collect(),
@@ -611,6 +751,10 @@ fn main() {
         matches.get_flag("estimate-fragment-length"),
     ));
     options_list.push(OptionCoverage::LogScale(matches.get_flag("log-scale")));
+    options_list.push(OptionCoverage::Spliced(matches.get_flag("spliced")));
+    options_list.push(OptionCoverage::CountDeletions(
+        matches.get_flag("count-deletions"),
+    ));
     options_list.push(OptionCoverage::PairedAsSingleEnd(
         matches.get_flag("paired-as-single-end"),
     ));
@@ -626,6 +770,17 @@ fn main() {
     options_list.push(OptionCoverage::FilterSingleEnd(
         matches.get_flag("filter-single-end"),
     ));
+    options_list.push(OptionCoverage::FilterUmiDuplicates(
+        matches.get_flag("filter-umi-duplicates"),
+    ));
+
+    if let Some(opt_threads) = matches.get_one::<String>("threads") {
+        let threads: usize = opt_threads.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid number of threads");
+            process::exit(1);
+        });
+        options_list.push(OptionCoverage::Threads(threads));
+    }
 
     config.save_fraglen = matches.get_flag("save-fraglen");
     config.save_cross_corr = matches.get_flag("save-crosscorrelation");
@@ -48,6 +48,7 @@ struct Config {
     include_supplementary: bool,
     include_qcfail: bool,
     fill_missing_quality: Option<char>,
+    threads: usize,
 }
 
 /* -------------------------------------------------------------------------- */
@@ -277,6 +278,7 @@ fn bam_to_fastq(config: &Config) -> Result<WriteStats, Box<dyn Error>> {
         read_sequence: true,
         read_auxiliary: false,
         read_qual: true,
+        threads: config.threads,
     };
 
     let file = File::open(&config.filename_bam)?;
@@ -379,6 +381,15 @@ fn main() {
                 .value_parser(clap::builder::ValueParser::new(parse_fill_missing_quality))
                 .help("Fill missing BAM qualities with this single ASCII character"),
         )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .num_args(1)
+                .default_value("1")
+                .value_parser(clap::value_parser!(usize))
+                .help("Number of threads used for BGZF decompression"),
+        )
         .get_matches();
 
     let config = Config {
@@ -392,6 +403,7 @@ fn main() {
         include_supplementary: matches.get_flag("include-supplementary"),
         include_qcfail: matches.get_flag("include-qcfail"),
         fill_missing_quality: matches.get_one::<char>("fill-missing-quality").copied(),
+        threads: *matches.get_one::<usize>("threads").unwrap(),
     };
 
     match bam_to_fastq(&config) {
@@ -0,0 +1,125 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::process;
+
+use clap::{Arg, ArgAction, Command};
+
+use rustynetics::bam_single_cell::{bam_export_fragments, import_barcodes};
+use rustynetics::coverage::OptionCoverage;
+use rustynetics::infologger::Logger;
+
+/* -------------------------------------------------------------------------- */
+
+fn export_fragments(
+    filename_in: &str,
+    filename_out: &str,
+    filename_barcodes: Option<&String>,
+    tn5_shift: bool,
+    options: Vec<OptionCoverage>,
+) -> Result<(), Box<dyn Error>> {
+    let mut options = options;
+
+    if let Some(filename) = filename_barcodes {
+        let barcodes: HashSet<String> = import_barcodes(filename)?.into_keys().collect();
+        options.push(OptionCoverage::FilterBarcodes(barcodes));
+    }
+    bam_export_fragments(filename_in, filename_out, tn5_shift, options)?;
+
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BAM To Fragments")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Export fragments of single-cell ATAC-seq alignments (fragments.tsv.gz)")
+        .arg(
+            Arg::new("barcodes")
+                .short('b')
+                .long("barcodes")
+                .help("File with whitelisted cell barcodes, one per line"),
+        )
+        .arg(
+            Arg::new("min-mapq")
+                .short('Q')
+                .long("min-mapq")
+                .value_parser(clap::value_parser!(i64))
+                .default_value("0")
+                .help("Minimum mapping quality"),
+        )
+        .arg(
+            Arg::new("no-tn5-shift")
+                .long("no-tn5-shift")
+                .action(ArgAction::SetTrue)
+                .help("Do not shift fragment ends by +4/-5 bps"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Number of threads used for BGZF decompression"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Verbose output"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The coordinate-sorted input BAM file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output fragment file [BGZF compressed if it ends in .gz]")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let mut options = vec![
+        OptionCoverage::FilterMapQ(*matches.get_one::<i64>("min-mapq").unwrap()),
+        OptionCoverage::Threads(*matches.get_one::<usize>("threads").unwrap()),
+    ];
+    if matches.get_flag("verbose") {
+        options.push(OptionCoverage::Logger(Logger::new_stderr()));
+    }
+
+    if let Err(e) = export_fragments(
+        matches.get_one::<String>("input").unwrap(),
+        matches.get_one::<String>("output").unwrap(),
+        matches.get_one::<String>("barcodes"),
+        !matches.get_flag("no-tn5-shift"),
+        options,
+    ) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
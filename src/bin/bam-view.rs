@@ -20,11 +20,12 @@
 
 use std::error::Error;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, BufWriter};
 use std::process;
 
 use clap::{Arg, Command};
 use rustynetics::bam::{BamReader, BamReaderOptions};
+use rustynetics::sam::SamWriter;
 
 /* -------------------------------------------------------------------------- */
 
@@ -33,11 +34,41 @@ struct Config {
     print_cigar: bool,
     print_sequence: bool,
     print_auxiliary: bool,
+    print_sam: bool,
+    print_sam_header: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn bam_view_sam(config: Config, filename_in: &str) -> Result<(), Box<dyn Error>> {
+    let file = File::open(filename_in)?;
+    let reader = BufReader::new(file);
+
+    let mut bam_reader = BamReader::new(reader, None)?;
+
+    let genome = bam_reader.get_genome().clone();
+    let writer = BufWriter::new(io::stdout());
+
+    let mut sam_writer = if config.print_sam_header {
+        SamWriter::new(writer, bam_reader.get_header(), &genome)?
+    } else {
+        SamWriter::new_without_header(writer, &genome)
+    };
+
+    for result in bam_reader.read_single_end() {
+        sam_writer.write_block(&result?.block)?;
+    }
+    sam_writer.flush()?;
+
+    Ok(())
 }
 
 /* -------------------------------------------------------------------------- */
 
 fn bam_view(config: Config, filename_in: &str) -> Result<(), Box<dyn Error>> {
+    if config.print_sam {
+        return bam_view_sam(config, filename_in);
+    }
     let file = File::open(filename_in)?;
     let reader = BufReader::new(file);
     let star = "*".to_string();
@@ -50,6 +81,7 @@ fn bam_view(config: Config, filename_in: &str) -> Result<(), Box<dyn Error>> {
         read_sequence: config.print_sequence,
         read_auxiliary: config.print_auxiliary,
         read_qual: false,
+        threads: 1,
     };
 
     let mut bam_reader = BamReader::new(reader, Some(options))?;
@@ -69,10 +101,10 @@ fn bam_view(config: Config, filename_in: &str) -> Result<(), Box<dyn Error>> {
         print!(" {:>40}", "ReadName");
     }
     if options.read_sequence {
-        print!(" {}", "Sequence");
+        print!(" Sequence");
     }
     if options.read_auxiliary {
-        print!(" {}", "Auxiliary");
+        print!(" Auxiliary");
     }
     println!();
 
@@ -98,21 +130,21 @@ fn bam_view(config: Config, filename_in: &str) -> Result<(), Box<dyn Error>> {
         );
 
         if options.read_cigar {
-            if block.cigar.0.len() > 0 {
+            if !block.cigar.0.is_empty() {
                 print!(" {:>20}", block.cigar.to_string());
             } else {
                 print!(" {:>20}", "-");
             }
         }
         if options.read_name {
-            if block.read_name.len() > 0 {
+            if !block.read_name.is_empty() {
                 print!(" {:>40}", block.read_name);
             } else {
                 print!(" {:>40}", "-");
             }
         }
         if options.read_sequence {
-            if block.seq.0.len() > 0 {
+            if !block.seq.0.is_empty() {
                 print!(" {}", block.seq);
             } else {
                 print!(" -");
@@ -120,10 +152,10 @@ fn bam_view(config: Config, filename_in: &str) -> Result<(), Box<dyn Error>> {
         }
         if options.read_auxiliary {
             if block.auxiliary.is_empty() {
-                print!("-");
+                print!(" -");
             } else {
                 for aux in block.auxiliary.iter() {
-                    print!(" {}", aux.to_string());
+                    print!(" {}", aux);
                 }
             }
         }
@@ -164,6 +196,20 @@ fn main() {
                 .action(clap::ArgAction::SetTrue)
                 .help("Print auxiliary information"),
         )
+        .arg(
+            Arg::new("sam")
+                .long("sam")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print records in SAM format"),
+        )
+        .arg(
+            Arg::new("header")
+                .short('H')
+                .long("header")
+                .action(clap::ArgAction::SetTrue)
+                .requires("sam")
+                .help("Print the SAM header (requires --sam)"),
+        )
         .arg(
             Arg::new("input")
                 .help("The input BAM file")
@@ -177,6 +223,8 @@ fn main() {
         print_cigar: !matches.get_flag("no-cigar"),
         print_sequence: matches.get_flag("sequence"),
         print_auxiliary: matches.get_flag("auxiliary"),
+        print_sam: matches.get_flag("sam"),
+        print_sam_header: matches.get_flag("header"),
     };
 
     let filename_in = matches.get_one::<String>("input").unwrap();
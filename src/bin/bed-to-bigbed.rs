@@ -0,0 +1,187 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::process;
+
+use clap::{Arg, Command};
+use flate2::read::GzDecoder;
+
+use rustynetics::bigbed::{AutoSql, AutoSqlField, BigBedFile, BigBedRecord, OptionBigBed};
+use rustynetics::genome::Genome;
+
+/* -------------------------------------------------------------------------- */
+
+fn read_bed(filename: &str) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    let file = File::open(filename)?;
+    let reader: Box<dyn BufRead> = if filename.ends_with(".gz") {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut rows = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        rows.push(line.split('\t').map(String::from).collect());
+    }
+    Ok(rows)
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn bed_to_bigbed(
+    filename_in: &str,
+    filename_genome: &str,
+    filename_out: &str,
+    filename_as: Option<&String>,
+    parameters: Vec<OptionBigBed>,
+) -> Result<(), Box<dyn Error>> {
+    let mut genome = Genome::default();
+    genome.import(filename_genome)?;
+
+    let rows = read_bed(filename_in)?;
+    let n_fields = rows.first().map_or(3, |row| row.len());
+
+    // Use standard BED fields followed by string fields if no autoSql
+    // declaration is given
+    let autosql = match filename_as {
+        Some(filename) => fs::read_to_string(filename)?.parse::<AutoSql>()?,
+        None => {
+            let mut autosql = AutoSql::bed(n_fields.min(12));
+            for i in autosql.fields.len()..n_fields {
+                let name = format!("field{}", i + 1);
+                autosql
+                    .fields
+                    .push(AutoSqlField::new("string", &name, "Undocumented field"));
+            }
+            autosql
+        }
+    };
+
+    let mut records = Vec::with_capacity(rows.len());
+    for row in rows {
+        if row.len() < 3 {
+            return Err(format!("invalid BED line `{}`", row.join("\t")).into());
+        }
+        let idx = genome
+            .get_idx(&row[0])
+            .ok_or(format!("sequence `{}` not found in genome", row[0]))?;
+        records.push((
+            idx,
+            BigBedRecord {
+                chrom: row[0].clone(),
+                from: row[1].parse()?,
+                to: row[2].parse()?,
+                rest: row[3..].join("\t"),
+            },
+        ));
+    }
+    records.sort_by_key(|(idx, r)| (*idx, r.from, r.to));
+
+    let mut writer = BigBedFile::new_writer(filename_out, genome, autosql, parameters)?;
+    for (_, record) in &records {
+        writer.write(record)?;
+    }
+    writer.close()
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("BED to bigBed")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Convert a BED file to bigBed format")
+        .arg(
+            Arg::new("as")
+                .long("as")
+                .num_args(1)
+                .help("File with autoSql declaration of the BED fields"),
+        )
+        .arg(
+            Arg::new("block-size")
+                .long("block-size")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("256")
+                .help("Number of items per index vertex"),
+        )
+        .arg(
+            Arg::new("items-per-slot")
+                .long("items-per-slot")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("512")
+                .help("Number of items per data block"),
+        )
+        .arg(
+            Arg::new("extra-index")
+                .long("extra-index")
+                .num_args(1)
+                .help("Comma-separated list of fields to index for searching, e.g. `name`"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input BED file")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("genome")
+                .help("File with chromosome names and lengths")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output bigBed file")
+                .required(true)
+                .index(3),
+        )
+        .get_matches();
+
+    let mut parameters = vec![
+        OptionBigBed::BlockSize(*matches.get_one::<usize>("block-size").unwrap()),
+        OptionBigBed::ItemsPerSlot(*matches.get_one::<usize>("items-per-slot").unwrap()),
+    ];
+    if let Some(fields) = matches.get_one::<String>("extra-index") {
+        parameters.push(OptionBigBed::ExtraIndex(
+            fields.split(',').map(String::from).collect(),
+        ));
+    }
+
+    if let Err(e) = bed_to_bigbed(
+        matches.get_one::<String>("input").unwrap(),
+        matches.get_one::<String>("genome").unwrap(),
+        matches.get_one::<String>("output").unwrap(),
+        matches.get_one::<String>("as"),
+        parameters,
+    ) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+
+use clap::{Arg, Command};
+use flate2::read::GzDecoder;
+
+use rustynetics::bigwig::{BigWigFile, OptionBigWig};
+use rustynetics::genome::Genome;
+
+/* -------------------------------------------------------------------------- */
+
+fn bedgraph_to_bigwig(
+    filename_in: &str,
+    filename_genome: &str,
+    filename_out: &str,
+    parameters: Vec<OptionBigWig>,
+) -> Result<(), Box<dyn Error>> {
+    let mut genome = Genome::default();
+    genome.import(filename_genome)?;
+
+    let file = File::open(filename_in)?;
+    let reader: Box<dyn BufRead> = if filename_in.ends_with(".gz") {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    // Items are passed on one at a time, so that memory usage does not
+    // depend on the size of the input
+    let mut writer = BigWigFile::new_stream_writer(filename_out, genome, parameters)?;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("invalid bedGraph line {}: `{}`", i + 1, line).into());
+        }
+        writer
+            .write_interval(
+                fields[0],
+                fields[1].parse()?,
+                fields[2].parse()?,
+                fields[3].parse()?,
+            )
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+    writer.close()
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("bedGraph to bigWig")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Convert a sorted bedGraph file to bigWig format without loading it into memory")
+        .arg(
+            Arg::new("block-size")
+                .long("block-size")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("256")
+                .help("Number of items per index vertex"),
+        )
+        .arg(
+            Arg::new("items-per-slot")
+                .long("items-per-slot")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1024")
+                .help("Number of items per data block"),
+        )
+        .arg(
+            Arg::new("reduction-levels")
+                .long("reduction-levels")
+                .num_args(1)
+                .help("Comma-separated list of zoom level resolutions [default: automatic]"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input bedGraph file, sorted by chromosome and position")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("genome")
+                .help("File with chromosome names and lengths")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The output bigWig file")
+                .required(true)
+                .index(3),
+        )
+        .get_matches();
+
+    let mut parameters = vec![
+        OptionBigWig::BlockSize(*matches.get_one::<usize>("block-size").unwrap()),
+        OptionBigWig::ItemsPerSlot(*matches.get_one::<usize>("items-per-slot").unwrap()),
+    ];
+    if let Some(levels) = matches.get_one::<String>("reduction-levels") {
+        match levels
+            .split(',')
+            .map(|x| x.trim().parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(levels) => parameters.push(OptionBigWig::ReductionLevels(levels)),
+            Err(e) => {
+                eprintln!("Error: invalid reduction levels: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Err(e) = bedgraph_to_bigwig(
+        matches.get_one::<String>("input").unwrap(),
+        matches.get_one::<String>("genome").unwrap(),
+        matches.get_one::<String>("output").unwrap(),
+        parameters,
+    ) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use clap::{Arg, Command};
+
+use rustynetics::bigbed::BigBedFile;
+
+/* -------------------------------------------------------------------------- */
+
+fn bigbed_to_bed(
+    filename_in: &str,
+    seq_regex: &str,
+    from: usize,
+    to: usize,
+    search: Option<&String>,
+    print_autosql: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = BigBedFile::new_reader(filename_in)?;
+    let mut writer = BufWriter::new(io::stdout().lock());
+
+    if print_autosql {
+        write!(writer, "{}", reader.autosql())?;
+        return Ok(());
+    }
+    if let Some(search) = search {
+        let (field, value) = search
+            .split_once('=')
+            .ok_or(format!("invalid search `{}`, expected FIELD=VALUE", search))?;
+        for record in reader.search_records(field, value)? {
+            writeln!(writer, "{}", record)?;
+        }
+        return Ok(());
+    }
+    for record in reader.query(seq_regex, from, to) {
+        writeln!(writer, "{}", record?)?;
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn main() {
+    let matches = Command::new("bigBed to BED")
+        .version("1.0")
+        .author("Philipp Benner [https://github.com/pbenner]")
+        .about("Print the items of a bigBed file in BED format, optionally restricted to a region")
+        .arg(
+            Arg::new("chrom")
+                .long("chrom")
+                .num_args(1)
+                .default_value(".*")
+                .help("Regular expression for chromosome names"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+                .help("Start of the queried region"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_parser(clap::value_parser!(usize))
+                .help("End of the queried region [default: end of chromosome]"),
+        )
+        .arg(
+            Arg::new("search")
+                .long("search")
+                .num_args(1)
+                .help("Print items where FIELD=VALUE using an extra index of the file"),
+        )
+        .arg(
+            Arg::new("autosql")
+                .long("autosql")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the autoSql declaration instead of items"),
+        )
+        .arg(
+            Arg::new("input")
+                .help("The input bigBed file")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    if let Err(e) = bigbed_to_bed(
+        matches.get_one::<String>("input").unwrap(),
+        matches.get_one::<String>("chrom").unwrap(),
+        *matches.get_one::<usize>("from").unwrap(),
+        matches
+            .get_one::<usize>("to")
+            .copied()
+            .unwrap_or(usize::MAX),
+        matches.get_one::<String>("search"),
+        matches.get_flag("autosql"),
+    ) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,100 @@
+use std::process;
+
+use clap::{Arg, ArgAction, Command};
+
+use rustynetics::bigwig::BigWigFile;
+use rustynetics::bigwig_summary::BigWigSummaryStat;
+use rustynetics::granges::GRanges;
+use rustynetics::granges_table::{OptionPrintScientific, OptionPrintStrand};
+use rustynetics::meta::MetaData;
+
+mod common;
+
+fn load_regions(path: &str) -> GRanges {
+    let mut reader = common::open_reader(Some(path)).unwrap_or_else(|error| {
+        eprintln!("opening BED failed: {error}");
+        process::exit(1);
+    });
+    let mut granges = GRanges::default();
+    granges.read_bed3(&mut reader).unwrap_or_else(|error| {
+        eprintln!("reading BED failed: {error}");
+        process::exit(1);
+    });
+    granges
+}
+
+fn main() {
+    let matches = Command::new("bigwig-summarize")
+        .about("Summarize BigWig data within BED regions, similar to bigWigAverageOverBed")
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .default_value("mean,mean0,min,max,coverage,sum")
+                .help("Comma-separated list of statistics [mean, mean0, min, max, coverage, sum]"),
+        )
+        .arg(
+            Arg::new("exact")
+                .long("exact")
+                .action(ArgAction::SetTrue)
+                .help("Use raw data only instead of zoom levels for large regions"),
+        )
+        .arg(
+            Arg::new("scientific")
+                .long("scientific")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(Arg::new("bigwig").required(true).index(1))
+        .arg(Arg::new("bed").required(true).index(2))
+        .arg(Arg::new("output").index(3))
+        .get_matches();
+
+    let bigwig = matches.get_one::<String>("bigwig").unwrap();
+    let bed = matches.get_one::<String>("bed").unwrap();
+    let output = matches.get_one::<String>("output").map(String::as_str);
+    let stats: Vec<BigWigSummaryStat> = matches
+        .get_one::<String>("stats")
+        .unwrap()
+        .split(',')
+        .map(|name| name.trim().parse())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|error| {
+            eprintln!("{error}");
+            process::exit(1);
+        });
+    let scientific = matches.get_flag("scientific");
+
+    let mut regions = load_regions(bed);
+
+    let mut reader = BigWigFile::new_reader(bigwig).unwrap_or_else(|error| {
+        eprintln!("opening BigWig failed: {error}");
+        process::exit(1);
+    });
+    let summaries = reader
+        .summarize_regions(&regions, !matches.get_flag("exact"))
+        .unwrap_or_else(|error| {
+            eprintln!("summarizing BigWig failed: {error}");
+            process::exit(1);
+        });
+
+    for stat in stats {
+        let values = summaries.iter().map(|s| s.get(stat)).collect();
+        let name = stat.to_string();
+        regions.meta.delete_meta(&name);
+        if let Err(error) = regions.meta.add(&name, MetaData::FloatArray(values)) {
+            eprintln!("adding column `{name}` failed: {error}");
+            process::exit(1);
+        }
+    }
+
+    let mut writer = common::open_writer(output).unwrap_or_else(|error| {
+        eprintln!("opening output failed: {error}");
+        process::exit(1);
+    });
+    if let Err(error) = regions.write_table(
+        &mut writer,
+        &[&OptionPrintStrand(true), &OptionPrintScientific(scientific)],
+    ) {
+        eprintln!("writing table failed: {error}");
+        process::exit(1);
+    }
+}
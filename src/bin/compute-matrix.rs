@@ -0,0 +1,214 @@
+use std::process;
+
+use clap::{Arg, ArgAction, Command};
+
+use rustynetics::bigwig::BigWigFile;
+use rustynetics::bigwig_summary::BigWigSummaryStat;
+use rustynetics::compute_matrix::{
+    compute_matrix, MatrixConfig, MatrixMode, MatrixRowStat, MatrixSignal, ReferencePoint,
+};
+use rustynetics::granges::GRanges;
+
+mod common;
+
+// Reads BED6 regions, falling back to unstranded BED3 regions
+fn load_regions(path: &str) -> GRanges {
+    let open = || {
+        common::open_reader(Some(path)).unwrap_or_else(|error| {
+            eprintln!("opening BED failed: {error}");
+            process::exit(1);
+        })
+    };
+    let mut granges = GRanges::default();
+    if granges.read_bed6(&mut open()).is_ok() {
+        return granges;
+    }
+    let mut granges = GRanges::default();
+    granges.read_bed3(&mut open()).unwrap_or_else(|error| {
+        eprintln!("reading BED failed: {error}");
+        process::exit(1);
+    });
+    granges
+}
+
+fn parse_or_exit<T: std::str::FromStr>(value: &str) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().unwrap_or_else(|error| {
+        eprintln!("{error}");
+        process::exit(1);
+    })
+}
+
+fn main() {
+    let matches = Command::new("compute-matrix")
+        .about("Compute a regions x bins matrix of BigWig signal for profile plots and heatmaps, similar to deepTools computeMatrix")
+        .arg(
+            Arg::new("scale-regions")
+                .long("scale-regions")
+                .value_parser(clap::value_parser!(usize))
+                .help("Scale regions to the given body length instead of using a reference point"),
+        )
+        .arg(
+            Arg::new("reference-point")
+                .long("reference-point")
+                .default_value("start")
+                .help("Reference point of regions [start, end, center]"),
+        )
+        .arg(
+            Arg::new("upstream")
+                .long("upstream")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("downstream")
+                .long("downstream")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("bin-size")
+                .long("bin-size")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("stat")
+                .long("stat")
+                .default_value("mean")
+                .help("Summary statistic of bins [mean, mean0, min, max, coverage, sum]"),
+        )
+        .arg(
+            Arg::new("ignore-strand")
+                .long("ignore-strand")
+                .action(ArgAction::SetTrue)
+                .help("Do not flip regions on the negative strand"),
+        )
+        .arg(
+            Arg::new("sort-by")
+                .long("sort-by")
+                .default_value("mean")
+                .help("Sort regions by a row statistic [mean, median, min, max, sum, keep]"),
+        )
+        .arg(
+            Arg::new("ascending")
+                .long("ascending")
+                .action(ArgAction::SetTrue)
+                .help("Sort regions in ascending order"),
+        )
+        .arg(
+            Arg::new("clusters")
+                .long("clusters")
+                .value_parser(clap::value_parser!(usize))
+                .help("Cluster regions into the given number of groups using k-means"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("labels")
+                .long("labels")
+                .help("Comma-separated list of track labels [default: file names]"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Save a profile plot as PNG"),
+        )
+        .arg(
+            Arg::new("heatmap")
+                .long("heatmap")
+                .help("Save a heatmap as PNG"),
+        )
+        .arg(Arg::new("bed").required(true).index(1))
+        .arg(Arg::new("output").required(true).index(2))
+        .arg(
+            Arg::new("bigwigs")
+                .required(true)
+                .num_args(1..)
+                .index(3),
+        )
+        .get_matches();
+
+    let bed = matches.get_one::<String>("bed").unwrap();
+    let output = matches.get_one::<String>("output").unwrap();
+    let bigwigs: Vec<&String> = matches.get_many::<String>("bigwigs").unwrap().collect();
+
+    let mode = match matches.get_one::<usize>("scale-regions") {
+        Some(&body_length) => MatrixMode::ScaleRegions { body_length },
+        None => MatrixMode::ReferencePoint(parse_or_exit::<ReferencePoint>(
+            matches.get_one::<String>("reference-point").unwrap(),
+        )),
+    };
+    let config = MatrixConfig {
+        mode,
+        upstream: *matches.get_one::<usize>("upstream").unwrap(),
+        downstream: *matches.get_one::<usize>("downstream").unwrap(),
+        bin_size: *matches.get_one::<usize>("bin-size").unwrap(),
+        stat: parse_or_exit::<BigWigSummaryStat>(matches.get_one::<String>("stat").unwrap()),
+        flip_negative_strand: !matches.get_flag("ignore-strand"),
+    };
+    let sort_by = match matches.get_one::<String>("sort-by").unwrap().as_str() {
+        "keep" => None,
+        name => Some(parse_or_exit::<MatrixRowStat>(name)),
+    };
+    let labels: Vec<String> = match matches.get_one::<String>("labels") {
+        Some(labels) => labels.split(',').map(String::from).collect(),
+        None => bigwigs.iter().map(|s| s.to_string()).collect(),
+    };
+
+    let regions = load_regions(bed);
+
+    let mut readers = bigwigs
+        .iter()
+        .map(|path| {
+            BigWigFile::new_reader(path).unwrap_or_else(|error| {
+                eprintln!("opening BigWig `{path}` failed: {error}");
+                process::exit(1);
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut signals: Vec<&mut dyn MatrixSignal> = readers
+        .iter_mut()
+        .map(|reader| reader as &mut dyn MatrixSignal)
+        .collect();
+
+    let mut matrix =
+        compute_matrix(&mut signals, &labels, &regions, &config).unwrap_or_else(|error| {
+            eprintln!("computing matrix failed: {error}");
+            process::exit(1);
+        });
+
+    if let Some(&k) = matches.get_one::<usize>("clusters") {
+        let seed = *matches.get_one::<u64>("seed").unwrap();
+        if let Err(error) = matrix.cluster_rows(k, seed) {
+            eprintln!("clustering regions failed: {error}");
+            process::exit(1);
+        }
+    }
+    if let Some(stat) = sort_by {
+        matrix.sort_rows(stat, !matches.get_flag("ascending"));
+    }
+
+    if let Err(error) = matrix.export_matrix(output, output.ends_with(".gz")) {
+        eprintln!("writing matrix failed: {error}");
+        process::exit(1);
+    }
+    if let Some(filename) = matches.get_one::<String>("profile") {
+        if let Err(error) = matrix.plot_profile(filename) {
+            eprintln!("plotting profile failed: {error}");
+            process::exit(1);
+        }
+    }
+    if let Some(filename) = matches.get_one::<String>("heatmap") {
+        if let Err(error) = matrix.plot_heatmap(filename) {
+            eprintln!("plotting heatmap failed: {error}");
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,1112 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::str::FromStr;
+
+use byteorder::LittleEndian;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use plotters::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+
+use crate::bigwig::BigWigReader;
+use crate::bigwig_summary::{BigWigRegionSummary, BigWigSummaryStat};
+use crate::genome::Genome;
+use crate::granges::GRanges;
+use crate::track::Track;
+
+/* -------------------------------------------------------------------------- */
+
+/// Maximum number of rows drawn per heatmap. Larger matrices are reduced by
+/// averaging consecutive rows.
+const HEATMAP_MAX_ROWS: usize = 800;
+
+/// Maximum number of iterations of k-means clustering.
+const KMEANS_MAX_ITERATIONS: usize = 100;
+
+/* -------------------------------------------------------------------------- */
+
+/// Source of signal for `compute_matrix`, implemented for bigWig readers and
+/// for tracks wrapped in a `TrackSignal`.
+pub trait MatrixSignal {
+    fn get_genome(&self) -> &Genome;
+
+    /// Summarizes the signal within each bin on the given sequence. Bins must
+    /// be sorted, non-overlapping and lie within the sequence, but may be
+    /// empty.
+    fn summarize_bins(
+        &mut self,
+        seqname: &str,
+        bins: &[(usize, usize)],
+    ) -> Result<Vec<BigWigRegionSummary>, Box<dyn Error>>;
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Adds value `x` covering `[from, to)` to all overlapping bins.
+fn summarize_bins_add(
+    summaries: &mut [BigWigRegionSummary],
+    bins: &[(usize, usize)],
+    from: usize,
+    to: usize,
+    x: f64,
+) {
+    let mut k = bins.partition_point(|bin| bin.1 <= from);
+    while k < bins.len() && bins[k].0 < to {
+        let n = to.min(bins[k].1) as f64 - from.max(bins[k].0) as f64;
+        if n > 0.0 {
+            summaries[k].add(n, n * x, x, x);
+        }
+        k += 1;
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: Read + Seek> MatrixSignal for BigWigReader<R> {
+    fn get_genome(&self) -> &Genome {
+        &self.genome
+    }
+
+    fn summarize_bins(
+        &mut self,
+        seqname: &str,
+        bins: &[(usize, usize)],
+    ) -> Result<Vec<BigWigRegionSummary>, Box<dyn Error>> {
+        let mut summaries: Vec<BigWigRegionSummary> = bins
+            .iter()
+            .map(|&(from, to)| BigWigRegionSummary::new(to - from))
+            .collect();
+
+        let (from, to) = match (bins.first(), bins.last()) {
+            (Some(first), Some(last)) if first.0 < last.1 => (first.0, last.1),
+            _ => return Ok(summaries),
+        };
+        let chrom_id = self
+            .genome
+            .get_idx(seqname)
+            .ok_or(format!("Sequence '{}' not found", seqname))?;
+
+        // Query the whole window at once, raw records contain a single value
+        for record in self.bwf.query_records::<LittleEndian, R>(
+            &mut self.reader,
+            None,
+            chrom_id as u32,
+            from as u32,
+            to as u32,
+        )? {
+            summarize_bins_add(
+                &mut summaries,
+                bins,
+                record.from as usize,
+                record.to as usize,
+                record.statistics.sum,
+            );
+        }
+        Ok(summaries)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Signal of a track for `compute_matrix`.
+pub struct TrackSignal<'a>(pub &'a dyn Track);
+
+impl MatrixSignal for TrackSignal<'_> {
+    fn get_genome(&self) -> &Genome {
+        self.0.get_genome()
+    }
+
+    /// Summarizes the track bins overlapping each bin, weighted by the size
+    /// of the overlap. Track bins with NaN values are treated as missing.
+    fn summarize_bins(
+        &mut self,
+        seqname: &str,
+        bins: &[(usize, usize)],
+    ) -> Result<Vec<BigWigRegionSummary>, Box<dyn Error>> {
+        let mut summaries: Vec<BigWigRegionSummary> = bins
+            .iter()
+            .map(|&(from, to)| BigWigRegionSummary::new(to - from))
+            .collect();
+
+        let (from, to) = match (bins.first(), bins.last()) {
+            (Some(first), Some(last)) if first.0 < last.1 => (first.0, last.1),
+            _ => return Ok(summaries),
+        };
+        let bin_size = self.0.get_bin_size();
+
+        // Align the queried window to track bins
+        let from = from / bin_size * bin_size;
+        let to = to.div_ceil(bin_size) * bin_size;
+
+        let granges = GRanges::new(vec![seqname.to_string()], vec![from], vec![to], vec!['*']);
+
+        for (i, &x) in self.0.get_slice(&granges.row(0))?.iter().enumerate() {
+            if x.is_nan() {
+                continue;
+            }
+            let bin_from = from + i * bin_size;
+            summarize_bins_add(&mut summaries, bins, bin_from, bin_from + bin_size, x);
+        }
+        Ok(summaries)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Anchor of regions in reference-point mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReferencePoint {
+    /// Region start, i.e. the TSS of genes
+    Start,
+    /// Region end, i.e. the TES of genes
+    End,
+    Center,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for ReferencePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ReferencePoint::Start => "start",
+            ReferencePoint::End => "end",
+            ReferencePoint::Center => "center",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for ReferencePoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" | "TSS" => Ok(ReferencePoint::Start),
+            "end" | "TES" => Ok(ReferencePoint::End),
+            "center" => Ok(ReferencePoint::Center),
+            _ => Err(format!("invalid reference point `{}`", s)),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Placement of bins relative to regions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatrixMode {
+    /// Bins upstream and downstream of a single position of each region
+    ReferencePoint(ReferencePoint),
+    /// Regions are scaled to `body_length` bases, with additional bins
+    /// upstream and downstream of the region
+    ScaleRegions { body_length: usize },
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Configuration of `compute_matrix`.
+#[derive(Clone, Debug)]
+pub struct MatrixConfig {
+    pub mode: MatrixMode,
+    /// Number of bases upstream of the reference point or region start
+    pub upstream: usize,
+    /// Number of bases downstream of the reference point or region end
+    pub downstream: usize,
+    pub bin_size: usize,
+    /// Statistic used to summarize the signal within each bin
+    pub stat: BigWigSummaryStat,
+    /// Reverse bins of regions on the negative strand, so that upstream is
+    /// always on the left
+    pub flip_negative_strand: bool,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Default for MatrixConfig {
+    fn default() -> Self {
+        MatrixConfig {
+            mode: MatrixMode::ReferencePoint(ReferencePoint::Start),
+            upstream: 1000,
+            downstream: 1000,
+            bin_size: 10,
+            stat: BigWigSummaryStat::Mean,
+            flip_negative_strand: true,
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl MatrixConfig {
+    fn body_length(&self) -> usize {
+        match self.mode {
+            MatrixMode::ReferencePoint(_) => 0,
+            MatrixMode::ScaleRegions { body_length } => body_length,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.bin_size == 0 {
+            return Err("bin size must be positive".to_string());
+        }
+        if let MatrixMode::ScaleRegions { body_length: 0 } = self.mode {
+            return Err("body length must be positive".to_string());
+        }
+        for (name, length) in [
+            ("upstream", self.upstream),
+            ("downstream", self.downstream),
+            ("body", self.body_length()),
+        ] {
+            if length % self.bin_size != 0 {
+                return Err(format!(
+                    "{} length {} is not a multiple of the bin size {}",
+                    name, length, self.bin_size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bins of each region.
+    pub fn num_bins(&self) -> usize {
+        (self.upstream + self.body_length() + self.downstream) / self.bin_size
+    }
+
+    /// Returns the bins of a region in genomic order, which may extend beyond
+    /// sequence boundaries, and whether they must be reversed.
+    fn region_bins(&self, from: usize, to: usize, strand: char) -> (Vec<(i64, i64)>, bool) {
+        let flip = self.flip_negative_strand && strand == '-';
+        let (left, right) = if flip {
+            (self.downstream as i64, self.upstream as i64)
+        } else {
+            (self.upstream as i64, self.downstream as i64)
+        };
+        let (from, to) = (from as i64, to as i64);
+        let bin_size = self.bin_size as i64;
+
+        let (left_end, right_start) = match self.mode {
+            MatrixMode::ReferencePoint(point) => {
+                let anchor = match (point, flip) {
+                    (ReferencePoint::Start, false) | (ReferencePoint::End, true) => from,
+                    (ReferencePoint::End, false) | (ReferencePoint::Start, true) => to,
+                    (ReferencePoint::Center, _) => (from + to) / 2,
+                };
+                (anchor, anchor)
+            }
+            MatrixMode::ScaleRegions { .. } => (from, to),
+        };
+        let mut bins = Vec::with_capacity(self.num_bins());
+
+        for i in 0..left / bin_size {
+            let start = left_end - left + i * bin_size;
+            bins.push((start, start + bin_size));
+        }
+        if let MatrixMode::ScaleRegions { body_length } = self.mode {
+            let n = body_length as i64 / bin_size;
+            for i in 0..n {
+                bins.push((from + (to - from) * i / n, from + (to - from) * (i + 1) / n));
+            }
+        }
+        for i in 0..right / bin_size {
+            let start = right_start + i * bin_size;
+            bins.push((start, start + bin_size));
+        }
+        (bins, flip)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Statistic of matrix rows used for sorting regions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatrixRowStat {
+    Mean,
+    Median,
+    Min,
+    Max,
+    Sum,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl MatrixRowStat {
+    /// Computes the statistic over all values that are not NaN, or NaN if
+    /// there are no such values.
+    fn compute(&self, values: impl Iterator<Item = f64>) -> f64 {
+        let mut values: Vec<f64> = values.filter(|x| !x.is_nan()).collect();
+        if values.is_empty() {
+            return f64::NAN;
+        }
+        match self {
+            MatrixRowStat::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            MatrixRowStat::Median => {
+                values.sort_by(f64::total_cmp);
+                let n = values.len();
+                if n % 2 == 1 {
+                    values[n / 2]
+                } else {
+                    (values[n / 2 - 1] + values[n / 2]) / 2.0
+                }
+            }
+            MatrixRowStat::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            MatrixRowStat::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            MatrixRowStat::Sum => values.iter().sum(),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for MatrixRowStat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MatrixRowStat::Mean => "mean",
+            MatrixRowStat::Median => "median",
+            MatrixRowStat::Min => "min",
+            MatrixRowStat::Max => "max",
+            MatrixRowStat::Sum => "sum",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for MatrixRowStat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(MatrixRowStat::Mean),
+            "median" => Ok(MatrixRowStat::Median),
+            "min" => Ok(MatrixRowStat::Min),
+            "max" => Ok(MatrixRowStat::Max),
+            "sum" => Ok(MatrixRowStat::Sum),
+            _ => Err(format!("invalid row statistic `{}`", s)),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Signal of several tracks within bins around a set of regions, as used
+/// for profile plots and heatmaps.
+#[derive(Clone, Debug)]
+pub struct SignalMatrix {
+    pub config: MatrixConfig,
+    /// Regions in the order of matrix rows
+    pub regions: GRanges,
+    /// Names of tracks
+    pub labels: Vec<String>,
+    /// Values indexed by track, region and bin, where bins without data
+    /// are NaN
+    pub values: Vec<Vec<Vec<f64>>>,
+    /// Cluster of each region, rows are grouped by cluster
+    pub clusters: Vec<usize>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Computes a regions × bins matrix of the signal of each track, similar to
+/// deepTools computeMatrix. Regions on sequences that are not part of a
+/// track, as well as bins beyond sequence boundaries, are filled with NaN.
+///
+/// # Arguments
+/// * `signals` - Tracks or bigWig readers providing the signal.
+/// * `labels` - Names of the tracks.
+/// * `regions` - Regions, e.g. genes or peak summits. The strand determines
+///   the orientation of bins if `config.flip_negative_strand` is set.
+/// * `config` - Placement of bins and summary statistic.
+///
+/// # Errors
+/// Returns an error if the configuration is invalid, if the number of labels
+/// does not match the number of signals or if reading a signal fails.
+pub fn compute_matrix(
+    signals: &mut [&mut dyn MatrixSignal],
+    labels: &[String],
+    regions: &GRanges,
+    config: &MatrixConfig,
+) -> Result<SignalMatrix, Box<dyn Error>> {
+    config.validate()?;
+
+    if signals.len() != labels.len() {
+        return Err(format!(
+            "number of labels ({}) does not match number of signals ({})",
+            labels.len(),
+            signals.len()
+        )
+        .into());
+    }
+    let mut values = vec![Vec::with_capacity(regions.num_rows()); signals.len()];
+
+    for i in 0..regions.num_rows() {
+        let seqname = &regions.seqnames[i];
+        let (bins, flip) = config.region_bins(
+            regions.ranges[i].from,
+            regions.ranges[i].to,
+            regions.strand[i],
+        );
+
+        for (signal, values) in signals.iter_mut().zip(values.iter_mut()) {
+            let length = match signal.get_genome().get_idx(seqname) {
+                Some(idx) => signal.get_genome().lengths[idx] as i64,
+                None => {
+                    values.push(vec![f64::NAN; bins.len()]);
+                    continue;
+                }
+            };
+            let clipped: Vec<(usize, usize)> = bins
+                .iter()
+                .map(|&(from, to)| (from.clamp(0, length) as usize, to.clamp(0, length) as usize))
+                .collect();
+
+            let mut row: Vec<f64> = signal
+                .summarize_bins(seqname, &clipped)?
+                .iter()
+                .map(|s| {
+                    if s.size > 0 {
+                        s.get(config.stat)
+                    } else {
+                        f64::NAN
+                    }
+                })
+                .collect();
+            if flip {
+                row.reverse();
+            }
+            values.push(row);
+        }
+    }
+    Ok(SignalMatrix {
+        config: config.clone(),
+        regions: regions.clone(),
+        labels: labels.to_vec(),
+        values,
+        clusters: vec![0; regions.num_rows()],
+    })
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl SignalMatrix {
+    pub fn num_rows(&self) -> usize {
+        self.regions.num_rows()
+    }
+
+    pub fn num_bins(&self) -> usize {
+        self.config.num_bins()
+    }
+
+    pub fn num_clusters(&self) -> usize {
+        self.clusters.iter().max().map_or(0, |&k| k + 1)
+    }
+
+    /// Returns the values of row `i` of all tracks.
+    fn row(&self, i: usize) -> impl Iterator<Item = f64> + '_ {
+        self.values
+            .iter()
+            .flat_map(move |track| track[i].iter().cloned())
+    }
+
+    /// Reorders rows, where row `i` of the result is row `order[i]`.
+    fn reorder(&mut self, order: &[usize]) {
+        self.regions = self.regions.subset(order);
+        for track in self.values.iter_mut() {
+            *track = order.iter().map(|&i| track[i].clone()).collect();
+        }
+        self.clusters = order.iter().map(|&i| self.clusters[i]).collect();
+    }
+
+    /// Sorts rows within each cluster by a statistic computed over the rows
+    /// of all tracks. Rows without data are placed last.
+    pub fn sort_rows(&mut self, stat: MatrixRowStat, descending: bool) {
+        let scores: Vec<f64> = (0..self.num_rows())
+            .map(|i| stat.compute(self.row(i)))
+            .collect();
+
+        let mut order: Vec<usize> = (0..self.num_rows()).collect();
+        order.sort_by(|&i, &j| {
+            let (a, b) = (scores[i], scores[j]);
+            self.clusters[i]
+                .cmp(&self.clusters[j])
+                .then_with(|| match (a.is_nan(), b.is_nan()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) if descending => b.total_cmp(&a),
+                    (false, false) => a.total_cmp(&b),
+                })
+        });
+        self.reorder(&order);
+    }
+
+    /// Clusters rows into `k` groups with k-means on the concatenated rows
+    /// of all tracks, where NaN values count as zero. Clusters are numbered
+    /// by first occurrence and rows are grouped by cluster, keeping their
+    /// relative order.
+    ///
+    /// # Arguments
+    /// * `k` - Number of clusters.
+    /// * `seed` - Seed for the k-means++ initialization.
+    ///
+    /// # Errors
+    /// Returns an error if `k` is zero or larger than the number of rows.
+    pub fn cluster_rows(&mut self, k: usize, seed: u64) -> Result<(), Box<dyn Error>> {
+        let n = self.num_rows();
+        if k == 0 || k > n {
+            return Err(format!("invalid number of clusters {} for {} regions", k, n).into());
+        }
+        let data: Vec<Vec<f64>> = (0..n)
+            .map(|i| {
+                self.row(i)
+                    .map(|x| if x.is_nan() { 0.0 } else { x })
+                    .collect()
+            })
+            .collect();
+        let distance =
+            |a: &[f64], b: &[f64]| -> f64 { a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum() };
+        let nearest = |centers: &[Vec<f64>], x: &[f64]| -> (usize, f64) {
+            centers
+                .iter()
+                .map(|c| distance(c, x))
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+        };
+
+        // k-means++ initialization
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut centers = vec![data[rng.gen_range(0..n)].clone()];
+        while centers.len() < k {
+            let weights: Vec<f64> = data.iter().map(|x| nearest(&centers, x).1).collect();
+            let total: f64 = weights.iter().sum();
+            let i = if total > 0.0 {
+                let mut r = rng.gen::<f64>() * total;
+                weights
+                    .iter()
+                    .position(|&w| {
+                        r -= w;
+                        r < 0.0
+                    })
+                    .unwrap_or(n - 1)
+            } else {
+                rng.gen_range(0..n)
+            };
+            centers.push(data[i].clone());
+        }
+
+        let mut assignment = vec![0; n];
+        for iteration in 0..KMEANS_MAX_ITERATIONS {
+            let mut changed = false;
+            for (i, x) in data.iter().enumerate() {
+                let c = nearest(&centers, x).0;
+                changed |= c != assignment[i];
+                assignment[i] = c;
+            }
+            if iteration > 0 && !changed {
+                break;
+            }
+            for (c, center) in centers.iter_mut().enumerate() {
+                let members: Vec<&Vec<f64>> = data
+                    .iter()
+                    .zip(&assignment)
+                    .filter(|(_, &a)| a == c)
+                    .map(|(x, _)| x)
+                    .collect();
+                if members.is_empty() {
+                    continue;
+                }
+                for (j, v) in center.iter_mut().enumerate() {
+                    *v = members.iter().map(|x| x[j]).sum::<f64>() / members.len() as f64;
+                }
+            }
+        }
+
+        // Number clusters by first occurrence
+        let mut ids = vec![usize::MAX; k];
+        let mut next = 0;
+        for &a in &assignment {
+            if ids[a] == usize::MAX {
+                ids[a] = next;
+                next += 1;
+            }
+        }
+        self.clusters = assignment.iter().map(|&a| ids[a]).collect();
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&i| self.clusters[i]);
+        self.reorder(&order);
+
+        Ok(())
+    }
+
+    /// Returns the mean signal of each bin over all rows of a track,
+    /// ignoring NaN values.
+    ///
+    /// # Arguments
+    /// * `track` - Index of the track.
+    /// * `cluster` - Restrict rows to the given cluster.
+    pub fn profile(&self, track: usize, cluster: Option<usize>) -> Vec<f64> {
+        let mut sum = vec![0.0; self.num_bins()];
+        let mut n = vec![0usize; self.num_bins()];
+
+        for (row, &c) in self.values[track].iter().zip(&self.clusters) {
+            if cluster.is_some_and(|cluster| cluster != c) {
+                continue;
+            }
+            for (j, &x) in row.iter().enumerate() {
+                if !x.is_nan() {
+                    sum[j] += x;
+                    n[j] += 1;
+                }
+            }
+        }
+        sum.iter()
+            .zip(&n)
+            .map(|(&s, &n)| if n > 0 { s / n as f64 } else { f64::NAN })
+            .collect()
+    }
+
+    /// Returns the row boundaries of clusters, starting with zero and
+    /// ending with the number of rows.
+    fn cluster_boundaries(&self) -> Vec<usize> {
+        let mut boundaries = vec![0];
+        for i in 1..self.num_rows() {
+            if self.clusters[i] != self.clusters[i - 1] {
+                boundaries.push(i);
+            }
+        }
+        boundaries.push(self.num_rows());
+        boundaries
+    }
+
+    fn cluster_label(&self, cluster: usize) -> String {
+        if self.num_clusters() > 1 {
+            format!("cluster_{}", cluster + 1)
+        } else {
+            "regions".to_string()
+        }
+    }
+
+    /// Writes the matrix in the format of deepTools computeMatrix, i.e. a
+    /// header line with parameters followed by one line per region.
+    pub fn write_matrix<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let n_tracks = self.labels.len();
+        let boundaries = self.cluster_boundaries();
+        let ref_point = match self.config.mode {
+            MatrixMode::ReferencePoint(ReferencePoint::Start) => json!("TSS"),
+            MatrixMode::ReferencePoint(ReferencePoint::End) => json!("TES"),
+            MatrixMode::ReferencePoint(ReferencePoint::Center) => json!("center"),
+            MatrixMode::ScaleRegions { .. } => json!(null),
+        };
+        let header = json!({
+            "upstream": vec![self.config.upstream; n_tracks],
+            "downstream": vec![self.config.downstream; n_tracks],
+            "body": vec![self.config.body_length(); n_tracks],
+            "bin size": vec![self.config.bin_size; n_tracks],
+            "ref point": vec![ref_point; n_tracks],
+            "bin avg type": self.config.stat.to_string(),
+            "unscaled 5 prime": vec![0; n_tracks],
+            "unscaled 3 prime": vec![0; n_tracks],
+            "group_labels": boundaries[..boundaries.len() - 1]
+                .iter()
+                .map(|&i| self.cluster_label(self.clusters[i]))
+                .collect::<Vec<String>>(),
+            "group_boundaries": if self.num_rows() > 0 { boundaries } else { vec![0, 0] },
+            "sample_labels": self.labels,
+            "sample_boundaries": (0..=n_tracks)
+                .map(|i| i * self.num_bins())
+                .collect::<Vec<usize>>(),
+        });
+        writeln!(writer, "@{}", header)?;
+
+        let names = self.regions.meta.get_column_str("name");
+
+        for i in 0..self.num_rows() {
+            write!(
+                writer,
+                "{}\t{}\t{}\t{}\t.\t{}",
+                self.regions.seqnames[i],
+                self.regions.ranges[i].from,
+                self.regions.ranges[i].to,
+                names.map_or(".", |names| names[i].as_str()),
+                self.regions.strand[i],
+            )?;
+            for x in self.row(i) {
+                if x.is_nan() {
+                    write!(writer, "\tnan")?;
+                } else {
+                    write!(writer, "\t{}", x)?;
+                }
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Exports the matrix to a file, see `write_matrix`.
+    pub fn export_matrix(&self, filename: &str, compress: bool) -> io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer: Box<dyn Write> = if compress {
+            Box::new(GzEncoder::new(file, Compression::default()))
+        } else {
+            Box::new(file)
+        };
+        self.write_matrix(&mut writer)
+    }
+
+    /// Returns the x-coordinate of the center of bin `j` relative to the
+    /// reference point or region start.
+    fn bin_position(&self, j: usize) -> f64 {
+        (j as f64 + 0.5) * self.config.bin_size as f64 - self.config.upstream as f64
+    }
+
+    fn x_range(&self) -> std::ops::Range<f64> {
+        -(self.config.upstream as f64)..(self.config.body_length() + self.config.downstream) as f64
+    }
+
+    fn x_desc(&self) -> String {
+        match self.config.mode {
+            MatrixMode::ReferencePoint(point) => format!("Distance from region {} (bp)", point),
+            MatrixMode::ScaleRegions { body_length } => {
+                format!("Position, regions scaled to {} bp", body_length)
+            }
+        }
+    }
+
+    /// Plots the average profile of each track, with one line per cluster
+    /// if rows are clustered, and saves it as PNG.
+    pub fn plot_profile(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let root = BitMapBackend::new(filename, (800, 500)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let clusters: Vec<Option<usize>> = if self.num_clusters() > 1 {
+            (0..self.num_clusters()).map(Some).collect()
+        } else {
+            vec![None]
+        };
+        let mut profiles = Vec::new();
+        for track in 0..self.labels.len() {
+            for &cluster in &clusters {
+                let label = match cluster {
+                    Some(c) => format!("{} ({})", self.labels[track], self.cluster_label(c)),
+                    None => self.labels[track].clone(),
+                };
+                profiles.push((label, self.profile(track, cluster)));
+            }
+        }
+        let finite = profiles
+            .iter()
+            .flat_map(|(_, p)| p.iter())
+            .cloned()
+            .filter(|x| x.is_finite());
+        let min_y = finite.clone().fold(f64::INFINITY, f64::min);
+        let max_y = finite.fold(f64::NEG_INFINITY, f64::max);
+        let (min_y, max_y) = if min_y < max_y {
+            (min_y, max_y)
+        } else if min_y.is_finite() {
+            (min_y - 1.0, min_y + 1.0)
+        } else {
+            (0.0, 1.0)
+        };
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Signal profile", ("sans-serif", 20))
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .margin(10)
+            .build_cartesian_2d(self.x_range(), min_y..max_y)?;
+
+        chart
+            .configure_mesh()
+            .x_desc(self.x_desc())
+            .y_desc(format!("Signal ({})", self.config.stat))
+            .draw()?;
+
+        // Mark the reference point or region boundaries
+        let mut marks = vec![0.0];
+        if let MatrixMode::ScaleRegions { body_length } = self.config.mode {
+            marks.push(body_length as f64);
+        }
+        for x in marks {
+            chart.draw_series(LineSeries::new(
+                vec![(x, min_y), (x, max_y)],
+                &BLACK.mix(0.3),
+            ))?;
+        }
+
+        for (i, (label, profile)) in profiles.into_iter().enumerate() {
+            let color = Palette99::pick(i).to_rgba();
+            let points: Vec<(f64, f64)> = profile
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| x.is_finite())
+                .map(|(j, &x)| (self.bin_position(j), x))
+                .collect();
+            chart
+                .draw_series(LineSeries::new(points, &color))?
+                .label(label)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+
+        // Ensure the plot is saved
+        root.present()?;
+
+        Ok(())
+    }
+
+    /// Plots a heatmap for each track, where rows correspond to regions in
+    /// matrix order and clusters are separated by horizontal lines, and
+    /// saves it as PNG. Colors are scaled between the minimum and maximum
+    /// of each track and missing values are drawn in gray.
+    pub fn plot_heatmap(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let n_tracks = self.labels.len().max(1);
+        let root = BitMapBackend::new(filename, (300 * n_tracks as u32, 900)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        // Reduce rows by averaging consecutive rows
+        let n = self.num_rows();
+        let m = n.min(HEATMAP_MAX_ROWS);
+        let groups: Vec<(usize, usize)> = (0..m).map(|g| (g * n / m, (g + 1) * n / m)).collect();
+
+        for (track, area) in root.split_evenly((1, n_tracks)).iter().enumerate() {
+            if track >= self.labels.len() {
+                break;
+            }
+            let values = &self.values[track];
+
+            let finite = values.iter().flatten().cloned().filter(|x| x.is_finite());
+            let min_z = finite.clone().fold(f64::INFINITY, f64::min);
+            let max_z = finite.fold(f64::NEG_INFINITY, f64::max);
+
+            let mut chart = ChartBuilder::on(area)
+                .caption(&self.labels[track], ("sans-serif", 20))
+                .x_label_area_size(40)
+                .y_label_area_size(10)
+                .margin(10)
+                .build_cartesian_2d(self.x_range(), 0.0..m.max(1) as f64)?;
+
+            chart
+                .configure_mesh()
+                .disable_mesh()
+                .y_labels(0)
+                .x_labels(5)
+                .x_desc(self.x_desc())
+                .draw()?;
+
+            let x_from = -(self.config.upstream as f64);
+            let bin_size = self.config.bin_size as f64;
+
+            chart.draw_series(groups.iter().enumerate().flat_map(|(g, &(from, to))| {
+                // Row 0 is drawn at the top
+                let y = (m - g) as f64;
+                (0..self.num_bins()).map(move |j| {
+                    let z = MatrixRowStat::Mean.compute(values[from..to].iter().map(|row| row[j]));
+                    let color = if z.is_finite() {
+                        heatmap_color((z - min_z) / (max_z - min_z))
+                    } else {
+                        RGBColor(220, 220, 220)
+                    };
+                    let x = x_from + j as f64 * bin_size;
+                    Rectangle::new([(x, y - 1.0), (x + bin_size, y)], color.filled())
+                })
+            }))?;
+
+            // Separate clusters
+            for &b in &self.cluster_boundaries()[1..] {
+                if b == 0 || b == n {
+                    continue;
+                }
+                let y = (m - b * m / n) as f64;
+                let range = self.x_range();
+                chart.draw_series(LineSeries::new(
+                    vec![(range.start, y), (range.end, y)],
+                    &BLACK,
+                ))?;
+            }
+        }
+
+        // Ensure the plot is saved
+        root.present()?;
+
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Maps `t` in `[0, 1]` to a color ranging from white to dark blue. Values of
+/// a constant track are mapped to the center.
+fn heatmap_color(t: f64) -> RGBColor {
+    let t = if t.is_finite() {
+        t.clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    let mix = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+    RGBColor(mix(255.0, 8.0), mix(255.0, 48.0), mix(255.0, 107.0))
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+
+    use crate::bigwig::{BigWigReader, BigWigStreamWriter};
+    use crate::bigwig_summary::BigWigSummaryStat;
+    use crate::compute_matrix::{
+        compute_matrix, MatrixConfig, MatrixMode, MatrixRowStat, MatrixSignal, ReferencePoint,
+        TrackSignal,
+    };
+    use crate::genome::Genome;
+    use crate::granges::GRanges;
+    use crate::track::MutableTrack;
+    use crate::track_simple::SimpleTrack;
+
+    #[test]
+    fn test_compute_matrix_reference_point() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![10000]);
+
+        // Bin j of the track has value j
+        let mut track = SimpleTrack::alloc("track".to_string(), genome, f64::NAN, 10);
+        {
+            let mut seq = track.get_sequence_mut("chr1").unwrap();
+            for j in 0..1000 {
+                seq.set(10 * j, j as f64);
+            }
+        }
+        let regions = GRanges::new(
+            vec!["chr1".into(), "chr1".into(), "chr1".into()],
+            vec![1000, 3000, 9950],
+            vec![2000, 4000, 9990],
+            vec!['+', '-', '+'],
+        );
+        let config = MatrixConfig {
+            upstream: 100,
+            downstream: 100,
+            bin_size: 50,
+            ..Default::default()
+        };
+        let labels = vec!["track".to_string()];
+        let matrix =
+            compute_matrix(&mut [&mut TrackSignal(&track)], &labels, &regions, &config).unwrap();
+
+        assert_eq!(matrix.values[0][0], vec![92.0, 97.0, 102.0, 107.0]);
+        // Negative strand regions are anchored at the end and flipped
+        assert_eq!(matrix.values[0][1], vec![407.0, 402.0, 397.0, 392.0]);
+        // Bins beyond the sequence end have no data
+        assert_eq!(matrix.values[0][2][..3], [987.0, 992.0, 997.0]);
+        assert!(matrix.values[0][2][3].is_nan());
+
+        let config = MatrixConfig {
+            mode: MatrixMode::ReferencePoint(ReferencePoint::Center),
+            flip_negative_strand: false,
+            ..config
+        };
+        let matrix =
+            compute_matrix(&mut [&mut TrackSignal(&track)], &labels, &regions, &config).unwrap();
+
+        assert_eq!(matrix.values[0][1], vec![342.0, 347.0, 352.0, 357.0]);
+    }
+
+    #[test]
+    fn test_compute_matrix_scale_regions() {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![10000]);
+
+        // Constant signal within [1000, 2000) and [5000, 5500)
+        let mut bww =
+            BigWigStreamWriter::new(Cursor::new(Vec::new()), genome.clone(), vec![]).unwrap();
+        bww.write_interval("chr1", 1000, 2000, 4.0).unwrap();
+        bww.write_interval("chr1", 5000, 5500, 2.0).unwrap();
+        bww.write_interval("chr1", 6000, 6100, 1.0).unwrap();
+        bww.close().unwrap();
+
+        let mut bwr = BigWigReader::new(Cursor::new(bww.into_inner().into_inner())).unwrap();
+        assert_eq!(MatrixSignal::get_genome(&bwr).lengths, vec![10000]);
+
+        let regions = GRanges::new(
+            vec!["chr1".into(), "chr1".into(), "chr1".into(), "chr2".into()],
+            vec![6000, 1000, 5000, 0],
+            vec![6100, 2000, 5500, 100],
+            vec!['+', '+', '-', '+'],
+        );
+        let config = MatrixConfig {
+            mode: MatrixMode::ScaleRegions { body_length: 400 },
+            upstream: 200,
+            downstream: 200,
+            bin_size: 100,
+            stat: BigWigSummaryStat::Mean0,
+            flip_negative_strand: true,
+        };
+        assert_eq!(config.num_bins(), 8);
+
+        let labels = vec!["bigwig".to_string()];
+        let mut matrix = compute_matrix(&mut [&mut bwr], &labels, &regions, &config).unwrap();
+
+        assert_eq!(matrix.values[0][0], vec![0., 0., 1., 1., 1., 1., 0., 0.]);
+        assert_eq!(matrix.values[0][1], vec![0., 0., 4., 4., 4., 4., 0., 0.]);
+        assert_eq!(matrix.values[0][2], vec![0., 0., 2., 2., 2., 2., 0., 0.]);
+        // Sequences that are not part of the file have no data
+        assert!(matrix.values[0][3].iter().all(|x| x.is_nan()));
+
+        matrix.sort_rows(MatrixRowStat::Max, true);
+        assert_eq!(matrix.regions.ranges[0].from, 1000);
+        assert_eq!(matrix.regions.ranges[1].from, 5000);
+        assert_eq!(matrix.regions.ranges[2].from, 6000);
+        assert_eq!(matrix.regions.seqnames[3], "chr2");
+
+        matrix.cluster_rows(2, 1).unwrap();
+        assert_eq!(matrix.num_clusters(), 2);
+        assert!(matrix.clusters.windows(2).all(|w| w[0] <= w[1]));
+        assert_ne!(matrix.clusters[0], matrix.clusters[3]);
+
+        // Profiles ignore missing values
+        assert_eq!(matrix.profile(0, None)[2], 7.0 / 3.0);
+
+        let mut buffer = Vec::new();
+        matrix.write_matrix(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        let header: serde_json::Value = serde_json::from_str(&lines[0][1..]).unwrap();
+        assert_eq!(header["sample_labels"], serde_json::json!(["bigwig"]));
+        assert_eq!(header["sample_boundaries"], serde_json::json!([0, 8]));
+        assert_eq!(header["body"], serde_json::json!([400]));
+        assert_eq!(lines.len(), 5);
+        assert!(lines[4].starts_with("chr2\t0\t100\t.\t.\t+\tnan"));
+    }
+}
@@ -18,10 +18,14 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
 
+use crate::bam::{BamReader, BamReaderOptions};
 use crate::infologger::Logger;
+use crate::read_filter::AuxFilter;
+use crate::read_stream::{ReadStream, ReadStreamType};
 
 /* -------------------------------------------------------------------------- */
 
@@ -49,9 +53,20 @@ pub enum OptionCoverage {
     FilterStrand(char),
     FilterPairedEnd(bool),
     FilterSingleEnd(bool),
+    FilterIncludeFlags(u16),
+    FilterExcludeFlags(u16),
+    FilterAuxiliary(Vec<AuxFilter>),
+    FilterBarcodes(HashSet<String>),
+    FilterUmiDuplicates(bool),
+    SubsampleFraction(f64),
+    SubsampleCount(usize),
+    SubsampleSeed(u64),
     SmoothenControl(bool),
     SmoothenSizes(Vec<usize>),
     SmoothenMin(f64),
+    Threads(usize),
+    Spliced(bool),
+    CountDeletions(bool),
 }
 
 /* -------------------------------------------------------------------------- */
@@ -83,16 +98,37 @@ impl fmt::Display for OptionCoverage {
             OptionCoverage::FilterStrand(strand) => write!(f, "Filter Strand: {}", strand),
             OptionCoverage::FilterPairedEnd(b) => write!(f, "Filter Paired End: {}", b),
             OptionCoverage::FilterSingleEnd(b) => write!(f, "Filter Single End: {}", b),
+            OptionCoverage::FilterIncludeFlags(flags) => {
+                write!(f, "Filter Include Flags: 0x{:x}", flags)
+            }
+            OptionCoverage::FilterExcludeFlags(flags) => {
+                write!(f, "Filter Exclude Flags: 0x{:x}", flags)
+            }
+            OptionCoverage::FilterAuxiliary(v) => write!(
+                f,
+                "Filter Auxiliary: {}",
+                v.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            OptionCoverage::FilterBarcodes(v) => write!(f, "Filter Barcodes: {} barcodes", v.len()),
+            OptionCoverage::FilterUmiDuplicates(b) => write!(f, "Filter UMI Duplicates: {}", b),
+            OptionCoverage::SubsampleFraction(x) => write!(f, "Subsample Fraction: {}", x),
+            OptionCoverage::SubsampleCount(n) => write!(f, "Subsample Count: {}", n),
+            OptionCoverage::SubsampleSeed(seed) => write!(f, "Subsample Seed: {}", seed),
             OptionCoverage::SmoothenControl(b) => write!(f, "Smoothen Control: {}", b),
             OptionCoverage::SmoothenSizes(v) => write!(f, "Smoothen Sizes: {:?}", v),
             OptionCoverage::SmoothenMin(min) => write!(f, "Smoothen Min: {}", min),
+            OptionCoverage::Threads(n) => write!(f, "Threads: {}", n),
+            OptionCoverage::Spliced(b) => write!(f, "Spliced: {}", b),
+            OptionCoverage::CountDeletions(b) => write!(f, "Count Deletions: {}", b),
         }
     }
 }
 
 /* -------------------------------------------------------------------------- */
 
-// Define the CoverageConfig struct
+// Define the CoverageConfig struct. If `subsample_count` is non-zero, the
+// subsampling fraction is set for each input file such that approximately
+// `subsample_count` reads are used.
 pub struct CoverageConfig {
     pub logger: Logger,
     pub binning_method: String,
@@ -115,10 +151,21 @@ pub struct CoverageConfig {
     pub filter_strand: char,
     pub filter_paired_end: bool,
     pub filter_single_end: bool,
+    pub filter_include_flags: u16,
+    pub filter_exclude_flags: u16,
+    pub filter_auxiliary: Vec<AuxFilter>,
+    pub filter_barcodes: HashSet<String>,
+    pub filter_umi_duplicates: bool,
+    pub subsample_fraction: f64,
+    pub subsample_count: usize,
+    pub subsample_seed: u64,
     pub remove_filtered_chroms: bool,
     pub smoothen_control: bool,
     pub smoothen_sizes: Vec<usize>,
     pub smoothen_min: f64,
+    pub threads: usize,
+    pub spliced: bool,
+    pub count_deletions: bool,
 }
 
 /* -------------------------------------------------------------------------- */
@@ -190,6 +237,30 @@ impl CoverageConfig {
             OptionCoverage::FilterSingleEnd(single_end) => {
                 self.filter_single_end = single_end;
             }
+            OptionCoverage::FilterIncludeFlags(flags) => {
+                self.filter_include_flags = flags;
+            }
+            OptionCoverage::FilterExcludeFlags(flags) => {
+                self.filter_exclude_flags = flags;
+            }
+            OptionCoverage::FilterAuxiliary(filters) => {
+                self.filter_auxiliary = filters;
+            }
+            OptionCoverage::FilterBarcodes(barcodes) => {
+                self.filter_barcodes = barcodes;
+            }
+            OptionCoverage::FilterUmiDuplicates(umi_duplicates) => {
+                self.filter_umi_duplicates = umi_duplicates;
+            }
+            OptionCoverage::SubsampleFraction(fraction) => {
+                self.subsample_fraction = fraction;
+            }
+            OptionCoverage::SubsampleCount(count) => {
+                self.subsample_count = count;
+            }
+            OptionCoverage::SubsampleSeed(seed) => {
+                self.subsample_seed = seed;
+            }
             OptionCoverage::RemoveFilteredChroms(remove) => {
                 self.remove_filtered_chroms = remove;
             }
@@ -202,6 +273,15 @@ impl CoverageConfig {
             OptionCoverage::SmoothenMin(min) => {
                 self.smoothen_min = min;
             }
+            OptionCoverage::Threads(threads) => {
+                self.threads = threads;
+            }
+            OptionCoverage::Spliced(spliced) => {
+                self.spliced = spliced;
+            }
+            OptionCoverage::CountDeletions(count_deletions) => {
+                self.count_deletions = count_deletions;
+            }
         }
     }
 }
@@ -233,12 +313,81 @@ impl CoverageConfig {
             filter_strand: '*',
             filter_paired_end: false,
             filter_single_end: false,
+            filter_include_flags: 0,
+            filter_exclude_flags: 0,
+            filter_auxiliary: Vec::new(),
+            filter_barcodes: HashSet::new(),
+            filter_umi_duplicates: false,
+            subsample_fraction: 1.0,
+            subsample_count: 0,
+            subsample_seed: 0,
             remove_filtered_chroms: false,
             smoothen_control: false,
             smoothen_sizes: Vec::new(),
             smoothen_min: 20.0,
+            threads: 1,
+            spliced: false,
+            count_deletions: false,
+        }
+    }
+
+    /// Returns the options used for opening BAM files, which decompress using
+    /// `threads` threads. Auxiliary fields are only read if reads are filtered
+    /// by them.
+    pub fn bam_reader_options(&self) -> BamReaderOptions {
+        let mut options = BamReaderOptions::with_threads(self.threads);
+        options.read_auxiliary = !self.filter_auxiliary.is_empty()
+            || !self.filter_barcodes.is_empty()
+            || self.filter_umi_duplicates;
+        options
+    }
+
+    /// Returns the stream of reads of a BAM file. Pairs are joined unless
+    /// `paired_as_single_end` is set, and aligned segments of reads are only
+    /// recorded if spliced coverage is requested.
+    pub fn read_stream<'a, R: std::io::Read + 'a>(
+        &self,
+        reader: &'a mut BamReader<R>,
+    ) -> ReadStreamType<'a> {
+        let join_pairs = !self.paired_as_single_end;
+        if self.spliced {
+            Box::pin(reader.read_spliced_stream(join_pairs, self.paired_end_strand_specific))
+        } else {
+            Box::pin(reader.read_simple_stream(join_pairs, self.paired_end_strand_specific))
         }
     }
+
+    /// Applies all read filters of the configuration to a stream of reads,
+    /// followed by subsampling (see `subsample_fraction`) and shifting the
+    /// reads.
+    pub fn filter_read_stream<'a>(&'a self, reads: ReadStreamType<'a>) -> ReadStreamType<'a> {
+        let logger = Some(&self.logger);
+        // First round of filtering
+        let reads = ReadStream::filter_paired_end(reads, logger, self.filter_paired_end);
+        let reads = ReadStream::filter_single_end(reads, logger, self.filter_single_end);
+        let reads = ReadStream::paired_as_single_end(reads, logger, self.paired_as_single_end);
+        let reads = ReadStream::filter_read_length(reads, logger, &self.filter_read_lengths);
+        let reads = ReadStream::filter_duplicates(reads, logger, self.filter_duplicates);
+        let reads = ReadStream::filter_mapq(reads, logger, self.filter_mapq);
+        let reads = ReadStream::filter_flags(
+            reads,
+            logger,
+            self.filter_include_flags,
+            self.filter_exclude_flags,
+        );
+        let reads = ReadStream::filter_auxiliary(reads, logger, &self.filter_auxiliary);
+        let reads = ReadStream::filter_barcodes(reads, logger, &self.filter_barcodes);
+        let reads = ReadStream::filter_umi_duplicates(reads, logger, self.filter_umi_duplicates);
+        // Second round of filtering
+        let reads = ReadStream::filter_strand(reads, logger, self.filter_strand);
+        let reads = ReadStream::subsample(
+            reads,
+            logger,
+            self.subsample_fraction,
+            self.subsample_seed,
+        );
+        ReadStream::shift_reads(reads, logger, &self.shift_reads)
+    }
 }
 
 /* -------------------------------------------------------------------------- */
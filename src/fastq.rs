@@ -242,7 +242,7 @@ fn reverse_complement(sequence: &str) -> String {
 
 /* -------------------------------------------------------------------------- */
 
-fn complement_base(base: u8) -> u8 {
+pub(crate) fn complement_base(base: u8) -> u8 {
     match base.to_ascii_uppercase() {
         b'=' => b'=',
         b'A' => b'T',
@@ -0,0 +1,245 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+
+use crate::bigbed::{
+    AutoSql, AutoSqlField, BigBedReader, BigBedRecord, BigBedWriter, OptionBigBed,
+};
+use crate::genome::Genome;
+use crate::granges::GRanges;
+use crate::meta::MetaData;
+use crate::netfile::NetFile;
+
+/* -------------------------------------------------------------------------- */
+
+impl GRanges {
+    /// Returns the autoSql declaration used by `write_bigbed`, i.e. the six
+    /// BED6 fields followed by one field for each `Meta` column other than
+    /// `name` and `score`. Integer columns are declared as `int`, float
+    /// columns as `double` and string columns as `string`.
+    ///
+    /// # Errors
+    /// Returns an error if a `Meta` column is neither an integer, float nor
+    /// string array.
+    pub fn bigbed_autosql(&self) -> Result<AutoSql, Box<dyn Error>> {
+        let mut autosql = AutoSql::bed(6);
+        autosql.name = "bed6".to_string();
+
+        for (name, data) in self.meta.iter() {
+            if name == "name" || name == "score" {
+                continue;
+            }
+            let kind = match data {
+                MetaData::IntArray(_) => "int",
+                MetaData::FloatArray(_) => "double",
+                MetaData::StringArray(_) => "string",
+                _ => {
+                    return Err(
+                        format!("meta column `{}` cannot be stored in a bigBed file", name).into(),
+                    )
+                }
+            };
+            autosql.fields.push(AutoSqlField::new(kind, name, name));
+        }
+        if autosql.fields.len() > 6 {
+            autosql.name = format!("bed6+{}", autosql.fields.len() - 6);
+        }
+        Ok(autosql)
+    }
+
+    /// Reads all items of a bigBed file. Fields are stored as typed `Meta`
+    /// columns as described in `BigBedReader::query_granges`.
+    pub fn read_bigbed<R: Read + Seek>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let mut bbr = BigBedReader::new(reader)?;
+
+        *self = bbr.query_granges(".*", 0, usize::MAX)?;
+
+        Ok(())
+    }
+
+    /// Imports a bigBed file from disk or a URL.
+    pub fn import_bigbed(&mut self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let file = NetFile::open(filename)?;
+        self.read_bigbed(file).map_err(|e| {
+            Box::new(io::Error::other(format!(
+                "importing bigBed file from `{}` failed: {}",
+                filename, e
+            ))) as Box<dyn Error>
+        })
+    }
+
+    /// Writes the ranges as bigBed file with the autoSql declaration returned
+    /// by `bigbed_autosql`. The `name` column defaults to `.` and the `score`
+    /// column to zero if missing; float scores are rounded. Ranges are sorted
+    /// by chromosome, in the order of the genome, and start position.
+    ///
+    /// # Errors
+    /// Returns an error if a sequence is not part of the genome or a `Meta`
+    /// column cannot be stored.
+    pub fn write_bigbed<W: Write + Seek>(
+        &self,
+        writer: W,
+        genome: &Genome,
+        parameters: Vec<OptionBigBed>,
+    ) -> Result<(), Box<dyn Error>> {
+        let autosql = self.bigbed_autosql()?;
+        let n = self.num_rows();
+
+        let mut seqidx = Vec::with_capacity(n);
+        for seqname in &self.seqnames {
+            let idx = genome.get_idx(seqname).ok_or(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Sequence '{}' not found", seqname),
+            ))?;
+            seqidx.push(idx);
+        }
+        let mut indices: Vec<usize> = (0..n).collect();
+        indices.sort_by_key(|&i| (seqidx[i], self.ranges[i].from, self.ranges[i].to));
+
+        let names = self.meta.get_column_str("name");
+        let scores: Vec<i64> = match self.meta.get_column("score") {
+            Some(MetaData::IntArray(v)) => v.clone(),
+            Some(MetaData::FloatArray(v)) => v.iter().map(|x| x.round() as i64).collect(),
+            _ => vec![0; n],
+        };
+        let columns: Vec<&MetaData> = self
+            .meta
+            .iter()
+            .filter(|(name, _)| *name != "name" && *name != "score")
+            .map(|(_, data)| data)
+            .collect();
+
+        let mut bbw = BigBedWriter::new(writer, genome.clone(), autosql, parameters)?;
+
+        for i in indices {
+            let mut fields = vec![
+                names.map_or(".".to_string(), |v| v[i].clone()),
+                scores[i].to_string(),
+                match self.strand[i] {
+                    '+' | '-' => self.strand[i].to_string(),
+                    _ => ".".to_string(),
+                },
+            ];
+            for column in &columns {
+                fields.push(match column {
+                    MetaData::IntArray(v) => v[i].to_string(),
+                    MetaData::FloatArray(v) => v[i].to_string(),
+                    MetaData::StringArray(v) => v[i].clone(),
+                    _ => unreachable!(),
+                });
+            }
+            bbw.write(&BigBedRecord {
+                chrom: self.seqnames[i].clone(),
+                from: self.ranges[i].from,
+                to: self.ranges[i].to,
+                rest: fields.join("\t"),
+            })?;
+        }
+        bbw.close()
+    }
+
+    /// Exports the ranges as bigBed file, see `write_bigbed`.
+    pub fn export_bigbed(
+        &self,
+        filename: &str,
+        genome: &Genome,
+        parameters: Vec<OptionBigBed>,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = File::create(filename)?;
+        self.write_bigbed(file, genome, parameters)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bigbed::BigBedReader;
+    use crate::genome::Genome;
+    use crate::granges::GRanges;
+    use crate::meta::MetaData;
+
+    #[test]
+    fn test_granges_bigbed() {
+        let genome = Genome::new(
+            vec!["chr1".to_string(), "chr2".to_string()],
+            vec![10000, 10000],
+        );
+        let mut granges = GRanges::new(
+            vec!["chr2".into(), "chr1".into(), "chr1".into()],
+            vec![500, 300, 100],
+            vec![600, 400, 250],
+            vec!['+', '*', '-'],
+        );
+        granges
+            .meta
+            .add(
+                "name",
+                MetaData::StringArray(vec!["c".into(), "b".into(), "a".into()]),
+            )
+            .unwrap();
+        granges
+            .meta
+            .add("signalValue", MetaData::FloatArray(vec![3.5, 2.25, 1.0]))
+            .unwrap();
+        granges
+            .meta
+            .add("peak", MetaData::IntArray(vec![50, 20, 10]))
+            .unwrap();
+
+        let mut buffer = Cursor::new(Vec::new());
+        granges.write_bigbed(&mut buffer, &genome, vec![]).unwrap();
+
+        let mut bbr = BigBedReader::new(Cursor::new(buffer.into_inner())).unwrap();
+        assert_eq!(bbr.autosql().name, "bed6+2");
+        assert_eq!(bbr.autosql().fields[6].kind, "double");
+        assert_eq!(bbr.autosql().fields[7].kind, "int");
+
+        let result = bbr.query_granges(".*", 0, usize::MAX).unwrap();
+        let expected = granges.subset(&[2, 1, 0]);
+
+        assert_eq!(result.seqnames, expected.seqnames);
+        assert_eq!(result.ranges, expected.ranges);
+        assert_eq!(result.strand, vec!['-', '.', '+']);
+        assert_eq!(
+            result.meta.get_column_str("name"),
+            expected.meta.get_column_str("name")
+        );
+        assert_eq!(result.meta.get_column_int("score"), Some(&vec![0, 0, 0]));
+        assert_eq!(
+            result.meta.get_column_float("signalValue"),
+            expected.meta.get_column_float("signalValue")
+        );
+        assert_eq!(
+            result.meta.get_column_int("peak"),
+            expected.meta.get_column_int("peak")
+        );
+
+        // Region query
+        let result = bbr.query_granges("chr1", 200, 300).unwrap();
+        assert_eq!(result.num_rows(), 1);
+        assert_eq!(result.meta.get_column_str("name").unwrap()[0], "a");
+    }
+}
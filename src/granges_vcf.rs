@@ -0,0 +1,153 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::granges::GRanges;
+use crate::meta::MetaData;
+use crate::range::Range;
+
+/* Bufread GRanges from VCF files
+ * -------------------------------------------------------------------------- */
+
+impl GRanges {
+    /// Reads the variant sites of a VCF file into the `GRanges` object. Each
+    /// record covers the reference allele, i.e. the range starts at the
+    /// 0-based position of `POS` and has the length of `REF`. The columns
+    /// `ID`, `REF` and `ALT` are stored as metadata columns "name", "ref" and
+    /// "alt". Header lines are skipped and genotypes are ignored.
+    ///
+    /// # Errors
+    /// Returns an error if reading fails or if records contain fewer than 5
+    /// columns or an invalid position.
+    pub fn bufread_vcf<R: BufRead>(&mut self, reader: &mut R) -> Result<(), Box<dyn Error>> {
+        let mut line = String::new();
+        let mut name = Vec::new();
+        let mut ref_ = Vec::new();
+        let mut alt = Vec::new();
+        while reader.read_line(&mut line)? > 0 {
+            if line.starts_with('#') || line.trim().is_empty() {
+                line.clear();
+                continue;
+            }
+            let fields: Vec<&str> = line.trim_end().split('\t').collect();
+            if fields.len() < 5 {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "VCF records must have at least 5 columns".to_string(),
+                )));
+            }
+            let position = match fields[1].parse::<usize>() {
+                Ok(position) if position > 0 => position - 1,
+                _ => {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid VCF position `{}`", fields[1]),
+                    )))
+                }
+            };
+            self.seqnames.push(fields[0].to_string());
+            self.ranges
+                .push(Range::new(position, position + fields[3].len().max(1)));
+            self.strand.push('*');
+            name.push(fields[2].to_string());
+            ref_.push(fields[3].to_string());
+            alt.push(fields[4].to_string());
+            line.clear();
+        }
+        self.meta.add("name", MetaData::StringArray(name))?;
+        self.meta.add("ref", MetaData::StringArray(ref_))?;
+        self.meta.add("alt", MetaData::StringArray(alt))?;
+        Ok(())
+    }
+}
+
+/* Read GRanges from VCF files
+ * -------------------------------------------------------------------------- */
+
+impl GRanges {
+    /// Reads the variant sites of a VCF file, see `bufread_vcf`.
+    pub fn read_vcf<R: Read>(&mut self, reader: &mut R) -> Result<(), Box<dyn Error>> {
+        self.bufread_vcf(&mut BufReader::new(reader))
+    }
+}
+
+/* Import GRanges from VCF files
+ * -------------------------------------------------------------------------- */
+
+impl GRanges {
+    /// Imports the variant sites of a VCF file, see `bufread_vcf`.
+    ///
+    /// # Arguments
+    /// - `filename`: Path to the input VCF file.
+    /// - `compress`: If true, reads the file as gzip-compressed (including
+    ///   BGZF-compressed `.vcf.gz` files).
+    ///
+    /// # Errors
+    /// Returns an error if file reading or decompression fails, or if the file format is invalid.
+    pub fn import_vcf(&mut self, filename: &str, compress: bool) -> Result<(), Box<dyn Error>> {
+        let file = File::open(filename)?;
+        let mut reader: Box<dyn BufRead> = if compress {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        self.bufread_vcf(&mut reader)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::granges::GRanges;
+
+    #[test]
+    fn test_read_vcf() {
+        let vcf = "\
+##fileformat=VCFv4.2
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO
+chr1\t101\trs1\tA\tG\t.\tPASS\t.
+chr2\t5\t.\tAC\tA\t50\tPASS\tDP=10
+";
+        let mut granges = GRanges::default();
+        granges.read_vcf(&mut Cursor::new(vcf)).unwrap();
+
+        assert_eq!(granges.num_rows(), 2);
+        assert_eq!(granges.seqnames, vec!["chr1", "chr2"]);
+        assert_eq!((granges.ranges[0].from, granges.ranges[0].to), (100, 101));
+        assert_eq!((granges.ranges[1].from, granges.ranges[1].to), (4, 6));
+        assert_eq!(
+            granges.meta.get_column_str("ref").unwrap(),
+            &vec!["A", "AC"]
+        );
+        assert_eq!(granges.meta.get_column_str("alt").unwrap(), &vec!["G", "A"]);
+        assert_eq!(
+            granges.meta.get_column_str("name").unwrap(),
+            &vec!["rs1", "."]
+        );
+    }
+}
@@ -23,11 +23,27 @@
 // Public crates
 pub mod alphabet;
 pub mod bam;
+pub mod bam_allele_counts;
+pub mod bam_bisulfite;
 pub mod bam_coverage;
+pub mod bam_duplicates;
+pub mod bam_index;
+pub mod bam_insert_size;
+pub mod bam_md;
+pub mod bam_merge;
+pub mod bam_modifications;
+pub mod bam_pileup;
+pub mod bam_single_cell;
+pub mod bam_sort;
+pub mod bam_stats;
+pub mod bam_subsample;
 pub mod bbi;
+pub mod bigbed;
 pub mod bgzf;
 pub mod bigwig;
 pub mod bigwig_map_plugin;
+pub mod bigwig_summary;
+pub mod compute_matrix;
 pub mod coverage;
 pub mod cpg;
 pub mod genes;
@@ -38,6 +54,7 @@ pub mod genome;
 pub mod granges;
 pub mod granges_bam;
 pub mod granges_bed;
+pub mod granges_bigbed;
 pub mod granges_bedgraph;
 pub mod granges_bigwig;
 pub mod granges_cpg;
@@ -49,6 +66,7 @@ pub mod granges_random;
 pub mod granges_row;
 pub mod granges_sort;
 pub mod granges_table;
+pub mod granges_vcf;
 #[macro_use]
 pub mod infologger;
 pub mod error;
@@ -70,7 +88,10 @@ pub mod progress;
 pub mod promoters;
 pub mod range;
 pub mod read;
+pub mod read_filter;
 pub mod read_stream;
+pub mod sam;
+pub mod sam_header;
 pub mod stringset;
 pub mod tf;
 pub mod track;
@@ -88,6 +109,8 @@ pub mod track_simple_wig;
 pub mod track_statistics;
 
 // Private crates
+#[cfg(test)]
+mod bam_fixtures;
 mod granges_find_endpoint;
 mod granges_find_nearest;
 mod granges_find_overlaps;
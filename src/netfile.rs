@@ -65,6 +65,12 @@ impl NetFile {
         let client = Client::new();
         let head_resp = client.head(url).send()?;
 
+        if head_resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                "File not found",
+            )));
+        }
         if !head_resp.status().is_success() {
             return Err(Box::new(io::Error::new(
                 io::ErrorKind::InvalidInput,
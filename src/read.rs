@@ -21,6 +21,7 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::bam::{BamAuxValue, BamAuxiliary, BamFlag};
 use crate::granges_row::GRange;
 use crate::range::Range;
 
@@ -34,6 +35,7 @@ use crate::range::Range;
 ///
 /// # Fields
 ///
+/// - `name`: The name of the read. Empty if read names were not loaded.
 /// - `seqname`: The name of the chromosome or sequence where the read is located.
 /// - `range`: A `Range` object indicating the start and end positions of the read.
 /// - `strand`: A character representing the read's strand ('+' for forward, '-' for reverse).
@@ -41,36 +43,91 @@ use crate::range::Range;
 ///   confidence of the read's alignment.
 /// - `duplicate`: A boolean flag indicating if the read is marked as a duplicate.
 /// - `paired_end`: A boolean flag indicating if the read is part of a paired-end read.
+/// - `blocks`: Aligned segments of reads whose alignment is not contiguous, e.g.
+///   spliced RNA-seq reads or read pairs. Empty by default, i.e. if the read
+///   covers `range` completely or if segments were not loaded (see
+///   `BamReader::read_spliced_stream`).
+/// - `flag`: The BAM flags of the alignment. For read pairs, the flags of the
+///   first mate (`READ1`) are used.
+/// - `auxiliary`: Auxiliary fields of the alignment, e.g. cell barcodes and UMIs
+///   of single-cell data. For read pairs, the fields of the first record are
+///   kept. Empty by default, i.e. if auxiliary fields were not loaded (see
+///   `BamReaderOptions::read_auxiliary`).
 ///
 /// # Examples
 ///
 /// ```
+/// use rustynetics::bam::BamFlag;
 /// use rustynetics::read::Read;
 /// use rustynetics::range::Range;
 ///
-/// let read = Read {
-///     seqname: "chr1".to_string(),
-///     range: Range::new(100, 150),
-///     strand: '+',
-///     mapq: 60,
-///     duplicate: false,
-///     paired_end: true,
-/// };
+/// let mut read = Read::new("chr1", Range::new(100, 150), '+', 60, false, true);
+/// read.name = "read1".to_string();
+/// read.flag = BamFlag(0x1);
 /// println!("{}", read);
 /// ```
 #[derive(Clone, Debug)]
 pub struct Read {
+    pub name: String,
     pub seqname: String,
     pub range: Range,
     pub strand: char,
     pub mapq: i64,
     pub duplicate: bool,
     pub paired_end: bool,
+    pub blocks: Vec<ReadBlock>,
+    pub flag: BamFlag,
+    pub auxiliary: Vec<BamAuxiliary>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Segment of a read alignment on the reference.
+///
+/// The type follows the CIGAR operations, i.e. `M`, `=` or `X` for aligned
+/// bases and `D` for deletions. Skipped regions (`N`) are not represented.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReadBlock {
+    pub range: Range,
+    pub type_: char,
 }
 
 /* -------------------------------------------------------------------------- */
 
 impl Read {
+    /// Creates a new `Read` without name, aligned segments, flags and
+    /// auxiliary fields.
+    ///
+    /// # Arguments
+    ///
+    /// - `seqname`: The name of the chromosome or sequence.
+    /// - `range`: The start and end positions of the read.
+    /// - `strand`: The strand of the read ('+', '-' or '*').
+    /// - `mapq`: The mapping quality of the read.
+    /// - `duplicate`: Whether the read is marked as a duplicate.
+    /// - `paired_end`: Whether the read is part of a paired-end read.
+    pub fn new(
+        seqname: &str,
+        range: Range,
+        strand: char,
+        mapq: i64,
+        duplicate: bool,
+        paired_end: bool,
+    ) -> Self {
+        Read {
+            name: String::new(),
+            seqname: seqname.to_string(),
+            range,
+            strand,
+            mapq,
+            duplicate,
+            paired_end,
+            blocks: Vec::new(),
+            flag: BamFlag(0),
+            auxiliary: Vec::new(),
+        }
+    }
+
     /// Converts the `Read` to a `GRange` object.
     ///
     /// The `GRange` object contains only the sequence name, range, and strand,
@@ -121,6 +178,38 @@ impl Read {
 
         Ok(Range::new(from, to))
     }
+
+    /// Returns the value of the auxiliary field with the given tag.
+    pub fn get_auxiliary(&self, tag: &[u8; 2]) -> Option<&BamAuxValue> {
+        self.auxiliary
+            .iter()
+            .find(|aux| &aux.tag == tag)
+            .map(|aux| &aux.value)
+    }
+
+    /// Returns the read group given by the `RG` tag.
+    pub fn read_group(&self) -> Option<&str> {
+        match self.get_auxiliary(b"RG") {
+            Some(BamAuxValue::Z(rg)) => Some(rg),
+            _ => None,
+        }
+    }
+
+    /// Returns the corrected cell barcode given by the `CB` tag.
+    pub fn cell_barcode(&self) -> Option<&str> {
+        match self.get_auxiliary(b"CB") {
+            Some(BamAuxValue::Z(cb)) => Some(cb),
+            _ => None,
+        }
+    }
+
+    /// Returns the corrected molecular barcode (UMI) given by the `UB` tag.
+    pub fn umi(&self) -> Option<&str> {
+        match self.get_auxiliary(b"UB") {
+            Some(BamAuxValue::Z(ub)) => Some(ub),
+            _ => None,
+        }
+    }
 }
 
 /* -------------------------------------------------------------------------- */
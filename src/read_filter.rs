@@ -0,0 +1,287 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bam::BamAuxValue;
+use crate::read::Read;
+
+/* -------------------------------------------------------------------------- */
+
+/// Condition on the value of an auxiliary field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuxPredicate {
+    Present,
+    Absent,
+    Eq(String),
+    Ne(String),
+    In(Vec<String>),
+    Lt(f64),
+    Le(f64),
+    Gt(f64),
+    Ge(f64),
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Filter on an auxiliary field of a read, e.g. `NM<=3`.
+///
+/// Filters are parsed from expressions of the form `TAG` (field present),
+/// `!TAG` (field absent), `TAG=v` or `TAG==v` (equal), `TAG=v1,v2,...` (equal
+/// to any value), `TAG!=v` (not equal), and `TAG<v`, `TAG<=v`, `TAG>v`,
+/// `TAG>=v` (numeric comparisons). Values are compared numerically if both
+/// the field and the given value are numbers. A missing field only satisfies
+/// `!TAG`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuxFilter {
+    pub tag: [u8; 2],
+    pub predicate: AuxPredicate,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl AuxFilter {
+    pub fn new(tag: &[u8; 2], predicate: AuxPredicate) -> Self {
+        AuxFilter {
+            tag: *tag,
+            predicate,
+        }
+    }
+
+    fn as_f64(value: &BamAuxValue) -> Option<f64> {
+        match value {
+            BamAuxValue::F(v) => Some(*v as f64),
+            BamAuxValue::D(v) => Some(*v),
+            _ => value.as_i64().map(|v| v as f64),
+        }
+    }
+
+    fn equals(value: &BamAuxValue, s: &str) -> bool {
+        match value {
+            BamAuxValue::Z(v) | BamAuxValue::H(v) => v == s,
+            BamAuxValue::A(v) => s.len() == 1 && s.as_bytes()[0] == *v,
+            _ => match (Self::as_f64(value), s.parse::<f64>()) {
+                (Some(a), Ok(b)) => a == b,
+                _ => false,
+            },
+        }
+    }
+
+    /// Returns `true` if the read satisfies the filter.
+    pub fn matches(&self, read: &Read) -> bool {
+        let value = match (read.get_auxiliary(&self.tag), &self.predicate) {
+            (None, AuxPredicate::Absent) => return true,
+            (None, _) | (Some(_), AuxPredicate::Absent) => return false,
+            (Some(value), _) => value,
+        };
+        let compare =
+            |f: fn(f64, f64) -> bool, b: f64| Self::as_f64(value).is_some_and(|a| f(a, b));
+
+        match &self.predicate {
+            AuxPredicate::Present => true,
+            AuxPredicate::Absent => false,
+            AuxPredicate::Eq(s) => Self::equals(value, s),
+            AuxPredicate::Ne(s) => !Self::equals(value, s),
+            AuxPredicate::In(v) => v.iter().any(|s| Self::equals(value, s)),
+            AuxPredicate::Lt(b) => compare(|a, b| a < b, *b),
+            AuxPredicate::Le(b) => compare(|a, b| a <= b, *b),
+            AuxPredicate::Gt(b) => compare(|a, b| a > b, *b),
+            AuxPredicate::Ge(b) => compare(|a, b| a >= b, *b),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for AuxFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negate, expr) = match s.strip_prefix('!') {
+            Some(expr) => (true, expr),
+            None => (false, s),
+        };
+        let tag: [u8; 2] = match expr.as_bytes().get(0..2) {
+            Some(&[a, b]) if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => [a, b],
+            _ => return Err(format!("invalid auxiliary tag in filter `{}`", s)),
+        };
+        let rest = &expr[2..];
+
+        if negate {
+            return if rest.is_empty() {
+                Ok(AuxFilter::new(&tag, AuxPredicate::Absent))
+            } else {
+                Err(format!("invalid auxiliary filter `{}`", s))
+            };
+        }
+        if rest.is_empty() {
+            return Ok(AuxFilter::new(&tag, AuxPredicate::Present));
+        }
+
+        let number = |v: &str| {
+            v.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number in auxiliary filter `{}`", s))
+        };
+        let predicate = if let Some(v) = rest.strip_prefix("<=") {
+            AuxPredicate::Le(number(v)?)
+        } else if let Some(v) = rest.strip_prefix(">=") {
+            AuxPredicate::Ge(number(v)?)
+        } else if let Some(v) = rest.strip_prefix("!=") {
+            AuxPredicate::Ne(v.to_string())
+        } else if let Some(v) = rest.strip_prefix('<') {
+            AuxPredicate::Lt(number(v)?)
+        } else if let Some(v) = rest.strip_prefix('>') {
+            AuxPredicate::Gt(number(v)?)
+        } else if let Some(v) = rest.strip_prefix("==").or_else(|| rest.strip_prefix('=')) {
+            if v.contains(',') {
+                AuxPredicate::In(v.split(',').map(String::from).collect())
+            } else {
+                AuxPredicate::Eq(v.to_string())
+            }
+        } else {
+            return Err(format!("invalid auxiliary filter `{}`", s));
+        };
+        Ok(AuxFilter::new(&tag, predicate))
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for AuxFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = String::from_utf8_lossy(&self.tag);
+        match &self.predicate {
+            AuxPredicate::Present => write!(f, "{}", tag),
+            AuxPredicate::Absent => write!(f, "!{}", tag),
+            AuxPredicate::Eq(v) => write!(f, "{}=={}", tag, v),
+            AuxPredicate::Ne(v) => write!(f, "{}!={}", tag, v),
+            AuxPredicate::In(v) => write!(f, "{}={}", tag, v.join(",")),
+            AuxPredicate::Lt(v) => write!(f, "{}<{}", tag, v),
+            AuxPredicate::Le(v) => write!(f, "{}<={}", tag, v),
+            AuxPredicate::Gt(v) => write!(f, "{}>{}", tag, v),
+            AuxPredicate::Ge(v) => write!(f, "{}>={}", tag, v),
+        }
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on_stream;
+    use futures::stream;
+
+    use crate::bam::{BamAuxValue, BamFlag};
+    use crate::bam_fixtures::mapped_read;
+    use crate::read::Read;
+    use crate::read_filter::{AuxFilter, AuxPredicate};
+    use crate::read_stream::ReadStream;
+
+    #[test]
+    fn test_aux_filter_parse() {
+        assert_eq!(
+            "NM<=3".parse::<AuxFilter>(),
+            Ok(AuxFilter::new(b"NM", AuxPredicate::Le(3.0)))
+        );
+        assert_eq!(
+            "!XS".parse::<AuxFilter>(),
+            Ok(AuxFilter::new(b"XS", AuxPredicate::Absent))
+        );
+        assert_eq!(
+            "RG=a,b".parse::<AuxFilter>(),
+            Ok(AuxFilter::new(
+                b"RG",
+                AuxPredicate::In(vec!["a".to_string(), "b".to_string()])
+            ))
+        );
+        assert_eq!(
+            "XT!=R".parse::<AuxFilter>(),
+            Ok(AuxFilter::new(b"XT", AuxPredicate::Ne("R".to_string())))
+        );
+        assert!("NM<=x".parse::<AuxFilter>().is_err());
+        assert!("!XS=1".parse::<AuxFilter>().is_err());
+        assert!("N".parse::<AuxFilter>().is_err());
+        assert_eq!("RG=a,b".parse::<AuxFilter>().unwrap().to_string(), "RG=a,b");
+    }
+
+    #[test]
+    fn test_aux_filter_matches() {
+        let r = mapped_read(
+            "chr1",
+            0,
+            10,
+            '+',
+            0x0,
+            vec![
+                (b"NM", BamAuxValue::CUnsigned(2)),
+                (b"RG", BamAuxValue::Z("lib1".to_string())),
+                (b"XT", BamAuxValue::A(b'U')),
+                (b"AS", BamAuxValue::F(-12.5)),
+            ],
+        );
+        let matches = |s: &str| s.parse::<AuxFilter>().unwrap().matches(&r);
+
+        assert!(matches("NM<=3"));
+        assert!(!matches("NM>2"));
+        assert!(matches("NM=2"));
+        assert!(matches("AS<-10"));
+        assert!(matches("!XS"));
+        assert!(!matches("XS"));
+        assert!(!matches("XS<1"));
+        assert!(matches("RG=lib0,lib1"));
+        assert!(!matches("RG=lib0"));
+        assert!(!matches("RG<1"));
+        assert!(matches("XT==U"));
+        assert!(matches("XT!=R"));
+    }
+
+    #[test]
+    fn test_filter_flags_and_auxiliary() {
+        let reads: Vec<Read> = [
+            (0x1 | 0x2, 1),
+            (0x1 | 0x2 | 0x100, 1),
+            (0x1 | 0x2 | 0x200, 1),
+            (0x1, 1),
+            (0x1 | 0x2, 5),
+        ]
+        .into_iter()
+        .map(|(flag, nm)| {
+            let mut r = mapped_read("chr1", 0, 10, '+', 0x0, vec![(b"NM", BamAuxValue::C(nm))]);
+            r.flag = BamFlag(flag);
+            r
+        })
+        .collect();
+        let filters = vec!["NM<=3".parse::<AuxFilter>().unwrap()];
+        let exclude = "SECONDARY,QCFAIL".parse::<BamFlag>().unwrap();
+
+        assert_eq!(exclude, BamFlag(0x300));
+
+        let reads = Box::pin(stream::iter(reads.into_iter().map(Ok)));
+        let reads = ReadStream::filter_flags(reads, None, 0x2, exclude.0);
+        let reads = ReadStream::filter_auxiliary(reads, None, &filters);
+
+        let flags: Vec<u16> = block_on_stream(reads).map(|r| r.unwrap().flag.0).collect();
+
+        assert_eq!(flags, vec![0x3]);
+    }
+}
@@ -19,17 +19,20 @@
 // SOFTWARE.
 
 use core::pin::Pin;
+use std::collections::{BTreeMap, HashSet};
 use std::io;
 
 use async_stream::stream;
 use futures::{Stream, StreamExt};
 
+use crate::bam_subsample::Subsampler;
 use crate::infologger::Logger;
 use crate::read;
+use crate::read_filter::AuxFilter;
 
 /* -------------------------------------------------------------------------- */
 
-type ReadStreamType<'a> = Pin<Box<dyn Stream<Item = io::Result<read::Read>> + 'a>>;
+pub type ReadStreamType<'a> = Pin<Box<dyn Stream<Item = io::Result<read::Read>> + 'a>>;
 
 /* -------------------------------------------------------------------------- */
 
@@ -383,6 +386,301 @@ impl ReadStream {
 
     /* -------------------------------------------------------------------------- */
 
+    /// Filters the input stream based on BAM flags.
+    ///
+    /// The function filters out reads that do not have all bits of `include_flags` set or that have
+    /// any bit of `exclude_flags` set, similar to the `-f` and `-F` options of `samtools view`. If
+    /// both are zero, the input stream is returned unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `stream_in`: The input stream of reads.
+    /// - `logger`: An optional logger for logging the number of filtered reads.
+    /// - `include_flags`: Flags that must be set.
+    /// - `exclude_flags`: Flags that must not be set.
+    ///
+    /// # Returns
+    ///
+    /// A new stream that only includes reads with admissible flags.
+    pub fn filter_flags<'a>(
+        mut stream_in: ReadStreamType<'a>,
+        logger: Option<&'a Logger>,
+        include_flags: u16,
+        exclude_flags: u16,
+    ) -> ReadStreamType<'a> {
+        if include_flags == 0 && exclude_flags == 0 {
+            return stream_in;
+        }
+
+        let output_stream = async_stream::stream! {
+            let mut n = 0;
+            let mut m = 0;
+
+            while let Some(item) = stream_in.next().await {
+                match item {
+                    Ok(r) => {
+                        if r.flag.contains(include_flags) && !r.flag.intersects(exclude_flags) {
+                            yield Ok(r);
+                            m += 1;
+                        }
+                        n += 1;
+                    },
+                    Err(e) => yield Err(e),
+                }
+            }
+
+            if let Some(log) = logger {
+                log!(log, "Filtered out {} reads with non-admissible flags (include: 0x{:x}, exclude: 0x{:x}) ({:.2}%)", n - m, include_flags, exclude_flags, 100.0 * (n - m) as f64 / n as f64);
+            }
+        };
+
+        Box::pin(output_stream)
+    }
+
+    /* -------------------------------------------------------------------------- */
+
+    /// Filters the input stream based on auxiliary fields.
+    ///
+    /// The function filters out reads that do not satisfy all of the given filters, e.g. `NM<=3` or
+    /// `RG=lib1,lib2`. If no filters are given, the input stream is returned unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `stream_in`: The input stream of reads.
+    /// - `logger`: An optional logger for logging the number of filtered reads.
+    /// - `filters`: The filters on auxiliary fields.
+    ///
+    /// # Returns
+    ///
+    /// A new stream that only includes reads satisfying all filters.
+    pub fn filter_auxiliary<'a>(
+        mut stream_in: ReadStreamType<'a>,
+        logger: Option<&'a Logger>,
+        filters: &'a [AuxFilter],
+    ) -> ReadStreamType<'a> {
+        if filters.is_empty() {
+            return stream_in;
+        }
+
+        let output_stream = async_stream::stream! {
+            let mut n = 0;
+            let mut m = 0;
+
+            while let Some(item) = stream_in.next().await {
+                match item {
+                    Ok(r) => {
+                        if filters.iter().all(|filter| filter.matches(&r)) {
+                            yield Ok(r);
+                            m += 1;
+                        }
+                        n += 1;
+                    },
+                    Err(e) => yield Err(e),
+                }
+            }
+
+            if let Some(log) = logger {
+                let filters : Vec<String> = filters.iter().map(|filter| filter.to_string()).collect();
+                log!(log, "Filtered out {} reads not satisfying `{}` ({:.2}%)", n - m, filters.join(" && "), 100.0 * (n - m) as f64 / n as f64);
+            }
+        };
+
+        Box::pin(output_stream)
+    }
+
+    /* -------------------------------------------------------------------------- */
+
+    /// Filters the input stream based on cell barcodes.
+    ///
+    /// The function filters out reads whose cell barcode (`CB` tag) is missing or not contained
+    /// in the whitelist. If the whitelist is empty, the input stream is returned unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `stream_in`: The input stream of reads.
+    /// - `logger`: An optional logger for logging the number of filtered reads.
+    /// - `whitelist`: The set of admissible cell barcodes.
+    ///
+    /// # Returns
+    ///
+    /// A new stream that only includes reads with a whitelisted cell barcode.
+    pub fn filter_barcodes<'a>(
+        mut stream_in: ReadStreamType<'a>,
+        logger: Option<&'a Logger>,
+        whitelist: &'a HashSet<String>,
+    ) -> ReadStreamType<'a> {
+        if whitelist.is_empty() {
+            return stream_in;
+        }
+
+        let output_stream = async_stream::stream! {
+            let mut n = 0;
+            let mut m = 0;
+
+            while let Some(item) = stream_in.next().await {
+                match item {
+                    Ok(r) => {
+                        if r.cell_barcode().is_some_and(|cb| whitelist.contains(cb)) {
+                            yield Ok(r);
+                            m += 1;
+                        }
+                        n += 1;
+                    },
+                    Err(e) => yield Err(e),
+                }
+            }
+
+            if let Some(log) = logger {
+                log!(log, "Filtered out {} reads without whitelisted cell barcode ({:.2}%)", n - m, 100.0 * (n - m) as f64 / n as f64);
+            }
+        };
+
+        Box::pin(output_stream)
+    }
+
+    /* -------------------------------------------------------------------------- */
+
+    /// Filters the input stream to exclude PCR duplicates of single-cell data.
+    ///
+    /// Reads are duplicates if they share the cell barcode (`CB` tag), the UMI (`UB` tag), the
+    /// strand and the 5' position, in which case only the first read is kept. Read pairs must share
+    /// both ends of the fragment. Reads without cell barcode or UMI are not filtered. The input
+    /// stream must be sorted by coordinate, which allows to forget reads that lie behind the current
+    /// position. If `switch` is `false`, the input stream is returned unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `stream_in`: The input stream of reads.
+    /// - `logger`: An optional logger for logging the number of filtered duplicates.
+    /// - `switch`: A boolean flag to indicate whether to filter duplicates.
+    ///
+    /// # Returns
+    ///
+    /// A new stream with one read per cell, UMI and position if `switch` is `true`.
+    pub fn filter_umi_duplicates<'a>(
+        mut stream_in: ReadStreamType<'a>,
+        logger: Option<&'a Logger>,
+        switch: bool,
+    ) -> ReadStreamType<'a> {
+        if !switch {
+            return stream_in;
+        }
+
+        let output_stream = async_stream::stream! {
+            let mut n = 0;
+            let mut m = 0;
+            let mut seqname = String::new();
+            let mut seen : BTreeMap<usize, HashSet<(String, String, usize, char)>> = BTreeMap::new();
+            let mut current = 0;
+
+            while let Some(item) = stream_in.next().await {
+                match item {
+                    Ok(r) => {
+                        n += 1;
+                        // Reads are indexed by the position that duplicates must share, i.e. the
+                        // 5' end of single reads and the end of read pairs
+                        let (position, key) = match (r.cell_barcode(), r.umi()) {
+                            (Some(cb), Some(ub)) => {
+                                let (position, start) = if r.paired_end {
+                                    (r.range.to, r.range.from)
+                                } else if r.strand == '-' {
+                                    (r.range.to, r.range.to)
+                                } else {
+                                    (r.range.from, r.range.from)
+                                };
+                                (position, (cb.to_string(), ub.to_string(), start, r.strand))
+                            },
+                            _ => {
+                                yield Ok(r);
+                                m += 1;
+                                continue;
+                            },
+                        };
+                        if r.seqname != seqname {
+                            seqname = r.seqname.clone();
+                            seen.clear();
+                            current = 0;
+                        }
+                        // Reads are sorted by the position of the last record, which is at least
+                        // the start of the read. All following reads therefore end after the
+                        // current start, and earlier positions can be forgotten
+                        if r.range.from > current {
+                            current = r.range.from;
+                            seen = seen.split_off(&current);
+                        }
+                        if seen.entry(position).or_default().insert(key) {
+                            yield Ok(r);
+                            m += 1;
+                        }
+                    },
+                    Err(e) => yield Err(e),
+                }
+            }
+
+            if let Some(log) = logger {
+                log!(log, "Filtered out {} UMI duplicates ({:.2}%)", n - m, 100.0 * (n - m) as f64 / n as f64);
+            }
+        };
+
+        Box::pin(output_stream)
+    }
+
+    /* -------------------------------------------------------------------------- */
+
+    /// Randomly subsamples the input stream.
+    ///
+    /// Reads are selected by hashing their names together with `seed` (see `Subsampler`), so that
+    /// mates are kept together and the selection is reproducible. If `fraction` is one or larger,
+    /// the input stream is returned unchanged.
+    ///
+    /// # Parameters
+    ///
+    /// - `stream_in`: The input stream of reads.
+    /// - `logger`: An optional logger for logging the number of removed reads.
+    /// - `fraction`: The fraction of reads to keep.
+    /// - `seed`: The seed of the random selection.
+    ///
+    /// # Returns
+    ///
+    /// A new stream with approximately the given fraction of reads.
+    pub fn subsample<'a>(
+        mut stream_in: ReadStreamType<'a>,
+        logger: Option<&'a Logger>,
+        fraction: f64,
+        seed: u64,
+    ) -> ReadStreamType<'a> {
+        if fraction >= 1.0 {
+            return stream_in;
+        }
+        let subsampler = Subsampler::new(fraction, seed);
+
+        let output_stream = async_stream::stream! {
+            let mut n = 0;
+            let mut m = 0;
+
+            while let Some(item) = stream_in.next().await {
+                match item {
+                    Ok(r) => {
+                        if subsampler.keep(&r.name) {
+                            yield Ok(r);
+                            m += 1;
+                        }
+                        n += 1;
+                    },
+                    Err(e) => yield Err(e),
+                }
+            }
+
+            if let Some(log) = logger {
+                log!(log, "Subsampled {} out of {} reads (seed: {})", m, n, seed);
+            }
+        };
+
+        Box::pin(output_stream)
+    }
+
+    /* -------------------------------------------------------------------------- */
+
     /// Shifts the reads based on their strand.
     ///
     /// This function modifies the read positions based on the specified shift values for forward and
@@ -413,9 +711,17 @@ impl ReadStream {
                         if r.strand == '+' {
                             r.range.from += shift[0];
                             r.range.to   += shift[0];
+                            for block in r.blocks.iter_mut() {
+                                block.range.from += shift[0];
+                                block.range.to   += shift[0];
+                            }
                         } else if r.strand == '-' {
                             r.range.from += shift[1];
                             r.range.to   += shift[1];
+                            for block in r.blocks.iter_mut() {
+                                block.range.from += shift[1];
+                                block.range.to   += shift[1];
+                            }
                         }
 
                         r.range.to  -= r.range.from;
@@ -0,0 +1,620 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::error::Error;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Write};
+use std::str::FromStr;
+
+use crate::bam::{
+    BamAuxValue, BamAuxiliary, BamBlock, BamCigar, BamFlag, BamHeader, BamQual, BamReaderType1,
+    BamSeq,
+};
+use crate::bam_index::{bam_reg2bin, BAI_DEPTH, BAI_MIN_SHIFT};
+use crate::genome::Genome;
+use crate::netfile::NetFile;
+
+/* -------------------------------------------------------------------------- */
+
+/// A reader for SAM files that produces the same `BamHeader`, `Genome` and
+/// `BamBlock` data structures as `BamReader`.
+///
+/// The header lines are parsed when the reader is created. Reference
+/// sequences are taken from the `@SQ` header lines.
+pub struct SamReader<R: BufRead> {
+    reader: R,
+    header: BamHeader,
+    genome: Genome,
+    line_number: usize,
+    pending: Option<String>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<R: BufRead> SamReader<R> {
+    /// Creates a new `SamReader` and parses the SAM header.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the header contains an invalid `@SQ` line.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut sam_reader = SamReader {
+            reader,
+            header: BamHeader::default(),
+            genome: Genome::default(),
+            line_number: 0,
+            pending: None,
+        };
+        let mut line = String::new();
+
+        while sam_reader.read_line(&mut line)? > 0 {
+            if !line.starts_with('@') {
+                sam_reader.pending = Some(line);
+                break;
+            }
+            let record = trim_line(&line);
+
+            if record.starts_with("@SQ\t") {
+                sam_reader.parse_sq(record)?;
+            }
+            sam_reader.header.text.push_str(record);
+            sam_reader.header.text.push('\n');
+        }
+        sam_reader.header.text_length = sam_reader.header.text.len() as i32;
+        sam_reader.header.n_ref = sam_reader.genome.len() as i32;
+
+        Ok(sam_reader)
+    }
+
+    /// Returns a reference to the SAM header.
+    pub fn get_header(&self) -> &BamHeader {
+        &self.header
+    }
+
+    /// Returns a reference to the reference sequences given in the header.
+    pub fn get_genome(&self) -> &Genome {
+        &self.genome
+    }
+
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        line.clear();
+        let bytes = self.reader.read_line(line)?;
+        if bytes > 0 {
+            self.line_number += 1;
+        }
+        Ok(bytes)
+    }
+
+    fn parse_sq(&mut self, record: &str) -> io::Result<()> {
+        let mut name = None;
+        let mut length = None;
+
+        for field in record.split('\t').skip(1) {
+            if let Some(v) = field.strip_prefix("SN:") {
+                name = Some(v.to_string());
+            } else if let Some(v) = field.strip_prefix("LN:") {
+                length = v.parse::<usize>().ok();
+            }
+        }
+        match (name, length) {
+            (Some(name), Some(length)) => {
+                self.genome
+                    .add_sequence(name, length)
+                    .map_err(|e| self.error(&e))?;
+                Ok(())
+            }
+            _ => Err(self.error("invalid @SQ header line")),
+        }
+    }
+
+    fn error(&self, msg: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} at line {}", msg, self.line_number),
+        )
+    }
+
+    /// Reads the next alignment record.
+    ///
+    /// # Returns
+    /// `Ok(None)` if no more records are available, otherwise the parsed
+    /// `BamBlock`.
+    pub fn read_record(&mut self) -> io::Result<Option<BamBlock>> {
+        let line = match self.pending.take() {
+            Some(line) => line,
+            None => {
+                let mut line = String::new();
+                if self.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                line
+            }
+        };
+        let line = trim_line(&line);
+
+        if line.is_empty() {
+            return self.read_record();
+        }
+        parse_sam_record(line, &self.genome)
+            .map(Some)
+            .map_err(|e| self.error(&e))
+    }
+
+    /// Reads all alignment records.
+    ///
+    /// # Returns
+    /// An iterator over `io::Result<BamReaderType1>`, where each item
+    /// represents a single read.
+    pub fn read_single_end(&mut self) -> impl Iterator<Item = io::Result<BamReaderType1>> + '_ {
+        std::iter::from_fn(move || match self.read_record() {
+            Ok(Some(block)) => Some(Ok(BamReaderType1 { block })),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// `SamFile` is a wrapper around `SamReader`, designed to read SAM files from
+/// either local files or HTTP sources.
+pub struct SamFile {
+    pub reader: SamReader<BufReader<NetFile>>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl SamFile {
+    /// Opens a SAM file by filename.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or the header is invalid.
+    pub fn open(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let file = NetFile::open(filename)?;
+        let reader = SamReader::new(BufReader::new(file))?;
+
+        Ok(SamFile { reader })
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A writer for SAM files.
+pub struct SamWriter<W: Write> {
+    writer: W,
+    genome: Genome,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl<W: Write> SamWriter<W> {
+    /// Creates a new `SamWriter` and writes the SAM header.
+    ///
+    /// If the header text does not contain any `@SQ` lines, they are
+    /// generated from `genome`.
+    ///
+    /// # Arguments
+    /// * `writer` - A writer that implements `Write`.
+    /// * `header` - The `BamHeader` containing the SAM header text.
+    /// * `genome` - The reference sequences used to resolve reference ids.
+    pub fn new(writer: W, header: &BamHeader, genome: &Genome) -> io::Result<Self> {
+        let mut sam_writer = Self::new_without_header(writer, genome);
+        let text = header.text.trim_end_matches('\0');
+
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            writeln!(sam_writer.writer, "{}", line)?;
+        }
+        if !text.lines().any(|l| l.starts_with("@SQ\t")) {
+            for (seqname, length) in genome.iter() {
+                writeln!(sam_writer.writer, "@SQ\tSN:{}\tLN:{}", seqname, length)?;
+            }
+        }
+        Ok(sam_writer)
+    }
+
+    /// Creates a new `SamWriter` that writes alignment records only.
+    pub fn new_without_header(writer: W, genome: &Genome) -> Self {
+        SamWriter {
+            writer,
+            genome: genome.clone(),
+        }
+    }
+
+    /// Writes a single record as a SAM line.
+    pub fn write_block(&mut self, block: &BamBlock) -> io::Result<()> {
+        let line = format_sam_record(block, &self.genome)?;
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Parses a single SAM alignment line into a `BamBlock`.
+///
+/// Derived fields such as the bin, `l_seq`, `n_cigar_op` and the read name
+/// length are computed from the parsed data.
+///
+/// # Arguments
+/// * `line` - A SAM alignment line without trailing newline.
+/// * `genome` - The reference sequences used to resolve reference names.
+pub fn parse_sam_record(line: &str, genome: &Genome) -> Result<BamBlock, String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+
+    if fields.len() < 11 {
+        return Err(format!(
+            "SAM record has {} instead of at least 11 fields",
+            fields.len()
+        ));
+    }
+    fn parse_int<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+        value
+            .parse::<T>()
+            .map_err(|_| format!("invalid {} `{}`", name, value))
+    }
+    let parse_ref = |name: &str| -> Result<i32, String> {
+        if name == "*" {
+            Ok(-1)
+        } else {
+            genome
+                .get_idx(name)
+                .map(|i| i as i32)
+                .ok_or_else(|| format!("reference `{}` not found in header", name))
+        }
+    };
+    let mut block = BamBlock::default();
+
+    block.read_name = if fields[0] == "*" {
+        String::new()
+    } else {
+        fields[0].to_string()
+    };
+    block.rname_len = (block.read_name.len() + 1) as u8;
+    block.flag = BamFlag(parse_int::<u16>("FLAG", fields[1])?);
+    block.ref_id = parse_ref(fields[2])?;
+    block.position = parse_int::<i32>("POS", fields[3])? - 1;
+    block.mapq = parse_int::<u8>("MAPQ", fields[4])?;
+    block.cigar = fields[5].parse::<BamCigar>()?;
+    block.n_cigar_op = block.cigar.0.len() as u16;
+    block.next_ref_id = if fields[6] == "=" {
+        block.ref_id
+    } else {
+        parse_ref(fields[6])?
+    };
+    block.next_position = parse_int::<i32>("PNEXT", fields[7])? - 1;
+    block.tlen = parse_int::<i32>("TLEN", fields[8])?;
+
+    if fields[9] != "*" {
+        block.l_seq = fields[9].len() as i32;
+        block.seq = BamSeq::encode(fields[9]);
+    }
+    if fields[10] == "*" {
+        block.qual = BamQual(vec![0xff; block.l_seq as usize]);
+    } else {
+        if fields[10].len() != block.l_seq as usize {
+            return Err("SEQ and QUAL have different lengths".to_string());
+        }
+        block.qual = BamQual(fields[10].bytes().map(|b| b.wrapping_sub(33)).collect());
+    }
+    for field in &fields[11..] {
+        block.auxiliary.push(parse_sam_auxiliary(field)?);
+    }
+
+    let from = block.position as i64;
+    let to = from + block.cigar.alignment_length().max(1) as i64;
+    block.bin = bam_reg2bin(from, to, BAI_MIN_SHIFT, BAI_DEPTH) as u16;
+
+    Ok(block)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Formats a `BamBlock` as a SAM alignment line without trailing newline.
+///
+/// # Arguments
+/// * `block` - The record to format.
+/// * `genome` - The reference sequences used to resolve reference ids.
+pub fn format_sam_record(block: &BamBlock, genome: &Genome) -> io::Result<String> {
+    let seqname = |ref_id: i32| -> io::Result<&str> {
+        if ref_id < 0 {
+            Ok("*")
+        } else {
+            genome
+                .seqnames
+                .get(ref_id as usize)
+                .map(|s| s.as_str())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid reference id `{}`", ref_id),
+                    )
+                })
+        }
+    };
+    let mut line = String::new();
+
+    let rname = seqname(block.ref_id)?;
+    let rnext = if block.next_ref_id >= 0 && block.next_ref_id == block.ref_id {
+        "="
+    } else {
+        seqname(block.next_ref_id)?
+    };
+    let qname = if block.read_name.is_empty() {
+        "*"
+    } else {
+        &block.read_name
+    };
+    let cigar = if block.cigar.0.is_empty() {
+        "*".to_string()
+    } else {
+        block.cigar.to_string()
+    };
+
+    write!(
+        line,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+        qname,
+        block.flag.0,
+        rname,
+        block.position + 1,
+        block.mapq,
+        cigar,
+        rnext,
+        block.next_position + 1,
+        block.tlen
+    )
+    .unwrap();
+
+    if block.l_seq <= 0 || block.seq.0.is_empty() {
+        line.push('*');
+    } else {
+        line.push_str(&block.seq.decode(block.l_seq as usize));
+    }
+    line.push('\t');
+
+    if block.qual.0.is_empty() || block.qual.0[0] == 0xff {
+        line.push('*');
+    } else {
+        line.extend(block.qual.0.iter().map(|&q| (q.saturating_add(33)) as char));
+    }
+
+    for aux in &block.auxiliary {
+        line.push('\t');
+        format_sam_auxiliary(&mut line, aux)?;
+    }
+    Ok(line)
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Parses a SAM optional field of the form `TAG:TYPE:VALUE`.
+///
+/// Integers are stored using the smallest BAM integer type that can hold
+/// the value.
+pub fn parse_sam_auxiliary(field: &str) -> Result<BamAuxiliary, String> {
+    let invalid = || format!("invalid optional field `{}`", field);
+    let mut parts = field.splitn(3, ':');
+
+    let tag = parts.next().ok_or_else(invalid)?.as_bytes();
+    let type_ = parts.next().ok_or_else(invalid)?;
+    let value = parts.next().ok_or_else(invalid)?;
+
+    if tag.len() != 2 {
+        return Err(invalid());
+    }
+    let value = match type_ {
+        "A" if value.len() == 1 => BamAuxValue::A(value.as_bytes()[0]),
+        "i" => {
+            let v = value.parse::<i64>().map_err(|_| invalid())?;
+            if v >= 0 {
+                if v <= u8::MAX as i64 {
+                    BamAuxValue::CUnsigned(v as u8)
+                } else if v <= u16::MAX as i64 {
+                    BamAuxValue::SUnsigned(v as u16)
+                } else if v <= u32::MAX as i64 {
+                    BamAuxValue::IUnsigned(v as u32)
+                } else {
+                    return Err(invalid());
+                }
+            } else if v >= i8::MIN as i64 {
+                BamAuxValue::C(v as i8)
+            } else if v >= i16::MIN as i64 {
+                BamAuxValue::S(v as i16)
+            } else if v >= i32::MIN as i64 {
+                BamAuxValue::I(v as i32)
+            } else {
+                return Err(invalid());
+            }
+        }
+        "f" => BamAuxValue::F(value.parse::<f32>().map_err(|_| invalid())?),
+        "Z" => BamAuxValue::Z(value.to_string()),
+        "H" => BamAuxValue::H(value.to_string()),
+        "B" => {
+            let mut items = value.split(',');
+            let subtype = items.next().ok_or_else(invalid)?;
+            macro_rules! parse_array {
+                ($t:ty, $variant:ident) => {
+                    BamAuxValue::$variant(
+                        items
+                            .map(|x| x.parse::<$t>().map_err(|_| invalid()))
+                            .collect::<Result<Vec<$t>, String>>()?,
+                    )
+                };
+            }
+            match subtype {
+                "c" => parse_array!(i8, BInt8),
+                "C" => parse_array!(u8, BUint8),
+                "s" => parse_array!(i16, BInt16),
+                "S" => parse_array!(u16, BUint16),
+                "i" => parse_array!(i32, BInt32),
+                "I" => parse_array!(u32, BUint32),
+                "f" => parse_array!(f32, BFloat32),
+                _ => return Err(invalid()),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(BamAuxiliary {
+        tag: [tag[0], tag[1]],
+        value,
+    })
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn format_sam_auxiliary(line: &mut String, aux: &BamAuxiliary) -> io::Result<()> {
+    fn join<T: ToString>(v: &[T]) -> String {
+        v.iter()
+            .map(|x| format!(",{}", x.to_string()))
+            .collect::<String>()
+    }
+    let tag = format!("{}{}", aux.tag[0] as char, aux.tag[1] as char);
+
+    let result = match &aux.value {
+        BamAuxValue::A(v) => write!(line, "{}:A:{}", tag, *v as char),
+        BamAuxValue::C(v) => write!(line, "{}:i:{}", tag, v),
+        BamAuxValue::CUnsigned(v) => write!(line, "{}:i:{}", tag, v),
+        BamAuxValue::S(v) => write!(line, "{}:i:{}", tag, v),
+        BamAuxValue::SUnsigned(v) => write!(line, "{}:i:{}", tag, v),
+        BamAuxValue::I(v) => write!(line, "{}:i:{}", tag, v),
+        BamAuxValue::IUnsigned(v) => write!(line, "{}:i:{}", tag, v),
+        BamAuxValue::F(v) => write!(line, "{}:f:{}", tag, v),
+        BamAuxValue::D(v) => write!(line, "{}:f:{}", tag, v),
+        BamAuxValue::Z(v) => write!(line, "{}:Z:{}", tag, v),
+        BamAuxValue::H(v) => write!(line, "{}:H:{}", tag, v),
+        BamAuxValue::BInt8(v) => write!(line, "{}:B:c{}", tag, join(v)),
+        BamAuxValue::BUint8(v) => write!(line, "{}:B:C{}", tag, join(v)),
+        BamAuxValue::BInt16(v) => write!(line, "{}:B:s{}", tag, join(v)),
+        BamAuxValue::BUint16(v) => write!(line, "{}:B:S{}", tag, join(v)),
+        BamAuxValue::BInt32(v) => write!(line, "{}:B:i{}", tag, join(v)),
+        BamAuxValue::BUint32(v) => write!(line, "{}:B:I{}", tag, join(v)),
+        BamAuxValue::BFloat32(v) => write!(line, "{}:B:f{}", tag, join(v)),
+        BamAuxValue::None() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Auxiliary field without value",
+            ))
+        }
+    };
+    result.unwrap();
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn trim_line(line: &str) -> &str {
+    line.trim_end_matches(&['\r', '\n'][..])
+}
+
+/* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+
+    use crate::bam::{BamAuxValue, BamCigar, BamReader, BamWriter};
+    use crate::genome::Genome;
+    use crate::sam::{parse_sam_record, SamFile, SamWriter};
+
+    #[test]
+    fn test_sam_read() {
+        let mut sam = SamFile::open("tests/test_sam_1.sam").unwrap();
+
+        let genome = sam.reader.get_genome().clone();
+        assert_eq!(genome.seqnames, vec!["ref", "ref2"]);
+        assert_eq!(genome.lengths, vec![45, 40]);
+        assert!(sam.reader.get_header().text.starts_with("@HD\tVN:1.6"));
+
+        let blocks: Vec<_> = sam
+            .reader
+            .read_single_end()
+            .map(|r| r.unwrap().block)
+            .collect();
+
+        assert_eq!(blocks.len(), 7);
+        assert_eq!(blocks[0].read_name, "r001");
+        assert_eq!(blocks[0].position, 6);
+        assert_eq!(blocks[0].next_ref_id, 0);
+        assert_eq!(blocks[0].cigar.to_string(), "8M2I4M1D3M");
+        assert_eq!(blocks[0].cigar.alignment_length(), 16);
+        assert_eq!(blocks[0].seq.decode(17), "TTAGATAAAGGATACTG");
+        assert_eq!(blocks[0].bin, 4681);
+        assert!(matches!(blocks[1].auxiliary[1].value, BamAuxValue::S(-300)));
+        assert!(
+            matches!(blocks[3].auxiliary[0].value, BamAuxValue::BInt16(ref v) if v == &vec![-1, 200, 3])
+        );
+        assert_eq!(blocks[6].ref_id, -1);
+        assert_eq!(blocks[6].position, -1);
+        assert_eq!(blocks[6].bin, 4680);
+    }
+
+    #[test]
+    fn test_sam_bam_roundtrip() {
+        let text = std::fs::read_to_string("tests/test_sam_1.sam").unwrap();
+        let mut sam = SamFile::open("tests/test_sam_1.sam").unwrap();
+        let header = sam.reader.get_header().clone();
+        let genome = sam.reader.get_genome().clone();
+
+        let mut writer = BamWriter::new(Vec::new(), &header, &genome).unwrap();
+        for item in sam.reader.read_single_end() {
+            writer.write_block(&item.unwrap().block).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BamReader::new(bytes.as_slice(), None).unwrap();
+        let mut writer = SamWriter::new(Vec::new(), reader.get_header(), &genome).unwrap();
+        for item in reader.read_single_end() {
+            writer.write_block(&item.unwrap().block).unwrap();
+        }
+
+        assert_eq!(String::from_utf8(writer.writer).unwrap(), text);
+    }
+
+    #[test]
+    fn test_sam_cigar() {
+        assert_eq!("*".parse::<BamCigar>().unwrap().0.len(), 0);
+        assert_eq!(
+            "3S6M1P1I4M".parse::<BamCigar>().unwrap().to_string(),
+            "3S6M1P1I4M"
+        );
+        assert!("M".parse::<BamCigar>().is_err());
+        assert!("10".parse::<BamCigar>().is_err());
+        assert!("10Q".parse::<BamCigar>().is_err());
+    }
+
+    #[test]
+    fn test_sam_record_out_of_range() {
+        let genome = Genome::new(vec!["ref".to_string()], vec![45]);
+        let record =
+            |flag: &str, mapq: &str| format!("r001\t{flag}\tref\t7\t{mapq}\t4M\t*\t0\t0\tACGT\t*");
+
+        let block = parse_sam_record(&record("65535", "255"), &genome).unwrap();
+        assert_eq!(block.flag.0, 65535);
+        assert_eq!(block.mapq, 255);
+
+        assert!(parse_sam_record(&record("0", "256"), &genome).is_err());
+        assert!(parse_sam_record(&record("65536", "0"), &genome).is_err());
+        assert!(parse_sam_record(&record("-1", "0"), &genome).is_err());
+    }
+}
@@ -0,0 +1,643 @@
+// Copyright (C) 2024 Philipp Benner
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the “Software”), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::genome::Genome;
+
+/* -------------------------------------------------------------------------- */
+
+/// Tags of a header record that are not interpreted, stored in their
+/// original order as `(tag, value)` pairs.
+pub type SamHeaderTags = Vec<(String, String)>;
+
+/* -------------------------------------------------------------------------- */
+
+/// The `@HD` header line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamHeaderHd {
+    pub version: String,
+    pub sort_order: Option<String>,
+    pub group_order: Option<String>,
+    pub sub_sort_order: Option<String>,
+    pub other: SamHeaderTags,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A `@SQ` header line describing a reference sequence.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamSequence {
+    pub name: String,
+    pub length: usize,
+    pub alternate_names: Vec<String>,
+    pub assembly: Option<String>,
+    pub md5: Option<String>,
+    pub species: Option<String>,
+    pub uri: Option<String>,
+    pub other: SamHeaderTags,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A `@RG` header line describing a read group.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamReadGroup {
+    pub id: String,
+    pub sample: Option<String>,
+    pub library: Option<String>,
+    pub platform: Option<String>,
+    pub platform_unit: Option<String>,
+    pub center: Option<String>,
+    pub description: Option<String>,
+    pub other: SamHeaderTags,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// A `@PG` header line describing a program that processed the data.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamProgram {
+    pub id: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub command_line: Option<String>,
+    pub previous_id: Option<String>,
+    pub description: Option<String>,
+    pub other: SamHeaderTags,
+}
+
+/* -------------------------------------------------------------------------- */
+
+/// Parsed representation of a SAM header.
+///
+/// Header lines are grouped by record type. Lines with unknown record types
+/// are kept verbatim in `other`, comments (`@CO`) in `comments`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamHeader {
+    pub hd: Option<SamHeaderHd>,
+    pub sequences: Vec<SamSequence>,
+    pub read_groups: Vec<SamReadGroup>,
+    pub programs: Vec<SamProgram>,
+    pub comments: Vec<String>,
+    pub other: Vec<String>,
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl SamHeader {
+    /// Returns the sort order given in the `@HD` line.
+    pub fn sort_order(&self) -> Option<&str> {
+        self.hd.as_ref().and_then(|hd| hd.sort_order.as_deref())
+    }
+
+    /// Sets the sort order (`SO` tag) of the `@HD` line, creating the line
+    /// if necessary.
+    pub fn set_sort_order(&mut self, sort_order: &str) {
+        self.hd_mut().sort_order = Some(sort_order.to_string());
+    }
+
+    /// Returns the `@HD` line, inserting a default line with version 1.6 if
+    /// the header has none.
+    pub fn hd_mut(&mut self) -> &mut SamHeaderHd {
+        self.hd.get_or_insert_with(|| SamHeaderHd {
+            version: "1.6".to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the reference sequences as a `Genome`.
+    pub fn genome(&self) -> Genome {
+        Genome::new(
+            self.sequences.iter().map(|s| s.name.clone()).collect(),
+            self.sequences.iter().map(|s| s.length).collect(),
+        )
+    }
+
+    /// Replaces all `@SQ` lines by the sequences of a `Genome`. Attributes of
+    /// sequences that are also present in `genome` are preserved, where
+    /// sequences found by an alternate name are renamed to the name used in
+    /// `genome` and keep their previous name as an alternate name. The `M5`
+    /// checksum is dropped if the length of a sequence changes.
+    pub fn set_genome(&mut self, genome: &Genome) {
+        let sequences = genome
+            .iter()
+            .map(|(name, &length)| {
+                let mut sequence =
+                    self.get_sequence(name)
+                        .cloned()
+                        .unwrap_or_else(|| SamSequence {
+                            name: name.clone(),
+                            length,
+                            ..Default::default()
+                        });
+                if sequence.name != *name {
+                    sequence.alternate_names.retain(|n| n != name);
+                    sequence.alternate_names.push(sequence.name.clone());
+                    sequence.name = name.clone();
+                }
+                if sequence.length != length {
+                    sequence.md5 = None;
+                    sequence.length = length;
+                }
+                sequence
+            })
+            .collect();
+        self.sequences = sequences;
+    }
+
+    /// Returns the `@SQ` line of a sequence, also considering alternate names.
+    pub fn get_sequence(&self, name: &str) -> Option<&SamSequence> {
+        self.sequences.iter().find(|s| s.name == name).or_else(|| {
+            self.sequences
+                .iter()
+                .find(|s| s.alternate_names.iter().any(|n| n == name))
+        })
+    }
+
+    /// Returns the read group with the given id.
+    pub fn get_read_group(&self, id: &str) -> Option<&SamReadGroup> {
+        self.read_groups.iter().find(|rg| rg.id == id)
+    }
+
+    /// Adds a read group, replacing an existing read group with the same id.
+    pub fn add_read_group(&mut self, read_group: SamReadGroup) {
+        match self
+            .read_groups
+            .iter_mut()
+            .find(|rg| rg.id == read_group.id)
+        {
+            Some(rg) => *rg = read_group,
+            None => self.read_groups.push(read_group),
+        }
+    }
+
+    /// Removes the read group with the given id.
+    pub fn remove_read_group(&mut self, id: &str) -> Option<SamReadGroup> {
+        let i = self.read_groups.iter().position(|rg| rg.id == id)?;
+        Some(self.read_groups.remove(i))
+    }
+
+    /// Returns the distinct sample names of all read groups.
+    pub fn samples(&self) -> Vec<&str> {
+        let mut samples: Vec<&str> = Vec::new();
+        for sample in self
+            .read_groups
+            .iter()
+            .filter_map(|rg| rg.sample.as_deref())
+        {
+            if !samples.contains(&sample) {
+                samples.push(sample);
+            }
+        }
+        samples
+    }
+
+    /// Returns the ids of all read groups that belong to a sample.
+    pub fn read_groups_of_sample(&self, sample: &str) -> Vec<&str> {
+        self.read_groups
+            .iter()
+            .filter(|rg| rg.sample.as_deref() == Some(sample))
+            .map(|rg| rg.id.as_str())
+            .collect()
+    }
+
+    /// Returns the program with the given id.
+    pub fn get_program(&self, id: &str) -> Option<&SamProgram> {
+        self.programs.iter().find(|pg| pg.id == id)
+    }
+
+    /// Returns the chain of programs that ends with the program `id`, starting
+    /// with the first program in the chain.
+    pub fn program_chain(&self, id: &str) -> Vec<&SamProgram> {
+        let mut chain = Vec::new();
+        let mut next = self.get_program(id);
+
+        while let Some(pg) = next {
+            // Guard against cyclic chains
+            if chain.iter().any(|p: &&SamProgram| p.id == pg.id) {
+                break;
+            }
+            chain.push(pg);
+            next = pg
+                .previous_id
+                .as_deref()
+                .and_then(|pp| self.get_program(pp));
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Returns the programs that are not referenced by the `PP` tag of any
+    /// other program, i.e. the last programs of each chain.
+    pub fn program_leaves(&self) -> Vec<&SamProgram> {
+        self.programs
+            .iter()
+            .filter(|pg| {
+                !self
+                    .programs
+                    .iter()
+                    .any(|other| other.previous_id.as_deref() == Some(pg.id.as_str()))
+            })
+            .collect()
+    }
+
+    /// Returns an id based on `id` that does not collide with any existing
+    /// program id, by appending a numeric suffix if necessary.
+    pub fn unique_program_id(&self, id: &str) -> String {
+        let mut candidate = id.to_string();
+        let mut i = 1;
+        while self.get_program(&candidate).is_some() {
+            candidate = format!("{}.{}", id, i);
+            i += 1;
+        }
+        candidate
+    }
+
+    /// Appends a `@PG` line to the header.
+    ///
+    /// The id of the program is made unique, and the `PP` tag is set to the
+    /// last program of the existing chain (if the chain is unambiguous).
+    ///
+    /// # Returns
+    /// The id assigned to the new program.
+    pub fn add_program(
+        &mut self,
+        name: &str,
+        version: Option<&str>,
+        command_line: Option<&str>,
+    ) -> String {
+        let id = self.unique_program_id(name);
+        let leaves = self.program_leaves();
+        let previous_id = if leaves.len() == 1 {
+            Some(leaves[0].id.clone())
+        } else {
+            None
+        };
+        self.programs.push(SamProgram {
+            id: id.clone(),
+            name: Some(name.to_string()),
+            version: version.map(|v| v.to_string()),
+            command_line: command_line.map(|v| v.to_string()),
+            previous_id,
+            ..Default::default()
+        });
+        id
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn parse_tags(line: &str) -> Result<SamHeaderTags, String> {
+    line.split('\t')
+        .skip(1)
+        .map(|field| {
+            if field.len() < 3 || field.as_bytes()[2] != b':' {
+                Err(format!("invalid header field `{}`", field))
+            } else {
+                Ok((field[..2].to_string(), field[3..].to_string()))
+            }
+        })
+        .collect()
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl FromStr for SamHeader {
+    type Err = String;
+
+    /// Parses the text of a SAM header.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut header = SamHeader::default();
+
+        for line in text.trim_end_matches('\0').lines() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            match line.get(..3).unwrap_or(line) {
+                "@HD" => {
+                    let mut hd = SamHeaderHd::default();
+                    for (tag, value) in parse_tags(line)? {
+                        match tag.as_str() {
+                            "VN" => hd.version = value,
+                            "SO" => hd.sort_order = Some(value),
+                            "GO" => hd.group_order = Some(value),
+                            "SS" => hd.sub_sort_order = Some(value),
+                            _ => hd.other.push((tag, value)),
+                        }
+                    }
+                    header.hd = Some(hd);
+                }
+                "@SQ" => {
+                    let mut sq = SamSequence::default();
+                    let mut length = None;
+                    for (tag, value) in parse_tags(line)? {
+                        match tag.as_str() {
+                            "SN" => sq.name = value,
+                            "LN" => length = value.parse::<usize>().ok(),
+                            "AN" => {
+                                sq.alternate_names = value.split(',').map(String::from).collect()
+                            }
+                            "AS" => sq.assembly = Some(value),
+                            "M5" => sq.md5 = Some(value),
+                            "SP" => sq.species = Some(value),
+                            "UR" => sq.uri = Some(value),
+                            _ => sq.other.push((tag, value)),
+                        }
+                    }
+                    if sq.name.is_empty() {
+                        return Err(format!("@SQ line without SN tag: `{}`", line));
+                    }
+                    sq.length = length
+                        .ok_or_else(|| format!("@SQ line with invalid LN tag: `{}`", line))?;
+                    header.sequences.push(sq);
+                }
+                "@RG" => {
+                    let mut rg = SamReadGroup::default();
+                    for (tag, value) in parse_tags(line)? {
+                        match tag.as_str() {
+                            "ID" => rg.id = value,
+                            "SM" => rg.sample = Some(value),
+                            "LB" => rg.library = Some(value),
+                            "PL" => rg.platform = Some(value),
+                            "PU" => rg.platform_unit = Some(value),
+                            "CN" => rg.center = Some(value),
+                            "DS" => rg.description = Some(value),
+                            _ => rg.other.push((tag, value)),
+                        }
+                    }
+                    if rg.id.is_empty() {
+                        return Err(format!("@RG line without ID tag: `{}`", line));
+                    }
+                    header.read_groups.push(rg);
+                }
+                "@PG" => {
+                    let mut pg = SamProgram::default();
+                    for (tag, value) in parse_tags(line)? {
+                        match tag.as_str() {
+                            "ID" => pg.id = value,
+                            "PN" => pg.name = Some(value),
+                            "VN" => pg.version = Some(value),
+                            "CL" => pg.command_line = Some(value),
+                            "PP" => pg.previous_id = Some(value),
+                            "DS" => pg.description = Some(value),
+                            _ => pg.other.push((tag, value)),
+                        }
+                    }
+                    if pg.id.is_empty() {
+                        return Err(format!("@PG line without ID tag: `{}`", line));
+                    }
+                    header.programs.push(pg);
+                }
+                "@CO" => {
+                    header
+                        .comments
+                        .push(line.get(4..).unwrap_or_default().to_string());
+                }
+                _ => header.other.push(line.to_string()),
+            }
+        }
+        Ok(header)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+fn write_tag(f: &mut fmt::Formatter<'_>, tag: &str, value: &Option<String>) -> fmt::Result {
+    if let Some(value) = value {
+        write!(f, "\t{}:{}", tag, value)?;
+    }
+    Ok(())
+}
+
+fn write_other(f: &mut fmt::Formatter<'_>, other: &SamHeaderTags) -> fmt::Result {
+    for (tag, value) in other {
+        write!(f, "\t{}:{}", tag, value)?;
+    }
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl fmt::Display for SamHeader {
+    /// Serializes the header as SAM header text, one line per record.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(hd) = &self.hd {
+            write!(f, "@HD\tVN:{}", hd.version)?;
+            write_tag(f, "SO", &hd.sort_order)?;
+            write_tag(f, "GO", &hd.group_order)?;
+            write_tag(f, "SS", &hd.sub_sort_order)?;
+            write_other(f, &hd.other)?;
+            writeln!(f)?;
+        }
+        for sq in &self.sequences {
+            write!(f, "@SQ\tSN:{}\tLN:{}", sq.name, sq.length)?;
+            if !sq.alternate_names.is_empty() {
+                write!(f, "\tAN:{}", sq.alternate_names.join(","))?;
+            }
+            write_tag(f, "AS", &sq.assembly)?;
+            write_tag(f, "M5", &sq.md5)?;
+            write_tag(f, "SP", &sq.species)?;
+            write_tag(f, "UR", &sq.uri)?;
+            write_other(f, &sq.other)?;
+            writeln!(f)?;
+        }
+        for rg in &self.read_groups {
+            write!(f, "@RG\tID:{}", rg.id)?;
+            write_tag(f, "SM", &rg.sample)?;
+            write_tag(f, "LB", &rg.library)?;
+            write_tag(f, "PL", &rg.platform)?;
+            write_tag(f, "PU", &rg.platform_unit)?;
+            write_tag(f, "CN", &rg.center)?;
+            write_tag(f, "DS", &rg.description)?;
+            write_other(f, &rg.other)?;
+            writeln!(f)?;
+        }
+        for pg in &self.programs {
+            write!(f, "@PG\tID:{}", pg.id)?;
+            write_tag(f, "PN", &pg.name)?;
+            write_tag(f, "PP", &pg.previous_id)?;
+            write_tag(f, "VN", &pg.version)?;
+            write_tag(f, "CL", &pg.command_line)?;
+            write_tag(f, "DS", &pg.description)?;
+            write_other(f, &pg.other)?;
+            writeln!(f)?;
+        }
+        for line in &self.other {
+            writeln!(f, "{}", line)?;
+        }
+        for comment in &self.comments {
+            writeln!(f, "@CO\t{}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+    use crate::bam::BamHeader;
+    use crate::genome::Genome;
+    use crate::sam::SamFile;
+    use crate::sam_header::{SamHeader, SamReadGroup};
+
+    const HEADER: &str = "@HD\tVN:1.6\tSO:coordinate\n\
+        @SQ\tSN:chr1\tLN:1000\tAN:1,chrom1\tAS:hg38\tM5:0123456789abcdef0123456789abcdef\n\
+        @SQ\tSN:chr2\tLN:500\tXX:custom\n\
+        @RG\tID:rg1\tSM:s1\tLB:lib1\tPL:ILLUMINA\n\
+        @RG\tID:rg2\tSM:s2\tLB:lib2\tPL:ILLUMINA\n\
+        @RG\tID:rg3\tSM:s1\tLB:lib3\tPL:ONT\n\
+        @PG\tID:bwa\tPN:bwa\tVN:0.7.17\tCL:bwa mem ref.fa r1.fq\n\
+        @PG\tID:samtools\tPN:samtools\tPP:bwa\tVN:1.17\n\
+        @CO\tsome comment\n";
+
+    #[test]
+    fn test_sam_header_parse() {
+        let header: SamHeader = HEADER.parse().unwrap();
+
+        assert_eq!(header.sort_order(), Some("coordinate"));
+        assert_eq!(header.sequences.len(), 2);
+        assert_eq!(header.sequences[0].alternate_names, vec!["1", "chrom1"]);
+        assert_eq!(header.sequences[0].assembly.as_deref(), Some("hg38"));
+        assert_eq!(header.get_sequence("chrom1").unwrap().name, "chr1");
+        assert_eq!(
+            header.sequences[1].other,
+            vec![("XX".to_string(), "custom".to_string())]
+        );
+
+        let genome = header.genome();
+        assert_eq!(genome.seqnames, vec!["chr1", "chr2"]);
+        assert_eq!(genome.lengths, vec![1000, 500]);
+
+        assert_eq!(header.samples(), vec!["s1", "s2"]);
+        assert_eq!(header.read_groups_of_sample("s1"), vec!["rg1", "rg3"]);
+        assert_eq!(
+            header.get_read_group("rg3").unwrap().platform.as_deref(),
+            Some("ONT")
+        );
+
+        let chain: Vec<_> = header
+            .program_chain("samtools")
+            .iter()
+            .map(|pg| pg.id.as_str())
+            .collect();
+        assert_eq!(chain, vec!["bwa", "samtools"]);
+        assert_eq!(header.comments, vec!["some comment"]);
+
+        // Serialization reproduces the original text
+        assert_eq!(header.to_string(), HEADER);
+
+        assert!("@SQ\tSN:chr1\n".parse::<SamHeader>().is_err());
+        assert!("@RG\tSM:s1\n".parse::<SamHeader>().is_err());
+
+        // Lines with multi-byte characters are kept as they are
+        let header: SamHeader = "@é\tXX:1\n".parse().unwrap();
+        assert_eq!(header.other, vec!["@é\tXX:1"]);
+    }
+
+    #[test]
+    fn test_sam_header_edit() {
+        let mut header: SamHeader = HEADER.parse().unwrap();
+
+        header.set_sort_order("queryname");
+        header.remove_read_group("rg2");
+        header.add_read_group(SamReadGroup {
+            id: "rg4".to_string(),
+            sample: Some("s3".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(header.samples(), vec!["s1", "s3"]);
+
+        let id1 = header.add_program("rustynetics", Some("1.0"), Some("bam-sort in.bam"));
+        let id2 = header.add_program("rustynetics", Some("1.0"), None);
+        assert_eq!(id1, "rustynetics");
+        assert_eq!(id2, "rustynetics.1");
+
+        let chain: Vec<_> = header
+            .program_chain(&id2)
+            .iter()
+            .map(|pg| pg.id.as_str())
+            .collect();
+        assert_eq!(
+            chain,
+            vec!["bwa", "samtools", "rustynetics", "rustynetics.1"]
+        );
+
+        let text = header.to_string();
+        assert!(text.starts_with("@HD\tVN:1.6\tSO:queryname\n"));
+        assert!(text.contains("@PG\tID:rustynetics.1\tPN:rustynetics\tPP:rustynetics\tVN:1.0\n"));
+
+        // Round trip through a BAM header
+        let bam_header = BamHeader::from(&header);
+        assert_eq!(bam_header.n_ref, 2);
+        assert_eq!(bam_header.text_length as usize, text.len());
+        assert_eq!(bam_header.parse().unwrap(), header);
+    }
+
+    #[test]
+    fn test_sam_header_set_genome() {
+        let mut header: SamHeader = HEADER.parse().unwrap();
+
+        // Sequences found by an alternate name are renamed
+        let genome = Genome::new(vec!["chrom1".to_string()], vec![1000]);
+        header.set_genome(&genome);
+        assert_eq!(header.sequences.len(), 1);
+        assert_eq!(header.sequences[0].name, "chrom1");
+        assert_eq!(header.sequences[0].alternate_names, vec!["1", "chr1"]);
+        assert_eq!(header.sequences[0].assembly.as_deref(), Some("hg38"));
+        assert_eq!(
+            header.sequences[0].md5.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert_eq!(header.genome(), genome);
+
+        // The old name can be used to rename the sequence back, where a
+        // different length invalidates the checksum
+        let genome = Genome::new(
+            vec!["chr1".to_string(), "chr3".to_string()],
+            vec![2000, 500],
+        );
+        header.set_genome(&genome);
+        assert_eq!(header.sequences[0].name, "chr1");
+        assert_eq!(header.sequences[0].alternate_names, vec!["1", "chrom1"]);
+        assert_eq!(header.sequences[0].assembly.as_deref(), Some("hg38"));
+        assert_eq!(header.sequences[0].md5, None);
+        assert_eq!(header.sequences[1].name, "chr3");
+        assert!(header.sequences[1].alternate_names.is_empty());
+        assert_eq!(header.genome(), genome);
+    }
+
+    #[test]
+    fn test_sam_header_file() {
+        let sam = SamFile::open("tests/test_sam_1.sam").unwrap();
+        let header = sam.reader.get_header().parse().unwrap();
+
+        assert_eq!(header.sort_order(), Some("coordinate"));
+        assert_eq!(header.genome().seqnames, vec!["ref", "ref2"]);
+        assert_eq!(header.read_groups_of_sample("sample1"), vec!["rg1"]);
+        assert_eq!(
+            header.get_program("prog1").unwrap().name.as_deref(),
+            Some("test")
+        );
+    }
+}
@@ -23,8 +23,9 @@ use std::fs::File;
 use std::io::{Read, Seek, Write};
 use std::{cell::RefCell, rc::Rc};
 
-use crate::bbi::{BBI_MAX_ZOOM_LEVELS, BBI_RES_INCREMENT};
-use crate::bigwig::{BigWigParameters, BigWigReader, BigWigWriter, OptionBigWig};
+use crate::bigwig::{
+    bigwig_automatic_reduction_levels, BigWigParameters, BigWigReader, BigWigWriter, OptionBigWig,
+};
 use crate::genome::Genome;
 use crate::granges_row::GRangesRow;
 use crate::netfile::NetFile;
@@ -284,34 +285,6 @@ impl<R: Read + Seek> Track for BigWigTrack<R> {
 /* -------------------------------------------------------------------------- */
 
 impl<'a> GenericTrack<'a> {
-    fn bigwig_automatic_reduction_levels(&self, items_per_slot: usize) -> Vec<i32> {
-        let c = (BBI_RES_INCREMENT as usize) * self.track.get_bin_size();
-        let mut n = Vec::new();
-        let mut l = 0;
-
-        // Get length of longest track
-        for &length in &self.track.get_genome().lengths {
-            if length / self.track.get_bin_size() > l {
-                l = length / self.track.get_bin_size();
-            }
-        }
-
-        // Initial zoom level
-        let mut r = std::cmp::max(100, c);
-
-        // Compute number of zoom levels
-        while n.len() <= BBI_MAX_ZOOM_LEVELS {
-            if l / r > items_per_slot {
-                n.push(r as i32);
-                r *= c;
-            } else {
-                break;
-            }
-        }
-
-        n
-    }
-
     pub fn write_bigwig<W: Write + Seek>(
         &self,
         writer: &mut W,
@@ -333,7 +306,11 @@ impl<'a> GenericTrack<'a> {
         // If no reduction levels are given, compute a default set of zoom levels
         if has_reduction_levels == false {
             parameters_arg.push(OptionBigWig::ReductionLevels(
-                self.bigwig_automatic_reduction_levels(items_per_slot),
+                bigwig_automatic_reduction_levels(
+                    self.track.get_genome(),
+                    self.track.get_bin_size(),
+                    items_per_slot,
+                ),
             ));
         }
 
@@ -286,27 +286,8 @@ impl<'a> GenericMutableTrack<'a> {
     /// This function will return an error if the read's position falls outside of the track's bin range.
     /// Specifically, a `ReadOutOfRangeError` is returned if the read cannot be mapped to any valid bins.
     pub fn add_read(&mut self, read: &Read, d: usize) -> Result<(), Box<dyn Error>> {
-        let bin_size = self.track.get_bin_size();
-        let mut seq = self.track.get_sequence_mut(&read.seqname)?;
-        let Range { from, to } = read.extend(d)?;
-
-        if from / bin_size >= seq.n_bins() {
-            return Err(Box::new(ReadOutOfRangeError(read.clone())));
-        }
-
-        for j in (from / bin_size)..=(to - 1) / bin_size {
-            if j >= seq.n_bins() {
-                break;
-            } else {
-                let mut v = seq.at_bin(j);
-                if v.is_nan() {
-                    v = 0.0;
-                }
-                seq.set_bin(j, v + 1.0);
-            }
-        }
-
-        Ok(())
+        let range = read.extend(d)?;
+        self.add_read_ranges(read, &[range], "simple")
     }
 
     /// Adds a single read to the coverage track by calculating and adding the fraction of overlap
@@ -334,30 +315,8 @@ impl<'a> GenericMutableTrack<'a> {
     /// This function returns an error if the read's position is outside of the valid bin range.
     /// Specifically, a `ReadOutOfRangeError` is returned if the read cannot be mapped to any valid bins.
     fn add_read_mean_overlap(&mut self, read: &Read, d: usize) -> Result<(), Box<dyn Error>> {
-        let bin_size = self.track.get_bin_size();
-        let mut seq = self.track.get_sequence_mut(&read.seqname)?;
-        let Range { from, to } = read.extend(d)?;
-
-        if from / bin_size >= seq.n_bins() {
-            return Err(Box::new(ReadOutOfRangeError(read.clone())));
-        }
-
-        for j in (from / bin_size)..=((to - 1) / bin_size) {
-            if j >= seq.n_bins() {
-                break;
-            } else {
-                let mut v = seq.at_bin(j);
-                if v.is_nan() {
-                    v = 0.0;
-                }
-                let jfrom = std::cmp::max(from, j * bin_size);
-                let jto = std::cmp::min(to, (j + 1) * bin_size);
-
-                seq.set_bin(j, v + (jto - jfrom) as f64 / bin_size as f64);
-            }
-        }
-
-        Ok(())
+        let range = read.extend(d)?;
+        self.add_read_ranges(read, &[range], "mean overlap")
     }
 
     /// Adds a single read to the coverage track by calculating and adding the number of overlapping
@@ -379,25 +338,60 @@ impl<'a> GenericMutableTrack<'a> {
     /// Returns `Ok(())` if the read was successfully added to the track.
     /// If the read's position is out of range, an error is returned.
     fn add_read_overlap(&mut self, read: &Read, d: usize) -> Result<(), Box<dyn Error>> {
+        let range = read.extend(d)?;
+        self.add_read_ranges(read, &[range], "overlap")
+    }
+
+    /// Adds the given aligned segments of a read to the coverage track. The segments must be sorted
+    /// and must not overlap. Bins are incremented depending on `method`:
+    ///
+    /// - `"simple"`: Increments each bin that overlaps any segment by 1, i.e. each bin is counted
+    ///   only once per read.
+    /// - `"overlap"`: Increments each bin by the number of nucleotides that overlap the segments.
+    /// - `"mean overlap"`: Increments each bin by the fraction of nucleotides that overlap the segments.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ReadOutOfRangeError` if the first segment starts beyond the end of the sequence.
+    fn add_read_ranges(
+        &mut self,
+        read: &Read,
+        ranges: &[Range],
+        method: &str,
+    ) -> Result<(), Box<dyn Error>> {
         let bin_size = self.track.get_bin_size();
         let mut seq = self.track.get_sequence_mut(&read.seqname)?;
-        let Range { from, to } = read.extend(d)?;
 
-        if from / bin_size >= seq.n_bins() {
-            return Err(Box::new(ReadOutOfRangeError(read.clone())));
+        if let Some(first) = ranges.first() {
+            if first.from / bin_size >= seq.n_bins() {
+                return Err(Box::new(ReadOutOfRangeError(read.clone())));
+            }
         }
 
-        for j in (from / bin_size)..=((to - 1) / bin_size) {
-            if j >= seq.n_bins() {
-                break;
-            } else {
+        let mut last_bin = None;
+        for &Range { from, to } in ranges {
+            for j in (from / bin_size)..=((to - 1) / bin_size) {
+                if j >= seq.n_bins() {
+                    break;
+                }
                 let mut v = seq.at_bin(j);
                 if v.is_nan() {
                     v = 0.0;
                 }
                 let jfrom = std::cmp::max(from, j * bin_size);
                 let jto = std::cmp::min(to, (j + 1) * bin_size);
-                seq.set_bin(j, v + (jto - jfrom) as f64);
+
+                match method {
+                    "overlap" => seq.set_bin(j, v + (jto - jfrom) as f64),
+                    "mean overlap" => seq.set_bin(j, v + (jto - jfrom) as f64 / bin_size as f64),
+                    _ => {
+                        // Count each bin only once per read
+                        if last_bin != Some(j) {
+                            seq.set_bin(j, v + 1.0);
+                        }
+                    }
+                }
+                last_bin = Some(j);
             }
         }
 
@@ -473,6 +467,95 @@ impl<'a> GenericMutableTrack<'a> {
         n
     }
 
+    /// Adds a single read to the coverage track, considering only the aligned segments of the read.
+    /// Skipped regions (`N` operations, i.e. introns) and the unsequenced part of read pairs receive
+    /// no coverage, and deletions (`D` operations) only if `count_deletions` is `true`. Reads without
+    /// segment information are treated as a single aligned segment. Reads are never extended.
+    ///
+    /// Overlapping segments, e.g. of overlapping mates, are counted only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `read` - A reference to the read to be added.
+    /// * `method` - The binning method, see `add_spliced_reads`.
+    /// * `count_deletions` - Whether deletions are counted as covered.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ReadOutOfRangeError` if the read starts beyond the end of the sequence.
+    fn add_read_spliced(
+        &mut self,
+        read: &Read,
+        method: &str,
+        count_deletions: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if read.blocks.is_empty() {
+            return self.add_read_ranges(read, &[read.range], method);
+        }
+
+        let mut ranges: Vec<Range> = read
+            .blocks
+            .iter()
+            .filter(|block| count_deletions || block.type_ != 'D')
+            .map(|block| block.range)
+            .filter(|range| range.to > range.from)
+            .collect();
+        ranges.sort_by_key(|range| range.from);
+
+        // Merge overlapping and adjacent segments
+        let mut merged: Vec<Range> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.from <= last.to => last.to = std::cmp::max(last.to, range.to),
+                _ => merged.push(range),
+            }
+        }
+
+        self.add_read_ranges(read, &merged, method)
+    }
+
+    /// Adds multiple reads to the coverage track, where only the aligned segments of each read receive
+    /// coverage (see `add_read_spliced`). This is required for RNA-seq data, where reads spanning an
+    /// intron would otherwise cover the entire intron.
+    ///
+    /// # Arguments
+    ///
+    /// * `reads` - An iterator over reads to be added to the track.
+    /// * `method` - A string specifying how the coverage should be computed, with the same values as
+    ///   in `add_reads`.
+    /// * `count_deletions` - Whether deletions within reads are counted as covered.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of reads successfully added to the track.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if an invalid `method` is provided.
+    pub fn add_spliced_reads(
+        &mut self,
+        reads: impl Iterator<Item = Read>,
+        method: &str,
+        count_deletions: bool,
+    ) -> usize {
+        let method = match method {
+            "" | "simple" | "default" => "simple",
+            "overlap" | "mean overlap" => method,
+            _ => panic!("invalid binning method"),
+        };
+        let mut n = 0;
+
+        for read in reads {
+            if self
+                .add_read_spliced(&read, method, count_deletions)
+                .is_ok()
+            {
+                n += 1;
+            }
+        }
+        n
+    }
+
     /// Combines treatment and control tracks from a ChIP-seq experiment into a single normalized track.
     /// At each genomic location, the number of binned reads from the treatment track is divided by the number
     /// of control reads, and a pseudocount is added to both treatment and control values to avoid division by zero.
@@ -1033,3 +1116,101 @@ impl fmt::Display for SequenceLengthMismatchError {
 }
 
 impl Error for SequenceLengthMismatchError {}
+
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests {
+
+    use futures::executor::block_on_stream;
+
+    use crate::bam::BamCigar;
+    use crate::genome::Genome;
+    use crate::range::Range;
+    use crate::read::Read;
+    use crate::read_stream::ReadStream;
+    use crate::track::Track;
+    use crate::track_generic::GenericMutableTrack;
+    use crate::track_simple::SimpleTrack;
+
+    fn spliced_read() -> Read {
+        // Aligned [0,10), intron [10,30), aligned [30,35), deletion [35,45), aligned [45,50)
+        let cigar: BamCigar = "10M20N5M10D5M".parse().unwrap();
+        let mut read = Read::new(
+            "chr1",
+            Range::new(0, cigar.alignment_length()),
+            '+',
+            30,
+            false,
+            false,
+        );
+        read.blocks = cigar.read_blocks(0);
+        read
+    }
+
+    fn empty_track() -> SimpleTrack {
+        let genome = Genome::new(vec!["chr1".to_string()], vec![60]);
+        SimpleTrack::alloc("".to_string(), genome, f64::NAN, 10)
+    }
+
+    #[test]
+    fn test_add_spliced_reads() {
+        let mut track = empty_track();
+        let n = GenericMutableTrack::wrap(&mut track).add_spliced_reads(
+            vec![spliced_read()].into_iter(),
+            "overlap",
+            false,
+        );
+        assert_eq!(n, 1);
+
+        let seq = track.get_sequence("chr1").unwrap();
+        let values: Vec<f64> = (0..6).map(|i| seq.at_bin(i)).collect();
+
+        assert_eq!(values[0], 10.0);
+        assert!(values[1].is_nan());
+        assert!(values[2].is_nan());
+        assert_eq!(values[3], 5.0);
+        assert_eq!(values[4], 5.0);
+        assert!(values[5].is_nan());
+    }
+
+    #[test]
+    fn test_add_spliced_reads_deletions() {
+        let mut track = empty_track();
+        GenericMutableTrack::wrap(&mut track).add_spliced_reads(
+            vec![spliced_read()].into_iter(),
+            "simple",
+            true,
+        );
+
+        let seq = track.get_sequence("chr1").unwrap();
+        let values: Vec<f64> = (0..6).map(|i| seq.at_bin(i)).collect();
+
+        assert_eq!(values[0], 1.0);
+        assert!(values[1].is_nan());
+        assert!(values[2].is_nan());
+        assert_eq!(values[3], 1.0);
+        assert_eq!(values[4], 1.0);
+        assert!(values[5].is_nan());
+    }
+
+    #[test]
+    fn test_add_spliced_reads_shifted() {
+        let reads = futures::stream::iter(vec![Ok(spliced_read())]);
+        let reads = ReadStream::shift_reads(Box::pin(reads), None, &[10, 0]);
+        let reads = block_on_stream(reads).map(|r| r.unwrap());
+
+        let mut track = empty_track();
+        GenericMutableTrack::wrap(&mut track).add_spliced_reads(reads, "overlap", false);
+
+        let seq = track.get_sequence("chr1").unwrap();
+        let values: Vec<f64> = (0..6).map(|i| seq.at_bin(i)).collect();
+
+        assert!(values[0].is_nan());
+        assert_eq!(values[1], 10.0);
+        assert!(values[2].is_nan());
+        assert!(values[3].is_nan());
+        assert_eq!(values[4], 5.0);
+        assert_eq!(values[5], 5.0);
+    }
+}
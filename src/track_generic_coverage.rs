@@ -24,11 +24,47 @@ use futures::executor::block_on_stream;
 
 use crate::bam::BamFile;
 use crate::coverage::CoverageConfig;
-use crate::read_stream::ReadStream;
 use crate::track_generic::GenericMutableTrack;
 
 /* -------------------------------------------------------------------------- */
 
+/// Sets the subsampling fraction such that approximately
+/// `config.subsample_count` reads of the given file pass all filters. This
+/// requires an additional pass over the file. Nothing is changed if no
+/// subsampling count is set.
+fn set_subsample_fraction(
+    config: &mut CoverageConfig,
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    if config.subsample_count == 0 {
+        return Ok(());
+    }
+    config.subsample_fraction = 1.0;
+
+    let mut n = 0;
+    {
+        let mut bam = BamFile::open(filename, Some(config.bam_reader_options()))?;
+        let reads = config.read_stream(&mut bam.reader);
+        for read in block_on_stream(config.filter_read_stream(reads)) {
+            read?;
+            n += 1;
+        }
+    }
+    if n > config.subsample_count {
+        config.subsample_fraction = config.subsample_count as f64 / n as f64;
+    }
+    log!(
+        config.logger,
+        "Subsampling {} out of {} reads from `{}`",
+        std::cmp::min(n, config.subsample_count),
+        n,
+        filename
+    );
+    Ok(())
+}
+
+/* -------------------------------------------------------------------------- */
+
 impl<'a> GenericMutableTrack<'a> {
     pub fn coverage_from_bam(
         mut config: CoverageConfig,
@@ -47,46 +83,13 @@ impl<'a> GenericMutableTrack<'a> {
             let mut err_opt = None;
             let fraglen = fraglen_treatment[i];
 
+            set_subsample_fraction(&mut config, filename)?;
+
             log!(config.logger, "Reading treatment tags from `{}`", filename);
-            let mut bam = BamFile::open(filename, None)?;
+            let mut bam = BamFile::open(filename, Some(config.bam_reader_options()))?;
 
-            let treatment = Box::pin(bam.reader.read_simple_stream(
-                !config.paired_as_single_end,
-                config.paired_end_strand_specific,
-            ));
-            // First round of filtering
-            let treatment = ReadStream::filter_paired_end(
-                treatment,
-                Some(&config.logger),
-                config.filter_paired_end,
-            );
-            let treatment = ReadStream::filter_single_end(
-                treatment,
-                Some(&config.logger),
-                config.filter_single_end,
-            );
-            let treatment = ReadStream::paired_as_single_end(
-                treatment,
-                Some(&config.logger),
-                config.paired_as_single_end,
-            );
-            let treatment = ReadStream::filter_read_length(
-                treatment,
-                Some(&config.logger),
-                &config.filter_read_lengths,
-            );
-            let treatment = ReadStream::filter_duplicates(
-                treatment,
-                Some(&config.logger),
-                config.filter_duplicates,
-            );
-            let treatment =
-                ReadStream::filter_mapq(treatment, Some(&config.logger), config.filter_mapq);
-            // Second round of filtering
-            let treatment =
-                ReadStream::filter_strand(treatment, Some(&config.logger), config.filter_strand);
-            let treatment =
-                ReadStream::shift_reads(treatment, Some(&config.logger), &config.shift_reads);
+            let treatment = config.read_stream(&mut bam.reader);
+            let treatment = config.filter_read_stream(treatment);
 
             let treatment_iter = block_on_stream(treatment).map_while(|item| match item {
                 Ok(read) => Some(read),
@@ -96,7 +99,15 @@ impl<'a> GenericMutableTrack<'a> {
                 }
             });
 
-            n_treatment += track1.add_reads(treatment_iter, fraglen, &config.binning_method);
+            n_treatment += if config.spliced {
+                track1.add_spliced_reads(
+                    treatment_iter,
+                    &config.binning_method,
+                    config.count_deletions,
+                )
+            } else {
+                track1.add_reads(treatment_iter, fraglen, &config.binning_method)
+            };
 
             if let Some(err) = err_opt {
                 return Err(Box::new(err));
@@ -125,46 +136,13 @@ impl<'a> GenericMutableTrack<'a> {
                 let mut err_opt = None;
                 let fraglen = fraglen_control[i];
 
+                set_subsample_fraction(&mut config, filename)?;
+
                 log!(config.logger, "Reading control tags from `{}`", filename);
-                let mut bam = BamFile::open(filename, None)?;
-                let control = Box::pin(bam.reader.read_simple_stream(
-                    !config.paired_as_single_end,
-                    config.paired_end_strand_specific,
-                ));
+                let mut bam = BamFile::open(filename, Some(config.bam_reader_options()))?;
+                let control = config.read_stream(&mut bam.reader);
 
-                // First round of filtering
-                let control = ReadStream::filter_paired_end(
-                    control,
-                    Some(&config.logger),
-                    config.filter_paired_end,
-                );
-                let control = ReadStream::filter_single_end(
-                    control,
-                    Some(&config.logger),
-                    config.filter_single_end,
-                );
-                let control = ReadStream::paired_as_single_end(
-                    control,
-                    Some(&config.logger),
-                    config.paired_as_single_end,
-                );
-                let control = ReadStream::filter_read_length(
-                    control,
-                    Some(&config.logger),
-                    &config.filter_read_lengths,
-                );
-                let control = ReadStream::filter_duplicates(
-                    control,
-                    Some(&config.logger),
-                    config.filter_duplicates,
-                );
-                let control =
-                    ReadStream::filter_mapq(control, Some(&config.logger), config.filter_mapq);
-                // Second round of filtering
-                let control =
-                    ReadStream::filter_strand(control, Some(&config.logger), config.filter_strand);
-                let control =
-                    ReadStream::shift_reads(control, Some(&config.logger), &config.shift_reads);
+                let control = config.filter_read_stream(control);
 
                 let control_iter = block_on_stream(control).map_while(|item| match item {
                     Ok(read) => Some(read),
@@ -174,7 +152,15 @@ impl<'a> GenericMutableTrack<'a> {
                     }
                 });
 
-                n_control += track2.add_reads(control_iter, fraglen, &config.binning_method);
+                n_control += if config.spliced {
+                    track2.add_spliced_reads(
+                        control_iter,
+                        &config.binning_method,
+                        config.count_deletions,
+                    )
+                } else {
+                    track2.add_reads(control_iter, fraglen, &config.binning_method)
+                };
 
                 if let Some(err) = err_opt {
                     return Err(Box::new(err));
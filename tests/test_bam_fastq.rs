@@ -63,6 +63,7 @@ mod tests {
             read_sequence: false,
             read_auxiliary: false,
             read_qual: false,
+            threads: 1,
         };
 
         let mut bam_reader = BamReader::new(reader, Some(options)).unwrap();
@@ -88,6 +89,7 @@ mod tests {
             read_sequence: false,
             read_auxiliary: false,
             read_qual: false,
+            threads: 1,
         };
 
         let mut bam_reader = BamReader::new(reader, Some(options)).unwrap();
@@ -110,6 +112,7 @@ mod tests {
             read_sequence: false,
             read_auxiliary: false,
             read_qual: false,
+            threads: 1,
         };
 
         let mut bam_reader = BamReader::new(reader, Some(options)).unwrap();
@@ -80,6 +80,7 @@ mod tests {
             read_sequence: false,
             read_auxiliary: false,
             read_qual: false,
+            threads: 1,
         };
         let mut bam_reader = BamReader::new(reader, Some(options)).unwrap();
         let mut counts = ExpectedCounts::default();